@@ -17,6 +17,7 @@
 //! Persistent database for parachain data.
 
 extern crate polkadot_primitives;
+extern crate polkadot_erasure_coding as erasure;
 extern crate parking_lot;
 extern crate parity_codec as codec;
 extern crate substrate_primitives;
@@ -30,8 +31,9 @@ extern crate log;
 use codec::{Encode, Decode};
 use kvdb::{KeyValueDB, DBTransaction};
 use kvdb_rocksdb::{Database, DatabaseConfig};
-use polkadot_primitives::Hash;
-use polkadot_primitives::parachain::{Id as ParaId, BlockData, Extrinsic};
+use parking_lot::Mutex;
+use polkadot_primitives::{Hash, BlakeTwo256, HashT};
+use polkadot_primitives::parachain::{Id as ParaId, BlockData, Extrinsic, ErasureChunk, CandidateReceipt};
 
 use std::collections::HashSet;
 use std::path::PathBuf;
@@ -41,7 +43,8 @@ use std::io;
 mod columns {
 	pub const DATA: Option<u32> = Some(0);
 	pub const META: Option<u32> = Some(1);
-	pub const NUM_COLUMNS: u32 = 2;
+	pub const CHUNKS: Option<u32> = Some(2);
+	pub const NUM_COLUMNS: u32 = 3;
 }
 
 /// Configuration for the availability store.
@@ -74,10 +77,25 @@ fn extrinsic_key(relay_parent: &Hash, candidate_hash: &Hash) -> Vec<u8> {
 	(relay_parent, candidate_hash, 1i8).encode()
 }
 
+fn erasure_root_key(relay_parent: &Hash, candidate_hash: &Hash) -> Vec<u8> {
+	(relay_parent, candidate_hash, 2i8).encode()
+}
+
+// prefix of the keys of all chunks of a candidate.
+fn erasure_chunks_prefix(relay_parent: &Hash, candidate_hash: &Hash) -> Vec<u8> {
+	(relay_parent, candidate_hash).encode()
+}
+
+fn erasure_chunk_key(relay_parent: &Hash, candidate_hash: &Hash, index: u32) -> Vec<u8> {
+	(relay_parent, candidate_hash, index).encode()
+}
+
 /// Handle to the availability store.
 #[derive(Clone)]
 pub struct Store {
 	inner: Arc<dyn KeyValueDB>,
+	// held while the candidates noted under a relay parent are read and updated.
+	meta_lock: Arc<Mutex<()>>,
 }
 
 impl Store {
@@ -95,6 +113,7 @@ impl Store {
 
 		Ok(Store {
 			inner: Arc::new(db),
+			meta_lock: Arc::new(Mutex::new(())),
 		})
 	}
 
@@ -102,25 +121,15 @@ impl Store {
 	pub fn new_in_memory() -> Self {
 		Store {
 			inner: Arc::new(::kvdb_memorydb::create(::columns::NUM_COLUMNS)),
+			meta_lock: Arc::new(Mutex::new(())),
 		}
 	}
 
 	/// Make some data available provisionally.
 	pub fn make_available(&self, data: Data) -> io::Result<()> {
+		let _meta_lock = self.meta_lock.lock();
 		let mut tx = DBTransaction::new();
-
-		// note the meta key.
-		let mut v = match self.inner.get(columns::META, data.relay_parent.as_ref()) {
-			Ok(Some(raw)) => Vec::decode(&mut &raw[..]).expect("all stored data serialized correctly; qed"),
-			Ok(None) => Vec::new(),
-			Err(e) => {
-				warn!(target: "availability", "Error reading from availability store: {:?}", e);
-				Vec::new()
-			}
-		};
-
-		v.push(data.candidate_hash);
-		tx.put_vec(columns::META, &data.relay_parent[..], v.encode());
+		self.note_candidate(&mut tx, data.relay_parent, data.candidate_hash);
 
 		tx.put_vec(
 			columns::DATA,
@@ -139,8 +148,47 @@ impl Store {
 		self.inner.write(tx)
 	}
 
+	/// Store an erasure-coded chunk of a candidate, along with the erasure root of
+	/// the given receipt.
+	///
+	/// Fails if the chunk isn't proven against the erasure root. Any number of chunks
+	/// of a candidate may be stored. They are pruned along with the candidate's other data.
+	pub fn add_erasure_chunk(
+		&self,
+		relay_parent: Hash,
+		receipt: &CandidateReceipt,
+		chunk: &ErasureChunk,
+	) -> io::Result<()> {
+		let proven = erasure::branch_hash(&receipt.erasure_root, &chunk.proof, chunk.index as usize)
+			.map_or(false, |hash| hash == BlakeTwo256::hash(&chunk.chunk));
+		if !proven {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "Erasure chunk not proven against the erasure root"));
+		}
+
+		let candidate_hash = receipt.hash();
+		let _meta_lock = self.meta_lock.lock();
+		let mut tx = DBTransaction::new();
+		self.note_candidate(&mut tx, relay_parent, candidate_hash);
+
+		// the candidate hash commits to the erasure root, so it never changes.
+		tx.put_vec(
+			columns::DATA,
+			erasure_root_key(&relay_parent, &candidate_hash).as_slice(),
+			receipt.erasure_root.encode(),
+		);
+
+		tx.put_vec(
+			columns::CHUNKS,
+			erasure_chunk_key(&relay_parent, &candidate_hash, chunk.index).as_slice(),
+			chunk.encode(),
+		);
+
+		self.inner.write(tx)
+	}
+
 	/// Note that a set of candidates have been included in a finalized block with given hash and parent hash.
 	pub fn candidates_finalized(&self, parent: Hash, finalized_candidates: HashSet<Hash>) -> io::Result<()> {
+		let _meta_lock = self.meta_lock.lock();
 		let mut tx = DBTransaction::new();
 
 		let v = match self.inner.get(columns::META, &parent[..]) {
@@ -157,6 +205,11 @@ impl Store {
 			if !finalized_candidates.contains(&candidate_hash) {
 				tx.delete(columns::DATA, block_data_key(&parent, &candidate_hash).as_slice());
 				tx.delete(columns::DATA, extrinsic_key(&parent, &candidate_hash).as_slice());
+				tx.delete(columns::DATA, erasure_root_key(&parent, &candidate_hash).as_slice());
+
+				for chunk in self.erasure_chunks(parent, candidate_hash) {
+					tx.delete(columns::CHUNKS, erasure_chunk_key(&parent, &candidate_hash, chunk.index).as_slice());
+				}
 			}
 		}
 
@@ -178,6 +231,45 @@ impl Store {
 		}
 	}
 
+	/// Query an erasure-coded chunk of a candidate by index.
+	pub fn get_erasure_chunk(&self, relay_parent: Hash, candidate_hash: Hash, index: u32) -> Option<ErasureChunk> {
+		let encoded_key = erasure_chunk_key(&relay_parent, &candidate_hash, index);
+		match self.inner.get(columns::CHUNKS, &encoded_key[..]) {
+			Ok(Some(raw)) => Some(
+				ErasureChunk::decode(&mut &raw[..]).expect("all stored data serialized correctly; qed")
+			),
+			Ok(None) => None,
+			Err(e) => {
+				warn!(target: "availability", "Error reading from availability store: {:?}", e);
+				None
+			}
+		}
+	}
+
+	/// Query all stored erasure-coded chunks of a candidate.
+	pub fn erasure_chunks(&self, relay_parent: Hash, candidate_hash: Hash) -> Vec<ErasureChunk> {
+		let prefix = erasure_chunks_prefix(&relay_parent, &candidate_hash);
+		self.inner.iter_from_prefix(columns::CHUNKS, &prefix[..])
+			.take_while(|&(ref key, _)| key.starts_with(&prefix[..]))
+			.map(|(_, raw)| ErasureChunk::decode(&mut &raw[..]).expect("all stored data serialized correctly; qed"))
+			.collect()
+	}
+
+	/// Query the erasure root of a candidate of which chunks are stored.
+	pub fn erasure_root(&self, relay_parent: Hash, candidate_hash: Hash) -> Option<Hash> {
+		let encoded_key = erasure_root_key(&relay_parent, &candidate_hash);
+		match self.inner.get(columns::DATA, &encoded_key[..]) {
+			Ok(Some(raw)) => Some(
+				Hash::decode(&mut &raw[..]).expect("all stored data serialized correctly; qed")
+			),
+			Ok(None) => None,
+			Err(e) => {
+				warn!(target: "availability", "Error reading from availability store: {:?}", e);
+				None
+			}
+		}
+	}
+
 	/// Query extrinsic data.
 	pub fn extrinsic(&self, relay_parent: Hash, candidate_hash: Hash) -> Option<Extrinsic> {
 		let encoded_key = extrinsic_key(&relay_parent, &candidate_hash);
@@ -192,6 +284,24 @@ impl Store {
 			}
 		}
 	}
+
+	// note a candidate under its relay parent, so that its data is pruned on finality.
+	// must be called with the meta lock held.
+	fn note_candidate(&self, tx: &mut DBTransaction, relay_parent: Hash, candidate_hash: Hash) {
+		let mut v: Vec<Hash> = match self.inner.get(columns::META, relay_parent.as_ref()) {
+			Ok(Some(raw)) => Vec::decode(&mut &raw[..]).expect("all stored data serialized correctly; qed"),
+			Ok(None) => Vec::new(),
+			Err(e) => {
+				warn!(target: "availability", "Error reading from availability store: {:?}", e);
+				Vec::new()
+			}
+		};
+
+		if !v.contains(&candidate_hash) {
+			v.push(candidate_hash);
+			tx.put_vec(columns::META, &relay_parent[..], v.encode());
+		}
+	}
 }
 
 #[cfg(test)]
@@ -242,4 +352,70 @@ mod tests {
 		assert!(store.extrinsic(relay_parent, candidate_1).is_some());
 		assert!(store.extrinsic(relay_parent, candidate_2).is_none());
 	}
+
+	#[test]
+	fn erasure_chunks_are_stored_and_pruned() {
+		use polkadot_primitives::parachain::HeadData;
+
+		let relay_parent = [1; 32].into();
+		let extrinsic = Extrinsic { outgoing_messages: Vec::new() };
+		let make_chunks = |block_data: &BlockData| {
+			let raw_chunks = erasure::obtain_chunks(4, block_data, &extrinsic).unwrap();
+			let branches = erasure::branches(raw_chunks.iter().map(|c| &c[..]).collect());
+			let root = branches.root();
+			let chunks: Vec<_> = branches.enumerate()
+				.map(|(index, (proof, chunk))| ErasureChunk { chunk: chunk.to_vec(), index: index as u32, proof })
+				.collect();
+
+			(root, chunks)
+		};
+		let receipt = |erasure_root: Hash| CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [255; 32].into(),
+			head_data: HeadData(vec![9, 9, 9]),
+			signature: Default::default(),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root,
+		};
+
+		let (root_1, chunks_1) = make_chunks(&BlockData(vec![1, 2, 3]));
+		let (root_2, chunks_2) = make_chunks(&BlockData(vec![4, 5, 6]));
+		let receipt_1 = receipt(root_1);
+		let receipt_2 = receipt(root_2);
+		let candidate_1 = receipt_1.hash();
+		let candidate_2 = receipt_2.hash();
+
+		let store = Store::new_in_memory();
+		store.add_erasure_chunk(relay_parent, &receipt_1, &chunks_1[0]).unwrap();
+		store.add_erasure_chunk(relay_parent, &receipt_1, &chunks_1[3]).unwrap();
+		store.add_erasure_chunk(relay_parent, &receipt_2, &chunks_2[1]).unwrap();
+
+		// chunks which aren't proven against the erasure root are rejected.
+		assert!(store.add_erasure_chunk(relay_parent, &receipt_2, &chunks_1[2]).is_err());
+		let mut misplaced = chunks_2[2].clone();
+		misplaced.index = 3;
+		assert!(store.add_erasure_chunk(relay_parent, &receipt_2, &misplaced).is_err());
+
+		assert_eq!(store.get_erasure_chunk(relay_parent, candidate_1, 0), Some(chunks_1[0].clone()));
+		assert_eq!(store.get_erasure_chunk(relay_parent, candidate_1, 3), Some(chunks_1[3].clone()));
+		assert!(store.get_erasure_chunk(relay_parent, candidate_1, 1).is_none());
+		assert_eq!(store.get_erasure_chunk(relay_parent, candidate_2, 1), Some(chunks_2[1].clone()));
+		assert!(store.get_erasure_chunk(relay_parent, candidate_2, 3).is_none());
+
+		assert_eq!(store.erasure_chunks(relay_parent, candidate_1), vec![chunks_1[0].clone(), chunks_1[3].clone()]);
+		assert_eq!(store.erasure_chunks(relay_parent, candidate_2), vec![chunks_2[1].clone()]);
+		assert_eq!(store.erasure_root(relay_parent, candidate_1), Some(root_1));
+		assert_eq!(store.erasure_root(relay_parent, candidate_2), Some(root_2));
+
+		store.candidates_finalized(relay_parent, [candidate_1].iter().cloned().collect()).unwrap();
+
+		assert_eq!(store.get_erasure_chunk(relay_parent, candidate_1, 3), Some(chunks_1[3].clone()));
+		assert!(store.get_erasure_chunk(relay_parent, candidate_2, 1).is_none());
+		assert!(store.erasure_chunks(relay_parent, candidate_2).is_empty());
+		assert!(store.erasure_root(relay_parent, candidate_2).is_none());
+	}
 }
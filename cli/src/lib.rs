@@ -24,6 +24,7 @@ extern crate tokio;
 
 extern crate substrate_cli as cli;
 extern crate polkadot_service as service;
+extern crate polkadot_parachain as parachain;
 extern crate exit_future;
 
 #[macro_use]
@@ -79,11 +80,20 @@ pub trait Worker: IntoExit {
 /// 9556-9591		Unassigned
 /// 9803-9874		Unassigned
 /// 9926-9949		Unassigned
+///
+/// If the first argument is `validation-worker`, this instead runs a parachain
+/// validation worker on stdin and stdout, as started by the validation host.
 pub fn run<I, T, W>(args: I, worker: W, version: cli::VersionInfo) -> error::Result<()> where
 	I: IntoIterator<Item = T>,
 	T: Into<std::ffi::OsString> + Clone,
 	W: Worker,
 {
+	let args: Vec<std::ffi::OsString> = args.into_iter().map(Into::into).collect();
+	if args.get(1).map_or(false, |arg| arg == parachain::validation_host::WORKER_ARG) {
+		return parachain::validation_host::run_worker()
+			.map_err(|e| format!("Validation worker failed: {}", e).into());
+	}
+
 	cli::parse_and_execute::<service::Factory, NoCustom, NoCustom, _, _, _, _, _>(
 		load_spec, &version, "parity-polkadot", args, worker,
 		|worker, _custom_args, mut config| {
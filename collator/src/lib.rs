@@ -53,6 +53,8 @@ extern crate tokio;
 extern crate polkadot_cli;
 extern crate polkadot_runtime;
 extern crate polkadot_primitives;
+extern crate polkadot_consensus;
+extern crate polkadot_erasure_coding as erasure;
 
 #[macro_use]
 extern crate log;
@@ -65,8 +67,11 @@ use std::time::Duration;
 use futures::{future, stream, Stream, Future, IntoFuture};
 use client::BlockchainEvents;
 use primitives::ed25519;
-use polkadot_primitives::{AccountId, BlockId, SessionKey};
-use polkadot_primitives::parachain::{self, BlockData, DutyRoster, HeadData, ConsolidatedIngress, Message, Id as ParaId};
+use polkadot_primitives::{AccountId, BlockId, SessionKey, BlakeTwo256, HashT};
+use polkadot_primitives::parachain::{
+	self, BlockData, DutyRoster, HeadData, ConsolidatedIngress, Message, Id as ParaId, PoVBlock,
+	Extrinsic, OutgoingMessage,
+};
 use polkadot_cli::{PolkadotService, CustomConfiguration, CoreApi, ParachainHost};
 use polkadot_cli::{Worker, IntoExit, ProvideRuntimeApi};
 use tokio::timer::Timeout;
@@ -86,6 +91,8 @@ pub enum Error<R> {
 	Polkadot(R),
 	/// Error on the collator side of things.
 	Collator(InvalidHead),
+	/// Failed to erasure-code the candidate.
+	Erasure(erasure::Error),
 }
 
 impl<R: fmt::Display> fmt::Display for Error<R> {
@@ -93,6 +100,7 @@ impl<R: fmt::Display> fmt::Display for Error<R> {
 		match *self {
 			Error::Polkadot(ref err) => write!(f, "Polkadot node error: {}", err),
 			Error::Collator(_) => write!(f, "Collator node error: Invalid head data"),
+			Error::Erasure(ref err) => write!(f, "Failed to erasure-code candidate: {:?}", err),
 		}
 	}
 }
@@ -107,7 +115,30 @@ pub trait ParachainContext: Clone {
 		&self,
 		last_head: HeadData,
 		ingress: I,
-	) -> Result<(BlockData, HeadData), InvalidHead>;
+	) -> Result<ParachainCandidate, InvalidHead>;
+}
+
+/// A parachain candidate produced by a `ParachainContext`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParachainCandidate {
+	/// The block data of the candidate.
+	pub block_data: BlockData,
+	/// The head data of the candidate.
+	pub head_data: HeadData,
+	/// Balances uploaded from the parachain's relay-chain account. These
+	/// must match the uploads reported by the validation function.
+	pub balance_uploads: Vec<(AccountId, u64)>,
+	/// Fees paid from the parachain's relay-chain account to the validators
+	/// backing the candidate.
+	pub fees: u64,
+	/// New validation code for the parachain. This must match the code
+	/// reported by the validation function. The candidate receipt only commits
+	/// to its hash, so the code must be submitted to the relay chain once the
+	/// upgrade is scheduled.
+	pub new_validation_code: Option<Vec<u8>>,
+	/// Messages to other parachains. These must match the messages posted
+	/// by the validation function.
+	pub outgoing_messages: Vec<OutgoingMessage>,
 }
 
 /// Relay chain context needed to collate.
@@ -170,12 +201,15 @@ pub fn collate_ingress<'a, R>(relay_context: R)
 }
 
 /// Produce a candidate for the parachain, with given contexts, parent head, and signing key.
+///
+/// The candidate is erasure-coded for the given number of validators at the relay parent.
 pub fn collate<'a, R, P>(
 	local_id: ParaId,
 	last_head: HeadData,
 	relay_context: R,
 	para_context: P,
 	key: Arc<ed25519::Pair>,
+	n_validators: usize,
 )
 	-> impl Future<Item=parachain::Collation, Error=Error<R::Error>> + 'a
 	where
@@ -185,28 +219,56 @@ pub fn collate<'a, R, P>(
 		P: ParachainContext + 'a,
 {
 	collate_ingress(relay_context).map_err(Error::Polkadot).and_then(move |ingress| {
-		let (block_data, head_data) = para_context.produce_candidate(
+		let candidate = para_context.produce_candidate(
 			last_head,
 			ingress.0.iter().flat_map(|&(id, ref msgs)| msgs.iter().cloned().map(move |msg| (id, msg)))
 		).map_err(Error::Collator)?;
 
-		let block_data_hash = block_data.hash();
+		let block_data_hash = candidate.block_data.hash();
 		let signature = key.sign(block_data_hash.as_ref()).into();
 
+		// validators sort the posted messages by target, keeping the order of those
+		// to the same parachain, and commit to each batch with an egress root.
+		let mut outgoing_messages = candidate.outgoing_messages;
+		outgoing_messages.sort_by_key(|msg| msg.target);
+
+		let mut egress_queue_roots = Vec::new();
+		{
+			let mut rest = &outgoing_messages[..];
+			while let Some(target) = rest.first().map(|msg| msg.target) {
+				let batch_len = rest.iter().take_while(|msg| msg.target == target).count();
+				let (batch, tail) = rest.split_at(batch_len);
+				let root = polkadot_consensus::egress_trie_root(batch.iter().map(|msg| &msg.data[..]));
+				egress_queue_roots.push((target, root));
+				rest = tail;
+			}
+		}
+
+		let extrinsic = Extrinsic { outgoing_messages };
+		let chunks = erasure::obtain_chunks(n_validators, &candidate.block_data, &extrinsic)
+			.map_err(Error::Erasure)?;
+		let erasure_root = erasure::branches(chunks.iter().map(|c| &c[..]).collect()).root();
+
 		let receipt = parachain::CandidateReceipt {
 			parachain_index: local_id,
 			collator: key_to_account_id(&*key),
 			signature,
-			head_data,
-			balance_uploads: Vec::new(),
-			egress_queue_roots: Vec::new(),
-			fees: 0,
+			head_data: candidate.head_data,
+			balance_uploads: candidate.balance_uploads,
+			egress_queue_roots,
+			fees: candidate.fees,
 			block_data_hash,
+			new_validation_code_hash: candidate.new_validation_code.as_ref()
+				.map(|code| BlakeTwo256::hash(&code[..])),
+			erasure_root,
 		};
 
 		Ok(parachain::Collation {
 			receipt,
-			block_data,
+			pov: PoVBlock {
+				block_data: candidate.block_data,
+				ingress,
+			},
 		})
 	})
 }
@@ -289,6 +351,14 @@ impl<P, E> Worker for CollationNode<P, E> where
 
 				let work = future::lazy(move || {
 					let api = client.runtime_api();
+
+					// parathreads only have candidates included in blocks they are scheduled for.
+					if !try_fr!(api.active_parachains(&id)).contains(&para_id)
+						&& !try_fr!(api.parathread_schedule(&id)).contains(&para_id)
+					{
+						return future::Either::A(future::ok(()));
+					}
+
 					let last_head = match try_fr!(api.parachain_head(&id, para_id)) {
 						Some(last_head) => last_head,
 						None => return future::Either::A(future::ok(())),
@@ -299,6 +369,7 @@ impl<P, E> Worker for CollationNode<P, E> where
 						try_fr!(api.authorities(&id)).as_slice(),
 						try_fr!(api.duty_roster(&id)),
 					);
+					let n_validators = try_fr!(api.validators(&id)).len();
 
 					let collation_work = collate(
 						para_id,
@@ -306,6 +377,7 @@ impl<P, E> Worker for CollationNode<P, E> where
 						ApiContext,
 						parachain_context,
 						key,
+						n_validators,
 					).map(move |collation| {
 						network.with_spec(|spec, ctx| spec.add_local_collation(
 							ctx,
@@ -375,7 +447,7 @@ mod tests {
 	use std::collections::{HashMap, BTreeSet};
 
 	use futures::Future;
-	use polkadot_primitives::parachain::{Message, Id as ParaId};
+	use polkadot_primitives::parachain::{Message, Id as ParaId, OutgoingMessage};
 
 	pub struct DummyRelayChainCtx {
 		egresses: HashMap<ParaId, Vec<Vec<Message>>>,
@@ -395,6 +467,71 @@ mod tests {
 		}
 	}
 
+	#[derive(Clone)]
+	struct PostingParachainCtx;
+
+	impl ParachainContext for PostingParachainCtx {
+		fn produce_candidate<I: IntoIterator<Item=(ParaId, Message)>>(
+			&self,
+			_last_head: HeadData,
+			_ingress: I,
+		) -> Result<ParachainCandidate, InvalidHead> {
+			let message = |target: u32, data: Vec<u8>| OutgoingMessage { target: target.into(), data };
+
+			Ok(ParachainCandidate {
+				block_data: BlockData(vec![1, 2, 3]),
+				head_data: HeadData(vec![4, 5, 6]),
+				balance_uploads: Vec::new(),
+				fees: 0,
+				new_validation_code: None,
+				outgoing_messages: vec![
+					message(3, vec![1]),
+					message(2, vec![2]),
+					message(3, vec![3]),
+				],
+			})
+		}
+	}
+
+	#[test]
+	fn commits_to_outgoing_messages() {
+		let dummy_ctx = DummyRelayChainCtx {
+			currently_routing: BTreeSet::new(),
+			egresses: HashMap::new(),
+		};
+		let key = Arc::new(ed25519::Pair::from_seed(&[1; 32]));
+
+		let collation = collate(
+			1.into(),
+			HeadData(Vec::new()),
+			dummy_ctx,
+			PostingParachainCtx,
+			key,
+			10,
+		).wait().unwrap();
+
+		let egress_root = |data: &[&[u8]]| polkadot_consensus::egress_trie_root(data.iter().cloned());
+		assert_eq!(collation.receipt.egress_queue_roots, vec![
+			(2.into(), egress_root(&[&[2]])),
+			(3.into(), egress_root(&[&[1], &[3]])),
+		]);
+
+		// the erasure root is the one validators compute from the extrinsic of valid execution.
+		let extrinsic = Extrinsic {
+			outgoing_messages: vec![
+				OutgoingMessage { target: 2.into(), data: vec![2] },
+				OutgoingMessage { target: 3.into(), data: vec![1] },
+				OutgoingMessage { target: 3.into(), data: vec![3] },
+			],
+		};
+		let (erasure_root, _) = polkadot_consensus::collation::erasure_chunks(
+			10,
+			&collation.pov.block_data,
+			&extrinsic,
+		).unwrap();
+		assert_eq!(collation.receipt.erasure_root, erasure_root);
+	}
+
 	#[test]
 	fn collates_ingress() {
 		let route_from = |x: &[ParaId]| {
@@ -36,6 +36,7 @@ use futures::prelude::*;
 use polkadot_primitives::{Block, BlockId};
 use polkadot_primitives::parachain::ParachainHost;
 use extrinsic_store::Store as ExtrinsicStore;
+use slashing_protection::Store as SignedStatements;
 use runtime_primitives::traits::ProvideRuntimeApi;
 
 use tokio::runtime::TaskExecutor;
@@ -85,7 +86,7 @@ fn prune_unneeded_availability<P>(client: Arc<P>, extrinsic_store: ExtrinsicStor
 				.iter()
 				.filter_map(|ex| RuntimeExtrinsic::decode(&mut ex.encode().as_slice()))
 				.filter_map(|ex| match ex.function {
-					Call::Parachains(ParachainsCall::set_heads(ref heads)) =>
+					Call::Parachains(ParachainsCall::set_heads(ref heads, _, _, _)) =>
 						Some(heads.iter().map(|c| c.candidate.hash()).collect()),
 					_ => None,
 				})
@@ -103,6 +104,23 @@ fn prune_unneeded_availability<P>(client: Arc<P>, extrinsic_store: ExtrinsicStor
 		})
 }
 
+// creates a task to forget the statements signed on top of all relay parents below
+// finalized blocks, on any fork. no more blocks can be finalized on top of those, so
+// the statements can't be used as proof of misbehavior anymore.
+fn prune_signed_statements<P>(client: Arc<P>, signed_statements: SignedStatements)
+	-> impl Future<Item=(),Error=()> + Send
+	where P: Send + Sync + BlockchainEvents<Block> + 'static
+{
+	client.finality_notification_stream()
+		.for_each(move |notification| {
+			if let Err(e) = signed_statements.prune_below(notification.header.number) {
+				warn!(target: "consensus", "Failed to prune signed statements: {:?}", e);
+			}
+
+			Ok(())
+		})
+}
+
 /// Parachain candidate attestation service handle.
 pub(crate) struct ServiceHandle {
 	thread: Option<thread::JoinHandle<()>>,
@@ -116,6 +134,7 @@ pub(crate) fn start<C, N, P>(
 	thread_pool: TaskExecutor,
 	key: Arc<ed25519::Pair>,
 	extrinsic_store: ExtrinsicStore,
+	signed_statements: SignedStatements,
 ) -> ServiceHandle
 	where
 		C: Collators + Send + Sync + 'static,
@@ -140,6 +159,7 @@ pub(crate) fn start<C, N, P>(
 			client.import_notification_stream()
 				.for_each(move |notification| {
 					let parent_hash = notification.hash;
+					let parent_number = notification.header.number;
 					if notification.is_new_best {
 						let res = client
 							.runtime_api()
@@ -148,6 +168,7 @@ pub(crate) fn start<C, N, P>(
 							.and_then(|authorities| {
 								consensus.get_or_instantiate(
 									parent_hash,
+									parent_number,
 									&authorities,
 									key.clone(),
 								)
@@ -190,13 +211,19 @@ pub(crate) fn start<C, N, P>(
 		runtime.spawn(notifications);
 		thread_pool.spawn(prune_old_sessions);
 
-		let prune_available = prune_unneeded_availability(client, extrinsic_store)
+		let prune_available = prune_unneeded_availability(client.clone(), extrinsic_store)
 			.select(exit.clone())
 			.then(|_| Ok(()));
 
 		// spawn this on the tokio executor since it's fine on a thread pool.
 		thread_pool.spawn(prune_available);
 
+		let prune_signed = prune_signed_statements(client, signed_statements)
+			.select(exit.clone())
+			.then(|_| Ok(()));
+
+		thread_pool.spawn(prune_signed);
+
 		if let Err(e) = runtime.block_on(exit) {
 			debug!("BFT event loop error {:?}", e);
 		}
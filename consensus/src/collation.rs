@@ -23,9 +23,10 @@ use std::sync::Arc;
 
 use polkadot_primitives::{Block, Hash, AccountId, BlockId};
 use polkadot_primitives::parachain::{Id as ParaId, Collation, Extrinsic, OutgoingMessage};
-use polkadot_primitives::parachain::{CandidateReceipt, ParachainHost};
-use runtime_primitives::traits::ProvideRuntimeApi;
-use parachain::{wasm_executor::{self, ExternalitiesError}, MessageRef};
+use polkadot_primitives::parachain::{CandidateReceipt, ParachainHost, BlockData, ErasureChunk};
+use polkadot_primitives::parachain::{ConsolidatedIngress, StructuredUnroutedIngress, FeeSchedule};
+use runtime_primitives::traits::{ProvideRuntimeApi, BlakeTwo256, Hash as HashT};
+use parachain::{wasm_executor::{self, ExternalitiesError}, validation_host::ValidationPool, MessageRef};
 
 use futures::prelude::*;
 
@@ -61,16 +62,25 @@ pub struct CollationFetch<C: Collators, P: ProvideRuntimeApi> {
 	collators: C,
 	live_fetch: Option<<C::Collation as IntoFuture>::Future>,
 	client: Arc<P>,
+	validation_pool: ValidationPool,
 }
 
 impl<C: Collators, P: ProvideRuntimeApi> CollationFetch<C, P> {
 	/// Create a new collation fetcher for the given chain.
-	pub fn new(parachain: ParaId, relay_parent: BlockId, relay_parent_hash: Hash, collators: C, client: Arc<P>) -> Self {
+	pub fn new(
+		parachain: ParaId,
+		relay_parent: BlockId,
+		relay_parent_hash: Hash,
+		collators: C,
+		client: Arc<P>,
+		validation_pool: ValidationPool,
+	) -> Self {
 		CollationFetch {
 			relay_parent_hash,
 			relay_parent,
 			collators,
 			client,
+			validation_pool,
 			parachain,
 			live_fetch: None,
 		}
@@ -85,10 +95,10 @@ impl<C: Collators, P: ProvideRuntimeApi> CollationFetch<C, P> {
 impl<C: Collators, P: ProvideRuntimeApi> Future for CollationFetch<C, P>
 	where P::Api: ParachainHost<Block>,
 {
-	type Item = (Collation, Extrinsic);
+	type Item = (Collation, Extrinsic, Vec<ErasureChunk>);
 	type Error = C::Error;
 
-	fn poll(&mut self) -> Poll<(Collation, Extrinsic), C::Error> {
+	fn poll(&mut self) -> Poll<(Collation, Extrinsic, Vec<ErasureChunk>), C::Error> {
 		loop {
 			let x = {
 				let parachain = self.parachain.clone();
@@ -100,9 +110,9 @@ impl<C: Collators, P: ProvideRuntimeApi> Future for CollationFetch<C, P>
 				try_ready!(poll)
 			};
 
-			match validate_collation(&*self.client, &self.relay_parent, &x) {
-				Ok(e) => {
-					return Ok(Async::Ready((x, e)))
+			match validate_collation(&*self.client, &self.validation_pool, &self.relay_parent, &x) {
+				Ok((e, chunks)) => {
+					return Ok(Async::Ready((x, e, chunks)))
 				}
 				Err(e) => {
 					debug!("Failed to validate parachain due to API error: {}", e);
@@ -145,6 +155,65 @@ error_chain! {
 			description("Parachain validation produced wrong head data."),
 			display("Parachain validation produced wrong head data (expected: {:?}, got {:?}", expected, got),
 		}
+		WrongBalanceUploads(expected: Vec<(AccountId, u64)>, got: Vec<(AccountId, u64)>) {
+			description("Parachain validation produced wrong balance uploads."),
+			display("Parachain validation produced wrong balance uploads (expected: {:?}, got {:?}", expected, got),
+		}
+		WrongValidationCode {
+			description("Parachain validation produced different new validation code than the candidate."),
+			display("Parachain validation produced different new validation code than the candidate."),
+		}
+		ValidationCodeTooLarge(size: usize, max: u32) {
+			description("Parachain validation produced new validation code which is too large."),
+			display("New validation code of {} bytes exceeds the maximum of {} bytes", size, max),
+		}
+		CandidatePendingAvailability(id: ParaId) {
+			description("Parachain already has a candidate pending availability."),
+			display("Parachain {:?} already has a candidate pending availability.", id),
+		}
+		CodeUpgradePending(id: ParaId) {
+			description("Candidate upgrades validation code while an upgrade is pending."),
+			display("Candidate for {:?} upgrades validation code while an upgrade is pending.", id),
+		}
+		Erasure(err: ::erasure::Error) {
+			description("Failed to erasure-code candidate data."),
+			display("Failed to erasure-code candidate data: {:?}", err),
+		}
+		ErasureRootMismatch(expected: Hash, got: Hash) {
+			description("Candidate has wrong erasure root."),
+			display("Candidate has wrong erasure root (expected: {:?}, got {:?})", expected, got),
+		}
+		IngressCanonicalityMismatch(expected: usize, got: usize) {
+			description("Got a different number of ingress queues than expected."),
+			display("Got {} ingress queues, but expected {}.", got, expected),
+		}
+		IngressChainMismatch(expected: ParaId, got: ParaId) {
+			description("Got ingress from an unexpected chain."),
+			display("Got ingress from chain {:?}, but expected chain {:?}.", got, expected),
+		}
+		IngressRootMismatch(id: ParaId, expected: Hash, got: Hash) {
+			description("Got unexpected ingress root."),
+			display(
+				"Got unexpected ingress root from {:?}. (expected: {:?}, got {:?})",
+				id, expected, got
+			),
+		}
+	}
+}
+
+impl Error {
+	/// Whether validation failed because of the local environment, such as the
+	/// validation worker failing or the runtime being unavailable, rather than
+	/// because of the candidate. Such failures say nothing about its validity.
+	///
+	/// Candidates of chains with a candidate pending availability are not
+	/// validated at all, so this is also true for them.
+	pub fn is_local(&self) -> bool {
+		match *self.kind() {
+			ErrorKind::Client(_) | ErrorKind::Erasure(_) | ErrorKind::CandidatePendingAvailability(_) => true,
+			ErrorKind::WasmValidation(ref e) => e.is_local(),
+			_ => false,
+		}
 	}
 }
 
@@ -155,6 +224,56 @@ pub fn egress_trie_root<A, I: IntoIterator<Item=A>>(messages: I) -> Hash
 	::trie::ordered_trie_root::<primitives::Blake2Hasher, _, _>(messages)
 }
 
+/// Erasure-code the block data and extrinsic of a candidate for the given number
+/// of validators. Returns the erasure root and one chunk for each validator,
+/// with a merkle proof against the root.
+pub fn erasure_chunks(
+	n_validators: usize,
+	block_data: &BlockData,
+	extrinsic: &Extrinsic,
+) -> Result<(Hash, Vec<ErasureChunk>), Error> {
+	let chunks = ::erasure::obtain_chunks(n_validators, block_data, extrinsic)
+		.map_err(ErrorKind::Erasure)?;
+	let branches = ::erasure::branches(chunks.iter().map(|c| &c[..]).collect());
+	let root = branches.root();
+
+	let chunks = branches
+		.enumerate()
+		.map(|(index, (proof, chunk))| ErasureChunk {
+			chunk: chunk.to_vec(),
+			index: index as u32,
+			proof,
+		})
+		.collect();
+
+	Ok((root, chunks))
+}
+
+/// Check that the given consolidated ingress matches the unrouted ingress roots
+/// on the relay chain, queue by queue and in order.
+pub fn validate_incoming(
+	roots: &StructuredUnroutedIngress,
+	ingress: &ConsolidatedIngress,
+) -> Result<(), Error> {
+	if roots.len() != ingress.0.len() {
+		return Err(ErrorKind::IngressCanonicalityMismatch(roots.len(), ingress.0.len()).into());
+	}
+
+	let all_iter = roots.iter().zip(&ingress.0);
+	for ((_, expected_from, root), &(ref got_id, ref messages)) in all_iter {
+		if expected_from != got_id {
+			return Err(ErrorKind::IngressChainMismatch(*expected_from, *got_id).into());
+		}
+
+		let got_root = egress_trie_root(messages.iter().map(|msg| &msg.0[..]));
+		if &got_root != root {
+			return Err(ErrorKind::IngressRootMismatch(*got_id, *root, got_root).into());
+		}
+	}
+
+	Ok(())
+}
+
 fn check_and_compute_extrinsic(
 	mut outgoing: Vec<OutgoingMessage>,
 	expected_egress_roots: &[(ParaId, Hash)],
@@ -205,17 +324,37 @@ fn check_and_compute_extrinsic(
 struct Externalities {
 	parachain_index: ParaId,
 	outgoing: Vec<OutgoingMessage>,
+	fee_schedule: FeeSchedule,
+	// fees declared in the candidate receipt.
+	available_fees: u64,
+	fees_charged: u64,
 }
 
 impl wasm_executor::Externalities for Externalities {
 	fn post_message(&mut self, message: MessageRef) -> Result<(), ExternalitiesError> {
-		// TODO: https://github.com/paritytech/polkadot/issues/92
-		// check per-message and per-byte fees for the parachain.
 		let target: ParaId = message.target.into();
 		if target == self.parachain_index {
 			return Err(ExternalitiesError::CannotPostMessage("posted message to self"));
 		}
 
+		let max_messages = self.fee_schedule.max_messages;
+		if self.outgoing.len() >= max_messages as usize {
+			return Err(ExternalitiesError::TooManyMessages(max_messages));
+		}
+
+		let max_size = self.fee_schedule.max_message_size;
+		if message.data.len() > max_size as usize {
+			return Err(ExternalitiesError::MessageTooLarge(message.data.len(), max_size));
+		}
+
+		let fees_charged = self.fee_schedule.compute_message_fee(message.data.len())
+			.and_then(|fee| fee.checked_add(self.fees_charged));
+
+		match fees_charged {
+			Some(total) if total <= self.available_fees => self.fees_charged = total,
+			_ => return Err(ExternalitiesError::FeesExceeded(self.available_fees)),
+		}
+
 		self.outgoing.push(OutgoingMessage {
 			target,
 			data: message.data.to_vec(),
@@ -240,46 +379,135 @@ impl Externalities {
 
 /// Check whether a given collation is valid. Returns `Ok` on success, error otherwise.
 ///
+/// On success, this yields the extrinsic and the erasure-coded chunks of the
+/// candidate, one for each validator at the relay parent. The erasure root of
+/// the chunks is checked against the candidate receipt.
+///
+/// This checks the ingress against the relay chain before executing the
+/// validation function, which is run in the given validation pool. Validation
+/// which times out or exceeds the pool's limits is an error.
+///
+/// Collations of chains with a candidate pending availability at the relay
+/// parent are refused, since the head they would be built on is stale.
+///
 /// This assumes that basic validity checks have been done:
 ///   - Block data hash is the same as linked in candidate receipt.
 pub fn validate_collation<P>(
 	client: &P,
+	validation_pool: &ValidationPool,
 	relay_parent: &BlockId,
 	collation: &Collation
-) -> Result<Extrinsic, Error> where
+) -> Result<(Extrinsic, Vec<ErasureChunk>), Error> where
 	P: ProvideRuntimeApi,
 	P::Api: ParachainHost<Block>,
 {
-	use parachain::ValidationParams;
+	use parachain::{IncomingMessage, ValidationParams};
 
 	let api = client.runtime_api();
 	let para_id = collation.receipt.parachain_index;
 	let validation_code = api.parachain_code(relay_parent, para_id)?
 		.ok_or_else(|| ErrorKind::InactiveParachain(para_id))?;
 
+	// the head of a chain with a candidate pending availability is about to be
+	// replaced, and the candidate couldn't be included on top of the relay parent.
+	let pending = api.pending_availability(relay_parent)?;
+	if pending.iter().any(|&(_, ref candidate)| candidate.parachain_index == para_id) {
+		return Err(ErrorKind::CandidatePendingAvailability(para_id).into());
+	}
+
 	let chain_head = api.parachain_head(relay_parent, para_id)?
 		.ok_or_else(|| ErrorKind::InactiveParachain(para_id))?;
 
+	// the code at the relay parent already includes any upgrade applied in it.
+	if collation.receipt.new_validation_code_hash.is_some() &&
+		api.parachain_pending_code(relay_parent, para_id)?.is_some()
+	{
+		return Err(ErrorKind::CodeUpgradePending(para_id).into());
+	}
+
+	let roots = api.ingress(relay_parent, para_id)?
+		.ok_or_else(|| ErrorKind::InactiveParachain(para_id))?;
+	validate_incoming(&roots, &collation.pov.ingress)?;
+
 	let params = ValidationParams {
 		parent_head: chain_head,
-		block_data: collation.block_data.0.clone(),
+		block_data: collation.pov.block_data.0.clone(),
+		ingress: collation.pov.ingress.0.iter()
+			.flat_map(|&(source, ref messages)| messages.iter().map(move |msg| IncomingMessage {
+				source: source.into_inner(),
+				data: msg.0.clone(),
+			}))
+			.collect(),
 	};
 
 	let mut ext = Externalities {
 		parachain_index: collation.receipt.parachain_index.clone(),
 		outgoing: Vec::new(),
+		fee_schedule: api.fee_schedule(relay_parent)?,
+		available_fees: collation.receipt.fees,
+		fees_charged: 0,
 	};
 
-	match wasm_executor::validate_candidate(&validation_code, params, &mut ext) {
-		Ok(result) => {
-			if result.head_data == collation.receipt.head_data.0 {
-				ext.final_checks(&collation.receipt)
-			} else {
-				Err(ErrorKind::WrongHeadData(
+	let execution_params = wasm_executor::ExecutionParams {
+		fuel_limit: api.validation_fuel_limit(relay_parent)?,
+	};
+	let res = validation_pool.validate_candidate(
+		&validation_code,
+		params,
+		&mut ext,
+		execution_params,
+	);
+
+	match res {
+		Ok(wasm_executor::ExecutionResult { result, .. }) => {
+			if result.head_data != collation.receipt.head_data.0 {
+				return Err(ErrorKind::WrongHeadData(
 					collation.receipt.head_data.0.clone(),
 					result.head_data
-				).into())
+				).into());
 			}
+
+			let balance_uploads: Vec<_> = result.balance_uploads.into_iter()
+				.map(|(account, amount)| (AccountId::from(account), amount))
+				.collect();
+
+			if balance_uploads != collation.receipt.balance_uploads {
+				return Err(ErrorKind::WrongBalanceUploads(
+					collation.receipt.balance_uploads.clone(),
+					balance_uploads,
+				).into());
+			}
+
+			if let Some(ref code) = result.new_validation_code {
+				let max_code_size = api.max_code_size(relay_parent)?;
+				if code.len() > max_code_size as usize {
+					return Err(ErrorKind::ValidationCodeTooLarge(code.len(), max_code_size).into());
+				}
+			}
+
+			let new_validation_code_hash = result.new_validation_code.as_ref()
+				.map(|code| BlakeTwo256::hash(&code[..]));
+			if new_validation_code_hash != collation.receipt.new_validation_code_hash {
+				return Err(ErrorKind::WrongValidationCode.into());
+			}
+
+			let extrinsic = ext.final_checks(&collation.receipt)?;
+
+			let n_validators = api.validators(relay_parent)?.len();
+			let (erasure_root, chunks) = erasure_chunks(
+				n_validators,
+				&collation.pov.block_data,
+				&extrinsic,
+			)?;
+
+			if erasure_root != collation.receipt.erasure_root {
+				return Err(ErrorKind::ErasureRootMismatch(
+					collation.receipt.erasure_root,
+					erasure_root,
+				).into());
+			}
+
+			Ok((extrinsic, chunks))
 		}
 		Err(e) => Err(e.into())
 	}
@@ -327,14 +555,141 @@ mod tests {
 		).is_err());
 	}
 
+	#[test]
+	fn ingress_checked_against_roots() {
+		use polkadot_primitives::parachain::{BlockIngressRoots, Message};
+
+		let message = |x: Vec<u8>| vec![Message(x)];
+		let root_of = |msgs: &[Message]| egress_trie_root(msgs.iter().map(|m| &m.0[..]));
+
+		let from_2 = message(vec![1, 2, 3]);
+		let from_3 = message(vec![4, 5, 6]);
+		let later_from_2 = message(vec![7, 8, 9]);
+
+		let roots = StructuredUnroutedIngress(vec![
+			(1, BlockIngressRoots(vec![(2.into(), root_of(&from_2)), (3.into(), root_of(&from_3))])),
+			(2, BlockIngressRoots(vec![(2.into(), root_of(&later_from_2))])),
+		]);
+
+		let good = ConsolidatedIngress(vec![
+			(2.into(), from_2.clone()),
+			(3.into(), from_3.clone()),
+			(2.into(), later_from_2.clone()),
+		]);
+		assert!(validate_incoming(&roots, &good).is_ok());
+
+		// missing queue.
+		let missing = ConsolidatedIngress(vec![
+			(2.into(), from_2.clone()),
+			(3.into(), from_3.clone()),
+		]);
+		match validate_incoming(&roots, &missing) {
+			Err(Error(ErrorKind::IngressCanonicalityMismatch(3, 2), _)) => {}
+			other => panic!("unexpected result: {:?}", other),
+		}
+
+		// wrong order.
+		let reordered = ConsolidatedIngress(vec![
+			(3.into(), from_3.clone()),
+			(2.into(), from_2.clone()),
+			(2.into(), later_from_2.clone()),
+		]);
+		match validate_incoming(&roots, &reordered) {
+			Err(Error(ErrorKind::IngressChainMismatch(_, _), _)) => {}
+			other => panic!("unexpected result: {:?}", other),
+		}
+
+		// tampered messages.
+		let tampered = ConsolidatedIngress(vec![
+			(2.into(), from_2.clone()),
+			(3.into(), message(vec![4, 5, 7])),
+			(2.into(), later_from_2.clone()),
+		]);
+		match validate_incoming(&roots, &tampered) {
+			Err(Error(ErrorKind::IngressRootMismatch(_, _, _), _)) => {}
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+
 	#[test]
 	fn ext_rejects_local_message() {
 		let mut ext = Externalities {
 			parachain_index: 5.into(),
 			outgoing: Vec::new(),
+			fee_schedule: FeeSchedule::default(),
+			available_fees: 0,
+			fees_charged: 0,
 		};
 
 		assert!(ext.post_message(MessageRef { target: 1, data: &[] }).is_ok());
 		assert!(ext.post_message(MessageRef { target: 5, data: &[] }).is_err());
 	}
+
+	#[test]
+	fn ext_checks_message_fees_and_limits() {
+		let mut ext = Externalities {
+			parachain_index: 5.into(),
+			outgoing: Vec::new(),
+			fee_schedule: FeeSchedule {
+				base: 10,
+				per_byte: 1,
+				max_messages: 3,
+				max_message_size: 4,
+			},
+			available_fees: 30,
+			fees_charged: 0,
+		};
+
+		match ext.post_message(MessageRef { target: 1, data: &[1, 2, 3, 4, 5] }) {
+			Err(ExternalitiesError::MessageTooLarge(5, 4)) => {}
+			other => panic!("unexpected result: {:?}", other),
+		}
+
+		assert!(ext.post_message(MessageRef { target: 1, data: &[1, 2, 3, 4] }).is_ok());
+		assert_eq!(ext.fees_charged, 14);
+
+		assert!(ext.post_message(MessageRef { target: 2, data: &[1, 2, 3, 4] }).is_ok());
+		assert_eq!(ext.fees_charged, 28);
+
+		// only 2 left, but the base fee is 10.
+		match ext.post_message(MessageRef { target: 2, data: &[] }) {
+			Err(ExternalitiesError::FeesExceeded(30)) => {}
+			other => panic!("unexpected result: {:?}", other),
+		}
+
+		ext.available_fees = 100;
+		assert!(ext.post_message(MessageRef { target: 3, data: &[] }).is_ok());
+
+		match ext.post_message(MessageRef { target: 3, data: &[] }) {
+			Err(ExternalitiesError::TooManyMessages(3)) => {}
+			other => panic!("unexpected result: {:?}", other),
+		}
+
+		assert_eq!(ext.outgoing.len(), 3);
+	}
+
+	#[test]
+	fn erasure_chunks_have_valid_proofs() {
+		let block_data = BlockData(vec![1, 2, 3, 4, 5]);
+		let extrinsic = Extrinsic {
+			outgoing_messages: vec![OutgoingMessage { target: 1.into(), data: vec![6, 7, 8] }],
+		};
+
+		let (root, chunks) = erasure_chunks(10, &block_data, &extrinsic).unwrap();
+		assert_eq!(chunks.len(), 10);
+
+		for (i, chunk) in chunks.iter().enumerate() {
+			assert_eq!(chunk.index as usize, i);
+			assert_eq!(
+				::erasure::branch_hash(&root, &chunk.proof, i).unwrap(),
+				BlakeTwo256::hash(&chunk.chunk),
+			);
+		}
+
+		let reconstructed = ::erasure::reconstruct(
+			10,
+			chunks.iter().skip(6).map(|c| (&c.chunk[..], c.index as usize)),
+		).unwrap();
+		assert_eq!(reconstructed, (block_data, extrinsic));
+	}
 }
@@ -23,6 +23,7 @@ error_chain! {
 	links {
 		Client(::client::error::Error, ::client::error::ErrorKind);
 		Consensus(::consensus::error::Error, ::consensus::error::ErrorKind);
+		Evaluation(::evaluation::Error, ::evaluation::ErrorKind);
 	}
 
 	errors {
@@ -18,9 +18,16 @@
 
 use super::MAX_TRANSACTIONS_SIZE;
 
-use codec::Encode;
-use polkadot_primitives::{Block, Hash, BlockNumber};
-use polkadot_primitives::parachain::Id as ParaId;
+use codec::{Encode, Decode};
+use polkadot_primitives::{Block, Hash, BlockNumber, SessionKey};
+use polkadot_primitives::parachain::{
+	Id as ParaId, Chain, DutyRoster, AttestedCandidate, ValidityAttestation, ValidityThreshold,
+};
+use polkadot_runtime::{Call, ParachainsCall, TimestampCall, UncheckedExtrinsic};
+use table::generic::Statement as GenericStatement;
+
+/// How far ahead of the current time a proposal's timestamp may be, in seconds.
+pub const MAX_TIMESTAMP_DRIFT: u64 = 60;
 
 error_chain! {
 	links {
@@ -52,6 +59,38 @@ error_chain! {
 			description("Proposal had wrong number."),
 			display("Proposal had wrong number. Expected {:?}, got {:?}", expected, got),
 		}
+		MissingParachainHeads {
+			description("Proposal did not include parachain heads."),
+			display("Proposal did not include parachain heads."),
+		}
+		MissingTimestamp {
+			description("Proposal did not include a timestamp."),
+			display("Proposal did not include a timestamp."),
+		}
+		TimestampInFuture(timestamp: u64, now: u64) {
+			description("Proposal had timestamp too far in the future."),
+			display("Proposal had timestamp {} too far ahead of {}", timestamp, now),
+		}
+		NoValidatorGroup(id: ParaId) {
+			description("Proposal included candidate for parachain without validator group."),
+			display("Proposal included candidate for parachain {:?} without validator group", id),
+		}
+		NotEnoughAttestations(id: ParaId, expected: usize, got: usize) {
+			description("Proposal included candidate without enough validity attestations."),
+			display("Candidate for parachain {:?} had {} validity attestations, expected {}", id, got, expected),
+		}
+		BadAttestingValidators(id: ParaId) {
+			description("Proposal included attestations by validators not in the validator group."),
+			display("Attesting validators for parachain {:?} don't match its validator group", id),
+		}
+		AttestationCountMismatch(id: ParaId, validators: usize, votes: usize) {
+			description("Proposal included a different number of attestations than attesting validators."),
+			display("Candidate for parachain {:?} had {} attesting validators, but {} attestations", id, validators, votes),
+		}
+		BadAttestationSignature(id: ParaId, authority: SessionKey) {
+			description("Proposal included attestation with bad signature."),
+			display("Attestation by {:?} for parachain {:?} had bad signature", authority, id),
+		}
 		ProposalTooLarge(size: usize) {
 			description("Proposal exceeded the maximum size."),
 			display(
@@ -62,14 +101,80 @@ error_chain! {
 	}
 }
 
+// check that every candidate is attested by a majority of its validator group,
+// the same way the runtime will on import.
+fn check_attestations(
+	candidates: &[AttestedCandidate],
+	parent_hash: &Hash,
+	authorities: &[SessionKey],
+	duty_roster: &DutyRoster,
+	validity_threshold: ValidityThreshold,
+) -> Result<()> {
+	for candidate in candidates {
+		let id = candidate.parachain_index();
+		let group: Vec<_> = authorities.iter()
+			.zip(&duty_roster.validator_duty)
+			.filter(|&(_, duty)| *duty == Chain::Parachain(id))
+			.map(|(authority, _)| *authority)
+			.collect();
+
+		if group.is_empty() {
+			bail!(ErrorKind::NoValidatorGroup(id));
+		}
+
+		// one bit per member of the group, in the order of the duty roster.
+		if candidate.validator_indices.0.len() != (group.len() + 7) / 8 {
+			bail!(ErrorKind::BadAttestingValidators(id));
+		}
+
+		let positions = candidate.voter_positions();
+		if positions.len() != candidate.validity_votes.len() {
+			bail!(ErrorKind::AttestationCountMismatch(id, positions.len(), candidate.validity_votes.len()));
+		}
+
+		let needed = validity_threshold.required_votes(group.len());
+		if positions.len() < needed {
+			bail!(ErrorKind::NotEnoughAttestations(id, needed, positions.len()));
+		}
+
+		let candidate_hash = candidate.candidate.hash();
+		for (position, attestation) in positions.into_iter().zip(&candidate.validity_votes) {
+			let authority = match group.get(position) {
+				Some(authority) => authority,
+				None => bail!(ErrorKind::BadAttestingValidators(id)),
+			};
+
+			let (statement, signature) = match *attestation {
+				ValidityAttestation::Implicit(ref sig) =>
+					(GenericStatement::Candidate(candidate.candidate.clone()), sig),
+				ValidityAttestation::Explicit(ref sig) =>
+					(GenericStatement::Valid(candidate_hash), sig),
+			};
+
+			if !::check_statement(&statement, signature, *authority, parent_hash) {
+				bail!(ErrorKind::BadAttestationSignature(id, *authority));
+			}
+		}
+	}
+
+	Ok(())
+}
+
 /// Attempt to evaluate a substrate block as a polkadot block, returning error
 /// upon any initial validity checks failing.
+///
+/// This checks the block's parachain candidates against the parachains and
+/// parathreads scheduled, duty roster and validity threshold at the parent block,
+/// and its timestamp against `now`.
 pub fn evaluate_initial(
 	proposal: &Block,
-	_now: u64,
+	now: u64,
 	parent_hash: &Hash,
 	parent_number: BlockNumber,
-	_active_parachains: &[ParaId],
+	scheduled_chains: &[ParaId],
+	authorities: &[SessionKey],
+	duty_roster: &DutyRoster,
+	validity_threshold: ValidityThreshold,
 ) -> Result<()> {
 	let transactions_size = proposal.extrinsics.iter().fold(0, |a, tx| {
 		a + Encode::encode(tx).len()
@@ -87,5 +192,157 @@ pub fn evaluate_initial(
 		bail!(ErrorKind::WrongNumber(parent_number + 1, proposal.header.number));
 	}
 
-	Ok(())
+	let mut timestamp = None;
+	let mut heads = None;
+	for extrinsic in &proposal.extrinsics {
+		let extrinsic = UncheckedExtrinsic::decode(&mut extrinsic.encode().as_slice())
+			.ok_or_else(|| ErrorKind::ProposalNotForPolkadot)?;
+
+		match extrinsic.function {
+			Call::Timestamp(TimestampCall::set(t)) if timestamp.is_none() => timestamp = Some(t.into()),
+			Call::Parachains(ParachainsCall::set_heads(ref h, _, _, _)) if heads.is_none() => heads = Some(h.clone()),
+			_ => {}
+		}
+	}
+
+	let timestamp: u64 = timestamp.ok_or_else(|| ErrorKind::MissingTimestamp)?;
+	if timestamp > now + MAX_TIMESTAMP_DRIFT {
+		bail!(ErrorKind::TimestampInFuture(timestamp, now));
+	}
+
+	let heads: Vec<AttestedCandidate> = heads.ok_or_else(|| ErrorKind::MissingParachainHeads)?;
+	if heads.len() > scheduled_chains.len() {
+		bail!(ErrorKind::TooManyCandidates(scheduled_chains.len(), heads.len()));
+	}
+
+	let mut last_id = None;
+	for candidate in &heads {
+		let id = candidate.parachain_index();
+		if last_id.map_or(false, |last| last >= id) {
+			bail!(ErrorKind::ParachainOutOfOrder);
+		}
+
+		if !scheduled_chains.contains(&id) {
+			bail!(ErrorKind::UnknownParachain(id));
+		}
+
+		last_id = Some(id);
+	}
+
+	check_attestations(&heads, parent_hash, authorities, duty_roster, validity_threshold)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_primitives::parachain::{CandidateReceipt, HeadData};
+	use substrate_keyring::Keyring;
+
+	fn attested(group: &[SessionKey], votes: &[(Keyring, bool)], parent_hash: &Hash) -> AttestedCandidate {
+		let candidate = CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: HeadData(vec![1, 2, 3]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 0,
+			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: [3; 32].into(),
+		};
+
+		let validity_votes = votes.iter().map(|&(key, implicit)| {
+			let authority: SessionKey = key.to_raw_public().into();
+			let attestation = if implicit {
+				let statement = GenericStatement::Candidate(candidate.clone());
+				ValidityAttestation::Implicit(::sign_table_statement(&statement, &key.pair(), parent_hash))
+			} else {
+				let statement = GenericStatement::Valid(candidate.hash());
+				ValidityAttestation::Explicit(::sign_table_statement(&statement, &key.pair(), parent_hash))
+			};
+
+			(authority, attestation)
+		}).collect::<Vec<_>>();
+
+		AttestedCandidate::from_attestations(candidate, group, validity_votes).unwrap()
+	}
+
+	#[test]
+	fn attestations_are_checked() {
+		let parent_hash = [9; 32].into();
+		let authorities: Vec<SessionKey> = [Keyring::Alice, Keyring::Bob, Keyring::Charlie, Keyring::Dave]
+			.iter()
+			.map(|k| k.to_raw_public().into())
+			.collect();
+		let duty_roster = DutyRoster {
+			validator_duty: vec![
+				Chain::Parachain(5.into()),
+				Chain::Parachain(5.into()),
+				Chain::Parachain(5.into()),
+				Chain::Relay,
+			],
+			secondary_checks: vec![None, None, None, Some(5.into())],
+		};
+
+		let group = &authorities[..3];
+
+		let check_with = |candidate: AttestedCandidate, threshold| check_attestations(
+			&[candidate],
+			&parent_hash,
+			&authorities,
+			&duty_roster,
+			threshold,
+		).map_err(|e| e.0);
+		let check = |candidate| check_with(candidate, ValidityThreshold::MAJORITY);
+
+		let valid = attested(group, &[(Keyring::Alice, true), (Keyring::Bob, false)], &parent_hash);
+		assert!(check(valid.clone()).is_ok());
+
+		let unanimity = ValidityThreshold { numerator: 1, denominator: 1 };
+		match check_with(valid.clone(), unanimity) {
+			Err(ErrorKind::NotEnoughAttestations(_, 3, 2)) => {}
+			x => panic!("unexpected result {:?}", x),
+		}
+
+		match check(attested(group, &[(Keyring::Alice, true)], &parent_hash)) {
+			Err(ErrorKind::NotEnoughAttestations(_, 2, 1)) => {}
+			x => panic!("unexpected result {:?}", x),
+		}
+
+		// Dave is at position 3, outside of the group.
+		let mut outsider = attested(group, &[(Keyring::Alice, true)], &parent_hash);
+		outsider.validator_indices.set(3, true);
+		outsider.validity_votes.push(valid.validity_votes[1].clone());
+		match check(outsider) {
+			Err(ErrorKind::BadAttestingValidators(..)) => {}
+			x => panic!("unexpected result {:?}", x),
+		}
+
+		let mut wrong_length = valid.clone();
+		wrong_length.validator_indices.0.push(0);
+		match check(wrong_length) {
+			Err(ErrorKind::BadAttestingValidators(..)) => {}
+			x => panic!("unexpected result {:?}", x),
+		}
+
+		let mut extra_vote = valid.clone();
+		extra_vote.validity_votes.push(valid.validity_votes[0].clone());
+		match check(extra_vote) {
+			Err(ErrorKind::AttestationCountMismatch(_, 2, 3)) => {}
+			x => panic!("unexpected result {:?}", x),
+		}
+
+		match check(attested(group, &[(Keyring::Alice, true), (Keyring::Bob, false)], &[0xff; 32].into())) {
+			Err(ErrorKind::BadAttestationSignature(..)) => {}
+			x => panic!("unexpected result {:?}", x),
+		}
+
+		let mut wrong_para = attested(group, &[(Keyring::Alice, true), (Keyring::Bob, false)], &parent_hash);
+		wrong_para.candidate.parachain_index = 6.into();
+		match check(wrong_para) {
+			Err(ErrorKind::NoValidatorGroup(..)) => {}
+			x => panic!("unexpected result {:?}", x),
+		}
+	}
 }
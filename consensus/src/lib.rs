@@ -31,7 +31,11 @@
 
 extern crate parking_lot;
 extern crate polkadot_availability_store as extrinsic_store;
+extern crate polkadot_erasure_coding as erasure;
 extern crate polkadot_statement_table as table;
+extern crate kvdb;
+extern crate kvdb_rocksdb;
+extern crate kvdb_memorydb;
 extern crate polkadot_parachain as parachain;
 extern crate polkadot_runtime;
 extern crate polkadot_primitives;
@@ -64,6 +68,7 @@ extern crate log;
 extern crate substrate_keyring;
 
 use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
 use std::sync::Arc;
 use std::time::{self, Duration, Instant};
 
@@ -71,16 +76,18 @@ use client::{BlockchainEvents, ChainHead, BlockBody};
 use client::blockchain::HeaderBackend;
 use client::block_builder::api::BlockBuilder as BlockBuilderApi;
 use client::runtime_api::Core;
-use codec::Encode;
+use codec::{Encode, Decode};
 use extrinsic_store::Store as ExtrinsicStore;
+use slashing_protection::Store as SignedStatements;
 use parking_lot::Mutex;
-use polkadot_primitives::{Hash, Block, BlockId, BlockNumber, Header, SessionKey};
+use polkadot_primitives::{Hash, Balance, Block, BlockId, BlockNumber, Header, SessionKey};
+use polkadot_runtime::DisputeVote;
 use polkadot_primitives::parachain::{
-	Id as ParaId, Chain, DutyRoster, BlockData, Extrinsic as ParachainExtrinsic, CandidateReceipt,
-	CandidateSignature
+	Id as ParaId, Chain, DutyRoster, PoVBlock, Extrinsic as ParachainExtrinsic, CandidateReceipt,
+	CandidateSignature, ErasureChunk, Activity, SignedActivity,
 };
 use polkadot_primitives::parachain::{
-	AttestedCandidate, ParachainHost, Statement as PrimitiveStatement
+	AttestedCandidate, ParachainHost, Statement as PrimitiveStatement, ValidityThreshold,
 };
 use primitives::{Ed25519AuthorityId as AuthorityId, ed25519};
 use runtime_primitives::{traits::ProvideRuntimeApi, ApplyError};
@@ -93,14 +100,15 @@ use futures::prelude::*;
 use futures::future::{self, Either};
 use collation::CollationFetch;
 use dynamic_inclusion::DynamicInclusion;
-use inherents::InherentData;
+use inherents::{InherentData, InherentIdentifier, ProvideInherentData, RuntimeString};
 use runtime_aura::timestamp::TimestampInherentData;
 use aura::SlotDuration;
 
 pub use self::collation::{validate_collation, egress_trie_root, Collators};
 pub use self::error::{ErrorKind, Error};
+pub use parachain::validation_host::{ValidationPool, WorkerConfig};
 pub use self::shared_table::{
-	SharedTable, ParachainWork, PrimedParachainWork, Validated, Statement, SignedStatement,
+	SharedTable, ParachainWork, PrimedParachainWork, Validated, ValidationFailure, Statement, SignedStatement,
 	GenericStatement
 };
 
@@ -111,6 +119,7 @@ mod error;
 mod shared_table;
 
 pub mod collation;
+pub mod slashing_protection;
 
 // block size limit.
 const MAX_TRANSACTIONS_SIZE: usize = 4 * 1024 * 1024;
@@ -122,14 +131,26 @@ pub trait TableRouter: Clone {
 	/// Errors when fetching data from the network.
 	type Error;
 	/// Future that resolves when candidate data is fetched.
-	type FetchCandidate: IntoFuture<Item=BlockData,Error=Self::Error>;
+	type FetchValidationProof: IntoFuture<Item=PoVBlock,Error=Self::Error>;
 
 	/// Call with local candidate data. This will make the data available on the network,
 	/// and sign, import, and broadcast a statement about the candidate.
-	fn local_candidate(&self, candidate: CandidateReceipt, block_data: BlockData, extrinsic: ParachainExtrinsic);
+	fn local_candidate(&self, candidate: CandidateReceipt, pov_block: PoVBlock, extrinsic: ParachainExtrinsic);
 
-	/// Fetch block data for a specific candidate.
-	fn fetch_block_data(&self, candidate: &CandidateReceipt) -> Self::FetchCandidate;
+	/// Send each validator the erasure-coded chunk of a candidate meant for it.
+	/// The chunk at index `i` is for the `i`-th validator.
+	fn distribute_erasure_chunks(&self, candidate: &CandidateReceipt, chunks: Vec<ErasureChunk>);
+
+	/// Fetch validation proof for a specific candidate.
+	fn fetch_pov_block(&self, candidate: &CandidateReceipt) -> Self::FetchValidationProof;
+
+	/// Broadcast the local validator's signed availability bit field.
+	fn local_availability(&self, availability: SignedActivity);
+
+	/// Recover the data of a candidate pending availability whose erasure chunk the
+	/// local validator doesn't hold, from the chunks held by the other validators.
+	/// `validators` are the validators at the relay parent, in the order of their chunks.
+	fn recover_candidate(&self, relay_parent: Hash, candidate: CandidateReceipt, validators: Vec<SessionKey>);
 }
 
 /// A long-lived network which can create parachain statement and BFT message routing processes on demand.
@@ -150,10 +171,12 @@ pub trait Network {
 /// Information about a specific group.
 #[derive(Debug, Clone, Default)]
 pub struct GroupInfo {
-	/// Authorities meant to check validity of candidates.
-	pub validity_guarantors: HashSet<SessionKey>,
+	/// Authorities meant to check validity of candidates, in the order of the duty roster.
+	pub validity_guarantors: Vec<SessionKey>,
 	/// Number of votes needed for validity.
 	pub needed_validity: usize,
+	/// Authorities meant to re-check candidates already backed by the group.
+	pub secondary_checkers: HashSet<SessionKey>,
 }
 
 /// Sign a table statement against a parent hash.
@@ -179,39 +202,67 @@ pub fn check_statement(statement: &Statement, signature: &CandidateSignature, si
 	signature.verify(&encoded[..], &signer.into())
 }
 
-fn make_group_info(roster: DutyRoster, authorities: &[AuthorityId], local_id: AuthorityId) -> Result<(HashMap<ParaId, GroupInfo>, LocalDuty), Error> {
+/// Sign an availability bit field against a parent hash.
+pub fn sign_availability(activity: &Activity, key: &ed25519::Pair, parent_hash: &Hash) -> CandidateSignature {
+	key.sign(&activity.signing_payload(parent_hash)).into()
+}
+
+/// Check signature on an availability bit field.
+pub fn check_availability(signed: &SignedActivity, signer: SessionKey, parent_hash: &Hash) -> bool {
+	use runtime_primitives::traits::Verify;
+
+	let payload = signed.activity.signing_payload(parent_hash);
+	signed.signature.verify(&payload[..], &signer.into())
+}
+
+fn make_group_info(
+	roster: DutyRoster,
+	validity_threshold: ValidityThreshold,
+	authorities: &[AuthorityId],
+	local_id: AuthorityId,
+) -> Result<(HashMap<ParaId, GroupInfo>, LocalDuty), Error> {
 	if roster.validator_duty.len() != authorities.len() {
 		bail!(ErrorKind::InvalidDutyRosterLength(authorities.len(), roster.validator_duty.len()))
 	}
 
+	if roster.secondary_checks.len() != authorities.len() {
+		bail!(ErrorKind::InvalidDutyRosterLength(authorities.len(), roster.secondary_checks.len()))
+	}
+
 	let mut local_validation = None;
+	let mut local_secondary_check = None;
 	let mut map = HashMap::new();
 
-	let duty_iter = authorities.iter().zip(&roster.validator_duty);
-	for (authority, v_duty) in duty_iter {
+	let duty_iter = authorities.iter().zip(&roster.validator_duty).zip(&roster.secondary_checks);
+	for ((authority, v_duty), secondary_check) in duty_iter {
 		if authority == &local_id {
 			local_validation = Some(v_duty.clone());
+			local_secondary_check = secondary_check.clone();
 		}
 
 		match *v_duty {
-			Chain::Relay => {}, // does nothing for now.
+			Chain::Relay => if let Some(ref id) = *secondary_check {
+				map.entry(id.clone()).or_insert_with(GroupInfo::default)
+					.secondary_checkers
+					.insert(authority.clone());
+			},
 			Chain::Parachain(ref id) => {
 				map.entry(id.clone()).or_insert_with(GroupInfo::default)
 					.validity_guarantors
-					.insert(authority.clone());
+					.push(authority.clone());
 			}
 		}
 	}
 
 	for live_group in map.values_mut() {
-		let validity_len = live_group.validity_guarantors.len();
-		live_group.needed_validity = validity_len / 2 + validity_len % 2;
+		live_group.needed_validity = validity_threshold.required_votes(live_group.validity_guarantors.len());
 	}
 
 	match local_validation {
 		Some(local_validation) => {
 			let local_duty = LocalDuty {
 				validation: local_validation,
+				secondary_check: local_secondary_check,
 			};
 
 			Ok((map, local_duty))
@@ -232,8 +283,16 @@ struct ParachainConsensus<C, N, P> {
 	handle: TaskExecutor,
 	/// Store for extrinsic data.
 	extrinsic_store: ExtrinsicStore,
+	/// Record of the statements signed by the local validator.
+	signed_statements: SignedStatements,
+	/// Where validation functions are executed, shared by all agreements.
+	validation_pool: ValidationPool,
 	/// Live agreements.
-	live_instances: Mutex<HashMap<Hash, Arc<AttestationTracker>>>,
+	live_instances: Arc<Mutex<HashMap<Hash, Arc<AttestationTracker>>>>,
+	/// Dispute votes gathered from the tables of agreements, including ended ones.
+	dispute_votes: DisputeVotes,
+	/// Candidates pending availability whose recovery has been started.
+	recovering: Arc<Mutex<HashSet<Hash>>>,
 }
 
 impl<C, N, P> ParachainConsensus<C, N, P> where
@@ -251,6 +310,7 @@ impl<C, N, P> ParachainConsensus<C, N, P> where
 	fn get_or_instantiate(
 		&self,
 		parent_hash: Hash,
+		parent_number: BlockNumber,
 		authorities: &[AuthorityId],
 		sign_with: Arc<ed25519::Pair>,
 	)
@@ -263,21 +323,38 @@ impl<C, N, P> ParachainConsensus<C, N, P> where
 
 		let id = BlockId::hash(parent_hash);
 		let duty_roster = self.client.runtime_api().duty_roster(&id)?;
+		let validity_threshold = self.client.runtime_api().validity_threshold(&id)?;
 
 		let (group_info, local_duty) = make_group_info(
 			duty_roster,
+			validity_threshold,
 			authorities,
 			sign_with.public().into(),
 		)?;
 
-		info!("Starting parachain attestation session on top of parent {:?}. Local parachain duty is {:?}",
-			parent_hash, local_duty.validation);
+		info!("Starting parachain attestation session on top of parent {:?}. Local parachain duty is {:?}, \
+			secondary check of {:?}", parent_hash, local_duty.validation, local_duty.secondary_check);
 
 		let active_parachains = self.client.runtime_api().active_parachains(&id)?;
 
 		debug!(target: "consensus", "Active parachains: {:?}", active_parachains);
+		if let Some(stats) = self.validation_pool.code_cache_stats() {
+			debug!(target: "consensus", "Validation code cache: {:?}", stats);
+		}
 
-		let table = Arc::new(SharedTable::new(group_info, sign_with.clone(), parent_hash, self.extrinsic_store.clone()));
+		// statements signed on top of this parent are pruned by number once finalized.
+		if let Err(e) = self.signed_statements.note_relay_parent(parent_hash, parent_number) {
+			warn!(target: "consensus", "Failed to note relay parent {:?} of signed statements: {:?}", parent_hash, e);
+		}
+
+		let table = Arc::new(SharedTable::new(
+			group_info,
+			sign_with.clone(),
+			parent_hash,
+			self.extrinsic_store.clone(),
+			self.signed_statements.clone(),
+			self.validation_pool.clone(),
+		));
 		let router = self.network.communication_for(
 			authorities,
 			table.clone(),
@@ -295,17 +372,66 @@ impl<C, N, P> ParachainConsensus<C, N, P> where
 			parent_hash.clone(),
 			self.collators.clone(),
 			self.client.clone(),
+			self.validation_pool.clone(),
 		));
 
+		let local_key: AuthorityId = sign_with.public().into();
+		let local_index = authorities.iter().position(|a| a == &local_key);
+
+		// signal which of the candidates pending availability we hold our chunk of.
+		if let Some(local_index) = local_index {
+			let pending = self.client.runtime_api().pending_availability(&id)?;
+			let mut activity = Activity::new(pending.len());
+			let mut missing = Vec::new();
+			for (i, &(relay_parent, ref receipt)) in pending.iter().enumerate() {
+				let candidate_hash = receipt.hash();
+				let held = self.extrinsic_store
+					.get_erasure_chunk(relay_parent, candidate_hash, local_index as u32)
+					.is_some();
+				activity.set(i, held);
+
+				if !held { missing.push((relay_parent, candidate_hash, receipt)) }
+			}
+
+			let signed = table.sign_and_import_availability(activity, local_index as u32);
+			router.local_availability(signed);
+
+			// recovery is started once per candidate; recovered chunks are signalled in the
+			// availability of later blocks.
+			let mut recovering = self.recovering.lock();
+			recovering.retain(|hash| missing.iter().any(|&(_, ref candidate_hash, _)| candidate_hash == hash));
+
+			let mut validators_at = HashMap::new();
+			for (relay_parent, candidate_hash, receipt) in missing {
+				if recovering.contains(&candidate_hash) { continue }
+
+				let validators = match validators_at.entry(relay_parent) {
+					Entry::Occupied(entry) => entry.into_mut(),
+					Entry::Vacant(entry) => match self.client.runtime_api().authorities(&BlockId::hash(relay_parent)) {
+						Ok(validators) => entry.insert(validators),
+						Err(e) => {
+							warn!(target: "consensus", "Failed to fetch validators at {:?} for recovery of {:?}: {:?}",
+								relay_parent, candidate_hash, e);
+							continue
+						}
+					},
+				};
+
+				recovering.insert(candidate_hash);
+				router.recover_candidate(relay_parent, receipt.clone(), validators.clone());
+			}
+		}
 		let drop_signal = dispatch_collation_work(
 			router.clone(),
 			&self.handle,
 			collation_work,
 			self.extrinsic_store.clone(),
+			local_index,
 		);
 
 		let tracker = Arc::new(AttestationTracker {
 			table,
+			parent_number,
 			started: Instant::now(),
 			_drop_signal: drop_signal
 		});
@@ -315,16 +441,37 @@ impl<C, N, P> ParachainConsensus<C, N, P> where
 		Ok(tracker)
 	}
 
-	/// Retain consensus sessions matching predicate.
+	/// Retain consensus sessions matching predicate. The dispute votes of the
+	/// others are kept, since their candidates may be disputed for a while.
 	fn retain<F: FnMut(&Hash) -> bool>(&self, mut pred: F) {
+		self.gather_dispute_votes();
 		self.live_instances.lock().retain(|k, _| pred(k))
 	}
+
+	/// Gather the dispute votes of the live agreements.
+	fn gather_dispute_votes(&self) {
+		let live_instances = self.live_instances.lock();
+		let mut dispute_votes = self.dispute_votes.lock();
+		for tracker in live_instances.values() {
+			for (candidate_hash, voter, signature) in tracker.table.disputes() {
+				dispute_votes.insert(
+					(candidate_hash, voter),
+					(tracker.parent_number, (candidate_hash, voter, false, signature)),
+				);
+			}
+		}
+	}
 }
 
+// votes in disputes about the validity of recently included candidates, by candidate
+// and voter, along with the number of the relay parent they are localized to.
+type DisputeVotes = Arc<Mutex<HashMap<(Hash, SessionKey), (BlockNumber, DisputeVote)>>>;
+
 /// Parachain consensus for a single block.
 struct AttestationTracker {
 	_drop_signal: exit_future::Signal,
 	table: Arc<SharedTable>,
+	parent_number: BlockNumber,
 	started: Instant,
 }
 
@@ -356,6 +503,8 @@ impl<C, N, P, TxApi> ProposerFactory<C, N, P, TxApi> where
 		thread_pool: TaskExecutor,
 		key: Arc<ed25519::Pair>,
 		extrinsic_store: ExtrinsicStore,
+		signed_statements: SignedStatements,
+		validation_pool: ValidationPool,
 		aura_slot_duration: SlotDuration,
 	) -> Self {
 		let parachain_consensus = Arc::new(ParachainConsensus {
@@ -364,7 +513,11 @@ impl<C, N, P, TxApi> ProposerFactory<C, N, P, TxApi> where
 			collators,
 			handle: thread_pool.clone(),
 			extrinsic_store: extrinsic_store.clone(),
-			live_instances: Mutex::new(HashMap::new()),
+			signed_statements: signed_statements.clone(),
+			validation_pool,
+			live_instances: Arc::new(Mutex::new(HashMap::new())),
+			dispute_votes: Arc::new(Mutex::new(HashMap::new())),
+			recovering: Arc::new(Mutex::new(HashSet::new())),
 		});
 
 		let service_handle = ::attestation_service::start(
@@ -373,6 +526,7 @@ impl<C, N, P, TxApi> ProposerFactory<C, N, P, TxApi> where
 			thread_pool,
 			key.clone(),
 			extrinsic_store,
+			signed_statements,
 		);
 
 		ProposerFactory {
@@ -383,6 +537,39 @@ impl<C, N, P, TxApi> ProposerFactory<C, N, P, TxApi> where
 			aura_slot_duration,
 		}
 	}
+
+	/// Get a provider of the local view of the live statement tables. It should be
+	/// registered with the inherent data providers used for block import.
+	pub fn local_candidates_provider(&self) -> LocalCandidatesProvider {
+		LocalCandidatesProvider {
+			live_instances: self.parachain_consensus.live_instances.clone(),
+		}
+	}
+}
+
+/// Provides the local view of the live statement tables as inherent data,
+/// so that the parachain heads of imported blocks can be checked against it.
+#[derive(Clone)]
+pub struct LocalCandidatesProvider {
+	live_instances: Arc<Mutex<HashMap<Hash, Arc<AttestationTracker>>>>,
+}
+
+impl ProvideInherentData for LocalCandidatesProvider {
+	fn inherent_identifier(&self) -> &'static InherentIdentifier {
+		&polkadot_runtime::LOCAL_CANDIDATES_INHERENT_IDENTIFIER
+	}
+
+	fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> Result<(), RuntimeString> {
+		let local_candidates: Vec<_> = self.live_instances.lock().values()
+			.map(|tracker| tracker.table.local_candidates())
+			.collect();
+
+		inherent_data.put_data(polkadot_runtime::LOCAL_CANDIDATES_INHERENT_IDENTIFIER, &local_candidates)
+	}
+
+	fn error_to_string(&self, error: &[u8]) -> Option<String> {
+		polkadot_runtime::ParachainsInherentError::decode(&mut &error[..]).map(|e| format!("{:?}", e))
+	}
 }
 
 impl<C, N, P, TxApi> consensus::Environment<Block> for ProposerFactory<C, N, P, TxApi> where
@@ -407,13 +594,16 @@ impl<C, N, P, TxApi> consensus::Environment<Block> for ProposerFactory<C, N, P,
 		let sign_with = self.key.clone();
 		let tracker = self.parachain_consensus.get_or_instantiate(
 			parent_hash,
+			parent_header.number,
 			authorities,
 			sign_with,
 		)?;
+		self.parachain_consensus.gather_dispute_votes();
 
 		Ok(Proposer {
 			client: self.parachain_consensus.client.clone(),
 			tracker,
+			dispute_votes: self.parachain_consensus.dispute_votes.clone(),
 			parent_hash,
 			parent_id,
 			parent_number: parent_header.number,
@@ -425,11 +615,14 @@ impl<C, N, P, TxApi> consensus::Environment<Block> for ProposerFactory<C, N, P,
 
 // dispatch collation work to be done in the background. returns a signal object
 // that should fire when the collation work is no longer necessary (e.g. when the proposer object is dropped)
+//
+// `local_index` is the index of the local validator, whose erasure chunk is kept locally.
 fn dispatch_collation_work<R, C, P>(
 	router: R,
 	handle: &TaskExecutor,
 	work: Option<CollationFetch<C, P>>,
 	extrinsic_store: ExtrinsicStore,
+	local_index: Option<usize>,
 ) -> exit_future::Signal where
 	C: Collators + Send + 'static,
 	P: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync + 'static,
@@ -448,20 +641,22 @@ fn dispatch_collation_work<R, C, P>(
 
 	let relay_parent = work.relay_parent();
 	let handled_work = work.then(move |result| match result {
-		Ok((collation, extrinsic)) => {
+		Ok((collation, extrinsic, chunks)) => {
 			let res = extrinsic_store.make_available(Data {
 				relay_parent,
 				parachain_id: collation.receipt.parachain_index,
 				candidate_hash: collation.receipt.hash(),
-				block_data: collation.block_data.clone(),
+				block_data: collation.pov.block_data.clone(),
 				extrinsic: Some(extrinsic.clone()),
+			}).and_then(|()| match local_index.and_then(|i| chunks.get(i)) {
+				Some(chunk) => extrinsic_store.add_erasure_chunk(relay_parent, &collation.receipt, chunk),
+				None => Ok(()),
 			});
 
 			match res {
 				Ok(()) => {
-					// TODO: https://github.com/paritytech/polkadot/issues/51
-					// Erasure-code and provide merkle branches.
-					router.local_candidate(collation.receipt, collation.block_data, extrinsic)
+					router.local_candidate(collation.receipt.clone(), collation.pov, extrinsic);
+					router.distribute_erasure_chunks(&collation.receipt, chunks);
 				}
 				Err(e) =>
 					warn!(target: "consensus", "Failed to make collation data available: {:?}", e),
@@ -484,6 +679,7 @@ fn dispatch_collation_work<R, C, P>(
 
 struct LocalDuty {
 	validation: Chain,
+	secondary_check: Option<ParaId>,
 }
 
 /// The Polkadot proposer logic.
@@ -495,6 +691,7 @@ pub struct Proposer<C: Send + Sync, TxApi: PoolChainApi> where
 	parent_id: BlockId,
 	parent_number: BlockNumber,
 	tracker: Arc<AttestationTracker>,
+	dispute_votes: DisputeVotes,
 	transaction_pool: Arc<Pool<TxApi>>,
 	slot_duration: SlotDuration,
 }
@@ -555,6 +752,7 @@ impl<C, TxApi> consensus::Proposer<Block> for Proposer<C, TxApi> where
 			client: self.client.clone(),
 			transaction_pool: self.transaction_pool.clone(),
 			table: self.tracker.table.clone(),
+			dispute_votes: self.dispute_votes.clone(),
 			believed_minimum_timestamp: believed_timestamp,
 			timing,
 			inherent_data: Some(inherent_data),
@@ -625,6 +823,7 @@ pub struct CreateProposal<C: Send + Sync, TxApi: PoolChainApi> {
 	client: Arc<C>,
 	transaction_pool: Arc<Pool<TxApi>>,
 	table: Arc<SharedTable>,
+	dispute_votes: DisputeVotes,
 	timing: ProposalTiming,
 	believed_minimum_timestamp: u64,
 	inherent_data: Option<InherentData>,
@@ -640,10 +839,67 @@ impl<C, TxApi> CreateProposal<C, TxApi> where
 		use client::block_builder::BlockBuilder;
 		use runtime_primitives::traits::{Hash as HashT, BlakeTwo256};
 
-		let mut inherent_data = self.inherent_data.take().expect("CreateProposal is not polled after finishing; qed");
-		inherent_data.put_data(polkadot_runtime::PARACHAIN_INHERENT_IDENTIFIER, &candidates).map_err(ErrorKind::InherentError)?;
-
 		let runtime_api = self.client.runtime_api();
+		let pending = runtime_api.pending_availability(&self.parent_id)?;
+
+		// the runtime rejects candidates of chains which already have one pending
+		// availability, and leaves out those of chains which can't pay for their
+		// balance uploads and fees, so neither are proposed.
+		let mut affordable = Vec::with_capacity(candidates.len());
+		for candidate in candidates {
+			if pending.iter().any(|&(_, ref p)| p.parachain_index == candidate.parachain_index()) {
+				debug!(target: "consensus", "Leaving out candidate of parachain {:?} which has one pending availability",
+					candidate.parachain_index());
+				continue;
+			}
+
+			let debit = candidate.candidate.balance_uploads.iter()
+				.try_fold(candidate.candidate.fees, |acc, &(_, amount)| acc.checked_add(amount));
+			let balance = runtime_api.parachain_balance(&self.parent_id, candidate.parachain_index())?;
+
+			match debit {
+				Some(debit) if Balance::from(debit) <= balance => affordable.push(candidate),
+				_ => debug!(target: "consensus", "Leaving out candidate of parachain {:?} which can't pay for it",
+					candidate.parachain_index()),
+			}
+		}
+
+		let mut inherent_data = self.inherent_data.take().expect("CreateProposal is not polled after finishing; qed");
+		inherent_data.put_data(polkadot_runtime::PARACHAIN_INHERENT_IDENTIFIER, &affordable).map_err(ErrorKind::InherentError)?;
+
+		// bit fields of the wrong length would make the block invalid.
+		let availability: Vec<_> = self.table.availability().into_iter()
+			.filter(|signed| signed.activity.0.len() == (pending.len() + 7) / 8)
+			.collect();
+		inherent_data.put_data(polkadot_runtime::AVAILABILITY_INHERENT_IDENTIFIER, &availability)
+			.map_err(ErrorKind::InherentError)?;
+
+		// the runtime can only check the group of unauthorized statements
+		// which name their candidate.
+		let misbehavior: Vec<_> = self.table.get_misbehavior().into_iter()
+			.filter(|&(_, ref misbehavior)| match *misbehavior {
+				table::generic::Misbehavior::UnauthorizedStatement(ref unauthorized) =>
+					match unauthorized.statement.statement {
+						GenericStatement::Candidate(_) => true,
+						GenericStatement::Valid(_) | GenericStatement::Invalid(_) => false,
+					},
+				_ => true,
+			})
+			.collect();
+		inherent_data.put_data(polkadot_runtime::MISBEHAVIOR_INHERENT_IDENTIFIER, &misbehavior)
+			.map_err(ErrorKind::InherentError)?;
+
+		// votes about candidates which can no longer be disputed are dropped. the
+		// runtime skips those it already applied.
+		let dispute_period = runtime_api.dispute_period(&self.parent_id)?;
+		let disputes: Vec<_> = {
+			let mut dispute_votes = self.dispute_votes.lock();
+			let parent_number = self.parent_number;
+			dispute_votes.retain(|_, &mut (number, _)| number + dispute_period >= parent_number);
+			dispute_votes.values().map(|&(_, ref vote)| vote.clone()).collect()
+		};
+		inherent_data.put_data(polkadot_runtime::DISPUTES_INHERENT_IDENTIFIER, &disputes)
+			.map_err(ErrorKind::InherentError)?;
 
 		let mut block_builder = BlockBuilder::at_block(&self.parent_id, &*self.client)?;
 
@@ -691,15 +947,23 @@ impl<C, TxApi> CreateProposal<C, TxApi> where
 				.join(", ")
 		);
 
-		// TODO: full re-evaluation
-		let active_parachains = runtime_api.active_parachains(&self.parent_id)?;
-		assert!(evaluation::evaluate_initial(
+		let mut scheduled_chains = runtime_api.active_parachains(&self.parent_id)?;
+		scheduled_chains.extend(runtime_api.parathread_schedule(&self.parent_id)?);
+		scheduled_chains.sort();
+
+		let authorities = runtime_api.authorities(&self.parent_id)?;
+		let duty_roster = runtime_api.duty_roster(&self.parent_id)?;
+		let validity_threshold = runtime_api.validity_threshold(&self.parent_id)?;
+		evaluation::evaluate_initial(
 			&new_block,
 			self.believed_minimum_timestamp,
 			&self.parent_hash,
 			self.parent_number,
-			&active_parachains,
-		).is_ok());
+			&scheduled_chains,
+			&authorities,
+			&duty_roster,
+			validity_threshold,
+		)?;
 
 		Ok(new_block)
 	}
@@ -742,4 +1006,21 @@ mod tests {
 		assert!(!check_statement(&statement, &sig, Keyring::Alice.to_raw_public().into(), &[0xff; 32].into()));
 		assert!(!check_statement(&statement, &sig, Keyring::Bob.to_raw_public().into(), &parent_hash));
 	}
+
+	#[test]
+	fn sign_and_check_availability() {
+		let mut activity = Activity::new(3);
+		activity.set(1, true);
+		let parent_hash = [2; 32].into();
+
+		let signed = SignedActivity {
+			validator_index: 0,
+			signature: sign_availability(&activity, &Keyring::Alice.pair(), &parent_hash),
+			activity,
+		};
+
+		assert!(check_availability(&signed, Keyring::Alice.to_raw_public().into(), &parent_hash));
+		assert!(!check_availability(&signed, Keyring::Alice.to_raw_public().into(), &[0xff; 32].into()));
+		assert!(!check_availability(&signed, Keyring::Bob.to_raw_public().into(), &parent_hash));
+	}
 }
@@ -17,15 +17,17 @@
 //! Parachain statement table meant to be shared with a message router
 //! and a consensus proposer.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 use extrinsic_store::{Data, Store as ExtrinsicStore};
+use slashing_protection::Store as SignedStatements;
 use table::{self, Table, Context as TableContextTrait};
 use polkadot_primitives::{Block, BlockId, Hash, SessionKey};
+use polkadot_runtime::LocalCandidates;
 use polkadot_primitives::parachain::{
-	Id as ParaId, BlockData, Collation, Extrinsic, CandidateReceipt,
-	AttestedCandidate, ParachainHost
+	Id as ParaId, Collation, Extrinsic, CandidateReceipt, ErasureChunk,
+	AttestedCandidate, ParachainHost, PoVBlock, Activity, SignedActivity, CandidateSignature,
 };
 
 use parking_lot::Mutex;
@@ -35,6 +37,7 @@ use super::{GroupInfo, TableRouter};
 use self::includable::IncludabilitySender;
 use primitives::ed25519;
 use runtime_primitives::{traits::ProvideRuntimeApi};
+use parachain::validation_host::ValidationPool;
 
 mod includable;
 
@@ -53,6 +56,10 @@ impl table::Context for TableContext {
 		self.groups.get(group).map_or(false, |g| g.validity_guarantors.contains(authority))
 	}
 
+	fn is_secondary_checker_of(&self, authority: &SessionKey, group: &ParaId) -> bool {
+		self.groups.get(group).map_or(false, |g| g.secondary_checkers.contains(authority))
+	}
+
 	fn requisite_votes(&self, group: &ParaId) -> usize {
 		self.groups.get(group).map_or(usize::max_value(), |g| g.needed_validity)
 	}
@@ -80,7 +87,10 @@ struct SharedTableInner {
 	proposed_digest: Option<Hash>,
 	checked_validity: HashSet<Hash>,
 	trackers: Vec<IncludabilitySender>,
+	availability: BTreeMap<u32, SignedActivity>,
 	extrinsic_store: ExtrinsicStore,
+	signed_statements: SignedStatements,
+	validation_pool: ValidationPool,
 }
 
 impl SharedTableInner {
@@ -95,7 +105,7 @@ impl SharedTableInner {
 		router: &R,
 		statement: table::SignedStatement,
 	) -> Option<ParachainWork<
-		<R::FetchCandidate as IntoFuture>::Future,
+		<R::FetchValidationProof as IntoFuture>::Future,
 	>> {
 		let summary = match self.table.import_statement(context, statement) {
 			Some(summary) => summary,
@@ -107,12 +117,14 @@ impl SharedTableInner {
 		let local_id = context.local_id();
 
 		let para_member = context.is_member_of(&local_id, &summary.group_id);
+		let secondary_check = !para_member
+			&& context.is_secondary_checker_of(&local_id, &summary.group_id);
 
 		let digest = &summary.candidate;
 
 		// TODO: consider a strategy based on the number of candidate votes as well.
 		// only check validity if this wasn't locally proposed.
-		let extra_work = para_member
+		let extra_work = (para_member || secondary_check)
 			&& self.proposed_digest.as_ref().map_or(true, |d| d != digest)
 			&& self.checked_validity.insert(digest.clone());
 
@@ -120,11 +132,11 @@ impl SharedTableInner {
 			match self.table.get_candidate(&digest) {
 				None => None, // TODO: handle table inconsistency somehow?
 				Some(candidate) => {
-					let fetch_block_data = router.fetch_block_data(candidate).into_future();
+					let fetch = router.fetch_pov_block(candidate).into_future();
 
 					Some(Work {
 						candidate_receipt: candidate.clone(),
-						fetch_block_data,
+						fetch,
 					})
 				}
 			}
@@ -133,7 +145,9 @@ impl SharedTableInner {
 		};
 
 		work.map(|work| ParachainWork {
+			secondary_check,
 			extrinsic_store: self.extrinsic_store.clone(),
+			validation_pool: self.validation_pool.clone(),
 			relay_parent: context.parent_hash.clone(),
 			work
 		})
@@ -149,47 +163,78 @@ impl SharedTableInner {
 	}
 }
 
+/// Why validating a collation didn't produce a valid candidate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationFailure {
+	/// The candidate is invalid.
+	Invalid,
+	/// The candidate could not be validated locally, e.g. because the validation
+	/// worker failed. This says nothing about the candidate, so no statement is made.
+	Local,
+}
+
 /// Produced after validating a candidate.
 pub struct Validated {
-	/// A statement about the validity of the candidate.
-	pub validity: table::Statement,
-	/// Block data to ensure availability of.
-	pub block_data: BlockData,
+	/// A statement about the validity of the candidate. `None` if the candidate
+	/// was re-checked by a secondary checker and found to be valid, since secondary
+	/// checkers only speak up when they disagree with the group, or if the
+	/// candidate could not be validated locally. The `Invalid` statement of a
+	/// secondary checker doesn't keep the candidate from being included, but is
+	/// used to dispute it once it is.
+	pub validity: Option<table::Statement>,
+	/// Proof-of-validation block, whose block data to ensure availability of.
+	pub pov_block: PoVBlock,
 	/// Extrinsic data to ensure availability of.
 	pub extrinsic: Option<Extrinsic>,
+	/// Erasure-coded chunks of a valid candidate, one for each validator at the
+	/// relay parent. Empty if the candidate wasn't found valid.
+	pub erasure_chunks: Vec<ErasureChunk>,
 }
 
 /// Future that performs parachain validation work.
 pub struct ParachainWork<D: Future> {
 	work: Work<D>,
+	secondary_check: bool,
 	relay_parent: Hash,
 	extrinsic_store: ExtrinsicStore,
+	validation_pool: ValidationPool,
 }
 
 impl<D: Future> ParachainWork<D> {
+	/// The receipt of the candidate to validate.
+	pub fn candidate_receipt(&self) -> &CandidateReceipt {
+		&self.work.candidate_receipt
+	}
+
 	/// Prime the parachain work with an API reference for extracting
 	/// chain information.
 	pub fn prime<P: ProvideRuntimeApi>(self, api: Arc<P>)
 		-> PrimedParachainWork<
 			D,
-			impl Send + FnMut(&BlockId, &Collation) -> Result<Extrinsic, ()>,
+			impl Send + FnMut(&BlockId, &Collation) -> Result<(Extrinsic, Vec<ErasureChunk>), ValidationFailure>,
 		>
 		where
 			P: Send + Sync + 'static,
 			P::Api: ParachainHost<Block>,
 	{
+		let validation_pool = self.validation_pool.clone();
 		let validate = move |id: &_, collation: &_| {
 			let res = ::collation::validate_collation(
 				&*api,
+				&validation_pool,
 				id,
 				collation,
 			);
 
 			match res {
-				Ok(e) => Ok(e),
+				Ok(validated) => Ok(validated),
+				Err(ref e) if e.is_local() => {
+					warn!(target: "consensus", "Unable to validate collation: {}", e);
+					Err(ValidationFailure::Local)
+				}
 				Err(e) => {
 					debug!(target: "consensus", "Encountered bad collation: {}", e);
-					Err(())
+					Err(ValidationFailure::Invalid)
 				}
 			}
 		};
@@ -199,7 +244,7 @@ impl<D: Future> ParachainWork<D> {
 
 	/// Prime the parachain work with a custom validation function.
 	pub fn prime_with<F>(self, validate: F) -> PrimedParachainWork<D, F>
-		where F: FnMut(&BlockId, &Collation) -> Result<Extrinsic, ()>
+		where F: FnMut(&BlockId, &Collation) -> Result<(Extrinsic, Vec<ErasureChunk>), ValidationFailure>
 	{
 		PrimedParachainWork { inner: self, validate }
 	}
@@ -207,7 +252,7 @@ impl<D: Future> ParachainWork<D> {
 
 struct Work<D: Future> {
 	candidate_receipt: CandidateReceipt,
-	fetch_block_data: D,
+	fetch: D,
 }
 
 /// Primed statement producer.
@@ -218,8 +263,8 @@ pub struct PrimedParachainWork<D: Future, F> {
 
 impl<D, F, Err> Future for PrimedParachainWork<D, F>
 	where
-		D: Future<Item=BlockData,Error=Err>,
-		F: FnMut(&BlockId, &Collation) -> Result<Extrinsic, ()>,
+		D: Future<Item=PoVBlock,Error=Err>,
+		F: FnMut(&BlockId, &Collation) -> Result<(Extrinsic, Vec<ErasureChunk>), ValidationFailure>,
 		Err: From<::std::io::Error>,
 {
 	type Item = Validated;
@@ -229,10 +274,10 @@ impl<D, F, Err> Future for PrimedParachainWork<D, F>
 		let work = &mut self.inner.work;
 		let candidate = &work.candidate_receipt;
 
-		let block = try_ready!(work.fetch_block_data.poll());
+		let pov_block = try_ready!(work.fetch.poll());
 		let validation_res = (self.validate)(
 			&BlockId::hash(self.inner.relay_parent),
-			&Collation { block_data: block.clone(), receipt: candidate.clone() },
+			&Collation { pov: pov_block.clone(), receipt: candidate.clone() },
 		);
 
 		let candidate_hash = candidate.hash();
@@ -240,25 +285,33 @@ impl<D, F, Err> Future for PrimedParachainWork<D, F>
 		debug!(target: "consensus", "Making validity statement about candidate {}: is_good? {:?}",
 			candidate_hash, validation_res.is_ok());
 
-		let (extrinsic, validity_statement) = match validation_res {
-			Err(()) => (None, GenericStatement::Invalid(candidate_hash)),
-			Ok(extrinsic) => {
+		let (extrinsic, erasure_chunks, validity_statement) = match validation_res {
+			Err(ValidationFailure::Invalid) => (None, Vec::new(), Some(GenericStatement::Invalid(candidate_hash))),
+			Err(ValidationFailure::Local) => (None, Vec::new(), None),
+			Ok((extrinsic, erasure_chunks)) => {
 				self.inner.extrinsic_store.make_available(Data {
 					relay_parent: self.inner.relay_parent,
 					parachain_id: work.candidate_receipt.parachain_index,
 					candidate_hash,
-					block_data: block.clone(),
+					block_data: pov_block.block_data.clone(),
 					extrinsic: Some(extrinsic.clone()),
 				})?;
 
-				(Some(extrinsic), GenericStatement::Valid(candidate_hash))
+				let validity = if self.inner.secondary_check {
+					None
+				} else {
+					Some(GenericStatement::Valid(candidate_hash))
+				};
+
+				(Some(extrinsic), erasure_chunks, validity)
 			}
 		};
 
 		Ok(Async::Ready(Validated {
 			validity: validity_statement,
-			block_data: block,
+			pov_block,
 			extrinsic,
+			erasure_chunks,
 		}))
 	}
 }
@@ -281,13 +334,16 @@ impl Clone for SharedTable {
 impl SharedTable {
 	/// Create a new shared table.
 	///
-	/// Provide the key to sign with, and the parent hash of the relay chain
-	/// block being built.
+	/// Provide the key to sign with, the parent hash of the relay chain
+	/// block being built, the record of statements signed by the key, and a
+	/// cache of validation code which may be shared between tables.
 	pub fn new(
 		groups: HashMap<ParaId, GroupInfo>,
 		key: Arc<ed25519::Pair>,
 		parent_hash: Hash,
 		extrinsic_store: ExtrinsicStore,
+		signed_statements: SignedStatements,
+		validation_pool: ValidationPool,
 	) -> Self {
 		SharedTable {
 			context: Arc::new(TableContext { groups, key, parent_hash }),
@@ -296,7 +352,10 @@ impl SharedTable {
 				proposed_digest: None,
 				checked_validity: HashSet::new(),
 				trackers: Vec::new(),
+				availability: BTreeMap::new(),
 				extrinsic_store,
+				signed_statements,
+				validation_pool,
 			}))
 		}
 	}
@@ -325,7 +384,7 @@ impl SharedTable {
 		router: &R,
 		statement: table::SignedStatement,
 	) -> Option<ParachainWork<
-		<R::FetchCandidate as IntoFuture>::Future,
+		<R::FetchValidationProof as IntoFuture>::Future,
 	>> {
 		self.inner.lock().import_remote_statement(&*self.context, router, statement)
 	}
@@ -341,7 +400,7 @@ impl SharedTable {
 			R: TableRouter,
 			I: IntoIterator<Item=table::SignedStatement>,
 			U: ::std::iter::FromIterator<Option<ParachainWork<
-				<R::FetchCandidate as IntoFuture>::Future,
+				<R::FetchValidationProof as IntoFuture>::Future,
 			>>>,
 	{
 		let mut inner = self.inner.lock();
@@ -352,24 +411,63 @@ impl SharedTable {
 	}
 
 	/// Sign and import a local statement.
+	///
+	/// Returns `None` if the statement conflicts with one signed earlier on top
+	/// of the same parent hash, possibly before a restart, since signing it
+	/// would be misbehavior.
 	pub fn sign_and_import(&self, statement: table::Statement)
-		-> SignedStatement
+		-> Option<SignedStatement>
 	{
 		let proposed_digest = match statement {
 			GenericStatement::Candidate(ref c) => Some(c.hash()),
 			_ => None,
 		};
 
+		let mut inner = self.inner.lock();
+		match inner.signed_statements.note_signed(self.context.parent_hash, &statement) {
+			Ok(true) => {},
+			Ok(false) => {
+				warn!(target: "consensus", "Refusing to sign statement conflicting with an earlier one: {:?}", statement);
+				return None;
+			}
+			Err(e) => {
+				warn!(target: "consensus", "Failed to record signed statement, not signing: {:?}", e);
+				return None;
+			}
+		}
+
 		let signed_statement = self.context.sign_statement(statement);
 
-		let mut inner = self.inner.lock();
 		if proposed_digest.is_some() {
 			inner.proposed_digest = proposed_digest;
 		}
 
 		inner.table.import_statement(&*self.context, signed_statement.clone());
 
-		signed_statement
+		Some(signed_statement)
+	}
+
+	/// Import an availability bit field with remote source, whose signature has
+	/// already been checked. Only the first bit field of each validator is kept.
+	pub fn import_availability(&self, availability: SignedActivity) {
+		self.inner.lock().availability.entry(availability.validator_index).or_insert(availability);
+	}
+
+	/// Sign and import the local availability bit field.
+	pub fn sign_and_import_availability(&self, activity: Activity, validator_index: u32) -> SignedActivity {
+		let signed = SignedActivity {
+			validator_index,
+			signature: ::sign_availability(&activity, &self.context.key, &self.context.parent_hash),
+			activity,
+		};
+
+		self.inner.lock().availability.insert(validator_index, signed.clone());
+		signed
+	}
+
+	/// Get the imported availability bit fields, in ascending order by validator index.
+	pub fn availability(&self) -> Vec<SignedActivity> {
+		self.inner.lock().availability.values().cloned().collect()
 	}
 
 	/// Execute a closure using a specific candidate.
@@ -388,20 +486,35 @@ impl SharedTable {
 		use polkadot_primitives::parachain::ValidityAttestation;
 
 		// we transform the types of the attestations gathered from the table
-		// into the type expected by the runtime. This may do signature
-		// aggregation in the future.
+		// into the type expected by the runtime, which refers to validators by
+		// their position in the group. This may do signature aggregation in the future.
 		let table_attestations = self.inner.lock().table.proposed_candidates(&*self.context);
 		table_attestations.into_iter()
-			.map(|attested| AttestedCandidate {
-				candidate: attested.candidate,
-				validity_votes: attested.validity_votes.into_iter().map(|(a, v)| match v {
+			.filter_map(|attested| {
+				let group = self.context.groups.get(&attested.group_id)?;
+				let votes = attested.validity_votes.into_iter().map(|(a, v)| match v {
 					GAttestation::Implicit(s) => (a, ValidityAttestation::Implicit(s)),
 					GAttestation::Explicit(s) => (a, ValidityAttestation::Explicit(s)),
-				}).collect(),
+				});
+
+				AttestedCandidate::from_attestations(attested.candidate, &group.validity_guarantors, votes)
 			})
 			.collect()
 	}
 
+	/// Get the local view of the candidates in the table, for checking the
+	/// parachain heads of blocks built on top of the same parent against.
+	pub fn local_candidates(&self) -> LocalCandidates {
+		let inner = self.inner.lock();
+		LocalCandidates {
+			relay_parent: self.context.parent_hash,
+			includable: inner.table.proposed_candidates(&*self.context).into_iter()
+				.map(|attested| attested.candidate.parachain_index)
+				.collect(),
+			invalid: inner.table.indicated_bad_candidates(&*self.context),
+		}
+	}
+
 	/// Get the number of total parachains.
 	pub fn num_parachains(&self) -> usize {
 		self.group_info().len()
@@ -417,6 +530,13 @@ impl SharedTable {
 		self.inner.lock().table.get_misbehavior().clone()
 	}
 
+	/// Get the `Invalid` statements of secondary checkers, along with the hashes of
+	/// the candidates they are about. The candidates should be disputed with them
+	/// once included.
+	pub fn disputes(&self) -> Vec<(Hash, SessionKey, CandidateSignature)> {
+		self.inner.lock().table.disputes()
+	}
+
 	/// Track includability  of a given set of candidate hashes.
 	pub fn track_includability<I>(&self, iterable: I) -> Includable
 		where I: IntoIterator<Item=Hash>
@@ -440,19 +560,36 @@ impl SharedTable {
 mod tests {
 	use super::*;
 	use substrate_keyring::Keyring;
+	use polkadot_primitives::parachain::{BlockData, ConsolidatedIngress, ErasureChunk};
 	use futures::future;
 
+	fn pov_block_with_data(data: Vec<u8>) -> PoVBlock {
+		PoVBlock {
+			block_data: BlockData(data),
+			ingress: ConsolidatedIngress(Vec::new()),
+		}
+	}
+
 	#[derive(Clone)]
 	struct DummyRouter;
 	impl TableRouter for DummyRouter {
 		type Error = ::std::io::Error;
-		type FetchCandidate = ::futures::future::FutureResult<BlockData,Self::Error>;
+		type FetchValidationProof = ::futures::future::FutureResult<PoVBlock,Self::Error>;
 
-		fn local_candidate(&self, _candidate: CandidateReceipt, _block_data: BlockData, _extrinsic: Extrinsic) {
+		fn local_candidate(&self, _candidate: CandidateReceipt, _pov_block: PoVBlock, _extrinsic: Extrinsic) {
+
+		}
+		fn distribute_erasure_chunks(&self, _candidate: &CandidateReceipt, _chunks: Vec<ErasureChunk>) {
 
 		}
-		fn fetch_block_data(&self, _candidate: &CandidateReceipt) -> Self::FetchCandidate {
-			future::ok(BlockData(vec![1, 2, 3, 4, 5]))
+		fn fetch_pov_block(&self, _candidate: &CandidateReceipt) -> Self::FetchValidationProof {
+			future::ok(pov_block_with_data(vec![1, 2, 3, 4, 5]))
+		}
+		fn local_availability(&self, _availability: SignedActivity) {
+
+		}
+		fn recover_candidate(&self, _relay_parent: Hash, _candidate: CandidateReceipt, _validators: Vec<SessionKey>) {
+
 		}
 	}
 
@@ -471,6 +608,7 @@ mod tests {
 		groups.insert(para_id, GroupInfo {
 			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
 			needed_validity: 2,
+			secondary_checkers: HashSet::new(),
 		});
 
 		let shared_table = SharedTable::new(
@@ -478,6 +616,8 @@ mod tests {
 			local_key.clone(),
 			parent_hash,
 			ExtrinsicStore::new_in_memory(),
+			SignedStatements::new_in_memory(),
+			ValidationPool::in_process(),
 		);
 
 		let candidate = CandidateReceipt {
@@ -489,6 +629,8 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: Default::default(),
 		};
 
 		let candidate_statement = GenericStatement::Candidate(candidate);
@@ -521,6 +663,7 @@ mod tests {
 		groups.insert(para_id, GroupInfo {
 			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
 			needed_validity: 1,
+			secondary_checkers: HashSet::new(),
 		});
 
 		let shared_table = SharedTable::new(
@@ -528,6 +671,8 @@ mod tests {
 			local_key.clone(),
 			parent_hash,
 			ExtrinsicStore::new_in_memory(),
+			SignedStatements::new_in_memory(),
+			ValidationPool::in_process(),
 		);
 
 		let candidate = CandidateReceipt {
@@ -539,6 +684,8 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: Default::default(),
 		};
 
 		let candidate_statement = GenericStatement::Candidate(candidate);
@@ -561,7 +708,8 @@ mod tests {
 		let store = ExtrinsicStore::new_in_memory();
 		let relay_parent = [0; 32].into();
 		let para_id = 5.into();
-		let block_data = BlockData(vec![1, 2, 3]);
+		let pov_block = pov_block_with_data(vec![1, 2, 3]);
+		let block_data = pov_block.block_data.clone();
 
 		let candidate = CandidateReceipt {
 			parachain_index: para_id,
@@ -572,6 +720,8 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: Default::default(),
 		};
 
 		let hash = candidate.hash();
@@ -579,29 +729,161 @@ mod tests {
 		let producer: ParachainWork<future::FutureResult<_, ::std::io::Error>> = ParachainWork {
 			work: Work {
 				candidate_receipt: candidate,
-				fetch_block_data: future::ok(block_data.clone()),
+				fetch: future::ok(pov_block.clone()),
 			},
+			secondary_check: false,
 			relay_parent,
 			extrinsic_store: store.clone(),
+			validation_pool: ValidationPool::in_process(),
 		};
 
-		let produced = producer.prime_with(|_, _| Ok(Extrinsic { outgoing_messages: Vec::new() }))
+		let extrinsic = Extrinsic { outgoing_messages: Vec::new() };
+		let (_, chunks) = ::collation::erasure_chunks(4, &block_data, &extrinsic).unwrap();
+		let produced = producer.prime_with(|_, _| Ok((extrinsic.clone(), chunks.clone())))
 			.wait()
 			.unwrap();
 
-		assert_eq!(produced.block_data, block_data);
-		assert_eq!(produced.validity, GenericStatement::Valid(hash));
+		assert_eq!(produced.pov_block, pov_block);
+		assert_eq!(produced.validity, Some(GenericStatement::Valid(hash)));
+		assert_eq!(produced.erasure_chunks, chunks);
 
 		assert_eq!(store.block_data(relay_parent, hash).unwrap(), block_data);
 		assert!(store.extrinsic(relay_parent, hash).is_some());
 	}
 
+	#[test]
+	fn local_validation_failure_makes_no_statement() {
+		let store = ExtrinsicStore::new_in_memory();
+		let relay_parent = [0; 32].into();
+		let pov_block = pov_block_with_data(vec![1, 2, 3]);
+
+		let candidate = CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: Default::default(),
+		};
+
+		let hash = candidate.hash();
+
+		let producer: ParachainWork<future::FutureResult<_, ::std::io::Error>> = ParachainWork {
+			work: Work {
+				candidate_receipt: candidate,
+				fetch: future::ok(pov_block.clone()),
+			},
+			secondary_check: false,
+			relay_parent,
+			extrinsic_store: store.clone(),
+			validation_pool: ValidationPool::in_process(),
+		};
+
+		let produced = producer.prime_with(|_, _| Err(ValidationFailure::Local))
+			.wait()
+			.unwrap();
+
+		assert!(produced.validity.is_none());
+		assert!(produced.extrinsic.is_none());
+		assert!(store.extrinsic(relay_parent, hash).is_none());
+	}
+
+	#[test]
+	fn secondary_checker_only_reports_invalidity() {
+		let mut groups = HashMap::new();
+
+		let para_id = ParaId::from(1);
+		let local_id = Keyring::Alice.to_raw_public().into();
+		let local_key = Arc::new(Keyring::Alice.pair());
+
+		let validity_other = Keyring::Bob.to_raw_public().into();
+		let validity_other_key = Keyring::Bob.pair();
+		let parent_hash = Default::default();
+
+		groups.insert(para_id, GroupInfo {
+			validity_guarantors: [validity_other].iter().cloned().collect(),
+			needed_validity: 1,
+			secondary_checkers: [local_id].iter().cloned().collect(),
+		});
+
+		let shared_table = SharedTable::new(
+			groups,
+			local_key.clone(),
+			parent_hash,
+			ExtrinsicStore::new_in_memory(),
+			SignedStatements::new_in_memory(),
+			ValidationPool::in_process(),
+		);
+
+		let candidate = CandidateReceipt {
+			parachain_index: para_id,
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: Default::default(),
+		};
+
+		let hash = candidate.hash();
+		let candidate_statement = GenericStatement::Candidate(candidate);
+
+		let signature = ::sign_table_statement(&candidate_statement, &validity_other_key, &parent_hash);
+		let signed_statement = ::table::generic::SignedStatement {
+			statement: candidate_statement,
+			signature: signature.into(),
+			sender: validity_other,
+		};
+
+		let produced = shared_table.import_remote_statement(&DummyRouter, signed_statement.clone())
+			.expect("secondary checker re-checks candidate")
+			.prime_with(|_, _| Ok((Extrinsic { outgoing_messages: Vec::new() }, Vec::new())))
+			.wait()
+			.unwrap();
+		assert!(produced.validity.is_none());
+
+		// fresh table, but the candidate turns out to be invalid this time.
+		let shared_table = SharedTable::new(
+			shared_table.group_info().clone(),
+			local_key,
+			parent_hash,
+			ExtrinsicStore::new_in_memory(),
+			SignedStatements::new_in_memory(),
+			ValidationPool::in_process(),
+		);
+
+		let produced = shared_table.import_remote_statement(&DummyRouter, signed_statement)
+			.expect("secondary checker re-checks candidate")
+			.prime_with(|_, _| Err(ValidationFailure::Invalid))
+			.wait()
+			.unwrap();
+		let invalidity = produced.validity.expect("disagreement is reported");
+		assert_eq!(invalidity, GenericStatement::Invalid(hash));
+
+		let signed = shared_table.sign_and_import(invalidity).expect("invalidity is signed");
+		assert!(shared_table.get_misbehavior().is_empty());
+
+		// the candidate may still be included, but is to be disputed.
+		let local_candidates = shared_table.local_candidates();
+		assert_eq!(local_candidates.includable, vec![para_id]);
+		assert!(local_candidates.invalid.is_empty());
+		assert_eq!(shared_table.disputes(), vec![(hash, local_id, signed.signature)]);
+	}
+
 	#[test]
 	fn full_availability() {
 		let store = ExtrinsicStore::new_in_memory();
 		let relay_parent = [0; 32].into();
 		let para_id = 5.into();
-		let block_data = BlockData(vec![1, 2, 3]);
+		let pov_block = pov_block_with_data(vec![1, 2, 3]);
+		let block_data = pov_block.block_data.clone();
 
 		let candidate = CandidateReceipt {
 			parachain_index: para_id,
@@ -612,6 +894,8 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: Default::default(),
 		};
 
 		let hash = candidate.hash();
@@ -619,19 +903,92 @@ mod tests {
 		let producer = ParachainWork {
 			work: Work {
 				candidate_receipt: candidate,
-				fetch_block_data: future::ok::<_, ::std::io::Error>(block_data.clone()),
+				fetch: future::ok::<_, ::std::io::Error>(pov_block.clone()),
 			},
+			secondary_check: false,
 			relay_parent,
 			extrinsic_store: store.clone(),
+			validation_pool: ValidationPool::in_process(),
 		};
 
-		let produced = producer.prime_with(|_, _| Ok(Extrinsic { outgoing_messages: Vec::new() }))
+		let produced = producer.prime_with(|_, _| Ok((Extrinsic { outgoing_messages: Vec::new() }, Vec::new())))
 			.wait()
 			.unwrap();
 
-		assert_eq!(produced.block_data, block_data);
+		assert_eq!(produced.pov_block, pov_block);
 
 		assert_eq!(store.block_data(relay_parent, hash).unwrap(), block_data);
 		assert!(store.extrinsic(relay_parent, hash).is_some());
 	}
+
+	#[test]
+	fn availability_is_kept_once_per_validator() {
+		let parent_hash = [1; 32].into();
+		let shared_table = SharedTable::new(
+			HashMap::new(),
+			Arc::new(Keyring::Alice.pair()),
+			parent_hash,
+			ExtrinsicStore::new_in_memory(),
+			SignedStatements::new_in_memory(),
+			ValidationPool::in_process(),
+		);
+
+		let remote = |validator_index, key: &Keyring, held: bool| {
+			let mut activity = Activity::new(1);
+			activity.set(0, held);
+			SignedActivity {
+				validator_index,
+				signature: ::sign_availability(&activity, &key.pair(), &parent_hash),
+				activity,
+			}
+		};
+
+		shared_table.import_availability(remote(2, &Keyring::Charlie, true));
+		shared_table.import_availability(remote(2, &Keyring::Charlie, false));
+
+		let mut local_activity = Activity::new(1);
+		local_activity.set(0, true);
+		let local = shared_table.sign_and_import_availability(local_activity, 0);
+		assert!(::check_availability(&local, Keyring::Alice.to_raw_public().into(), &parent_hash));
+
+		let availability = shared_table.availability();
+		assert_eq!(availability, vec![local, remote(2, &Keyring::Charlie, true)]);
+	}
+
+	#[test]
+	fn conflicting_statement_is_not_signed_after_restart() {
+		let parent_hash = [1; 32].into();
+		let signed_statements = SignedStatements::new_in_memory();
+		let make_table = || SharedTable::new(
+			HashMap::new(),
+			Arc::new(Keyring::Alice.pair()),
+			parent_hash,
+			ExtrinsicStore::new_in_memory(),
+			signed_statements.clone(),
+			ValidationPool::in_process(),
+		);
+
+		let candidate = CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: Default::default(),
+		};
+		let mut other_candidate = candidate.clone();
+		other_candidate.fees = 0;
+
+		assert!(make_table().sign_and_import(GenericStatement::Candidate(candidate.clone())).is_some());
+
+		// a fresh table, as after a restart, still knows about the first candidate.
+		let table = make_table();
+		assert!(table.sign_and_import(GenericStatement::Candidate(other_candidate)).is_none());
+		assert!(table.sign_and_import(GenericStatement::Invalid(candidate.hash())).is_none());
+		assert!(table.sign_and_import(GenericStatement::Candidate(candidate)).is_some());
+	}
 }
@@ -0,0 +1,257 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Persistent record of the statements signed by the local validator.
+//!
+//! The statement table only lives in memory, so a restarted validator would
+//! otherwise have no way of knowing which statements it has already signed
+//! on top of a relay parent. Every statement is recorded here before it is
+//! signed, and statements which would conflict with a recorded one are refused.
+//!
+//! Relay parents are indexed by block number, so that the statements signed on
+//! top of every fork below the last finalized block can be pruned.
+
+use codec::{Encode, Decode};
+use kvdb::{KeyValueDB, DBTransaction};
+use kvdb_rocksdb::{Database, DatabaseConfig};
+use polkadot_primitives::{Hash, BlockNumber};
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::io;
+
+use super::{Statement, GenericStatement};
+
+mod columns {
+	pub const STATEMENTS: Option<u32> = Some(0);
+	pub const RELAY_PARENTS: Option<u32> = Some(1);
+	pub const NUM_COLUMNS: u32 = 2;
+}
+
+/// Configuration for the store of signed statements.
+pub struct Config {
+	/// Cache size in bytes. If `None` default is used.
+	pub cache_size: Option<usize>,
+	/// Path to the database.
+	pub path: PathBuf,
+}
+
+// whether the same signer issuing both statements is misbehavior.
+fn conflicting(a: &Statement, b: &Statement) -> bool {
+	match (a, b) {
+		(&GenericStatement::Candidate(ref a), &GenericStatement::Candidate(ref b)) => a != b,
+		(&GenericStatement::Candidate(ref c), &GenericStatement::Valid(ref d))
+			| (&GenericStatement::Valid(ref d), &GenericStatement::Candidate(ref c))
+			| (&GenericStatement::Candidate(ref c), &GenericStatement::Invalid(ref d))
+			| (&GenericStatement::Invalid(ref d), &GenericStatement::Candidate(ref c))
+			=> &c.hash() == d,
+		(&GenericStatement::Valid(ref a), &GenericStatement::Invalid(ref b))
+			| (&GenericStatement::Invalid(ref a), &GenericStatement::Valid(ref b))
+			=> a == b,
+		(&GenericStatement::Valid(_), &GenericStatement::Valid(_))
+			| (&GenericStatement::Invalid(_), &GenericStatement::Invalid(_))
+			=> false,
+	}
+}
+
+/// Handle to the store of signed statements.
+#[derive(Clone)]
+pub struct Store {
+	inner: Arc<dyn KeyValueDB>,
+}
+
+impl Store {
+	/// Create a new `Store` with given config on disk.
+	pub fn new(config: Config) -> io::Result<Self> {
+		let mut db_config = DatabaseConfig::with_columns(Some(columns::NUM_COLUMNS));
+		db_config.memory_budget = config.cache_size;
+
+		let path = config.path.to_str().ok_or_else(|| io::Error::new(
+			io::ErrorKind::Other,
+			format!("Bad database path: {:?}", config.path),
+		))?;
+
+		let db = Database::open(&db_config, &path)?;
+
+		Ok(Store {
+			inner: Arc::new(db),
+		})
+	}
+
+	/// Create a new `Store` in-memory. Useful for tests.
+	pub fn new_in_memory() -> Self {
+		Store {
+			inner: Arc::new(::kvdb_memorydb::create(columns::NUM_COLUMNS)),
+		}
+	}
+
+	/// Record that the local validator is about to sign a statement on top of
+	/// the given relay parent.
+	///
+	/// Returns `false` without recording anything if signing the statement would
+	/// be misbehavior given the statements already signed on top of the relay parent.
+	/// The statement must only be signed if this returns `true`.
+	pub fn note_signed(&self, relay_parent: Hash, statement: &Statement) -> io::Result<bool> {
+		let mut signed = self.signed(relay_parent)?;
+
+		if signed.iter().any(|s| conflicting(s, statement)) {
+			return Ok(false);
+		}
+
+		if !signed.contains(statement) {
+			signed.push(statement.clone());
+
+			let mut tx = DBTransaction::new();
+			tx.put_vec(columns::STATEMENTS, &relay_parent[..], signed.encode());
+			self.inner.write(tx)?;
+		}
+
+		Ok(true)
+	}
+
+	/// Note the block number of a relay parent statements may be signed on top of,
+	/// so that they are pruned once no more blocks can be finalized on top of it.
+	pub fn note_relay_parent(&self, relay_parent: Hash, number: BlockNumber) -> io::Result<()> {
+		let key = number.encode();
+		let mut relay_parents = self.relay_parents(&key)?;
+
+		if !relay_parents.contains(&relay_parent) {
+			relay_parents.push(relay_parent);
+
+			let mut tx = DBTransaction::new();
+			tx.put_vec(columns::RELAY_PARENTS, &key[..], relay_parents.encode());
+			self.inner.write(tx)?;
+		}
+
+		Ok(())
+	}
+
+	/// Query the statements signed on top of a relay parent.
+	pub fn signed(&self, relay_parent: Hash) -> io::Result<Vec<Statement>> {
+		Ok(match self.inner.get(columns::STATEMENTS, &relay_parent[..])? {
+			Some(raw) => Vec::decode(&mut &raw[..]).expect("all stored data serialized correctly; qed"),
+			None => Vec::new(),
+		})
+	}
+
+	/// Forget the statements signed on top of all noted relay parents below the
+	/// given block number, on any fork. This should be called with the number of
+	/// the last finalized block, as no more blocks can be finalized on top of them.
+	pub fn prune_below(&self, finalized: BlockNumber) -> io::Result<()> {
+		let mut tx = DBTransaction::new();
+		for (key, value) in self.inner.iter(columns::RELAY_PARENTS) {
+			let number = BlockNumber::decode(&mut &key[..])
+				.expect("all stored data serialized correctly; qed");
+			if number >= finalized { continue }
+
+			let relay_parents: Vec<Hash> = Decode::decode(&mut &value[..])
+				.expect("all stored data serialized correctly; qed");
+			for relay_parent in relay_parents {
+				tx.delete(columns::STATEMENTS, &relay_parent[..]);
+			}
+			tx.delete(columns::RELAY_PARENTS, &key[..]);
+		}
+
+		self.inner.write(tx)
+	}
+
+	// the relay parents noted at the block number with the given key.
+	fn relay_parents(&self, key: &[u8]) -> io::Result<Vec<Hash>> {
+		Ok(match self.inner.get(columns::RELAY_PARENTS, key)? {
+			Some(raw) => Vec::decode(&mut &raw[..]).expect("all stored data serialized correctly; qed"),
+			None => Vec::new(),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_primitives::parachain::{CandidateReceipt, HeadData};
+
+	fn candidate(head_data: Vec<u8>) -> CandidateReceipt {
+		CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: HeadData(head_data),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 0,
+			block_data_hash: [2; 32].into(),
+			new_validation_code_hash: None,
+			erasure_root: [3; 32].into(),
+		}
+	}
+
+	#[test]
+	fn conflicting_statements_are_refused() {
+		let relay_parent = [1; 32].into();
+		let other_parent = [2; 32].into();
+		let candidate_a = candidate(vec![1, 2, 3]);
+		let candidate_b = candidate(vec![4, 5, 6]);
+		let digest: Hash = [9; 32].into();
+
+		let store = Store::new_in_memory();
+		assert!(store.note_signed(relay_parent, &GenericStatement::Candidate(candidate_a.clone())).unwrap());
+		assert!(store.note_signed(relay_parent, &GenericStatement::Valid(digest)).unwrap());
+
+		// signing the same statement again is fine.
+		assert!(store.note_signed(relay_parent, &GenericStatement::Valid(digest)).unwrap());
+
+		assert!(!store.note_signed(relay_parent, &GenericStatement::Candidate(candidate_b.clone())).unwrap());
+		assert!(!store.note_signed(relay_parent, &GenericStatement::Valid(candidate_a.hash())).unwrap());
+		assert!(!store.note_signed(relay_parent, &GenericStatement::Invalid(candidate_a.hash())).unwrap());
+		assert!(!store.note_signed(relay_parent, &GenericStatement::Invalid(digest)).unwrap());
+
+		assert_eq!(store.signed(relay_parent).unwrap(), vec![
+			GenericStatement::Candidate(candidate_a),
+			GenericStatement::Valid(digest),
+		]);
+
+		// statements on top of other relay parents are unaffected.
+		assert!(store.note_signed(other_parent, &GenericStatement::Candidate(candidate_b)).unwrap());
+		assert_eq!(store.signed(other_parent).unwrap().len(), 1);
+	}
+
+	#[test]
+	fn statements_below_finalized_number_are_pruned() {
+		let statement = GenericStatement::Valid([9; 32].into());
+		let parent_a: Hash = [1; 32].into();
+		let fork_a: Hash = [2; 32].into();
+		let parent_b: Hash = [3; 32].into();
+		let parent_c: Hash = [4; 32].into();
+
+		let store = Store::new_in_memory();
+		for &(relay_parent, number) in &[(parent_a, 5), (fork_a, 5), (parent_b, 6), (parent_c, 7)] {
+			store.note_relay_parent(relay_parent, number).unwrap();
+			assert!(store.note_signed(relay_parent, &statement).unwrap());
+		}
+
+		// noting a relay parent twice is fine.
+		store.note_relay_parent(parent_a, 5).unwrap();
+
+		// finalizing block 7 means no more blocks can be built at 6 or below.
+		store.prune_below(7).unwrap();
+		assert!(store.signed(parent_a).unwrap().is_empty());
+		assert!(store.signed(fork_a).unwrap().is_empty());
+		assert!(store.signed(parent_b).unwrap().is_empty());
+		assert_eq!(store.signed(parent_c).unwrap(), vec![statement.clone()]);
+
+		store.prune_below(8).unwrap();
+		assert!(store.signed(parent_c).unwrap().is_empty());
+	}
+}
@@ -135,6 +135,13 @@ fn code_params(n_validators: usize) -> Result<CodeParams, Error> {
 	})
 }
 
+/// The number of chunks needed to reconstruct the data when it is split among
+/// `n_validators`. This is `f + 1`, where `f` is the maximum number of faulty validators.
+pub fn recovery_threshold(n_validators: usize) -> Result<usize, Error> {
+	let params = code_params(n_validators)?;
+	Ok(params.data_shards)
+}
+
 /// Obtain erasure-coded chunks, one for each validator.
 ///
 /// Works only up to 256 validators, and `n_validators` must be non-zero.
@@ -368,6 +375,7 @@ mod tests {
 		).unwrap();
 
 		assert_eq!(chunks.len(), 10);
+		assert_eq!(recovery_threshold(10).unwrap(), 4);
 
 		// any 4 chunks should work.
 		let reconstructed = reconstruct(
@@ -219,7 +219,7 @@ impl CollatorPool {
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use polkadot_primitives::parachain::{CandidateReceipt, BlockData, HeadData};
+	use polkadot_primitives::parachain::{CandidateReceipt, BlockData, PoVBlock, HeadData, ConsolidatedIngress};
 	use substrate_primitives::H512;
 	use futures::Future;
 
@@ -271,8 +271,13 @@ mod tests {
 				egress_queue_roots: vec![],
 				fees: 0,
 				block_data_hash: [3; 32].into(),
+				new_validation_code_hash: None,
+				erasure_root: Default::default(),
+			},
+			pov: PoVBlock {
+				block_data: BlockData(vec![4, 5, 6]),
+				ingress: ConsolidatedIngress(Vec::new()),
 			},
-			block_data: BlockData(vec![4, 5, 6]),
 		});
 
 		rx1.wait().unwrap();
@@ -298,8 +303,13 @@ mod tests {
 				egress_queue_roots: vec![],
 				fees: 0,
 				block_data_hash: [3; 32].into(),
+				new_validation_code_hash: None,
+				erasure_root: Default::default(),
+			},
+			pov: PoVBlock {
+				block_data: BlockData(vec![4, 5, 6]),
+				ingress: ConsolidatedIngress(Vec::new()),
 			},
-			block_data: BlockData(vec![4, 5, 6]),
 		});
 
 		let (tx, rx) = oneshot::channel();
@@ -23,7 +23,9 @@ use sr_primitives::traits::ProvideRuntimeApi;
 use substrate_network::consensus_gossip::ConsensusMessage;
 use polkadot_consensus::{Network, SharedTable, Collators, Statement, GenericStatement};
 use polkadot_primitives::{AccountId, Block, Hash, SessionKey};
-use polkadot_primitives::parachain::{Id as ParaId, Collation, Extrinsic, ParachainHost, BlockData};
+use polkadot_primitives::parachain::{
+	Id as ParaId, Collation, Extrinsic, ParachainHost, PoVBlock, SignedActivity, CandidateReceipt,
+};
 use codec::Decode;
 
 use futures::prelude::*;
@@ -129,7 +131,7 @@ impl<P, E> Network for ConsensusNetwork<P,E> where
 	/// Instantiate a table router using the given shared table.
 	fn communication_for(
 		&self,
-		_validators: &[SessionKey],
+		validators: &[SessionKey],
 		table: Arc<SharedTable>,
 		task_executor: TaskExecutor,
 	) -> Self::TableRouter {
@@ -139,7 +141,7 @@ impl<P, E> Network for ConsensusNetwork<P,E> where
 
 		let local_session_key = table.session_key();
 		let table_router = Router::new(
-			table,
+			table.clone(),
 			self.network.clone(),
 			self.api.clone(),
 			task_executor.clone(),
@@ -158,6 +160,7 @@ impl<P, E> Network for ConsensusNetwork<P,E> where
 				spec.new_consensus(ctx, parent_hash, CurrentConsensus {
 					knowledge,
 					local_session_key,
+					validators: validators.to_vec(),
 				});
 
 				MessageProcessTask {
@@ -171,6 +174,26 @@ impl<P, E> Network for ConsensusNetwork<P,E> where
 
 		task_executor.spawn(process_task);
 
+		// spin up a task in the background that imports the availability
+		// bit fields of other validators.
+		let availability_stream = self.network.consensus_gossip().write()
+			.messages_for(table_router.availability_topic());
+		let availability_validators = validators.to_vec();
+		let availability_task = availability_stream.for_each(move |msg| {
+			if let Some(signed) = SignedActivity::decode(&mut msg.as_slice()) {
+				let checked = availability_validators.get(signed.validator_index as usize)
+					.map_or(false, |&key| ::polkadot_consensus::check_availability(&signed, key, &parent_hash));
+
+				if checked {
+					table.import_availability(signed);
+				}
+			}
+
+			Ok(())
+		});
+
+		task_executor.spawn(availability_task);
+
 		table_router
 	}
 }
@@ -212,8 +235,9 @@ impl<P: ProvideRuntimeApi + Send + Sync + 'static, E: Clone> Collators for Conse
 struct KnowledgeEntry {
 	knows_block_data: Vec<SessionKey>,
 	knows_extrinsic: Vec<SessionKey>,
-	block_data: Option<BlockData>,
+	pov: Option<PoVBlock>,
 	extrinsic: Option<Extrinsic>,
+	receipt: Option<CandidateReceipt>,
 }
 
 /// Tracks knowledge of peers.
@@ -239,6 +263,7 @@ impl Knowledge {
 				let mut entry = self.candidates.entry(c.hash()).or_insert_with(Default::default);
 				entry.knows_block_data.push(from);
 				entry.knows_extrinsic.push(from);
+				entry.receipt = Some(c.clone());
 			}
 			GenericStatement::Valid(ref hash) => {
 				let mut entry = self.candidates.entry(*hash).or_insert_with(Default::default);
@@ -253,9 +278,9 @@ impl Knowledge {
 	}
 
 	/// Note a candidate collated or seen locally.
-	pub(crate) fn note_candidate(&mut self, hash: Hash, block_data: Option<BlockData>, extrinsic: Option<Extrinsic>) {
+	pub(crate) fn note_candidate(&mut self, hash: Hash, pov: Option<PoVBlock>, extrinsic: Option<Extrinsic>) {
 		let entry = self.candidates.entry(hash).or_insert_with(Default::default);
-		entry.block_data = entry.block_data.take().or(block_data);
+		entry.pov = entry.pov.take().or(pov);
 		entry.extrinsic = entry.extrinsic.take().or(extrinsic);
 	}
 }
@@ -264,29 +289,46 @@ impl Knowledge {
 pub(crate) struct CurrentConsensus {
 	knowledge: Arc<Mutex<Knowledge>>,
 	local_session_key: SessionKey,
+	// the validators of the session, whose indices are those of their erasure chunks.
+	validators: Vec<SessionKey>,
 }
 
 impl CurrentConsensus {
 	#[cfg(test)]
-	pub(crate) fn new(knowledge: Arc<Mutex<Knowledge>>, local_session_key: SessionKey) -> Self {
+	pub(crate) fn new(
+		knowledge: Arc<Mutex<Knowledge>>,
+		local_session_key: SessionKey,
+		validators: Vec<SessionKey>,
+	) -> Self {
 		CurrentConsensus {
 			knowledge,
-			local_session_key
+			local_session_key,
+			validators,
 		}
 	}
 
-	// execute a closure with locally stored block data for a candidate, or a slice of session identities
+	// the index of the local validator among the session's validators.
+	fn local_index(&self) -> Option<usize> {
+		self.validators.iter().position(|k| k == &self.local_session_key)
+	}
+
+	// execute a closure with locally stored proof-of-validation for a candidate, or a slice of session identities
 	// we believe should have the data.
-	fn with_block_data<F, U>(&self, hash: &Hash, f: F) -> U
-		where F: FnOnce(Result<&BlockData, &[SessionKey]>) -> U
+	fn with_pov_block<F, U>(&self, hash: &Hash, f: F) -> U
+		where F: FnOnce(Result<&PoVBlock, &[SessionKey]>) -> U
 	{
 		let knowledge = self.knowledge.lock();
 		let res = knowledge.candidates.get(hash)
 			.ok_or(&[] as &_)
-			.and_then(|entry| entry.block_data.as_ref().ok_or(&entry.knows_block_data[..]));
+			.and_then(|entry| entry.pov.as_ref().ok_or(&entry.knows_block_data[..]));
 
 		f(res)
 	}
+
+	// the receipt of a candidate, if it has been seen.
+	fn candidate_receipt(&self, hash: &Hash) -> Option<CandidateReceipt> {
+		self.knowledge.lock().candidates.get(hash).and_then(|entry| entry.receipt.clone())
+	}
 }
 
 // 3 is chosen because sessions change infrequently and usually
@@ -388,16 +430,28 @@ impl LiveConsensusInstances {
 		self.recent.as_slice()
 	}
 
-	/// Call a closure with block data from consensus session at parent hash.
+	/// The validators of the consensus session at parent hash, and the index of the
+	/// local validator among them. `None` if the session is unknown.
+	pub(crate) fn validators(&self, parent_hash: &Hash) -> Option<(&[SessionKey], Option<usize>)> {
+		self.live_instances.get(parent_hash).map(|c| (&c.validators[..], c.local_index()))
+	}
+
+	/// The receipt of a candidate in the consensus session at parent hash. `None` if
+	/// the session is unknown or the receipt hasn't been seen.
+	pub(crate) fn candidate_receipt(&self, parent_hash: &Hash, c_hash: &Hash) -> Option<CandidateReceipt> {
+		self.live_instances.get(parent_hash).and_then(|c| c.candidate_receipt(c_hash))
+	}
+
+	/// Call a closure with proof-of-validation block from consensus session at parent hash.
 	///
 	/// This calls the closure with `Some(data)` where the session and data are live,
 	/// `Err(Some(keys))` when the session is live but the data unknown, with a list of keys
 	/// who have the data, and `Err(None)` where the session is unknown.
-	pub(crate) fn with_block_data<F, U>(&self, parent_hash: &Hash, c_hash: &Hash, f: F) -> U
-		where F: FnOnce(Result<&BlockData, Option<&[SessionKey]>>) -> U
+	pub(crate) fn with_pov_block<F, U>(&self, parent_hash: &Hash, c_hash: &Hash, f: F) -> U
+		where F: FnOnce(Result<&PoVBlock, Option<&[SessionKey]>>) -> U
 	{
 		match self.live_instances.get(parent_hash) {
-			Some(c) => c.with_block_data(c_hash, |res| f(res.map_err(Some))),
+			Some(c) => c.with_pov_block(c_hash, |res| f(res.map_err(Some))),
 			None => f(Err(None))
 		}
 	}
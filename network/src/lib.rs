@@ -27,6 +27,7 @@ extern crate sr_primitives;
 
 extern crate polkadot_consensus;
 extern crate polkadot_availability_store as av_store;
+extern crate polkadot_erasure_coding as erasure;
 extern crate polkadot_primitives;
 
 extern crate arrayvec;
@@ -44,11 +45,15 @@ mod collator_pool;
 mod local_collations;
 mod router;
 pub mod consensus;
+pub mod recovery;
 
 use codec::{Decode, Encode};
 use futures::sync::oneshot;
 use polkadot_primitives::{AccountId, Block, SessionKey, Hash, Header};
-use polkadot_primitives::parachain::{Id as ParaId, BlockData, CandidateReceipt, Collation};
+use polkadot_primitives::parachain::{
+	Id as ParaId, BlockData, CandidateReceipt, Collation, PoVBlock, ErasureChunk,
+};
+use sr_primitives::traits::{BlakeTwo256, Hash as HashT};
 use substrate_network::{NodeIndex, RequestId, Context, Severity};
 use substrate_network::{message, generic_message};
 use substrate_network::specialization::NetworkSpecialization as Specialization;
@@ -58,11 +63,16 @@ use self::collator_pool::{CollatorPool, Role, Action};
 use self::local_collations::LocalCollations;
 
 use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
 
 #[cfg(test)]
 mod tests;
 
+// how long to wait for a validator to serve an erasure chunk, including the time
+// spent waiting for it to connect.
+const ERASURE_CHUNK_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Polkadot protocol id.
 pub const DOT_PROTOCOL_ID: ::substrate_network::ProtocolId = *b"dot";
 
@@ -77,12 +87,39 @@ pub struct Status {
 	collating_for: Option<(AccountId, ParaId)>,
 }
 
-struct BlockDataRequest {
+struct PoVBlockRequest {
 	attempted_peers: HashSet<SessionKey>,
 	consensus_parent: Hash,
 	candidate_hash: Hash,
 	block_data_hash: Hash,
-	sender: oneshot::Sender<BlockData>,
+	sender: oneshot::Sender<PoVBlock>,
+}
+
+// an erasure chunk sent to us, with the erasure root claimed by the sender.
+struct ReceivedChunk {
+	from: NodeIndex,
+	candidate_hash: Hash,
+	erasure_root: Hash,
+	chunk: ErasureChunk,
+}
+
+// the maximum number of erasure chunks kept around until their candidate receipts are seen.
+const MAX_DEFERRED_CHUNKS: usize = 1024;
+
+struct ErasureChunkRequest {
+	validator: SessionKey,
+	relay_parent: Hash,
+	candidate_hash: Hash,
+	erasure_root: Hash,
+	index: u32,
+	requested_at: Instant,
+	sender: oneshot::Sender<ErasureChunk>,
+}
+
+impl ErasureChunkRequest {
+	fn expired(&self, now: Instant) -> bool {
+		now.duration_since(self.requested_at) >= ERASURE_CHUNK_REQUEST_TIMEOUT
+	}
 }
 
 // ensures collator-protocol messages are sent in correct order.
@@ -147,6 +184,17 @@ pub enum Message {
 	CollatorRole(Role),
 	/// A collation provided by a peer. Relay parent and collation.
 	Collation(Hash, Collation),
+	/// Requesting a proof-of-validation block by (relay_parent, candidate_hash).
+	RequestPovBlock(RequestId, Hash, Hash),
+	/// Provide a proof-of-validation block by candidate hash or nothing if unknown.
+	PovBlock(RequestId, Option<PoVBlock>),
+	/// An erasure-coded chunk of a candidate meant for the receiving validator, by
+	/// (relay_parent, candidate_hash, erasure_root, chunk).
+	ErasureChunk(Hash, Hash, Hash, ErasureChunk),
+	/// Requesting an erasure chunk by (relay_parent, candidate_hash, index).
+	RequestErasureChunk(RequestId, Hash, Hash, u32),
+	/// Provide an erasure chunk with its merkle proof or nothing if unknown.
+	ErasureChunkResponse(RequestId, Option<ErasureChunk>),
 }
 
 fn send_polkadot_message(ctx: &mut Context<Block>, to: NodeIndex, message: Message) {
@@ -155,6 +203,14 @@ fn send_polkadot_message(ctx: &mut Context<Block>, to: NodeIndex, message: Messa
 	ctx.send_message(to, generic_message::Message::ChainSpecific(encoded))
 }
 
+// whether the chunk is proven to be at its index in the erasure trie with the given root.
+fn check_erasure_chunk(erasure_root: &Hash, chunk: &ErasureChunk) -> bool {
+	match erasure::branch_hash(erasure_root, &chunk.proof, chunk.index as usize) {
+		Ok(hash) => hash == BlakeTwo256::hash(&chunk.chunk),
+		Err(_) => false,
+	}
+}
+
 /// Polkadot protocol attachment for substrate.
 pub struct PolkadotProtocol {
 	peers: HashMap<NodeIndex, PeerInfo>,
@@ -163,9 +219,15 @@ pub struct PolkadotProtocol {
 	validators: HashMap<SessionKey, NodeIndex>,
 	local_collations: LocalCollations<Collation>,
 	live_consensus: LiveConsensusInstances,
-	in_flight: HashMap<(RequestId, NodeIndex), BlockDataRequest>,
-	pending: Vec<BlockDataRequest>,
+	in_flight: HashMap<(RequestId, NodeIndex), PoVBlockRequest>,
+	pending: Vec<PoVBlockRequest>,
+	in_flight_chunks: HashMap<(RequestId, NodeIndex), ErasureChunkRequest>,
+	pending_chunks: Vec<ErasureChunkRequest>,
+	// received erasure chunks whose candidate receipts haven't been seen yet, by relay parent.
+	deferred_chunks: HashMap<Hash, Vec<ReceivedChunk>>,
 	extrinsic_store: Option<::av_store::Store>,
+	// candidates whose data is being recovered from erasure chunks.
+	recovering: HashSet<Hash>,
 	next_req_id: u64,
 }
 
@@ -181,16 +243,20 @@ impl PolkadotProtocol {
 			live_consensus: LiveConsensusInstances::new(),
 			in_flight: HashMap::new(),
 			pending: Vec::new(),
+			in_flight_chunks: HashMap::new(),
+			pending_chunks: Vec::new(),
+			deferred_chunks: HashMap::new(),
 			extrinsic_store: None,
+			recovering: HashSet::new(),
 			next_req_id: 1,
 		}
 	}
 
-	/// Fetch block data by candidate receipt.
-	fn fetch_block_data(&mut self, ctx: &mut Context<Block>, candidate: &CandidateReceipt, relay_parent: Hash) -> oneshot::Receiver<BlockData> {
+	/// Fetch proof-of-validation block by candidate receipt.
+	fn fetch_pov_block(&mut self, ctx: &mut Context<Block>, candidate: &CandidateReceipt, relay_parent: Hash) -> oneshot::Receiver<PoVBlock> {
 		let (tx, rx) = oneshot::channel();
 
-		self.pending.push(BlockDataRequest {
+		self.pending.push(PoVBlockRequest {
 			attempted_peers: Default::default(),
 			consensus_parent: relay_parent,
 			candidate_hash: candidate.hash(),
@@ -202,6 +268,36 @@ impl PolkadotProtocol {
 		rx
 	}
 
+	/// Fetch an erasure chunk of a candidate from the validator it was sent to.
+	///
+	/// The chunk is checked against the erasure root of the receipt before it is returned. The
+	/// request is sent once the validator is connected and sent again if it reconnects. The
+	/// receiver is dropped if the validator doesn't have the chunk, sends a bad one or doesn't
+	/// serve it in time.
+	pub fn fetch_erasure_chunk(
+		&mut self,
+		ctx: &mut Context<Block>,
+		validator: SessionKey,
+		relay_parent: Hash,
+		candidate: &CandidateReceipt,
+		index: u32,
+	) -> oneshot::Receiver<ErasureChunk> {
+		let (tx, rx) = oneshot::channel();
+
+		self.pending_chunks.push(ErasureChunkRequest {
+			validator,
+			relay_parent,
+			candidate_hash: candidate.hash(),
+			erasure_root: candidate.erasure_root,
+			index,
+			requested_at: Instant::now(),
+			sender: tx,
+		});
+
+		self.dispatch_pending_chunk_requests(ctx);
+		rx
+	}
+
 	/// Note new consensus session.
 	fn new_consensus(
 		&mut self,
@@ -224,6 +320,7 @@ impl PolkadotProtocol {
 
 	fn remove_consensus(&mut self, parent_hash: &Hash) {
 		self.live_consensus.remove(parent_hash);
+		self.deferred_chunks.remove(parent_hash);
 	}
 
 	fn dispatch_pending_requests(&mut self, ctx: &mut Context<Block>) {
@@ -236,7 +333,7 @@ impl PolkadotProtocol {
 			let parent = pending.consensus_parent;
 			let c_hash = pending.candidate_hash;
 
-			let still_pending = self.live_consensus.with_block_data(&parent, &c_hash, |x| match x {
+			let still_pending = self.live_consensus.with_pov_block(&parent, &c_hash, |x| match x {
 				Ok(data @ &_) => {
 					// answer locally.
 					let _ = pending.sender.send(data.clone());
@@ -256,7 +353,7 @@ impl PolkadotProtocol {
 						send_polkadot_message(
 							ctx,
 							who,
-							Message::RequestBlockData(req_id, parent, c_hash)
+							Message::RequestPovBlock(req_id, parent, c_hash)
 						);
 
 						in_flight.insert((req_id, who), pending);
@@ -277,16 +374,50 @@ impl PolkadotProtocol {
 		self.pending = new_pending;
 	}
 
+	fn dispatch_pending_chunk_requests(&mut self, ctx: &mut Context<Block>) {
+		let now = Instant::now();
+		let mut new_pending = Vec::new();
+
+		for pending in ::std::mem::replace(&mut self.pending_chunks, Vec::new()) {
+			// dropping the sender signals the failure to whoever requested the chunk.
+			if pending.expired(now) { continue }
+
+			match self.validators.get(&pending.validator).cloned() {
+				Some(who) => {
+					let req_id = self.next_req_id;
+					self.next_req_id += 1;
+
+					send_polkadot_message(
+						ctx,
+						who,
+						Message::RequestErasureChunk(req_id, pending.relay_parent, pending.candidate_hash, pending.index),
+					);
+
+					self.in_flight_chunks.insert((req_id, who), pending);
+				}
+				None => new_pending.push(pending),
+			}
+		}
+
+		self.pending_chunks = new_pending;
+	}
+
+	// drop erasure chunk requests which weren't served in time.
+	fn prune_chunk_requests(&mut self, now: Instant) {
+		self.in_flight_chunks.retain(|_, req| !req.expired(now));
+		self.pending_chunks.retain(|req| !req.expired(now));
+	}
+
 	fn on_polkadot_message(&mut self, ctx: &mut Context<Block>, who: NodeIndex, msg: Message) {
 		trace!(target: "p_net", "Polkadot message from {}: {:?}", who, msg);
 		match msg {
 			Message::SessionKey(key) => self.on_session_key(ctx, who, key),
 			Message::RequestBlockData(req_id, relay_parent, candidate_hash) => {
 				let block_data = self.live_consensus
-					.with_block_data(
+					.with_pov_block(
 						&relay_parent,
 						&candidate_hash,
-						|res| res.ok().map(|b| b.clone()),
+						|res| res.ok().map(|pov| pov.block_data.clone()),
 					)
 					.or_else(|| self.extrinsic_store.as_ref()
 						.and_then(|s| s.block_data(relay_parent, candidate_hash))
@@ -294,9 +425,29 @@ impl PolkadotProtocol {
 
 				send_polkadot_message(ctx, who, Message::BlockData(req_id, block_data));
 			}
-			Message::BlockData(req_id, data) => self.on_block_data(ctx, who, req_id, data),
+			Message::RequestPovBlock(req_id, relay_parent, candidate_hash) => {
+				let pov_block = self.live_consensus.with_pov_block(
+					&relay_parent,
+					&candidate_hash,
+					|res| res.ok().map(|pov| pov.clone()),
+				);
+
+				send_polkadot_message(ctx, who, Message::PovBlock(req_id, pov_block));
+			}
+			Message::RequestErasureChunk(req_id, relay_parent, candidate_hash, index) => {
+				let chunk = self.extrinsic_store.as_ref()
+					.and_then(|s| s.get_erasure_chunk(relay_parent, candidate_hash, index));
+
+				send_polkadot_message(ctx, who, Message::ErasureChunkResponse(req_id, chunk));
+			}
+			Message::BlockData(_, _) =>
+				ctx.report_peer(who, Severity::Bad("Unexpected block data response")),
+			Message::PovBlock(req_id, data) => self.on_pov_block(ctx, who, req_id, data),
 			Message::Collation(relay_parent, collation) => self.on_collation(ctx, who, relay_parent, collation),
 			Message::CollatorRole(role) => self.on_new_role(ctx, who, role),
+			Message::ErasureChunk(relay_parent, candidate_hash, erasure_root, chunk) =>
+				self.on_erasure_chunk(ctx, who, relay_parent, candidate_hash, erasure_root, chunk),
+			Message::ErasureChunkResponse(req_id, chunk) => self.on_erasure_chunk_response(ctx, who, req_id, chunk),
 		}
 	}
 
@@ -339,13 +490,14 @@ impl PolkadotProtocol {
 		}
 
 		self.dispatch_pending_requests(ctx);
+		self.dispatch_pending_chunk_requests(ctx);
 	}
 
-	fn on_block_data(&mut self, ctx: &mut Context<Block>, who: NodeIndex, req_id: RequestId, data: Option<BlockData>) {
+	fn on_pov_block(&mut self, ctx: &mut Context<Block>, who: NodeIndex, req_id: RequestId, data: Option<PoVBlock>) {
 		match self.in_flight.remove(&(req_id, who)) {
 			Some(req) => {
 				if let Some(data) = data {
-					if data.hash() == req.block_data_hash {
+					if data.block_data.hash() == req.block_data_hash {
 						let _ = req.sender.send(data);
 						return
 					}
@@ -354,7 +506,133 @@ impl PolkadotProtocol {
 				self.pending.push(req);
 				self.dispatch_pending_requests(ctx);
 			}
-			None => ctx.report_peer(who, Severity::Bad("Unexpected block data response")),
+			None => ctx.report_peer(who, Severity::Bad("Unexpected proof-of-validation block response")),
+		}
+	}
+
+	fn on_erasure_chunk_response(
+		&mut self,
+		ctx: &mut Context<Block>,
+		who: NodeIndex,
+		req_id: RequestId,
+		chunk: Option<ErasureChunk>,
+	) {
+		let req = match self.in_flight_chunks.remove(&(req_id, who)) {
+			Some(req) => req,
+			None => {
+				ctx.report_peer(who, Severity::Bad("Unexpected erasure chunk response"));
+				return
+			}
+		};
+
+		let chunk = match chunk {
+			Some(chunk) => chunk,
+			None => return,
+		};
+
+		if chunk.index == req.index && check_erasure_chunk(&req.erasure_root, &chunk) {
+			let _ = req.sender.send(chunk);
+		} else {
+			ctx.report_peer(who, Severity::Bad("Sent erasure chunk with invalid proof"));
+		}
+	}
+
+	// a validator sent us our erasure chunk of a candidate.
+	fn on_erasure_chunk(
+		&mut self,
+		ctx: &mut Context<Block>,
+		who: NodeIndex,
+		relay_parent: Hash,
+		candidate_hash: Hash,
+		erasure_root: Hash,
+		chunk: ErasureChunk,
+	) {
+		let from_validator = self.peers.get(&who)
+			.map_or(false, |info| !info.validator_keys.as_slice().is_empty());
+
+		if !from_validator {
+			ctx.report_peer(who, Severity::Bad("Sent erasure chunk without registering as validator"));
+			return;
+		}
+
+		let local_index = match self.live_consensus.validators(&relay_parent) {
+			Some((_, local_index)) => local_index,
+			None => {
+				trace!(target: "p_net", "Erasure chunk from {} for unknown consensus session {:?}", who, relay_parent);
+				return
+			}
+		};
+
+		if local_index != Some(chunk.index as usize) {
+			ctx.report_peer(who, Severity::Bad("Sent erasure chunk meant for another validator"));
+			return;
+		}
+
+		let received = ReceivedChunk {
+			from: who,
+			candidate_hash,
+			erasure_root,
+			chunk,
+		};
+
+		match self.live_consensus.candidate_receipt(&relay_parent, &candidate_hash) {
+			Some(receipt) => self.import_erasure_chunk(ctx, relay_parent, &receipt, received),
+			None => {
+				// the chunk may overtake the statement carrying the receipt.
+				let n_deferred: usize = self.deferred_chunks.values().map(|d| d.len()).sum();
+				if n_deferred >= MAX_DEFERRED_CHUNKS {
+					trace!(target: "p_net", "Dropping erasure chunk of unknown candidate {:?} from {}", candidate_hash, who);
+					return
+				}
+
+				self.deferred_chunks.entry(relay_parent).or_insert_with(Vec::new).push(received);
+			}
+		}
+	}
+
+	// check an erasure chunk against the erasure root of the candidate receipt and store it.
+	fn import_erasure_chunk(
+		&mut self,
+		ctx: &mut Context<Block>,
+		relay_parent: Hash,
+		receipt: &CandidateReceipt,
+		received: ReceivedChunk,
+	) {
+		if received.erasure_root != receipt.erasure_root {
+			ctx.report_peer(received.from, Severity::Bad("Sent erasure chunk with wrong erasure root"));
+			return;
+		}
+
+		if !check_erasure_chunk(&receipt.erasure_root, &received.chunk) {
+			ctx.report_peer(received.from, Severity::Bad("Sent erasure chunk with invalid proof"));
+			return;
+		}
+
+		if let Some(ref store) = self.extrinsic_store {
+			if let Err(e) = store.add_erasure_chunk(relay_parent, receipt, &received.chunk) {
+				warn!(target: "p_net", "Failed to store erasure chunk: {:?}", e);
+			}
+		}
+	}
+
+	// import deferred erasure chunks whose candidate receipts have been seen since.
+	fn import_deferred_chunks(&mut self, ctx: &mut Context<Block>) {
+		let mut ready = Vec::new();
+		{
+			let live_consensus = &self.live_consensus;
+			for (relay_parent, deferred) in self.deferred_chunks.iter_mut() {
+				for d in ::std::mem::replace(deferred, Vec::new()) {
+					match live_consensus.candidate_receipt(relay_parent, &d.candidate_hash) {
+						Some(receipt) => ready.push((*relay_parent, receipt, d)),
+						None => deferred.push(d),
+					}
+				}
+			}
+		}
+		self.deferred_chunks.retain(|_, deferred| !deferred.is_empty());
+
+		for (relay_parent, receipt, received) in ready {
+			self.import_erasure_chunk(ctx, relay_parent, &receipt, received);
 		}
 	}
 
@@ -473,7 +751,7 @@ impl Specialization<Block> for PolkadotProtocol {
 					let retain = peer != &who;
 					if !retain {
 						let (sender, _) = oneshot::channel();
-						pending.push(::std::mem::replace(val, BlockDataRequest {
+						pending.push(::std::mem::replace(val, PoVBlockRequest {
 							attempted_peers: Default::default(),
 							consensus_parent: Default::default(),
 							candidate_hash: Default::default(),
@@ -485,7 +763,18 @@ impl Specialization<Block> for PolkadotProtocol {
 					retain
 				});
 			}
+			{
+				let in_flight_chunks = ::std::mem::replace(&mut self.in_flight_chunks, HashMap::new());
+				for ((req_id, peer), req) in in_flight_chunks {
+					if peer == who {
+						self.pending_chunks.push(req);
+					} else {
+						self.in_flight_chunks.insert((req_id, peer), req);
+					}
+				}
+			}
 			self.dispatch_pending_requests(ctx);
+			self.dispatch_pending_chunk_requests(ctx);
 		}
 	}
 
@@ -512,6 +801,9 @@ impl Specialization<Block> for PolkadotProtocol {
 		self.collators.collect_garbage(None);
 		self.local_collations.collect_garbage(None);
 		self.dispatch_pending_requests(ctx);
+		self.prune_chunk_requests(Instant::now());
+		self.dispatch_pending_chunk_requests(ctx);
+		self.import_deferred_chunks(ctx);
 
 		for collator_action in self.collators.maintain_peers() {
 			match collator_action {
@@ -613,8 +905,64 @@ impl PolkadotProtocol {
 		}
 	}
 
+	/// Send the erasure chunks of a candidate to the validators of the consensus
+	/// session they are meant for. The chunk of the local validator is stored.
+	fn distribute_erasure_chunks(
+		&mut self,
+		ctx: &mut Context<Block>,
+		relay_parent: Hash,
+		candidate: &CandidateReceipt,
+		chunks: Vec<ErasureChunk>,
+	) {
+		let (validators, local_index) = match self.live_consensus.validators(&relay_parent) {
+			Some(x) => x,
+			None => return,
+		};
+
+		let candidate_hash = candidate.hash();
+		let erasure_root = candidate.erasure_root;
+		for chunk in chunks {
+			let index = chunk.index as usize;
+			if Some(index) == local_index {
+				if let Some(ref store) = self.extrinsic_store {
+					if let Err(e) = store.add_erasure_chunk(relay_parent, candidate, &chunk) {
+						warn!(target: "p_net", "Failed to store erasure chunk: {:?}", e);
+					}
+				}
+				continue
+			}
+
+			let who = validators.get(index).and_then(|key| self.validators.get(key));
+			match who {
+				Some(who) => send_polkadot_message(
+					ctx,
+					*who,
+					Message::ErasureChunk(relay_parent, candidate_hash, erasure_root, chunk),
+				),
+				None => debug!(target: "p_net", "Validator {} not connected to receive erasure chunk", index),
+			}
+		}
+	}
+
 	/// register availability store.
 	pub fn register_availability_store(&mut self, extrinsic_store: ::av_store::Store) {
 		self.extrinsic_store = Some(extrinsic_store);
 	}
+
+	/// Note that recovery of a candidate from erasure chunks is starting. Returns the
+	/// availability store to recover into, or `None` if the candidate is already being
+	/// recovered or there is no store.
+	fn begin_recovery(&mut self, candidate_hash: Hash) -> Option<::av_store::Store> {
+		let store = self.extrinsic_store.clone()?;
+		if self.recovering.insert(candidate_hash) {
+			Some(store)
+		} else {
+			None
+		}
+	}
+
+	/// Note that recovery of a candidate has finished.
+	fn end_recovery(&mut self, candidate_hash: &Hash) {
+		self.recovering.remove(candidate_hash);
+	}
 }
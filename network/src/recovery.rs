@@ -0,0 +1,457 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Recovery of candidate data from erasure chunks.
+//!
+//! When the validators which backed a candidate are unavailable, its block data
+//! can still be reconstructed from the erasure chunks held by the other validators.
+//! Chunks are requested from every validator and checked against the erasure root
+//! of the candidate receipt as they arrive. Once enough of them are present, the
+//! block data and extrinsic are reconstructed and made available locally, along with
+//! the chunk of the local validator.
+//!
+//! Chunk requests which aren't served in time count as failed. Data which doesn't
+//! match the receipt is reconstructed again from the most recent chunks as more arrive.
+
+use polkadot_primitives::{Hash, SessionKey};
+use polkadot_primitives::parachain::{BlockData, CandidateReceipt, ErasureChunk, Extrinsic};
+
+use futures::prelude::*;
+use futures::stream::FuturesUnordered;
+use futures::sync::oneshot;
+
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+
+use super::NetworkService;
+
+/// Errors in recovering candidate data.
+#[derive(Debug)]
+pub enum Error {
+	/// The erasure coding could not be used.
+	Erasure(::erasure::Error),
+	/// Too few validators served their chunk. Contains the validators which failed to.
+	NotEnoughChunks(Vec<SessionKey>),
+	/// The reconstructed block data doesn't match the candidate receipt.
+	BlockDataMismatch,
+}
+
+/// Candidate data recovered from erasure chunks.
+#[derive(Debug)]
+pub struct Recovered {
+	/// The block data of the candidate.
+	pub block_data: BlockData,
+	/// The extrinsic of the candidate.
+	pub extrinsic: Extrinsic,
+	/// Validators which failed to serve a valid chunk before recovery completed.
+	pub failed: Vec<SessionKey>,
+}
+
+/// Recovers candidate data from the erasure chunks held by validators.
+#[derive(Clone)]
+pub struct AvailabilityRecovery {
+	network: Arc<NetworkService>,
+	store: ::av_store::Store,
+}
+
+impl AvailabilityRecovery {
+	/// Create a new recovery service. Recovered data is made available in the given store.
+	pub fn new(network: Arc<NetworkService>, store: ::av_store::Store) -> Self {
+		AvailabilityRecovery { network, store }
+	}
+
+	/// Recover the data of a candidate included at the given relay parent.
+	///
+	/// `validators` are the validators of the relay parent, in the order they were
+	/// assigned chunks, and `local_index` is the index of the local validator among them.
+	/// The returned future should be spawned in the background; it resolves once recovery
+	/// has succeeded or every validator has failed.
+	pub fn recover(
+		&self,
+		relay_parent: Hash,
+		receipt: &CandidateReceipt,
+		validators: &[SessionKey],
+		local_index: Option<usize>,
+	) -> Recovery {
+		let candidate_hash = receipt.hash();
+
+		// chunks are stored under the candidate hash, which commits to the erasure root.
+		let local_chunks = self.store.erasure_chunks(relay_parent, candidate_hash);
+		let held: HashSet<_> = local_chunks.iter().map(|c| c.index as usize).collect();
+
+		let requests: Vec<_> = self.network.with_spec(|spec, ctx| validators.iter()
+			.enumerate()
+			.filter(|&(index, _)| !held.contains(&index))
+			.map(|(index, validator)| {
+				let rx = spec.fetch_erasure_chunk(
+					ctx,
+					*validator,
+					relay_parent,
+					receipt,
+					index as u32,
+				);
+				(*validator, rx)
+			})
+			.collect()
+		);
+
+		Recovery::new(
+			self.store.clone(),
+			relay_parent,
+			receipt,
+			validators.len(),
+			local_index,
+			local_chunks,
+			requests,
+		)
+	}
+}
+
+type ChunkResponse = Box<Future<Item=(SessionKey, Option<ErasureChunk>), Error=()> + Send>;
+
+/// A future which resolves to the data of a candidate recovered from erasure chunks.
+pub struct Recovery {
+	store: ::av_store::Store,
+	relay_parent: Hash,
+	candidate_hash: Hash,
+	receipt: CandidateReceipt,
+	n_validators: usize,
+	local_index: Option<usize>,
+	chunks: Vec<ErasureChunk>,
+	// the number of chunks the last failed reconstruction was attempted with.
+	attempted_with: usize,
+	last_error: Option<Error>,
+	failed: Vec<SessionKey>,
+	responses: FuturesUnordered<ChunkResponse>,
+}
+
+impl Recovery {
+	fn new(
+		store: ::av_store::Store,
+		relay_parent: Hash,
+		receipt: &CandidateReceipt,
+		n_validators: usize,
+		local_index: Option<usize>,
+		local_chunks: Vec<ErasureChunk>,
+		requests: Vec<(SessionKey, oneshot::Receiver<ErasureChunk>)>,
+	) -> Self {
+		let responses = requests.into_iter()
+			.map(|(validator, rx)| -> ChunkResponse {
+				// a dropped sender means the validator failed to serve a valid chunk.
+				Box::new(rx.then(move |res| Ok((validator, res.ok()))))
+			})
+			.collect();
+
+		Recovery {
+			store,
+			relay_parent,
+			candidate_hash: receipt.hash(),
+			receipt: receipt.clone(),
+			n_validators,
+			local_index,
+			chunks: local_chunks,
+			attempted_with: 0,
+			last_error: None,
+			failed: Vec::new(),
+			responses,
+		}
+	}
+
+	// reconstruct from the most recent `threshold` chunks.
+	fn reconstruct(&mut self, threshold: usize) -> Result<Recovered, Error> {
+		let (block_data, extrinsic) = {
+			let recent = &self.chunks[self.chunks.len() - threshold..];
+			::erasure::reconstruct(
+				self.n_validators,
+				recent.iter().map(|c| (&c.chunk[..], c.index as usize)),
+			).map_err(Error::Erasure)?
+		};
+
+		if block_data.hash() != self.receipt.block_data_hash {
+			return Err(Error::BlockDataMismatch);
+		}
+
+		let made_available = self.store.make_available(::av_store::Data {
+			relay_parent: self.relay_parent,
+			parachain_id: self.receipt.parachain_index,
+			candidate_hash: self.candidate_hash,
+			block_data: block_data.clone(),
+			extrinsic: Some(extrinsic.clone()),
+		});
+
+		if let Err(e) = made_available {
+			warn!(target: "p_net", "Failed to store recovered data of candidate {:?}: {:?}", self.candidate_hash, e);
+		}
+
+		if let Some(local_index) = self.local_index {
+			self.store_local_chunk(local_index, &block_data, &extrinsic);
+		}
+
+		Ok(Recovered {
+			block_data,
+			extrinsic,
+			failed: mem::replace(&mut self.failed, Vec::new()),
+		})
+	}
+
+	// erasure-code the recovered data again to obtain the chunk of the local validator.
+	fn store_local_chunk(&self, local_index: usize, block_data: &BlockData, extrinsic: &Extrinsic) {
+		let chunks = match ::erasure::obtain_chunks(self.n_validators, block_data, extrinsic) {
+			Ok(chunks) => chunks,
+			Err(e) => {
+				warn!(target: "p_net", "Failed to erasure-code recovered data of {:?}: {:?}", self.candidate_hash, e);
+				return
+			}
+		};
+
+		let branches = ::erasure::branches(chunks.iter().map(|c| &c[..]).collect());
+		if branches.root() != self.receipt.erasure_root {
+			warn!(target: "p_net", "Recovered data of {:?} doesn't match its erasure root", self.candidate_hash);
+			return
+		}
+
+		let local_chunk = branches.enumerate()
+			.nth(local_index)
+			.map(|(index, (proof, chunk))| ErasureChunk { chunk: chunk.to_vec(), index: index as u32, proof });
+
+		if let Some(chunk) = local_chunk {
+			let res = self.store.add_erasure_chunk(self.relay_parent, &self.receipt, &chunk);
+			if let Err(e) = res {
+				warn!(target: "p_net", "Failed to store recovered erasure chunk of {:?}: {:?}", self.candidate_hash, e);
+			}
+		}
+	}
+}
+
+impl Future for Recovery {
+	type Item = Recovered;
+	type Error = Error;
+
+	fn poll(&mut self) -> Poll<Recovered, Error> {
+		let threshold = ::erasure::recovery_threshold(self.n_validators).map_err(Error::Erasure)?;
+
+		loop {
+			if self.chunks.len() >= threshold && self.chunks.len() > self.attempted_with {
+				match self.reconstruct(threshold) {
+					Ok(recovered) => return Ok(Async::Ready(recovered)),
+					Err(e) => {
+						debug!(target: "p_net", "Failed to reconstruct {:?} from {} chunks: {:?}",
+							self.candidate_hash, self.chunks.len(), e);
+						self.attempted_with = self.chunks.len();
+						self.last_error = Some(e);
+					}
+				}
+			}
+
+			match self.responses.poll() {
+				Ok(Async::Ready(Some((_, Some(chunk))))) => self.chunks.push(chunk),
+				Ok(Async::Ready(Some((validator, None)))) => {
+					debug!(target: "p_net", "Validator {:?} failed to serve erasure chunk of {:?}",
+						validator, self.candidate_hash);
+					self.failed.push(validator);
+				}
+				Ok(Async::Ready(None)) => return Err(match self.last_error.take() {
+					Some(e) => e,
+					None => Error::NotEnoughChunks(mem::replace(&mut self.failed, Vec::new())),
+				}),
+				Ok(Async::NotReady) => return Ok(Async::NotReady),
+				Err(()) => unreachable!("chunk responses never fail; failures are mapped to `None`; qed"),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_primitives::parachain::HeadData;
+	use substrate_primitives::H512;
+
+	type Requests = Vec<(SessionKey, oneshot::Receiver<ErasureChunk>)>;
+
+	struct TestCandidate {
+		store: ::av_store::Store,
+		relay_parent: Hash,
+		block_data: BlockData,
+		receipt: CandidateReceipt,
+		chunks: Vec<ErasureChunk>,
+		validators: Vec<SessionKey>,
+	}
+
+	impl TestCandidate {
+		// a request of each validator's chunk, answered through the returned senders.
+		fn requests(&self) -> (Vec<oneshot::Sender<ErasureChunk>>, Requests) {
+			self.validators.iter()
+				.map(|v| {
+					let (tx, rx) = oneshot::channel();
+					(tx, (*v, rx))
+				})
+				.unzip()
+		}
+	}
+
+	fn make_receipt(block_data: &BlockData, erasure_root: Hash) -> CandidateReceipt {
+		CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [255; 32].into(),
+			head_data: HeadData(vec![9, 9, 9]),
+			signature: H512::from([1; 64]).into(),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: block_data.hash(),
+			new_validation_code_hash: None,
+			erasure_root,
+		}
+	}
+
+	// a candidate whose data is erasure-coded into the given chunks of its block data.
+	fn make_candidate_with_chunks(block_data: BlockData, raw_chunks: Vec<Vec<u8>>) -> TestCandidate {
+		let branches = ::erasure::branches(raw_chunks.iter().map(|c| &c[..]).collect());
+		let receipt = make_receipt(&block_data, branches.root());
+		let chunks = branches.enumerate()
+			.map(|(index, (proof, chunk))| ErasureChunk { chunk: chunk.to_vec(), index: index as u32, proof })
+			.collect();
+
+		TestCandidate {
+			store: ::av_store::Store::new_in_memory(),
+			relay_parent: [1; 32].into(),
+			block_data,
+			receipt,
+			chunks,
+			validators: (0..raw_chunks.len()).map(|i| [i as u8; 32].into()).collect(),
+		}
+	}
+
+	fn make_candidate(n_validators: usize) -> TestCandidate {
+		let block_data = BlockData(vec![1, 2, 3, 4, 5]);
+		let raw_chunks = ::erasure::obtain_chunks(
+			n_validators,
+			&block_data,
+			&Extrinsic { outgoing_messages: Vec::new() },
+		).unwrap();
+
+		make_candidate_with_chunks(block_data, raw_chunks)
+	}
+
+	#[test]
+	fn recovers_from_threshold_of_chunks() {
+		let candidate = make_candidate(10);
+		let (senders, requests) = candidate.requests();
+		let TestCandidate { store, relay_parent, block_data, receipt, chunks, validators } = candidate;
+
+		let recovery = Recovery::new(store.clone(), relay_parent, &receipt, 10, Some(0), Vec::new(), requests);
+
+		// validators 0 and 1 fail, 2 to 5 serve their chunks and the rest don't answer.
+		let mut senders = senders.into_iter();
+		drop(senders.by_ref().take(2).collect::<Vec<_>>());
+		for (tx, chunk) in senders.by_ref().take(4).zip(&chunks[2..6]) {
+			tx.send(chunk.clone()).unwrap();
+		}
+		let _unanswered: Vec<_> = senders.collect();
+
+		let recovered = recovery.wait().unwrap();
+		assert_eq!(recovered.block_data, block_data);
+		assert_eq!(recovered.failed, vec![validators[0], validators[1]]);
+		assert_eq!(store.block_data(relay_parent, receipt.hash()), Some(block_data));
+
+		// the local validator gets back the chunk it failed to serve.
+		assert_eq!(store.get_erasure_chunk(relay_parent, receipt.hash(), 0), Some(chunks[0].clone()));
+	}
+
+	#[test]
+	fn retries_reconstruction_with_more_chunks() {
+		let block_data = BlockData(vec![1, 2, 3, 4, 5]);
+		let extrinsic = Extrinsic { outgoing_messages: Vec::new() };
+
+		// the first chunk is swapped for one of other data, so the erasure root
+		// commits to chunks which don't all encode the same data.
+		let mut raw_chunks = ::erasure::obtain_chunks(4, &block_data, &extrinsic).unwrap();
+		raw_chunks[0] = ::erasure::obtain_chunks(4, &BlockData(vec![5, 4, 3, 2, 1]), &extrinsic).unwrap()
+			.swap_remove(0);
+
+		let candidate = make_candidate_with_chunks(block_data, raw_chunks);
+		let (senders, requests) = candidate.requests();
+
+		let recovery = Recovery::new(
+			candidate.store.clone(),
+			candidate.relay_parent,
+			&candidate.receipt,
+			4,
+			None,
+			Vec::new(),
+			requests,
+		);
+
+		// the data reconstructed from the first two chunks doesn't match, the
+		// data reconstructed from the next two does.
+		for (tx, chunk) in senders.into_iter().zip(&candidate.chunks[..3]) {
+			tx.send(chunk.clone()).unwrap();
+		}
+
+		let recovered = recovery.wait().unwrap();
+		assert_eq!(recovered.block_data, candidate.block_data);
+	}
+
+	#[test]
+	fn uses_local_chunk() {
+		let candidate = make_candidate(4);
+		let (senders, requests) = candidate.requests();
+
+		// we have our own chunk, but only one other validator serves theirs.
+		let recovery = Recovery::new(
+			candidate.store.clone(),
+			candidate.relay_parent,
+			&candidate.receipt,
+			4,
+			None,
+			vec![candidate.chunks[3].clone()],
+			requests.into_iter().take(3).collect(),
+		);
+
+		let mut senders = senders.into_iter();
+		senders.next().unwrap().send(candidate.chunks[0].clone()).unwrap();
+		drop(senders);
+
+		// two chunks are required to recover with 4 validators.
+		let recovered = recovery.wait().unwrap();
+		assert_eq!(recovered.block_data, candidate.block_data);
+	}
+
+	#[test]
+	fn fails_without_enough_chunks() {
+		let candidate = make_candidate(4);
+
+		// all senders are dropped straight away.
+		let (_, requests) = candidate.requests();
+
+		let recovery = Recovery::new(
+			candidate.store.clone(),
+			candidate.relay_parent,
+			&candidate.receipt,
+			4,
+			None,
+			Vec::new(),
+			requests,
+		);
+
+		match recovery.wait() {
+			Err(Error::NotEnoughChunks(failed)) => assert_eq!(failed, candidate.validators),
+			other => panic!("unexpected result: {:?}", other.map(|r| r.block_data)),
+		}
+	}
+}
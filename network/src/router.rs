@@ -25,7 +25,9 @@
 use sr_primitives::traits::{ProvideRuntimeApi, BlakeTwo256, Hash as HashT};
 use polkadot_consensus::{SharedTable, TableRouter, SignedStatement, GenericStatement, ParachainWork};
 use polkadot_primitives::{Block, Hash, SessionKey};
-use polkadot_primitives::parachain::{BlockData, Extrinsic, CandidateReceipt, ParachainHost};
+use polkadot_primitives::parachain::{
+	Extrinsic, CandidateReceipt, ParachainHost, PoVBlock, ErasureChunk, SignedActivity,
+};
 
 use codec::Encode;
 use futures::prelude::*;
@@ -37,6 +39,7 @@ use std::io;
 use std::sync::Arc;
 
 use consensus::Knowledge;
+use recovery::AvailabilityRecovery;
 use super::NetworkService;
 
 fn attestation_topic(parent_hash: Hash) -> Hash {
@@ -46,6 +49,13 @@ fn attestation_topic(parent_hash: Hash) -> Hash {
 	BlakeTwo256::hash(&v[..])
 }
 
+fn availability_topic(parent_hash: Hash) -> Hash {
+	let mut v = parent_hash.as_ref().to_vec();
+	v.extend(b"availability");
+
+	BlakeTwo256::hash(&v[..])
+}
+
 /// Table routing implementation.
 pub struct Router<P> {
 	table: Arc<SharedTable>,
@@ -54,6 +64,7 @@ pub struct Router<P> {
 	task_executor: TaskExecutor,
 	parent_hash: Hash,
 	attestation_topic: Hash,
+	availability_topic: Hash,
 	knowledge: Arc<Mutex<Knowledge>>,
 	deferred_statements: Arc<Mutex<DeferredStatements>>,
 }
@@ -74,6 +85,7 @@ impl<P> Router<P> {
 			task_executor,
 			parent_hash,
 			attestation_topic: attestation_topic(parent_hash),
+			availability_topic: availability_topic(parent_hash),
 			knowledge,
 			deferred_statements: Arc::new(Mutex::new(DeferredStatements::new())),
 		}
@@ -83,6 +95,11 @@ impl<P> Router<P> {
 	pub(crate) fn gossip_topic(&self) -> Hash {
 		self.attestation_topic
 	}
+
+	/// Get the availability bit field topic for gossip.
+	pub(crate) fn availability_topic(&self) -> Hash {
+		self.availability_topic
+	}
 }
 
 impl<P> Clone for Router<P> {
@@ -94,6 +111,7 @@ impl<P> Clone for Router<P> {
 			task_executor: self.task_executor.clone(),
 			parent_hash: self.parent_hash.clone(),
 			attestation_topic: self.attestation_topic.clone(),
+			availability_topic: self.availability_topic.clone(),
 			deferred_statements: self.deferred_statements.clone(),
 			knowledge: self.knowledge.clone(),
 		}
@@ -154,30 +172,44 @@ impl<P: ProvideRuntimeApi + Send + Sync + 'static> Router<P>
 	fn create_work<D>(&self, candidate_hash: Hash, producer: ParachainWork<D>)
 		-> impl Future<Item=(),Error=()>
 		where
-		D: Future<Item=BlockData,Error=io::Error> + Send + 'static,
+		D: Future<Item=PoVBlock,Error=io::Error> + Send + 'static,
 	{
 		let table = self.table.clone();
 		let network = self.network.clone();
 		let knowledge = self.knowledge.clone();
 		let attestation_topic = self.attestation_topic.clone();
+		let parent_hash = self.parent_hash;
+		let candidate = producer.candidate_receipt().clone();
 
 		producer.prime(self.api.clone())
 			.map(move |produced| {
 				// store the data before broadcasting statements, so other peers can fetch.
 				knowledge.lock().note_candidate(
 					candidate_hash,
-					Some(produced.block_data),
+					Some(produced.pov_block),
 					produced.extrinsic,
 				);
 
+				// a valid candidate stays available even if its proposer goes offline.
+				if !produced.erasure_chunks.is_empty() {
+					let chunks = produced.erasure_chunks;
+					network.with_spec(|spec, ctx| spec.distribute_erasure_chunks(
+						ctx,
+						parent_hash,
+						&candidate,
+						chunks,
+					));
+				}
+
 				let mut gossip = network.consensus_gossip().write();
 
 				// propagate the statement.
 				// consider something more targeted than gossip in the future.
-				let signed = table.sign_and_import(produced.validity);
-				network.with_spec(|_, ctx|
-					gossip.multicast(ctx, attestation_topic, signed.encode(), false)
-				);
+				if let Some(signed) = produced.validity.and_then(|v| table.sign_and_import(v)) {
+					network.with_spec(|_, ctx|
+						gossip.multicast(ctx, attestation_topic, signed.encode(), false)
+					);
+				}
 			})
 			.map_err(|e| debug!(target: "p_net", "Failed to produce statements: {:?}", e))
 	}
@@ -187,24 +219,72 @@ impl<P: ProvideRuntimeApi + Send> TableRouter for Router<P>
 	where P::Api: ParachainHost<Block>
 {
 	type Error = io::Error;
-	type FetchCandidate = BlockDataReceiver;
+	type FetchValidationProof = PoVReceiver;
 
-	fn local_candidate(&self, receipt: CandidateReceipt, block_data: BlockData, extrinsic: Extrinsic) {
+	fn local_candidate(&self, receipt: CandidateReceipt, pov_block: PoVBlock, extrinsic: Extrinsic) {
 		// give to network to make available.
 		let hash = receipt.hash();
-		let candidate = self.table.sign_and_import(GenericStatement::Candidate(receipt));
+		let candidate = match self.table.sign_and_import(GenericStatement::Candidate(receipt)) {
+			Some(candidate) => candidate,
+			None => return,
+		};
+
+		{
+			let mut knowledge = self.knowledge.lock();
+			knowledge.note_statement(candidate.sender, &candidate.statement);
+			knowledge.note_candidate(hash, Some(pov_block), Some(extrinsic));
+		}
 
-		self.knowledge.lock().note_candidate(hash, Some(block_data), Some(extrinsic));
 		let mut gossip = self.network.consensus_gossip().write();
 		self.network.with_spec(|_spec, ctx| {
 			gossip.multicast(ctx, self.attestation_topic, candidate.encode(), false);
 		});
 	}
 
-	fn fetch_block_data(&self, candidate: &CandidateReceipt) -> BlockDataReceiver {
+	fn distribute_erasure_chunks(&self, candidate: &CandidateReceipt, chunks: Vec<ErasureChunk>) {
+		let parent_hash = self.parent_hash;
+		self.network.with_spec(|spec, ctx| {
+			spec.distribute_erasure_chunks(ctx, parent_hash, candidate, chunks)
+		});
+	}
+
+	fn fetch_pov_block(&self, candidate: &CandidateReceipt) -> PoVReceiver {
 		let parent_hash = self.parent_hash;
-		let rx = self.network.with_spec(|spec, ctx| { spec.fetch_block_data(ctx, candidate, parent_hash) });
-		BlockDataReceiver { inner: rx }
+		let rx = self.network.with_spec(|spec, ctx| { spec.fetch_pov_block(ctx, candidate, parent_hash) });
+		PoVReceiver { inner: rx }
+	}
+
+	fn local_availability(&self, availability: SignedActivity) {
+		let mut gossip = self.network.consensus_gossip().write();
+		self.network.with_spec(|_spec, ctx| {
+			gossip.multicast(ctx, self.availability_topic, availability.encode(), false);
+		});
+	}
+
+	fn recover_candidate(&self, relay_parent: Hash, candidate: CandidateReceipt, validators: Vec<SessionKey>) {
+		let candidate_hash = candidate.hash();
+
+		// at most one recovery per candidate, and only where data can be made available.
+		let store = match self.network.with_spec(|spec, _| spec.begin_recovery(candidate_hash)) {
+			Some(store) => store,
+			None => return,
+		};
+
+		let local_index = validators.iter().position(|v| v == &self.table.session_key());
+		let recovery = AvailabilityRecovery::new(self.network.clone(), store)
+			.recover(relay_parent, &candidate, &validators, local_index);
+
+		let network = self.network.clone();
+		self.task_executor.spawn(recovery.then(move |res| {
+			match res {
+				Ok(recovered) => debug!(target: "p_net", "Recovered candidate {:?}; validators which failed: {:?}",
+					candidate_hash, recovered.failed),
+				Err(e) => warn!(target: "p_net", "Failed to recover candidate {:?}: {:?}", candidate_hash, e),
+			}
+
+			network.with_spec(|spec, _| spec.end_recovery(&candidate_hash));
+			Ok(())
+		}));
 	}
 }
 
@@ -215,16 +295,16 @@ impl<P> Drop for Router<P> {
 	}
 }
 
-/// Receiver for block data.
-pub struct BlockDataReceiver {
-	inner: ::futures::sync::oneshot::Receiver<BlockData>,
+/// Receiver for proof-of-validation blocks.
+pub struct PoVReceiver {
+	inner: ::futures::sync::oneshot::Receiver<PoVBlock>,
 }
 
-impl Future for BlockDataReceiver {
-	type Item = BlockData;
+impl Future for PoVReceiver {
+	type Item = PoVBlock;
 	type Error = io::Error;
 
-	fn poll(&mut self) -> Poll<BlockData, io::Error> {
+	fn poll(&mut self) -> Poll<PoVBlock, io::Error> {
 		self.inner.poll().map_err(|_| io::Error::new(
 			io::ErrorKind::Other,
 			"Sending end of channel hung up",
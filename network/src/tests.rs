@@ -16,13 +16,15 @@
 
 //! Tests for polkadot and consensus network.
 
-use super::{PolkadotProtocol, Status, Message, FullStatus};
+use super::{PolkadotProtocol, Status, Message, FullStatus, ERASURE_CHUNK_REQUEST_TIMEOUT};
 use consensus::{CurrentConsensus, Knowledge};
 
 use parking_lot::Mutex;
 use polkadot_consensus::GenericStatement;
 use polkadot_primitives::{Block, SessionKey};
-use polkadot_primitives::parachain::{CandidateReceipt, HeadData, BlockData};
+use polkadot_primitives::parachain::{
+	CandidateReceipt, HeadData, BlockData, PoVBlock, ConsolidatedIngress, Extrinsic, ErasureChunk,
+};
 use substrate_primitives::H512;
 use codec::Encode;
 use substrate_network::{
@@ -32,6 +34,7 @@ use substrate_network::{
 };
 
 use std::sync::Arc;
+use std::time::Instant;
 use futures::Future;
 
 #[derive(Default)]
@@ -87,11 +90,40 @@ fn make_status(status: &Status, roles: Roles) -> FullStatus {
 
 fn make_consensus(local_key: SessionKey) -> (CurrentConsensus, Arc<Mutex<Knowledge>>) {
 	let knowledge = Arc::new(Mutex::new(Knowledge::new()));
-	let c = CurrentConsensus::new(knowledge.clone(), local_key);
+	let c = CurrentConsensus::new(knowledge.clone(), local_key, vec![local_key]);
 
 	(c, knowledge)
 }
 
+// a candidate receipt committing to the erasure chunks of its data among `n_validators`.
+fn make_chunked_candidate(block_data: BlockData, n_validators: usize) -> (CandidateReceipt, Vec<ErasureChunk>) {
+	let raw_chunks = ::erasure::obtain_chunks(
+		n_validators,
+		&block_data,
+		&Extrinsic { outgoing_messages: Vec::new() },
+	).unwrap();
+	let branches = ::erasure::branches(raw_chunks.iter().map(|c| &c[..]).collect());
+	let erasure_root = branches.root();
+	let chunks = branches.enumerate()
+		.map(|(index, (proof, chunk))| ErasureChunk { chunk: chunk.to_vec(), index: index as u32, proof })
+		.collect();
+
+	let receipt = CandidateReceipt {
+		parachain_index: 5.into(),
+		collator: [255; 32].into(),
+		head_data: HeadData(vec![9, 9, 9]),
+		signature: H512::from([1; 64]).into(),
+		balance_uploads: Vec::new(),
+		egress_queue_roots: Vec::new(),
+		fees: 1_000_000,
+		block_data_hash: block_data.hash(),
+		new_validation_code_hash: None,
+		erasure_root,
+	};
+
+	(receipt, chunks)
+}
+
 fn on_message(protocol: &mut PolkadotProtocol, ctx: &mut TestContext, from: NodeIndex, message: Message) {
 	let encoded = message.encode();
 	protocol.on_message(ctx, from, &mut Some(GenericMessage::ChainSpecific(encoded)));
@@ -138,8 +170,11 @@ fn fetches_from_those_with_knowledge() {
 	let parent_hash = [0; 32].into();
 	let local_key = [1; 32].into();
 
-	let block_data = BlockData(vec![1, 2, 3, 4]);
-	let block_data_hash = block_data.hash();
+	let pov_block = PoVBlock {
+		block_data: BlockData(vec![1, 2, 3, 4]),
+		ingress: ConsolidatedIngress(Vec::new()),
+	};
+	let block_data_hash = pov_block.block_data.hash();
 	let candidate_receipt = CandidateReceipt {
 		parachain_index: 5.into(),
 		collator: [255; 32].into(),
@@ -149,6 +184,8 @@ fn fetches_from_those_with_knowledge() {
 		egress_queue_roots: Vec::new(),
 		fees: 1_000_000,
 		block_data_hash,
+		new_validation_code_hash: None,
+		erasure_root: Default::default(),
 	};
 
 	let candidate_hash = candidate_receipt.hash();
@@ -161,7 +198,7 @@ fn fetches_from_those_with_knowledge() {
 	protocol.new_consensus(&mut TestContext::default(), parent_hash, consensus);
 
 	knowledge.lock().note_statement(a_key, &GenericStatement::Valid(candidate_hash));
-	let recv = protocol.fetch_block_data(&mut TestContext::default(), &candidate_receipt, parent_hash);
+	let recv = protocol.fetch_pov_block(&mut TestContext::default(), &candidate_receipt, parent_hash);
 
 	// connect peer A
 	{
@@ -175,7 +212,7 @@ fn fetches_from_those_with_knowledge() {
 		let mut ctx = TestContext::default();
 		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
 		assert!(protocol.validators.contains_key(&a_key));
-		assert!(ctx.has_message(peer_a, Message::RequestBlockData(1, parent_hash, candidate_hash)));
+		assert!(ctx.has_message(peer_a, Message::RequestPovBlock(1, parent_hash, candidate_hash)));
 	}
 
 	knowledge.lock().note_statement(b_key, &GenericStatement::Valid(candidate_hash));
@@ -185,7 +222,7 @@ fn fetches_from_those_with_knowledge() {
 		let mut ctx = TestContext::default();
 		protocol.on_connect(&mut ctx, peer_b, make_status(&status, Roles::AUTHORITY));
 		on_message(&mut protocol, &mut ctx, peer_b, Message::SessionKey(b_key));
-		assert!(!ctx.has_message(peer_b, Message::RequestBlockData(2, parent_hash, candidate_hash)));
+		assert!(!ctx.has_message(peer_b, Message::RequestPovBlock(2, parent_hash, candidate_hash)));
 
 	}
 
@@ -194,15 +231,15 @@ fn fetches_from_those_with_knowledge() {
 		let mut ctx = TestContext::default();
 		protocol.on_disconnect(&mut ctx, peer_a);
 		assert!(!protocol.validators.contains_key(&a_key));
-		assert!(ctx.has_message(peer_b, Message::RequestBlockData(2, parent_hash, candidate_hash)));
+		assert!(ctx.has_message(peer_b, Message::RequestPovBlock(2, parent_hash, candidate_hash)));
 	}
 
-	// peer B comes back with block data.
+	// peer B comes back with the proof-of-validation block.
 	{
 		let mut ctx = TestContext::default();
-		on_message(&mut protocol, &mut ctx, peer_b, Message::BlockData(2, Some(block_data.clone())));
+		on_message(&mut protocol, &mut ctx, peer_b, Message::PovBlock(2, Some(pov_block.clone())));
 		drop(protocol);
-		assert_eq!(recv.wait().unwrap(), block_data);
+		assert_eq!(recv.wait().unwrap(), pov_block);
 	}
 }
 
@@ -225,6 +262,8 @@ fn fetches_available_block_data() {
 		egress_queue_roots: Vec::new(),
 		fees: 1_000_000,
 		block_data_hash,
+		new_validation_code_hash: None,
+		erasure_root: Default::default(),
 	};
 
 	let candidate_hash = candidate_receipt.hash();
@@ -256,6 +295,248 @@ fn fetches_available_block_data() {
 	}
 }
 
+#[test]
+fn stores_valid_erasure_chunks() {
+	let mut protocol = PolkadotProtocol::new(None);
+	let av_store = ::av_store::Store::new_in_memory();
+	protocol.register_availability_store(av_store.clone());
+
+	let peer_a = 1;
+	let parent_hash = [0; 32].into();
+	let local_key = [1; 32].into();
+	let a_key = [3; 32].into();
+	let validators = vec![a_key, local_key, [4; 32].into(), [5; 32].into()];
+
+	let knowledge = Arc::new(Mutex::new(Knowledge::new()));
+	let consensus = CurrentConsensus::new(knowledge.clone(), local_key, validators.clone());
+	protocol.new_consensus(&mut TestContext::default(), parent_hash, consensus);
+
+	let (receipt, chunks) = make_chunked_candidate(BlockData(vec![1, 2, 3, 4]), validators.len());
+	let candidate_hash = receipt.hash();
+	let erasure_root = receipt.erasure_root;
+
+	// connect peer A as a validator.
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&Status { collating_for: None }, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+	}
+
+	// a chunk meant for another validator is rejected.
+	{
+		let mut ctx = TestContext::default();
+		let msg = Message::ErasureChunk(parent_hash, candidate_hash, erasure_root, chunks[2].clone());
+		on_message(&mut protocol, &mut ctx, peer_a, msg);
+		assert!(ctx.disabled.contains(&peer_a));
+	}
+
+	// a chunk of a candidate whose receipt is unknown is kept until the receipt is seen.
+	{
+		let mut ctx = TestContext::default();
+		let msg = Message::ErasureChunk(parent_hash, candidate_hash, erasure_root, chunks[1].clone());
+		on_message(&mut protocol, &mut ctx, peer_a, msg);
+		assert!(ctx.disabled.is_empty());
+		assert!(av_store.get_erasure_chunk(parent_hash, candidate_hash, 1).is_none());
+
+		knowledge.lock().note_statement(a_key, &GenericStatement::Candidate(receipt.clone()));
+		protocol.maintain_peers(&mut ctx);
+		assert!(ctx.disabled.is_empty());
+		assert_eq!(av_store.get_erasure_chunk(parent_hash, candidate_hash, 1), Some(chunks[1].clone()));
+	}
+
+	// a chunk which doesn't match its proof is rejected.
+	{
+		let mut ctx = TestContext::default();
+		let mut bad_chunk = chunks[1].clone();
+		bad_chunk.chunk[0] ^= 1;
+
+		let msg = Message::ErasureChunk(parent_hash, candidate_hash, erasure_root, bad_chunk);
+		on_message(&mut protocol, &mut ctx, peer_a, msg);
+		assert!(ctx.disabled.contains(&peer_a));
+		assert_eq!(av_store.get_erasure_chunk(parent_hash, candidate_hash, 1), Some(chunks[1].clone()));
+	}
+
+	// a chunk proven against a root other than the receipt's is rejected.
+	{
+		let mut ctx = TestContext::default();
+		let (other_receipt, other_chunks) = make_chunked_candidate(BlockData(vec![5, 6, 7]), validators.len());
+		let other_root = other_receipt.erasure_root;
+		let other_chunk = other_chunks[1].clone();
+
+		let msg = Message::ErasureChunk(parent_hash, candidate_hash, other_root, other_chunk);
+		on_message(&mut protocol, &mut ctx, peer_a, msg);
+		assert!(ctx.disabled.contains(&peer_a));
+		assert_eq!(av_store.get_erasure_chunk(parent_hash, candidate_hash, 1), Some(chunks[1].clone()));
+	}
+}
+
+#[test]
+fn distributes_erasure_chunks() {
+	let mut protocol = PolkadotProtocol::new(None);
+	let av_store = ::av_store::Store::new_in_memory();
+	protocol.register_availability_store(av_store.clone());
+
+	let peer_a = 1;
+	let parent_hash = [0; 32].into();
+	let local_key = [1; 32].into();
+	let a_key = [3; 32].into();
+	let validators = vec![a_key, local_key, [4; 32].into(), [5; 32].into()];
+
+	let knowledge = Arc::new(Mutex::new(Knowledge::new()));
+	let consensus = CurrentConsensus::new(knowledge, local_key, validators.clone());
+	protocol.new_consensus(&mut TestContext::default(), parent_hash, consensus);
+
+	let (receipt, chunks) = make_chunked_candidate(BlockData(vec![1, 2, 3, 4]), validators.len());
+	let candidate_hash = receipt.hash();
+	let erasure_root = receipt.erasure_root;
+
+	// connect peer A as a validator.
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&Status { collating_for: None }, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+	}
+
+	// peer A gets its chunk and ours is stored locally.
+	let mut ctx = TestContext::default();
+	protocol.distribute_erasure_chunks(&mut ctx, parent_hash, &receipt, chunks.clone());
+	assert!(ctx.has_message(peer_a, Message::ErasureChunk(parent_hash, candidate_hash, erasure_root, chunks[0].clone())));
+	assert_eq!(ctx.messages.len(), 1);
+	assert_eq!(av_store.get_erasure_chunk(parent_hash, candidate_hash, 1), Some(chunks[1].clone()));
+}
+
+#[test]
+fn fetches_and_serves_erasure_chunks() {
+	let mut protocol = PolkadotProtocol::new(None);
+	let av_store = ::av_store::Store::new_in_memory();
+	protocol.register_availability_store(av_store.clone());
+
+	let peer_a = 1;
+	let parent_hash = [0; 32].into();
+	let a_key = [3; 32].into();
+
+	let (receipt, chunks) = make_chunked_candidate(BlockData(vec![1, 2, 3, 4]), 4);
+	let candidate_hash = receipt.hash();
+
+	// connect peer A as a validator.
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&Status { collating_for: None }, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+	}
+
+	// a response with a bad proof is rejected.
+	{
+		let mut ctx = TestContext::default();
+		let recv = protocol.fetch_erasure_chunk(&mut ctx, a_key, parent_hash, &receipt, 0);
+		assert!(ctx.has_message(peer_a, Message::RequestErasureChunk(1, parent_hash, candidate_hash, 0)));
+
+		on_message(&mut protocol, &mut ctx, peer_a, Message::ErasureChunkResponse(1, Some(chunks[1].clone())));
+		assert!(ctx.disabled.contains(&peer_a));
+		assert!(recv.wait().is_err());
+	}
+
+	// a valid response is passed on.
+	{
+		let mut ctx = TestContext::default();
+		let recv = protocol.fetch_erasure_chunk(&mut ctx, a_key, parent_hash, &receipt, 0);
+		assert!(ctx.has_message(peer_a, Message::RequestErasureChunk(2, parent_hash, candidate_hash, 0)));
+
+		on_message(&mut protocol, &mut ctx, peer_a, Message::ErasureChunkResponse(2, Some(chunks[0].clone())));
+		assert!(ctx.disabled.is_empty());
+		assert_eq!(recv.wait().unwrap(), chunks[0]);
+	}
+
+	// unsolicited responses are rejected.
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, peer_a, Message::ErasureChunkResponse(2, Some(chunks[0].clone())));
+		assert!(ctx.disabled.contains(&peer_a));
+	}
+
+	// chunks in the availability store are served.
+	av_store.add_erasure_chunk(parent_hash, &receipt, &chunks[2]).unwrap();
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, peer_a, Message::RequestErasureChunk(10, parent_hash, candidate_hash, 2));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::RequestErasureChunk(11, parent_hash, candidate_hash, 3));
+		assert!(ctx.has_message(peer_a, Message::ErasureChunkResponse(10, Some(chunks[2].clone()))));
+		assert!(ctx.has_message(peer_a, Message::ErasureChunkResponse(11, None)));
+	}
+}
+
+#[test]
+fn erasure_chunk_requests_survive_reconnects_and_time_out() {
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let peer_a = 1;
+	let peer_b = 2;
+	let parent_hash = [0; 32].into();
+	let a_key = [3; 32].into();
+	let status = Status { collating_for: None };
+
+	let (receipt, chunks) = make_chunked_candidate(BlockData(vec![1, 2, 3, 4]), 4);
+	let candidate_hash = receipt.hash();
+
+	// the validator isn't connected yet, so the request waits.
+	let recv = {
+		let mut ctx = TestContext::default();
+		let recv = protocol.fetch_erasure_chunk(&mut ctx, a_key, parent_hash, &receipt, 0);
+		assert!(ctx.messages.is_empty());
+		recv
+	};
+
+	// it is sent once the validator connects.
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&status, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+		assert!(ctx.has_message(peer_a, Message::RequestErasureChunk(1, parent_hash, candidate_hash, 0)));
+	}
+
+	// and sent again when it reconnects.
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_disconnect(&mut ctx, peer_a);
+		protocol.on_connect(&mut ctx, peer_b, make_status(&status, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_b, Message::SessionKey(a_key));
+		assert!(ctx.has_message(peer_b, Message::RequestErasureChunk(2, parent_hash, candidate_hash, 0)));
+
+		on_message(&mut protocol, &mut ctx, peer_b, Message::ErasureChunkResponse(2, Some(chunks[0].clone())));
+		assert!(ctx.disabled.is_empty());
+		assert_eq!(recv.wait().unwrap(), chunks[0]);
+	}
+
+	// requests which aren't answered in time are dropped.
+	{
+		let mut ctx = TestContext::default();
+		let recv = protocol.fetch_erasure_chunk(&mut ctx, a_key, parent_hash, &receipt, 1);
+		assert!(ctx.has_message(peer_b, Message::RequestErasureChunk(3, parent_hash, candidate_hash, 1)));
+
+		protocol.prune_chunk_requests(Instant::now() + ERASURE_CHUNK_REQUEST_TIMEOUT);
+		assert!(recv.wait().is_err());
+
+		on_message(&mut protocol, &mut ctx, peer_b, Message::ErasureChunkResponse(3, Some(chunks[1].clone())));
+		assert!(ctx.disabled.contains(&peer_b));
+	}
+}
+
+#[test]
+fn recovers_each_candidate_once() {
+	let mut protocol = PolkadotProtocol::new(None);
+	let candidate_hash = [1; 32].into();
+
+	// nothing to recover into without an availability store.
+	assert!(protocol.begin_recovery(candidate_hash).is_none());
+
+	protocol.register_availability_store(::av_store::Store::new_in_memory());
+	assert!(protocol.begin_recovery(candidate_hash).is_some());
+	assert!(protocol.begin_recovery(candidate_hash).is_none());
+
+	protocol.end_recovery(&candidate_hash);
+	assert!(protocol.begin_recovery(candidate_hash).is_some());
+}
+
 #[test]
 fn remove_bad_collator() {
 	let mut protocol = PolkadotProtocol::new(None);
@@ -18,6 +18,7 @@
 //!
 //! When compiled with standard library support, this crate exports a `wasm`
 //! module that can be used to validate parachain WASM.
+//! The `validation_host` module runs validation in an isolated worker process.
 //!
 //! ## Parachain WASM
 //!
@@ -37,6 +38,9 @@
 //!              ^~~returned pointer
 //! ```
 //!
+//! Before execution, the module is instrumented with gas metering, which calls
+//! an imported `gas` function. Validation code must not import `gas` itself.
+//!
 //! The `wasm_api` module (enabled only with the wasm-api feature) provides utilities
 //!  for setting up a parachain WASM module in Rust.
 
@@ -58,6 +62,18 @@ extern crate core;
 #[cfg(feature = "std")]
 extern crate wasmi;
 
+#[cfg(feature = "std")]
+extern crate parity_wasm;
+
+#[cfg(feature = "std")]
+extern crate pwasm_utils;
+
+#[cfg(feature = "std")]
+extern crate blake2_rfc;
+
+#[cfg(feature = "std")]
+extern crate lru_cache;
+
 #[cfg(feature = "std")]
 #[macro_use]
 extern crate error_chain;
@@ -68,11 +84,13 @@ use alloc::vec::Vec;
 #[cfg(feature = "std")]
 pub mod wasm_executor;
 
+#[cfg(feature = "std")]
+pub mod validation_host;
+
 #[cfg(feature = "wasm-api")]
 pub mod wasm_api;
 
 /// Validation parameters for evaluating the parachain validity function.
-// TODO: consolidated ingress and balance downloads
 #[derive(PartialEq, Eq, Decode)]
 #[cfg_attr(feature = "std", derive(Debug, Encode))]
 pub struct ValidationParams {
@@ -80,19 +98,38 @@ pub struct ValidationParams {
 	pub block_data: Vec<u8>,
 	/// Previous head-data.
 	pub parent_head: Vec<u8>,
+	/// Incoming messages, in the order they should be processed.
+	///
+	/// The validator checks these against the ingress roots posted to the
+	/// relay chain before executing the validation function.
+	pub ingress: Vec<IncomingMessage>,
+}
+
+/// A message from another parachain, as passed into the validation function.
+#[derive(PartialEq, Eq, Decode)]
+#[cfg_attr(feature = "std", derive(Debug, Encode))]
+pub struct IncomingMessage {
+	/// The source parachain.
+	pub source: u32,
+	/// The data of the message.
+	pub data: Vec<u8>,
 }
 
 /// The result of parachain validation.
-// TODO: egress and balance uploads
 #[derive(PartialEq, Eq, Encode)]
 #[cfg_attr(feature = "std", derive(Debug, Decode))]
 pub struct ValidationResult {
 	/// New head data that should be included in the relay chain state.
 	pub head_data: Vec<u8>,
+	/// Balances uploaded from the parachain to relay-chain accounts,
+	/// as (account, amount) pairs.
+	pub balance_uploads: Vec<([u8; 32], u64)>,
+	/// New validation code for the parachain, to be used after the relay
+	/// chain's upgrade delay has passed.
+	pub new_validation_code: Option<Vec<u8>>,
 }
 
 /// A reference to a message.
-#[cfg(feature = "std")]
 pub struct MessageRef<'a> {
 	/// The target parachain.
 	pub target: u32,
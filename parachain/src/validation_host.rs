@@ -0,0 +1,406 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Isolated execution of parachain validation functions.
+//!
+//! Validation functions can be run in a separate worker process, so that a
+//! pathological candidate or a bug in the WASM interpreter cannot stall or
+//! crash the node. The worker is the current executable started with the
+//! `WORKER_ARG` argument, which must call `run_worker`.
+//!
+//! Requests and responses are exchanged over the worker's stdin and stdout
+//! as SCALE-encoded frames, each prefixed with its length as a little-endian `u32`.
+//! The host keeps a pool of workers so that several candidates can be validated
+//! at once. It enforces a wall-clock timeout and a limit on the resident memory
+//! of each worker, killing it and starting a new one when either is exceeded.
+
+use std::{cmp, env, io};
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use codec::{Decode, Encode};
+
+use {MessageRef, ValidationParams};
+use wasm_executor::{
+	self, CodeCache, CodeCacheStats, Error, ErrorKind, ExecutionParams, ExecutionResult, Externalities,
+	ExternalitiesError,
+};
+
+/// The command-line argument which starts a validation worker.
+pub const WORKER_ARG: &str = "validation-worker";
+
+/// The default wall-clock timeout of a single validation, in milliseconds.
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+
+/// The default limit on the resident memory of a worker, in bytes.
+pub const DEFAULT_MEMORY_LIMIT: u64 = 2 * 1024 * 1024 * 1024;
+
+/// The default number of worker processes validating candidates at once.
+pub const DEFAULT_MAX_WORKERS: usize = 4;
+
+// the largest frame either side will accept.
+const MAX_FRAME_SIZE: usize = 1024 * 1024 * 1024;
+
+// how often the memory usage of the worker is checked while waiting for a response.
+const POLL_INTERVAL_MS: u64 = 50;
+
+#[derive(Encode, Decode)]
+struct Request {
+	code: Vec<u8>,
+	params: ValidationParams,
+	fuel_limit: u64,
+}
+
+#[derive(Encode, Decode)]
+enum Response {
+	/// The candidate is valid. Contains the messages posted during validation.
+	Valid(ExecutionResult, Vec<(u32, Vec<u8>)>),
+	/// Validation ran out of fuel. Contains the fuel limit.
+	OutOfFuel(u64),
+	/// Validation failed for the given reason.
+	Invalid(String),
+}
+
+fn write_frame<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+	writer.write_all(&(data.len() as u32).encode())?;
+	writer.write_all(data)?;
+	writer.flush()
+}
+
+// reads a frame, returning `None` if the stream ended before it started.
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+	let mut len = [0u8; 4];
+	match reader.read_exact(&mut len) {
+		Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		other => other?,
+	}
+
+	let len = u32::decode(&mut &len[..]).expect("4 bytes always decode to a u32; qed") as usize;
+	if len > MAX_FRAME_SIZE {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too large"));
+	}
+
+	let mut data = vec![0; len];
+	reader.read_exact(&mut data)?;
+	Ok(Some(data))
+}
+
+// externalities of the worker, which record messages to be checked by the host.
+struct RecordingExternalities {
+	messages: Vec<(u32, Vec<u8>)>,
+}
+
+impl Externalities for RecordingExternalities {
+	fn post_message(&mut self, message: MessageRef) -> Result<(), ExternalitiesError> {
+		self.messages.push((message.target, message.data.to_vec()));
+		Ok(())
+	}
+}
+
+/// Run a validation worker, serving requests from stdin until it is closed.
+///
+/// Nothing else may be written to stdout while the worker is running.
+pub fn run_worker() -> io::Result<()> {
+	let cache = CodeCache::default();
+	let stdin = io::stdin();
+	let mut stdin = stdin.lock();
+	let stdout = io::stdout();
+	let mut stdout = stdout.lock();
+
+	while let Some(frame) = read_frame(&mut stdin)? {
+		let request = Request::decode(&mut &frame[..])
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed validation request"))?;
+
+		let mut ext = RecordingExternalities { messages: Vec::new() };
+		let response = match wasm_executor::validate_candidate_cached(
+			&cache,
+			&request.code,
+			request.params,
+			&mut ext,
+			ExecutionParams { fuel_limit: request.fuel_limit },
+		) {
+			Ok(result) => Response::Valid(result, ext.messages),
+			Err(Error(ErrorKind::OutOfFuel(limit), _)) => Response::OutOfFuel(limit),
+			Err(e) => Response::Invalid(e.to_string()),
+		};
+
+		write_frame(&mut stdout, &response.encode())?;
+	}
+
+	Ok(())
+}
+
+/// Configuration of validation worker processes.
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+	/// Wall-clock time a single validation may take.
+	pub timeout: Duration,
+	/// Resident memory a worker may use, in bytes. Only enforced on Linux.
+	pub memory_limit: u64,
+	/// Number of worker processes validating candidates at once. Further
+	/// requests wait for a worker to become free.
+	pub max_workers: usize,
+}
+
+impl Default for WorkerConfig {
+	fn default() -> Self {
+		WorkerConfig {
+			timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+			memory_limit: DEFAULT_MEMORY_LIMIT,
+			max_workers: DEFAULT_MAX_WORKERS,
+		}
+	}
+}
+
+// a running worker process. it is killed when dropped.
+struct Worker {
+	child: Child,
+	stdin: ChildStdin,
+	responses: mpsc::Receiver<io::Result<Vec<u8>>>,
+}
+
+impl Worker {
+	fn spawn() -> io::Result<Self> {
+		let mut child = Command::new(env::current_exe()?)
+			.arg(WORKER_ARG)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::inherit())
+			.spawn()?;
+
+		let stdin = child.stdin.take().expect("stdin of worker is piped; qed");
+		let mut stdout = child.stdout.take().expect("stdout of worker is piped; qed");
+
+		// responses are read on a separate thread so that the host can
+		// time out while waiting for them.
+		let (tx, rx) = mpsc::channel();
+		thread::Builder::new()
+			.name("validation-worker-reader".into())
+			.spawn(move || loop {
+				let frame = match read_frame(&mut stdout) {
+					Ok(Some(frame)) => Ok(frame),
+					Ok(None) => break,
+					Err(e) => Err(e),
+				};
+
+				let failed = frame.is_err();
+				if tx.send(frame).is_err() || failed { break }
+			})?;
+
+		Ok(Worker { child, stdin, responses: rx })
+	}
+}
+
+impl Drop for Worker {
+	fn drop(&mut self) {
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
+
+#[cfg(target_os = "linux")]
+fn resident_memory(pid: u32) -> Option<u64> {
+	let status = ::std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+	let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+	let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+	Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory(_pid: u32) -> Option<u64> {
+	None
+}
+
+// the workers of a host which are idle, and the number of workers started in total.
+struct Workers {
+	idle: Vec<Worker>,
+	running: usize,
+}
+
+/// Executes validation functions in a pool of worker processes.
+///
+/// Up to `WorkerConfig::max_workers` requests are executed at once, each in its
+/// own worker. Workers are started on demand and kept for later requests. A worker
+/// is discarded after a timeout, a memory overrun or a crash.
+pub struct ValidationHost {
+	config: WorkerConfig,
+	workers: Mutex<Workers>,
+	worker_released: Condvar,
+}
+
+impl ValidationHost {
+	/// Create a new validation host. No worker is started until the first request.
+	pub fn new(config: WorkerConfig) -> Self {
+		ValidationHost {
+			config,
+			workers: Mutex::new(Workers { idle: Vec::new(), running: 0 }),
+			worker_released: Condvar::new(),
+		}
+	}
+
+	// take an idle worker, start a new one, or wait for one to be released.
+	fn acquire_worker(&self) -> Result<Worker, Error> {
+		let max_workers = cmp::max(self.config.max_workers, 1);
+		let mut workers = self.workers.lock().expect("worker lock poisoned");
+		loop {
+			if let Some(worker) = workers.idle.pop() {
+				return Ok(worker);
+			}
+
+			if workers.running < max_workers {
+				workers.running += 1;
+				drop(workers);
+
+				return Worker::spawn().map_err(|e| {
+					self.release_worker(None);
+					ErrorKind::WorkerFailed(e.to_string()).into()
+				});
+			}
+
+			workers = self.worker_released.wait(workers).expect("worker lock poisoned");
+		}
+	}
+
+	// return a worker to the pool, or note that it was discarded.
+	fn release_worker(&self, worker: Option<Worker>) {
+		let mut workers = self.workers.lock().expect("worker lock poisoned");
+		match worker {
+			Some(worker) => workers.idle.push(worker),
+			None => workers.running -= 1,
+		}
+
+		self.worker_released.notify_one();
+	}
+
+	/// Validate a candidate in the worker process.
+	///
+	/// Messages posted by the validation function are passed on to
+	/// `externalities` once the worker has returned.
+	pub fn validate_candidate<E: Externalities>(
+		&self,
+		code: &[u8],
+		params: ValidationParams,
+		externalities: &mut E,
+		execution_params: ExecutionParams,
+	) -> Result<ExecutionResult, Error> {
+		let request = Request {
+			code: code.to_vec(),
+			params,
+			fuel_limit: execution_params.fuel_limit,
+		}.encode();
+
+		let response = {
+			let mut worker = self.acquire_worker()?;
+			let response = self.run_request(&mut worker, &request);
+
+			if response.is_ok() {
+				self.release_worker(Some(worker));
+			} else {
+				// kill the worker; a new one will be started when needed.
+				drop(worker);
+				self.release_worker(None);
+			}
+
+			response?
+		};
+
+		match Response::decode(&mut &response[..]) {
+			Some(Response::Valid(result, messages)) => {
+				for (target, data) in messages {
+					externalities.post_message(MessageRef { target, data: &data })?;
+				}
+				Ok(result)
+			}
+			Some(Response::OutOfFuel(limit)) => Err(ErrorKind::OutOfFuel(limit).into()),
+			Some(Response::Invalid(reason)) => Err(ErrorKind::WorkerRejected(reason).into()),
+			None => Err(ErrorKind::WorkerFailed("Malformed response".into()).into()),
+		}
+	}
+
+	fn run_request(&self, worker: &mut Worker, request: &[u8]) -> Result<Vec<u8>, Error> {
+		write_frame(&mut worker.stdin, request).map_err(|e| ErrorKind::WorkerFailed(e.to_string()))?;
+
+		let deadline = Instant::now() + self.config.timeout;
+		loop {
+			let now = Instant::now();
+			if now >= deadline {
+				bail!(ErrorKind::Timeout(self.config.timeout));
+			}
+
+			let wait = cmp::min(deadline - now, Duration::from_millis(POLL_INTERVAL_MS));
+			match worker.responses.recv_timeout(wait) {
+				Ok(Ok(frame)) => return Ok(frame),
+				Ok(Err(e)) => bail!(ErrorKind::WorkerFailed(e.to_string())),
+				Err(mpsc::RecvTimeoutError::Disconnected) => bail!(ErrorKind::WorkerFailed("Worker exited".into())),
+				Err(mpsc::RecvTimeoutError::Timeout) => {}
+			}
+
+			if let Some(used) = resident_memory(worker.child.id()) {
+				if used > self.config.memory_limit {
+					bail!(ErrorKind::MemoryLimitExceeded(self.config.memory_limit));
+				}
+			}
+		}
+	}
+}
+
+/// Where validation functions are executed.
+#[derive(Clone)]
+pub enum ValidationPool {
+	/// In the current process, with a cache of prepared code.
+	InProcess(Arc<CodeCache>),
+	/// In a worker process.
+	External(Arc<ValidationHost>),
+}
+
+impl ValidationPool {
+	/// Execute validation functions in the current process.
+	pub fn in_process() -> Self {
+		ValidationPool::InProcess(Arc::new(CodeCache::default()))
+	}
+
+	/// Execute validation functions in a worker process with the given configuration.
+	pub fn external(config: WorkerConfig) -> Self {
+		ValidationPool::External(Arc::new(ValidationHost::new(config)))
+	}
+
+	/// Statistics of the code cache, if it is kept in this process.
+	pub fn code_cache_stats(&self) -> Option<CodeCacheStats> {
+		match *self {
+			ValidationPool::InProcess(ref cache) => Some(cache.stats()),
+			ValidationPool::External(_) => None,
+		}
+	}
+
+	/// Validate a candidate. See `wasm_executor::validate_candidate`.
+	pub fn validate_candidate<E: Externalities>(
+		&self,
+		code: &[u8],
+		params: ValidationParams,
+		externalities: &mut E,
+		execution_params: ExecutionParams,
+	) -> Result<ExecutionResult, Error> {
+		match *self {
+			ValidationPool::InProcess(ref cache) =>
+				wasm_executor::validate_candidate_cached(cache, code, params, externalities, execution_params),
+			ValidationPool::External(ref host) =>
+				host.validate_candidate(code, params, externalities, execution_params),
+		}
+	}
+}
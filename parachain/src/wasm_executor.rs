@@ -28,12 +28,43 @@ use wasmi::memory_units::{Bytes, Pages, RoundUpTo};
 
 use super::{ValidationParams, ValidationResult, MessageRef};
 
+use lru_cache::LruCache;
+
 use std::cell::RefCell;
 use std::fmt;
+use std::sync::{Arc, Mutex};
 
 mod ids {
 	/// Post a message to another parachain.
 	pub const POST_MESSAGE: usize = 1;
+	/// Charge fuel, called by injected metering code.
+	pub const GAS: usize = 2;
+}
+
+/// Default amount of fuel available to a validation function.
+pub const DEFAULT_FUEL_LIMIT: u64 = 1_000_000_000;
+
+/// Parameters for executing a validation function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionParams {
+	/// Maximum amount of fuel the validation function may consume. Roughly one
+	/// unit of fuel is consumed per instruction executed.
+	pub fuel_limit: u64,
+}
+
+impl Default for ExecutionParams {
+	fn default() -> Self {
+		ExecutionParams { fuel_limit: DEFAULT_FUEL_LIMIT }
+	}
+}
+
+/// The outcome of a validation function which returned successfully.
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ExecutionResult {
+	/// The result returned by the validation function.
+	pub result: ValidationResult,
+	/// Fuel consumed by the validation function, as metered by the executor.
+	pub fuel_used: u64,
 }
 
 error_chain! {
@@ -53,6 +84,45 @@ error_chain! {
 			description("Validation function returned invalid data."),
 			display("Validation function returned invalid data."),
 		}
+		/// The validation function ran out of fuel.
+		OutOfFuel(limit: u64) {
+			description("Validation function ran out of fuel."),
+			display("Validation function exceeded the fuel limit of {}", limit),
+		}
+		/// Validation in a worker process took longer than allowed.
+		Timeout(limit: ::std::time::Duration) {
+			description("Validation timed out."),
+			display("Validation exceeded the time limit of {:?}", limit),
+		}
+		/// The validation worker used more memory than allowed.
+		MemoryLimitExceeded(limit: u64) {
+			description("Validation worker exceeded its memory limit."),
+			display("Validation worker exceeded the memory limit of {} bytes", limit),
+		}
+		/// The validation worker could not be started or stopped responding.
+		WorkerFailed(reason: String) {
+			description("Validation worker failed."),
+			display("Validation worker failed: {}", reason),
+		}
+		/// The validation worker rejected the candidate.
+		WorkerRejected(reason: String) {
+			description("Validation worker rejected the candidate."),
+			display("Validation worker rejected the candidate: {}", reason),
+		}
+	}
+}
+
+impl ErrorKind {
+	/// Whether the error is caused by the local execution environment rather than
+	/// the candidate, so it says nothing about the candidate's validity.
+	///
+	/// Running out of time or memory is down to the candidate, like running out of
+	/// fuel, so only failures of the worker itself are local.
+	pub fn is_local(&self) -> bool {
+		match *self {
+			ErrorKind::WorkerFailed(_) => true,
+			_ => false,
+		}
 	}
 }
 
@@ -61,6 +131,12 @@ error_chain! {
 pub enum ExternalitiesError {
 	/// Unable to post a message due to the given reason.
 	CannotPostMessage(&'static str),
+	/// Posted more messages than allowed. Contains the limit.
+	TooManyMessages(u32),
+	/// Posted a message larger than allowed. Contains the size and the limit.
+	MessageTooLarge(usize, u32),
+	/// Posted messages whose fees exceed those available. Contains the available fees.
+	FeesExceeded(u64),
 }
 
 /// Externalities for parachain validation.
@@ -74,6 +150,12 @@ impl fmt::Display for ExternalitiesError {
 		match *self {
 			ExternalitiesError::CannotPostMessage(ref s)
 				=> write!(f, "Cannot post message: {}", s),
+			ExternalitiesError::TooManyMessages(max)
+				=> write!(f, "Cannot post more than {} messages", max),
+			ExternalitiesError::MessageTooLarge(size, max)
+				=> write!(f, "Message of {} bytes exceeds maximum size of {} bytes", size, max),
+			ExternalitiesError::FeesExceeded(available)
+				=> write!(f, "Message fees exceed available fees of {}", available),
 		}
 	}
 }
@@ -81,6 +163,19 @@ impl fmt::Display for ExternalitiesError {
 impl wasmi::HostError for ExternalitiesError {}
 impl ::std::error::Error for ExternalitiesError {}
 
+// raised from the `gas` host function when the fuel limit is exceeded.
+#[derive(Debug)]
+struct OutOfFuel;
+
+impl fmt::Display for OutOfFuel {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Out of fuel")
+	}
+}
+
+impl wasmi::HostError for OutOfFuel {}
+impl ::std::error::Error for OutOfFuel {}
+
 struct Resolver {
 	max_memory: u32, // in pages.
 	memory: RefCell<Option<MemoryRef>>,
@@ -109,6 +204,22 @@ impl ModuleImportResolver for Resolver {
 					))
 				}
 			}
+			"gas" => {
+				let index = ids::GAS;
+				let (params, ret_ty): (&[ValueType], Option<ValueType>) =
+				(&[ValueType::I32], None);
+
+				if signature.params() != params || signature.return_type() != ret_ty {
+					Err(WasmError::Instantiation(
+						format!("Export {} has a bad signature", field_name)
+					))
+				} else {
+					Ok(wasmi::FuncInstance::alloc_host(
+						wasmi::Signature::new(&params[..], ret_ty),
+						index,
+					))
+				}
+			}
 			_ => {
 				Err(WasmError::Instantiation(
 					format!("Export {} not found", field_name),
@@ -144,6 +255,8 @@ impl ModuleImportResolver for Resolver {
 struct ValidationExternals<'a, E: 'a> {
 	externalities: &'a mut E,
 	memory: &'a MemoryRef,
+	fuel_used: u64,
+	fuel_limit: u64,
 }
 
 impl<'a, E: 'a + Externalities> ValidationExternals<'a, E> {
@@ -174,6 +287,21 @@ impl<'a, E: 'a + Externalities> ValidationExternals<'a, E> {
 	}
 }
 
+impl<'a, E: 'a> ValidationExternals<'a, E> {
+	/// Signature: gas(u32) -> None
+	/// usage: gas(amount). Called by injected metering code.
+	fn ext_gas(&mut self, args: ::wasmi::RuntimeArgs) -> Result<(), Trap> {
+		let amount: u32 = args.nth_checked(0)?;
+
+		self.fuel_used = self.fuel_used.saturating_add(amount as u64);
+		if self.fuel_used > self.fuel_limit {
+			Err(Trap::new(wasmi::TrapKind::Host(Box::new(OutOfFuel))))
+		} else {
+			Ok(())
+		}
+	}
+}
+
 impl<'a, E: 'a + Externalities> Externals for ValidationExternals<'a, E> {
 	fn invoke_index(
 		&mut self,
@@ -182,37 +310,169 @@ impl<'a, E: 'a + Externalities> Externals for ValidationExternals<'a, E> {
 	) -> Result<Option<RuntimeValue>, Trap> {
 		match index {
 			ids::POST_MESSAGE => self.ext_post_message(args).map(|_| None),
+			ids::GAS => self.ext_gas(args).map(|_| None),
 			_ => panic!("no externality at given index"),
 		}
 	}
 }
 
+// instrument the validation code with gas metering.
+fn instrument_module(validation_code: &[u8]) -> Result<Module, Error> {
+	let module: ::parity_wasm::elements::Module = ::parity_wasm::deserialize_buffer(validation_code)
+		.map_err(|e| WasmError::Validation(format!("Failed to deserialize module: {}", e)))?;
+
+	let module = ::pwasm_utils::inject_gas_counter(module, &::pwasm_utils::rules::Set::default())
+		.map_err(|_| WasmError::Validation("Failed to inject gas metering".to_owned()))?;
+
+	Module::from_parity_wasm_module(module).map_err(Into::into)
+}
+
+// convert an error from executing the module, extracting errors raised
+// by the host.
+fn execution_error(e: WasmError, fuel_limit: u64) -> Error {
+	let host_error = e.as_host_error().map(|he| {
+		if he.downcast_ref::<OutOfFuel>().is_some() {
+			Some(ErrorKind::OutOfFuel(fuel_limit).into())
+		} else {
+			he.downcast_ref::<ExternalitiesError>()
+				.map(|ee| ErrorKind::Externalities(ee.clone()).into())
+		}
+	});
+
+	match host_error {
+		Some(Some(err)) => err,
+		_ => e.into(),
+	}
+}
+
+/// Default number of prepared modules kept by a `CodeCache`.
+pub const DEFAULT_CODE_CACHE_SIZE: usize = 16;
+
+/// Hit and miss statistics of a `CodeCache`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CodeCacheStats {
+	/// Number of lookups answered from the cache.
+	pub hits: u64,
+	/// Number of lookups which had to prepare the module.
+	pub misses: u64,
+}
+
+struct CodeCacheInner {
+	modules: LruCache<[u8; 32], Arc<Module>>,
+	stats: CodeCacheStats,
+}
+
+/// An LRU cache of prepared validation code, keyed by the blake2-256 hash of the code.
+///
+/// Preparation covers parsing, validation and instrumentation of the code, so that
+/// a cached module can be instantiated directly. This is safe to share between threads.
+pub struct CodeCache {
+	inner: Mutex<CodeCacheInner>,
+}
+
+impl CodeCache {
+	/// Create a new cache holding up to `capacity` prepared modules.
+	pub fn new(capacity: usize) -> Self {
+		CodeCache {
+			inner: Mutex::new(CodeCacheInner {
+				modules: LruCache::new(capacity),
+				stats: CodeCacheStats::default(),
+			}),
+		}
+	}
+
+	/// Get the hit and miss statistics of the cache.
+	pub fn stats(&self) -> CodeCacheStats {
+		self.inner.lock().expect("code cache lock poisoned").stats
+	}
+
+	/// Get the prepared module for the given validation code, preparing and
+	/// caching it if it isn't cached already.
+	pub fn prepare_module(&self, validation_code: &[u8]) -> Result<Arc<Module>, Error> {
+		let mut code_hash = [0u8; 32];
+		code_hash.copy_from_slice(::blake2_rfc::blake2b::blake2b(32, &[], validation_code).as_bytes());
+
+		{
+			let mut inner = self.inner.lock().expect("code cache lock poisoned");
+			if let Some(module) = inner.modules.get_mut(&code_hash).map(|m| m.clone()) {
+				inner.stats.hits += 1;
+				return Ok(module);
+			}
+
+			inner.stats.misses += 1;
+		}
+
+		// prepare without holding the lock, as this may take a while.
+		let module = Arc::new(instrument_module(validation_code)?);
+
+		self.inner.lock().expect("code cache lock poisoned").modules.insert(code_hash, module.clone());
+		Ok(module)
+	}
+}
+
+impl Default for CodeCache {
+	fn default() -> Self {
+		CodeCache::new(DEFAULT_CODE_CACHE_SIZE)
+	}
+}
+
 /// Validate a candidate under the given validation code.
 ///
+/// The validation code is instrumented with gas metering, and execution fails with
+/// `ErrorKind::OutOfFuel` once the fuel limit in `execution_params` is exceeded.
+///
 /// This will fail if the validation code is not a proper parachain validation module.
 pub fn validate_candidate<E: Externalities>(
 	validation_code: &[u8],
 	params: ValidationParams,
 	externalities: &mut E,
-) -> Result<ValidationResult, Error> {
+	execution_params: ExecutionParams,
+) -> Result<ExecutionResult, Error> {
+	let module = instrument_module(validation_code)?;
+	execute_module(&module, params, externalities, execution_params)
+}
+
+/// Validate a candidate under the given validation code, using a cache of
+/// prepared modules.
+///
+/// This behaves like `validate_candidate`, but skips preparing the code when
+/// it is found in the cache.
+pub fn validate_candidate_cached<E: Externalities>(
+	cache: &CodeCache,
+	validation_code: &[u8],
+	params: ValidationParams,
+	externalities: &mut E,
+	execution_params: ExecutionParams,
+) -> Result<ExecutionResult, Error> {
+	let module = cache.prepare_module(validation_code)?;
+	execute_module(&module, params, externalities, execution_params)
+}
+
+/// Execute validation code which has been prepared by `CodeCache::prepare_module`.
+pub fn execute_module<E: Externalities>(
+	module: &Module,
+	params: ValidationParams,
+	externalities: &mut E,
+	execution_params: ExecutionParams,
+) -> Result<ExecutionResult, Error> {
 	use wasmi::LINEAR_MEMORY_PAGE_SIZE;
 
 	// maximum memory in bytes
 	const MAX_MEM: u32 = 1024 * 1024 * 1024; // 1 GiB
 
+	let fuel_limit = execution_params.fuel_limit;
+
 	// instantiate the module.
 	let memory;
 	let mut externals;
 	let module = {
-		let module = Module::from_buffer(validation_code)?;
-
 		let module_resolver = Resolver {
 			max_memory: MAX_MEM / LINEAR_MEMORY_PAGE_SIZE.0 as u32,
 			memory: RefCell::new(None),
 		};
 
 		let module = ModuleInstance::new(
-			&module,
+			module,
 			&wasmi::ImportsBuilder::new().with_resolver("env", &module_resolver),
 		)?;
 
@@ -224,9 +484,12 @@ pub fn validate_candidate<E: Externalities>(
 		externals = ValidationExternals {
 			externalities,
 			memory: &memory,
+			fuel_used: 0,
+			fuel_limit,
 		};
 
-		module.run_start(&mut externals).map_err(WasmError::Trap)?
+		module.run_start(&mut externals)
+			.map_err(|trap| execution_error(WasmError::Trap(trap), fuel_limit))?
 	};
 
 	// allocate call data in memory.
@@ -258,13 +521,9 @@ pub fn validate_candidate<E: Externalities>(
 		"validate",
 		&[RuntimeValue::I32(offset as i32), RuntimeValue::I32(len as i32)],
 		&mut externals,
-	)
-		.map_err(|e| -> Error {
-			e.as_host_error()
-				.and_then(|he| he.downcast_ref::<ExternalitiesError>())
-				.map(|ee| ErrorKind::Externalities(ee.clone()).into())
-				.unwrap_or_else(move || e.into())
-		})?;
+	).map_err(|e| execution_error(e, fuel_limit))?;
+
+	let fuel_used = externals.fuel_used;
 
 	match output {
 		Some(RuntimeValue::I32(len_offset)) => {
@@ -291,6 +550,7 @@ pub fn validate_candidate<E: Externalities>(
 				ValidationResult::decode(&mut &mem[return_offset..][..len])
 					.ok_or_else(|| ErrorKind::BadReturn)
 					.map_err(Into::into)
+					.map(|result| ExecutionResult { result, fuel_used })
 			})
 		}
 		_ => bail!(ErrorKind::BadReturn),
@@ -23,7 +23,8 @@ extern crate polkadot_parachain as parachain;
 extern crate tiny_keccak;
 
 use parachain::{MessageRef, ValidationParams};
-use parachain::wasm_executor::{Externalities, ExternalitiesError};
+use parachain::wasm_executor::{Externalities, ExternalitiesError, ExecutionParams, CodeCache, CodeCacheStats};
+use parachain::validation_host::ValidationPool;
 use codec::{Decode, Encode};
 
 /// Head data for this parachain.
@@ -81,15 +82,19 @@ fn execute_good_on_parent() {
 		ValidationParams {
 			parent_head: parent_head.encode(),
 			block_data: block_data.encode(),
+			ingress: Vec::new(),
 		},
 		&mut DummyExt,
+		ExecutionParams::default(),
 	).unwrap();
 
-	let new_head = HeadData::decode(&mut &ret.head_data[..]).unwrap();
+	let new_head = HeadData::decode(&mut &ret.result.head_data[..]).unwrap();
 
 	assert_eq!(new_head.number, 1);
 	assert_eq!(new_head.parent_hash, hash_head(&parent_head));
 	assert_eq!(new_head.post_state, hash_state(512));
+	assert!(ret.result.balance_uploads.is_empty());
+	assert!(ret.fuel_used > 0);
 }
 
 #[test]
@@ -115,11 +120,13 @@ fn execute_good_chain_on_parent() {
 			ValidationParams {
 				parent_head: parent_head.encode(),
 				block_data: block_data.encode(),
+				ingress: Vec::new(),
 			},
 			&mut DummyExt,
+			ExecutionParams::default(),
 		).unwrap();
 
-		let new_head = HeadData::decode(&mut &ret.head_data[..]).unwrap();
+		let new_head = HeadData::decode(&mut &ret.result.head_data[..]).unwrap();
 
 		assert_eq!(new_head.number, number + 1);
 		assert_eq!(new_head.parent_hash, hash_head(&parent_head));
@@ -149,7 +156,107 @@ fn execute_bad_on_parent() {
 		ValidationParams {
 			parent_head: parent_head.encode(),
 			block_data: block_data.encode(),
+			ingress: Vec::new(),
 		},
 		&mut DummyExt,
+		ExecutionParams::default(),
 	).unwrap_err();
 }
+
+#[test]
+fn execute_out_of_fuel() {
+	let parent_head = HeadData {
+		number: 0,
+		parent_hash: [0; 32],
+		post_state: hash_state(0),
+	};
+
+	let block_data = BlockData {
+		state: 0,
+		add: 512,
+	};
+
+	let err = parachain::wasm_executor::validate_candidate(
+		TEST_CODE,
+		ValidationParams {
+			parent_head: parent_head.encode(),
+			block_data: block_data.encode(),
+			ingress: Vec::new(),
+		},
+		&mut DummyExt,
+		ExecutionParams { fuel_limit: 10 },
+	).unwrap_err();
+
+	match *err.kind() {
+		parachain::wasm_executor::ErrorKind::OutOfFuel(10) => {}
+		ref other => panic!("unexpected error: {:?}", other),
+	}
+}
+
+#[test]
+fn execute_cached() {
+	let cache = CodeCache::new(4);
+
+	let mut parent_head = HeadData {
+		number: 0,
+		parent_hash: [0; 32],
+		post_state: hash_state(0),
+	};
+
+	for i in 0..3 {
+		let block_data = BlockData {
+			state: i,
+			add: 1,
+		};
+
+		let ret = parachain::wasm_executor::validate_candidate_cached(
+			&cache,
+			TEST_CODE,
+			ValidationParams {
+				parent_head: parent_head.encode(),
+				block_data: block_data.encode(),
+				ingress: Vec::new(),
+			},
+			&mut DummyExt,
+			ExecutionParams::default(),
+		).unwrap();
+
+		let new_head = HeadData::decode(&mut &ret.result.head_data[..]).unwrap();
+		assert_eq!(new_head.number, i + 1);
+		assert_eq!(new_head.post_state, hash_state(i + 1));
+		parent_head = new_head;
+	}
+
+	assert_eq!(cache.stats(), CodeCacheStats { hits: 2, misses: 1 });
+}
+
+#[test]
+fn execute_in_process_pool() {
+	let pool = ValidationPool::in_process();
+
+	let parent_head = HeadData {
+		number: 0,
+		parent_hash: [0; 32],
+		post_state: hash_state(0),
+	};
+
+	let block_data = BlockData {
+		state: 0,
+		add: 512,
+	};
+
+	let ret = pool.validate_candidate(
+		TEST_CODE,
+		ValidationParams {
+			parent_head: parent_head.encode(),
+			block_data: block_data.encode(),
+			ingress: Vec::new(),
+		},
+		&mut DummyExt,
+		ExecutionParams::default(),
+	).unwrap();
+
+	let new_head = HeadData::decode(&mut &ret.result.head_data[..]).unwrap();
+	assert_eq!(new_head.post_state, hash_state(512));
+	assert_eq!(pool.code_cache_stats(), Some(CodeCacheStats { hits: 0, misses: 1 }));
+}
@@ -0,0 +1,140 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validation of candidates in worker processes which run out of time or memory.
+//!
+//! The validation host starts the current executable as its worker, so this test
+//! runs without the default harness and serves as the worker when asked to.
+
+extern crate polkadot_parachain as parachain;
+
+use std::env;
+use std::time::Duration;
+
+use parachain::{MessageRef, ValidationParams};
+use parachain::wasm_executor::{Externalities, ExternalitiesError, ExecutionParams, Error, ErrorKind};
+use parachain::validation_host::{self, ValidationHost, WorkerConfig};
+
+// (module
+//   (import "env" "memory" (memory 1))
+//   (func (export "validate") (param i32 i32) (result i32)
+//     (loop (br 0))
+//     (i32.const 0)))
+const LOOP_CODE: &[u8] = &[
+	0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+	// types
+	0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f,
+	// imports
+	0x02, 0x0f, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x01,
+	// functions
+	0x03, 0x02, 0x01, 0x00,
+	// exports
+	0x07, 0x0c, 0x01, 0x08, 0x76, 0x61, 0x6c, 0x69, 0x64, 0x61, 0x74, 0x65, 0x00, 0x00,
+	// code
+	0x0a, 0x0b, 0x01, 0x09, 0x00,
+	0x03, 0x40, 0x0c, 0x00, 0x0b,
+	0x41, 0x00, 0x0b,
+];
+
+// (module
+//   (import "env" "memory" (memory 1))
+//   (func (export "validate") (param i32 i32) (result i32)
+//     (drop (grow_memory (i32.const 4096)))
+//     (i32.store (i32.const 0x10000000) (i32.const 1))
+//     (loop (br 0))
+//     (i32.const 0)))
+const MEMORY_CODE: &[u8] = &[
+	0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+	// types
+	0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f,
+	// imports
+	0x02, 0x0f, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x01,
+	// functions
+	0x03, 0x02, 0x01, 0x00,
+	// exports
+	0x07, 0x0c, 0x01, 0x08, 0x76, 0x61, 0x6c, 0x69, 0x64, 0x61, 0x74, 0x65, 0x00, 0x00,
+	// code
+	0x0a, 0x1c, 0x01, 0x1a, 0x00,
+	0x41, 0x80, 0x20, 0x40, 0x00, 0x1a,
+	0x41, 0x80, 0x80, 0x80, 0x80, 0x01, 0x41, 0x01, 0x36, 0x02, 0x00,
+	0x03, 0x40, 0x0c, 0x00, 0x0b,
+	0x41, 0x00, 0x0b,
+];
+
+struct DummyExt;
+impl Externalities for DummyExt {
+	fn post_message(&mut self, _message: MessageRef) -> Result<(), ExternalitiesError> {
+		Ok(())
+	}
+}
+
+fn validate(config: WorkerConfig, code: &[u8]) -> Result<(), Error> {
+	let host = ValidationHost::new(config);
+	host.validate_candidate(
+		code,
+		ValidationParams {
+			parent_head: Vec::new(),
+			block_data: Vec::new(),
+			ingress: Vec::new(),
+		},
+		&mut DummyExt,
+		// the code would otherwise run out of fuel first.
+		ExecutionParams { fuel_limit: u64::max_value() },
+	).map(|_| ())
+}
+
+fn validation_out_of_time_is_invalid() {
+	let config = WorkerConfig {
+		timeout: Duration::from_millis(500),
+		..Default::default()
+	};
+
+	let err = validate(config, LOOP_CODE).unwrap_err();
+	match *err.kind() {
+		ErrorKind::Timeout(_) => {}
+		ref other => panic!("expected a timeout, got {:?}", other),
+	}
+	assert!(!err.kind().is_local());
+}
+
+#[cfg(target_os = "linux")]
+fn validation_out_of_memory_is_invalid() {
+	let config = WorkerConfig {
+		memory_limit: 64 * 1024 * 1024,
+		..Default::default()
+	};
+
+	let err = validate(config, MEMORY_CODE).unwrap_err();
+	match *err.kind() {
+		ErrorKind::MemoryLimitExceeded(_) => {}
+		ref other => panic!("expected the memory limit to be exceeded, got {:?}", other),
+	}
+	assert!(!err.kind().is_local());
+}
+
+// the memory limit is only enforced on Linux.
+#[cfg(not(target_os = "linux"))]
+fn validation_out_of_memory_is_invalid() {}
+
+fn main() {
+	if env::args().nth(1).map_or(false, |arg| arg == validation_host::WORKER_ARG) {
+		validation_host::run_worker().expect("validation worker failed");
+		return;
+	}
+
+	validation_out_of_time_is_invalid();
+	validation_out_of_memory_is_invalid();
+}
@@ -18,7 +18,7 @@
 
 use rstd::prelude::*;
 use rstd::cmp::Ordering;
-use super::{Hash, SessionKey};
+use super::{Hash, SessionKey, BlockNumber, Balance};
 
 use {AccountId};
 
@@ -64,6 +64,64 @@ pub enum Chain {
 pub struct DutyRoster {
 	/// Lookup from validator index to chain on which that validator has a duty to validate.
 	pub validator_duty: Vec<Chain>,
+	/// Lookup from validator index to the parachain whose candidates that validator
+	/// re-checks after they have been backed by the parachain's group, if any.
+	/// Only validators of the relay chain are secondary checkers.
+	pub secondary_checks: Vec<Option<Id>>,
+}
+
+/// The share of a validator group which must be exceeded by the attestations to a
+/// candidate's validity for the candidate to be included.
+///
+/// Used both by the runtime when checking attestations and by validators when
+/// deciding which candidates can be proposed, so the two can't disagree.
+///
+/// Every member of a group counts the same; the stake behind validators isn't
+/// taken into account.
+// TODO: support thresholds weighted by the stake of validators. The statement
+// table would need to count the weight of validity votes rather than their number.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[cfg_attr(feature = "std", serde(deny_unknown_fields))]
+pub struct ValidityThreshold {
+	/// Numerator of the share of the group.
+	pub numerator: u32,
+	/// Denominator of the share of the group. Must not be zero.
+	pub denominator: u32,
+}
+
+impl ValidityThreshold {
+	/// More than half of the group.
+	pub const MAJORITY: ValidityThreshold = ValidityThreshold { numerator: 1, denominator: 2 };
+	/// More than two thirds of the group.
+	pub const SUPERMAJORITY: ValidityThreshold = ValidityThreshold { numerator: 2, denominator: 3 };
+
+	/// Whether this is a share of less than the whole group.
+	pub fn is_valid(&self) -> bool {
+		self.numerator < self.denominator
+	}
+
+	/// The number of attestations needed from a validator group of `group_len` members.
+	/// An invalid threshold requires the whole group.
+	///
+	/// This is the smallest number of members exceeding the share of the group, so a
+	/// group of even size needs one attestation more than half of it for a majority.
+	pub fn required_votes(&self, group_len: usize) -> usize {
+		if !self.is_valid() {
+			return group_len;
+		}
+
+		let (numerator, denominator) = (self.numerator as u64, self.denominator as u64);
+		let needed = group_len as u64 * numerator / denominator + 1;
+		needed as usize
+	}
+}
+
+impl Default for ValidityThreshold {
+	fn default() -> Self {
+		ValidityThreshold::MAJORITY
+	}
 }
 
 /// An outgoing message
@@ -128,6 +186,12 @@ pub struct CandidateReceipt {
 	pub fees: u64,
 	/// blake2-256 Hash of block data.
 	pub block_data_hash: Hash,
+	/// blake2-256 hash of new validation code for the parachain, as produced by the
+	/// validation function. The code itself is submitted to the relay chain separately.
+	pub new_validation_code_hash: Option<Hash>,
+	/// Merkle root of the erasure-coded chunks of the block data and extrinsic,
+	/// one for each validator at the relay parent.
+	pub erasure_root: Hash,
 }
 
 impl CandidateReceipt {
@@ -169,25 +233,123 @@ impl Ord for CandidateReceipt {
 #[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
 #[cfg_attr(feature = "std", serde(deny_unknown_fields))]
 pub struct Collation {
-	/// Block data.
-	pub block_data: BlockData,
 	/// Candidate receipt itself.
 	pub receipt: CandidateReceipt,
+	/// A proof-of-validation for the receipt.
+	pub pov: PoVBlock,
+}
+
+/// A Proof-of-Validation block.
+///
+/// This contains everything a validator needs to check the validity of a
+/// candidate, apart from the relay-chain state it is checked against.
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[cfg_attr(feature = "std", serde(deny_unknown_fields))]
+pub struct PoVBlock {
+	/// Block data.
+	pub block_data: BlockData,
+	/// Ingress for the parachain, which must match the unrouted
+	/// ingress roots on the relay chain.
+	pub ingress: ConsolidatedIngress,
+}
+
+/// A chunk of the erasure-coded block data and extrinsic of a candidate,
+/// along with a merkle proof of its inclusion under the candidate's erasure root.
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct ErasureChunk {
+	/// The erasure-coded chunk of data.
+	pub chunk: Vec<u8>,
+	/// The index of the chunk, which is the index of the validator it is meant for.
+	pub index: u32,
+	/// Merkle proof of the chunk's hash under the erasure root.
+	pub proof: Vec<Vec<u8>>,
 }
 
 /// Parachain ingress queue message.
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
 pub struct Message(#[cfg_attr(feature = "std", serde(with="bytes"))] pub Vec<u8>);
 
 /// Consolidated ingress queue data.
 ///
 /// This is just an ordered vector of other parachains' egress queues,
-/// obtained according to the routing rules.
-#[derive(Default, PartialEq, Eq, Clone)]
+/// obtained according to the routing rules. The same parachain may appear
+/// more than once.
+#[derive(Default, PartialEq, Eq, Clone, Encode, Decode)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
 pub struct ConsolidatedIngress(pub Vec<(Id, Vec<Message>)>);
 
+/// Egress queue roots posted to a single parachain within one relay-chain block.
+///
+/// This is an ordered vector of (sender, egress root) pairs, sorted ascending
+/// by the ID of the sending parachain. Each parachain may appear at most once.
+#[derive(Default, PartialEq, Eq, Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+pub struct BlockIngressRoots(pub Vec<(Id, Hash)>);
+
+/// All ingress roots to a parachain which have not yet been routed, ordered
+/// ascending by the relay-chain block number at which they were posted.
+#[derive(Default, PartialEq, Eq, Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+pub struct StructuredUnroutedIngress(pub Vec<(BlockNumber, BlockIngressRoots)>);
+
+impl StructuredUnroutedIngress {
+	/// Get the number of ingress roots across all blocks.
+	pub fn len(&self) -> usize {
+		self.0.iter().fold(0, |a, &(_, ref roots)| a + roots.0.len())
+	}
+
+	/// Returns an iterator over all ingress roots. The block number indicates
+	/// the height at which that root was posted to the relay chain and the parachain ID
+	/// is the sender of the messages.
+	pub fn iter(&self) -> impl Iterator<Item=(BlockNumber, &Id, &Hash)> {
+		self.0.iter().flat_map(|&(n, ref roots)|
+			roots.0.iter().map(move |&(ref from, ref root)| (n, from, root))
+		)
+	}
+}
+
+/// Pricing and limits for messages posted by a parachain candidate.
+///
+/// The fees for all messages posted by a candidate must be covered by the
+/// fees declared in its receipt.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[cfg_attr(feature = "std", serde(deny_unknown_fields))]
+pub struct FeeSchedule {
+	/// Fee charged for every posted message.
+	pub base: u64,
+	/// Fee charged for every byte of posted message data.
+	pub per_byte: u64,
+	/// Maximum number of messages a single candidate may post.
+	pub max_messages: u32,
+	/// Maximum size of a single message, in bytes.
+	pub max_message_size: u32,
+}
+
+impl FeeSchedule {
+	/// Compute the fee for posting a message of `len` bytes. `None` on overflow.
+	pub fn compute_message_fee(&self, len: usize) -> Option<u64> {
+		self.per_byte.checked_mul(len as u64)?.checked_add(self.base)
+	}
+}
+
+impl Default for FeeSchedule {
+	// free and effectively unlimited.
+	fn default() -> Self {
+		FeeSchedule {
+			base: 0,
+			per_byte: 0,
+			max_messages: u32::max_value(),
+			max_message_size: u32::max_value(),
+		}
+	}
+}
+
 /// Parachain block data.
 ///
 /// contains everything required to validate para-block, may contain block and witness data
@@ -219,10 +381,61 @@ pub struct HeadData(#[cfg_attr(feature = "std", serde(with="bytes"))] pub Vec<u8
 pub struct ValidationCode(#[cfg_attr(feature = "std", serde(with="bytes"))] pub Vec<u8>);
 
 /// Activity bit field
+///
+/// Bits are ordered from the most significant bit of the first byte onwards.
 #[derive(PartialEq, Eq, Clone, Default, Encode, Decode)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
 pub struct Activity(#[cfg_attr(feature = "std", serde(with="bytes"))] pub Vec<u8>);
 
+impl Activity {
+	/// Create a bit field with room for `len` bits, all unset.
+	pub fn new(len: usize) -> Self {
+		Activity(::rstd::iter::repeat(0).take((len + 7) / 8).collect())
+	}
+
+	/// Whether the bit at `index` is set. Bits out of range are unset.
+	pub fn get(&self, index: usize) -> bool {
+		self.0.get(index / 8).map_or(false, |byte| byte & (0x80 >> (index % 8)) != 0)
+	}
+
+	/// Set the bit at `index`. Panics if it is out of range.
+	pub fn set(&mut self, index: usize, value: bool) {
+		let mask = 0x80 >> (index % 8);
+		if value {
+			self.0[index / 8] |= mask;
+		} else {
+			self.0[index / 8] &= !mask;
+		}
+	}
+
+	/// The payload signed by a validator signalling this availability activity
+	/// on top of the given relay parent.
+	pub fn signing_payload(&self, parent_hash: &Hash) -> Vec<u8> {
+		use codec::Encode;
+
+		let mut encoded = b"availability".to_vec();
+		self.using_encoded(|s| encoded.extend(s));
+		encoded.extend(parent_hash.as_ref());
+		encoded
+	}
+}
+
+/// A validator's signed statement of which candidates pending availability it holds
+/// its erasure chunk of.
+///
+/// Bit `i` of the activity refers to the `i`-th candidate pending availability
+/// at the relay parent.
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct SignedActivity {
+	/// The index of the validator in the authority set.
+	pub validator_index: u32,
+	/// The candidates the validator holds its chunk of.
+	pub activity: Activity,
+	/// The validator's signature on the activity's signing payload.
+	pub signature: CandidateSignature,
+}
+
 /// Statements which can be made about parachain candidates.
 #[derive(Clone, PartialEq, Eq, Encode)]
 #[cfg_attr(feature = "std", derive(Debug))]
@@ -254,16 +467,54 @@ pub enum ValidityAttestation {
 }
 
 /// An attested candidate.
+///
+/// Attestations are given by members of the validator group of the candidate's
+/// parachain: the validators with a duty to validate it, in the order of the
+/// duty roster.
 #[derive(Clone, PartialEq, Decode, Encode)]
 #[cfg_attr(feature = "std", derive(Debug))]
 pub struct AttestedCandidate {
 	/// The candidate data.
 	pub candidate: CandidateReceipt,
-	/// Validity attestations.
-	pub validity_votes: Vec<(SessionKey, ValidityAttestation)>,
+	/// Validity attestations, in the order of the set bits of `validator_indices`.
+	pub validity_votes: Vec<ValidityAttestation>,
+	/// Which members of the validator group attested to the candidate's validity.
+	pub validator_indices: Activity,
 }
 
 impl AttestedCandidate {
+	/// Create an attested candidate from attestations given by members of the
+	/// validator group, which is ordered as in the duty roster.
+	///
+	/// Returns `None` if an attestation is given by a validator outside of the group,
+	/// or multiple attestations by the same validator.
+	pub fn from_attestations<I>(candidate: CandidateReceipt, group: &[SessionKey], attestations: I)
+		-> Option<Self>
+		where I: IntoIterator<Item=(SessionKey, ValidityAttestation)>
+	{
+		let mut votes = Vec::new();
+		for (validator, attestation) in attestations {
+			let position = group.iter().position(|v| v == &validator)?;
+			votes.push((position, attestation));
+		}
+
+		votes.sort_unstable_by_key(|&(position, _)| position);
+
+		let mut validator_indices = Activity::new(group.len());
+		for &(position, _) in &votes {
+			if validator_indices.get(position) {
+				return None;
+			}
+			validator_indices.set(position, true);
+		}
+
+		Some(AttestedCandidate {
+			candidate,
+			validity_votes: votes.into_iter().map(|(_, attestation)| attestation).collect(),
+			validator_indices,
+		})
+	}
+
 	/// Get the candidate.
 	pub fn candidate(&self) -> &CandidateReceipt {
 		&self.candidate
@@ -273,6 +524,36 @@ impl AttestedCandidate {
 	pub fn parachain_index(&self) -> Id {
 		self.candidate.parachain_index
 	}
+
+	/// The positions in the validator group of the validators which attested to
+	/// the candidate, in the order of `validity_votes`.
+	pub fn voter_positions(&self) -> Vec<usize> {
+		(0..self.validator_indices.0.len() * 8)
+			.filter(|&i| self.validator_indices.get(i))
+			.collect()
+	}
+}
+
+/// An attested candidate in the encoding used before attestations referred to
+/// validators by their position in the validator group.
+///
+/// Still accepted by the runtime as inherent data from nodes which haven't
+/// upgraded, and converted using the duty roster.
+#[derive(Clone, PartialEq, Decode, Encode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct LegacyAttestedCandidate {
+	/// The candidate data.
+	pub candidate: CandidateReceipt,
+	/// Validity attestations, naming the validator with each.
+	pub validity_votes: Vec<(SessionKey, ValidityAttestation)>,
+}
+
+impl LegacyAttestedCandidate {
+	/// Convert to the compact encoding, given the validator group of the candidate's
+	/// parachain ordered as in the duty roster. See `AttestedCandidate::from_attestations`.
+	pub fn into_compact(self, group: &[SessionKey]) -> Option<AttestedCandidate> {
+		AttestedCandidate::from_attestations(self.candidate, group, self.validity_votes)
+	}
 }
 
 decl_runtime_apis! {
@@ -282,12 +563,45 @@ decl_runtime_apis! {
 		fn validators() -> Vec<AccountId>;
 		/// Get the current duty roster.
 		fn duty_roster() -> DutyRoster;
+		/// Get the duty roster of a future block, assuming the validators and scheduled
+		/// chains stay the same until then. `None` if the roster is reshuffled at random
+		/// every block. Secondary checkers are chosen at random, so none are assigned.
+		fn duty_roster_at(number: BlockNumber) -> Option<DutyRoster>;
 		/// Get the currently active parachains.
 		fn active_parachains() -> Vec<Id>;
+		/// Get the parathreads whose candidates may be included in the next block.
+		fn parathread_schedule() -> Vec<Id>;
 		/// Get the given parachain's head data blob.
 		fn parachain_head(id: Id) -> Option<Vec<u8>>;
+		/// Get the most recent heads of the given parachain, oldest first, along with the
+		/// block number at which each became the head and the hash of its candidate.
+		fn recent_heads(id: Id) -> Vec<(BlockNumber, Hash, Vec<u8>)>;
+		/// Get the free balance of the relay-chain account of the given parachain, which
+		/// pays for the balance uploads and fees of its candidates.
+		fn parachain_balance(id: Id) -> Balance;
 		/// Get the given parachain's head code blob.
 		fn parachain_code(id: Id) -> Option<Vec<u8>>;
+		/// Get the hash of the validation code scheduled to replace the given parachain's
+		/// code, along with the block number at the end of which it comes into use.
+		fn parachain_pending_code(id: Id) -> Option<(BlockNumber, Hash)>;
+		/// Get all the unrouted ingress roots targeting the given parachain,
+		/// or `None` if the parachain doesn't exist.
+		fn ingress(to: Id) -> Option<StructuredUnroutedIngress>;
+		/// Get the fee schedule for messages posted by parachains.
+		fn fee_schedule() -> FeeSchedule;
+		/// Get the amount of fuel a parachain's validation function may consume
+		/// when validating a candidate.
+		fn validation_fuel_limit() -> u64;
+		/// Get the maximum size of a parachain's validation code, in bytes.
+		fn max_code_size() -> u32;
+		/// Get the share of a validator group which must attest to a candidate's validity.
+		fn validity_threshold() -> ValidityThreshold;
+		/// Get the candidates pending availability along with the relay parent each was
+		/// included on top of. Availability bit fields refer to candidates in this order.
+		fn pending_availability() -> Vec<(Hash, CandidateReceipt)>;
+		/// Get the number of blocks after its inclusion during which a candidate may be
+		/// disputed.
+		fn dispute_period() -> BlockNumber;
 	}
 }
 
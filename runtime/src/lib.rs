@@ -28,10 +28,6 @@ extern crate secp256k1;
 #[cfg(test)]
 extern crate tiny_keccak;
 
-
-#[macro_use]
-extern crate bitvec;
-
 #[macro_use]
 extern crate parity_codec_derive;
 extern crate parity_codec as codec;
@@ -67,12 +63,14 @@ extern crate srml_treasury as treasury;
 extern crate srml_upgrade_key as upgrade_key;
 
 extern crate polkadot_primitives as primitives;
+extern crate polkadot_statement_table as statement_table;
 
 #[cfg(test)]
 extern crate substrate_keyring as keyring;
 
 mod parachains;
 mod claims;
+mod slots;
 
 use rstd::prelude::*;
 use substrate_primitives::u32_trait::{_2, _4};
@@ -102,7 +100,14 @@ pub use sr_primitives::BuildStorage;
 pub use consensus::Call as ConsensusCall;
 pub use timestamp::Call as TimestampCall;
 pub use balances::Call as BalancesCall;
-pub use parachains::{Call as ParachainsCall, INHERENT_IDENTIFIER as PARACHAIN_INHERENT_IDENTIFIER};
+pub use parachains::{
+	Call as ParachainsCall, INHERENT_IDENTIFIER as PARACHAIN_INHERENT_IDENTIFIER,
+	LEGACY_INHERENT_IDENTIFIER as LEGACY_PARACHAIN_INHERENT_IDENTIFIER,
+	AVAILABILITY_INHERENT_IDENTIFIER, MISBEHAVIOR_INHERENT_IDENTIFIER,
+	LOCAL_CANDIDATES_INHERENT_IDENTIFIER, RELAY_PARENT_INHERENT_IDENTIFIER,
+	DISPUTES_INHERENT_IDENTIFIER, LocalCandidates, DisputeVote,
+	InherentError as ParachainsInherentError,
+};
 pub use sr_primitives::{Permill, Perbill};
 pub use timestamp::BlockPeriod;
 pub use srml_support::StorageValue;
@@ -112,7 +117,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("polkadot"),
 	impl_name: create_runtime_str!("parity-polkadot"),
 	authoring_version: 1,
-	spec_version: 106,
+	spec_version: 132,
 	impl_version: 0,
 	apis: RUNTIME_API_VERSIONS,
 };
@@ -223,7 +228,10 @@ impl grandpa::Trait for Runtime {
 	type Event = Event;
 }
 
-impl parachains::Trait for Runtime {}
+impl parachains::Trait for Runtime {
+	type Event = Event;
+	type HandleMisbehavior = parachains::Slasher<Runtime>;
+}
 
 impl upgrade_key::Trait for Runtime {
 	type Event = Event;
@@ -238,6 +246,10 @@ impl claims::Trait for Runtime {
 	type Event = Event;
 }
 
+impl slots::Trait for Runtime {
+	type Event = Event;
+}
+
 construct_runtime!(
 	pub enum Runtime with Log(InternalLog: DigestItem<Hash, SessionKey>) where
 		Block = Block,
@@ -260,10 +272,11 @@ construct_runtime!(
 		CouncilMotions: council_motions::{Module, Call, Storage, Event<T>, Origin},
 		CouncilSeats: council_seats::{Config<T>},
 		Treasury: treasury,
-		Parachains: parachains::{Module, Call, Storage, Config<T>, Inherent},
+		Parachains: parachains::{Module, Call, Storage, Config<T>, Inherent, Event<T>},
 		Sudo: sudo,
 		UpgradeKey: upgrade_key,
 		Claims: claims,
+		Slots: slots,
 	}
 );
 
@@ -323,6 +336,9 @@ impl_runtime_apis! {
 		}
 
 		fn check_inherents(block: Block, data: InherentData) -> CheckInherentsResult {
+			// the parachain heads are checked against the statement table on top of the parent.
+			let mut data = data;
+			let _ = data.put_data(parachains::RELAY_PARENT_INHERENT_IDENTIFIER, &block.header.parent_hash);
 			data.check_extrinsics(&block)
 		}
 
@@ -344,15 +360,53 @@ impl_runtime_apis! {
 		fn duty_roster() -> parachain::DutyRoster {
 			Parachains::calculate_duty_roster()
 		}
+		fn duty_roster_at(number: BlockNumber) -> Option<parachain::DutyRoster> {
+			Parachains::future_duty_roster(number)
+		}
 		fn active_parachains() -> Vec<parachain::Id> {
 			Parachains::active_parachains()
 		}
+		fn parathread_schedule() -> Vec<parachain::Id> {
+			Parachains::parathread_schedule()
+		}
+		fn recent_heads(id: parachain::Id) -> Vec<(BlockNumber, Hash, Vec<u8>)> {
+			Parachains::recent_heads(&id)
+		}
 		fn parachain_head(id: parachain::Id) -> Option<Vec<u8>> {
 			Parachains::parachain_head(&id)
 		}
+		fn parachain_balance(id: parachain::Id) -> Balance {
+			Balances::free_balance(&Parachains::parachain_account(id))
+		}
 		fn parachain_code(id: parachain::Id) -> Option<Vec<u8>> {
 			Parachains::parachain_code(&id)
 		}
+		fn parachain_pending_code(id: parachain::Id) -> Option<(BlockNumber, Hash)> {
+			Parachains::parachain_pending_code(&id)
+		}
+		fn ingress(to: parachain::Id) -> Option<parachain::StructuredUnroutedIngress> {
+			Parachains::ingress(to).map(parachain::StructuredUnroutedIngress)
+		}
+		fn fee_schedule() -> parachain::FeeSchedule {
+			Parachains::fee_schedule()
+		}
+		fn validation_fuel_limit() -> u64 {
+			Parachains::validation_fuel_limit()
+		}
+		fn max_code_size() -> u32 {
+			Parachains::max_code_size()
+		}
+		fn validity_threshold() -> parachain::ValidityThreshold {
+			Parachains::validity_threshold()
+		}
+		fn pending_availability() -> Vec<(Hash, parachain::CandidateReceipt)> {
+			Parachains::pending_availability().into_iter()
+				.map(|pending| (pending.relay_parent, pending.candidate))
+				.collect()
+		}
+		fn dispute_period() -> BlockNumber {
+			Parachains::dispute_period()
+		}
 	}
 
 	impl fg_primitives::GrandpaApi<Block> for Runtime {
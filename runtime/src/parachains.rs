@@ -17,27 +17,203 @@
 //! Main parachains logic. For now this is just the determination of which validators do what.
 
 use rstd::prelude::*;
+use rstd::collections::btree_map::BTreeMap;
 use codec::Decode;
 
-use bitvec::BigEndian;
-use sr_primitives::traits::{Hash as HashT, BlakeTwo256};
-use primitives::parachain::{Id as ParaId, Chain, DutyRoster, AttestedCandidate, Statement};
-use {system, session};
+use sr_primitives::traits::{Hash as HashT, BlakeTwo256, SimpleArithmetic, One, Zero, As};
+use primitives::{Hash, AccountId, SessionKey};
+use primitives::parachain::{
+	Id as ParaId, Chain, DutyRoster, AttestedCandidate, LegacyAttestedCandidate, CandidateReceipt, Statement,
+	BlockIngressRoots, FeeSchedule, ValidityThreshold, SignedActivity, CandidateSignature,
+};
+use {system, session, balances, staking, statement_table};
+use slots::FIRST_AUCTIONED_PARA_ID;
 
 use srml_support::{StorageValue, StorageMap};
 use srml_support::dispatch::Result;
 
-use inherents::{ProvideInherent, InherentData, RuntimeString, MakeFatalError, InherentIdentifier};
+use inherents::{ProvideInherent, InherentData, IsFatalError, InherentIdentifier};
 
 #[cfg(any(feature = "std", test))]
 use sr_primitives::{self, ChildrenStorageMap};
 
-#[cfg(any(feature = "std", test))]
 use rstd::marker::PhantomData;
 
-use system::ensure_inherent;
+use system::{ensure_inherent, ensure_signed};
+
+/// The default number of relay-chain blocks between a candidate scheduling
+/// new validation code and the code coming into use.
+pub const DEFAULT_CODE_UPGRADE_DELAY: u64 = 10;
+
+/// The default amount of fuel the validation function of a parachain may consume
+/// when validating a candidate. Roughly one unit is consumed per instruction.
+pub const DEFAULT_VALIDATION_FUEL_LIMIT: u64 = 1_000_000_000;
+
+/// The default maximum size of new validation code for a parachain, in bytes.
+pub const DEFAULT_MAX_CODE_SIZE: u32 = 1024 * 1024;
+
+/// The default number of relay-chain blocks a candidate may remain pending
+/// availability before it is reverted.
+pub const DEFAULT_AVAILABILITY_TIMEOUT: u64 = 20;
+
+/// The default deposit reserved for registering a parathread: 10 dollars,
+/// at 10^14 units to the dollar.
+pub const DEFAULT_PARATHREAD_DEPOSIT: u64 = 1_000_000_000_000_000;
+
+/// The default maximum number of parathreads scheduled for a block.
+pub const DEFAULT_MAX_PARATHREADS_PER_BLOCK: u32 = 2;
+
+/// The number of recent heads of each parachain kept in storage.
+pub const MAX_HEAD_HISTORY: usize = 16;
+
+/// The default number of relay-chain blocks after its inclusion during which
+/// a candidate may be disputed, and after which an unresolved dispute is dropped.
+pub const DEFAULT_DISPUTE_PERIOD: u64 = 100;
+
+/// A candidate which has been included in a block, but is not yet known to be
+/// available. Its effects are only applied once it becomes available.
+#[derive(Clone, PartialEq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct PendingCandidate<BlockNumber> {
+	/// The candidate. Its attestations were checked on inclusion.
+	pub candidate: CandidateReceipt,
+	/// The relay-chain block the candidate was included on top of.
+	pub relay_parent: Hash,
+	/// The relay-chain block the candidate was included in.
+	pub included_at: BlockNumber,
+	/// Indices of the authorities which attested to the candidate's validity.
+	pub voters: Vec<u32>,
+	/// Indices of the authorities which have signalled holding their chunk of the candidate.
+	pub available_from: Vec<u32>,
+}
+
+/// A candidate included in a recent block, which may still be disputed.
+#[derive(Clone, PartialEq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct IncludedCandidate<BlockNumber> {
+	/// The parachain of the candidate.
+	pub parachain_index: ParaId,
+	/// The relay-chain block the candidate was included on top of. Votes in a
+	/// dispute about the candidate are statements localized to it.
+	pub relay_parent: Hash,
+	/// The relay-chain block the candidate was included in.
+	pub included_at: BlockNumber,
+	/// The head of the parachain the candidate was built on.
+	pub parent_head: Vec<u8>,
+	/// The authorities which attested to the candidate's validity.
+	pub backers: Vec<SessionKey>,
+	/// Whether the candidate became available and its effects were applied.
+	pub enacted: bool,
+}
+
+/// A dispute about the validity of an included candidate.
+#[derive(Clone, PartialEq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Dispute<BlockNumber> {
+	/// The disputed candidate.
+	pub candidate: IncludedCandidate<BlockNumber>,
+	/// The relay-chain block the dispute was raised in.
+	pub raised_at: BlockNumber,
+	/// The authorities which consider the candidate valid, starting with its backers.
+	pub valid: Vec<SessionKey>,
+	/// The authorities which consider the candidate invalid.
+	pub invalid: Vec<SessionKey>,
+}
+
+/// A candidate pending availability in the encoding used before attestations referred
+/// to validators by their position in the validator group.
+#[derive(Encode, Decode)]
+struct LegacyPendingCandidate<BlockNumber> {
+	attested: LegacyAttestedCandidate,
+	relay_parent: Hash,
+	included_at: BlockNumber,
+	voters: Vec<u32>,
+	available_from: Vec<u32>,
+}
+
+/// The storage key candidates pending availability were kept under in the legacy encoding.
+const LEGACY_PENDING_AVAILABILITY_KEY: &[u8] = b"Parachains PendingAvailability";
+
+/// A report of misbehavior in the statement table of the parent block: the
+/// offending authority, along with proof.
+pub type MisbehaviorReport = (SessionKey, statement_table::Misbehavior);
+
+/// A vote about the validity of a recently included candidate: the hash of the
+/// candidate, the voting authority, whether it considers the candidate valid and
+/// the signature on its statement, localized to the relay parent of the candidate.
+pub type DisputeVote = (Hash, SessionKey, bool, CandidateSignature);
+
+/// Something which punishes authorities proven to have misbehaved in the statement table.
+pub trait HandleMisbehavior {
+	/// Punish the authority with the given index.
+	fn handle_misbehavior(authority_index: usize);
+}
+
+impl HandleMisbehavior for () {
+	fn handle_misbehavior(_authority_index: usize) { }
+}
+
+/// Reports misbehaving validators to staking, which slashes them along with
+/// their nominators as it does validators found offline.
+///
+/// Unlike being offline, misbehavior in the statement table can't be accidental,
+/// so it isn't subject to the grace period staking allows offline validators.
+pub struct Slasher<T>(PhantomData<T>);
+
+impl<T: Trait + staking::Trait> HandleMisbehavior for Slasher<T> {
+	fn handle_misbehavior(authority_index: usize) {
+		let validators = <session::Module<T>>::validators();
+		if let Some(validator) = validators.get(authority_index) {
+			// report as many offences as it takes to use up the grace period.
+			let grace = <staking::Module<T>>::offline_slash_grace();
+			let offences = grace.saturating_sub(<staking::Module<T>>::slash_count(validator)) as usize + 1;
+			<staking::Module<T>>::on_offline_validator(validator.clone(), offences);
+			<Module<T>>::deposit_event(RawEvent::MisbehaviorReported(validator.clone()));
+		}
+	}
+}
+
+pub trait Trait: session::Trait + balances::Trait + system::Trait<AccountId = AccountId> {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
 
-pub trait Trait: session::Trait {}
+	/// Punishes authorities proven to have misbehaved.
+	type HandleMisbehavior: HandleMisbehavior;
+}
+
+/// An event in this module.
+decl_event!(
+	pub enum Event<T> where
+		N = <T as system::Trait>::BlockNumber
+	{
+		/// A parachain was registered.
+		ParachainRegistered(ParaId),
+		/// A parachain was deregistered.
+		ParachainDeregistered(ParaId),
+		/// A parathread was registered by the given account.
+		ParathreadRegistered(ParaId, AccountId),
+		/// A parathread was deregistered.
+		ParathreadDeregistered(ParaId),
+		/// A candidate became the head of a chain: the hash of the candidate and its collator.
+		HeadUpdated(ParaId, Hash, AccountId),
+		/// A candidate scheduled new validation code, coming into use at the end of the given block.
+		CodeUpgradeScheduled(ParaId, N),
+		/// New validation code came into use.
+		CodeUpgraded(ParaId),
+		/// Scheduled validation code wasn't submitted before it was due to come into
+		/// use, so the upgrade was dropped.
+		CodeUpgradeDropped(ParaId),
+		/// The validity of the candidate with the given hash was disputed.
+		DisputeRaised(ParaId, Hash),
+		/// A dispute was resolved: whether the candidate was found valid. Invalid
+		/// candidates are reverted.
+		DisputeResolved(Hash, bool),
+		/// A dispute was dropped without enough votes on either side.
+		DisputeTimedOut(Hash),
+		/// A validator was reported to staking for misbehavior in the statement table.
+		MisbehaviorReported(AccountId),
+	}
+);
 
 decl_storage! {
 	trait Store for Module<T: Trait> as Parachains {
@@ -47,6 +223,93 @@ decl_storage! {
 		pub Code get(parachain_code): map ParaId => Option<Vec<u8>>;
 		// The heads of the parachains registered at present. these are kept sorted.
 		pub Heads get(parachain_head): map ParaId => Option<Vec<u8>>;
+		// The most recent heads of each chain, oldest first, along with the block in which
+		// each became the head and the hash of its candidate. At most `MAX_HEAD_HISTORY` are kept.
+		pub RecentHeads get(recent_heads): map ParaId => Vec<(T::BlockNumber, Hash, Vec<u8>)>;
+		// The watermark heights of the parachains registered at present.
+		// For every parachain, this is the relay-chain block height up to which all
+		// ingress to that parachain has been routed.
+		pub Watermarks get(watermark): map ParaId => Option<T::BlockNumber>;
+
+		// Unrouted ingress. Maps (block number, to_chain) pairs to [(from_chain, egress_root)].
+		//
+		// There may be an entry under (i, p) in this map for every i between the parachain's
+		// watermark and the current block.
+		pub UnroutedIngress: map (T::BlockNumber, ParaId) => Option<Vec<(ParaId, Hash)>>;
+
+		// Fees and limits for messages posted by parachain candidates.
+		pub MessageFeeSchedule get(fee_schedule) config(): FeeSchedule;
+		// The amount of fuel the validation function of a parachain may consume.
+		pub ValidationFuelLimit get(validation_fuel_limit) config(): u64 = DEFAULT_VALIDATION_FUEL_LIMIT;
+		// The share of a validator group which must attest to a candidate's validity.
+		pub RequiredValidity get(validity_threshold) config(): ValidityThreshold;
+
+		// The number of relay-chain blocks between a candidate scheduling new
+		// validation code and the code coming into use.
+		pub CodeUpgradeDelay get(code_upgrade_delay) config():
+			T::BlockNumber = T::BlockNumber::sa(DEFAULT_CODE_UPGRADE_DELAY);
+		// The hash of validation code scheduled to replace the code of a parachain, along
+		// with the block number at the end of which it comes into use.
+		pub PendingCode get(parachain_pending_code): map ParaId => Option<(T::BlockNumber, Hash)>;
+		// Validation code submitted for the scheduled upgrade of a parachain.
+		pub SubmittedCode get(parachain_submitted_code): map ParaId => Option<Vec<u8>>;
+		// The maximum size of new validation code for a parachain, in bytes.
+		pub MaxCodeSize get(max_code_size) config(): u32 = DEFAULT_MAX_CODE_SIZE;
+		// The parachains whose pending code comes into use at the end of a block.
+		CodeUpgradesAt: map T::BlockNumber => Vec<ParaId>;
+
+		// Candidates included in past blocks which are not yet available, sorted
+		// ascending by parachain ID. Availability bit fields refer to them in this order.
+		pub PendingCandidates get(pending_availability): Vec<PendingCandidate<T::BlockNumber>>;
+		// The number of relay-chain blocks a candidate may remain pending
+		// availability before it is reverted.
+		pub AvailabilityTimeout get(availability_timeout) config():
+			T::BlockNumber = T::BlockNumber::sa(DEFAULT_AVAILABILITY_TIMEOUT);
+
+		// The parathreads registered at present, sorted ascending. They share code and
+		// head storage with parachains, but only have candidates included in the blocks
+		// they are scheduled for.
+		pub Parathreads get(parathreads): Vec<ParaId>;
+		// The account which registered each parathread, along with its deposit.
+		pub ParathreadOwners get(parathread_owner): map ParaId => Option<(T::AccountId, T::Balance)>;
+		// The deposit reserved for registering a parathread.
+		pub ParathreadDeposit get(parathread_deposit) config():
+			T::Balance = T::Balance::sa(DEFAULT_PARATHREAD_DEPOSIT);
+		// The maximum number of parathreads scheduled for a block.
+		pub MaxParathreadsPerBlock get(max_parathreads_per_block) config():
+			u32 = DEFAULT_MAX_PARATHREADS_PER_BLOCK;
+
+		// The minimum number of validators in the group of a chain. Chains which can't all
+		// be given groups of this size take turns having one. Zero means no minimum.
+		pub MinGroupSize get(min_group_size) config(): u32;
+		// The maximum number of validators in the group of a chain. Validators left over
+		// validate the relay chain. Zero means no maximum.
+		pub MaxGroupSize get(max_group_size) config(): u32;
+		// The number of blocks after which validator groups rotate to the next chain.
+		// Zero means groups are reshuffled at random every block.
+		pub GroupRotationFrequency get(group_rotation_frequency) config(): T::BlockNumber;
+		// Bids placed in the current block for including a candidate of a parathread
+		// in the next one: the parathread, the bidder and the reserved bid.
+		pub ParathreadClaims get(parathread_claims): Vec<(ParaId, T::AccountId, T::Balance)>;
+		// The parathreads whose candidates may be included in the current block, sorted ascending.
+		pub ParathreadSchedule get(parathread_schedule): Vec<ParaId>;
+
+		// Candidates included in recent blocks which may still be disputed.
+		pub IncludedCandidates get(included_candidate): map Hash => Option<IncludedCandidate<T::BlockNumber>>;
+		// The hashes of the candidates included in each recent block.
+		IncludedAt: map T::BlockNumber => Vec<Hash>;
+		// The number of relay-chain blocks after its inclusion during which a candidate
+		// may be disputed, and after which an unresolved dispute is dropped.
+		pub DisputePeriod get(dispute_period) config():
+			T::BlockNumber = T::BlockNumber::sa(DEFAULT_DISPUTE_PERIOD);
+		// Ongoing disputes, by the hash of the disputed candidate.
+		pub Disputes get(dispute): map Hash => Option<Dispute<T::BlockNumber>>;
+		// The balance uploads and fee shares of enacted candidates, held back from their
+		// recipients while the candidates may still be disputed. Candidates found invalid
+		// return them to their chain.
+		pub HeldCredits get(held_credits): map Hash => Vec<(AccountId, u64)>;
+		// The hashes of the candidates disputed at present, in the order the disputes were raised.
+		pub OpenDisputes get(open_disputes): Vec<Hash>;
 
 		// Did the parachain heads get updated in this block?
 		DidUpdate: bool;
@@ -68,9 +331,11 @@ decl_storage! {
 			for (id, code, genesis) in p {
 				let code_key = Self::hash(&<Code<T>>::key_for(&id)).to_vec();
 				let head_key = Self::hash(&<Heads<T>>::key_for(&id)).to_vec();
+				let watermark_key = Self::hash(&<Watermarks<T>>::key_for(&id)).to_vec();
 
 				storage.insert(code_key, code.encode());
 				storage.insert(head_key, genesis.encode());
+				storage.insert(watermark_key, T::BlockNumber::zero().encode());
 			}
 		});
 	}
@@ -79,20 +344,43 @@ decl_storage! {
 decl_module! {
 	/// Parachains module.
 	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
-		/// Provide candidate receipts for parachains, in ascending order by id.
-		fn set_heads(origin, heads: Vec<AttestedCandidate>) -> Result {
+		fn deposit_event<T>() = default;
+
+		/// Provide candidate receipts for parachains, in ascending order by id, along with
+		/// the availability bit fields signed by validators on top of the parent block.
+		///
+		/// Candidates are only applied once more than two thirds of the validators
+		/// have signalled holding their chunk of them. Candidates of chains which can't
+		/// pay for their balance uploads and fees are left out. Authorities proven to have
+		/// misbehaved in the statement table of the parent block are punished, and
+		/// votes about the validity of recently included candidates are applied to
+		/// their disputes.
+		fn set_heads(
+			origin,
+			heads: Vec<AttestedCandidate>,
+			availability: Vec<SignedActivity>,
+			misbehavior: Vec<MisbehaviorReport>,
+			disputes: Vec<DisputeVote>
+		) -> Result {
 			ensure_inherent(origin)?;
 			ensure!(!<DidUpdate<T>>::exists(), "Parachain heads must be updated only once in the block");
 
 			let active_parachains = Self::active_parachains();
+			let scheduled_chains = Self::scheduled_chains();
+
+			Self::migrate_pending_candidates();
+
+			// availability refers to the candidates pending at the parent block, so it
+			// is accounted before any new candidates are added.
+			let (enacted, still_pending) = Self::process_availability(&availability)?;
 
 			// perform integrity checks before writing to storage.
 			{
-				let n_parachains = active_parachains.len();
+				let n_parachains = scheduled_chains.len();
 				ensure!(heads.len() <= n_parachains, "Too many parachain candidates");
 
 				let mut last_id = None;
-				let mut iter = active_parachains.iter();
+				let mut iter = scheduled_chains.iter();
 				for head in &heads {
 					// proposed heads must be ascending order by parachain ID without duplicate.
 					ensure!(
@@ -100,22 +388,53 @@ decl_module! {
 						"Parachain candidates out of order by ID"
 					);
 
-					// must be unknown since active parachains are always sorted.
+					// must be unknown since scheduled chains are always sorted.
 					ensure!(
 						iter.find(|x| x == &&head.parachain_index()).is_some(),
 						"Submitted candidate for unregistered or out-of-order parachain {}"
 					);
 
+					Self::check_egress_queue_roots(head, &active_parachains)?;
+
+					// only one candidate per parachain may be pending availability.
+					ensure!(
+						still_pending.iter().all(|p| p.candidate.parachain_index != head.parachain_index()),
+						"Parachain already has a candidate pending availability"
+					);
+
+					// only one code upgrade may be pending at a time, including one
+					// scheduled by a candidate enacted in this block.
+					ensure!(
+						head.candidate.new_validation_code_hash.is_none() || (
+							!<PendingCode<T>>::exists(&head.parachain_index())
+								&& !enacted.iter().any(|p| p.candidate.parachain_index == head.parachain_index()
+									&& p.candidate.new_validation_code_hash.is_some())
+						),
+						"Parachain already has a pending code upgrade"
+					);
+
 					last_id = Some(head.parachain_index());
 				}
 			}
 
-			Self::check_attestations(&heads)?;
+			// candidates of chains which can't pay for them are left out rather
+			// than failing the whole inherent.
+			let heads: Vec<_> = heads.into_iter()
+				.filter(|head| Self::check_balance(&head.candidate).is_ok())
+				.collect();
 
+			let voters = Self::check_attestations(&heads)?;
+			let offenders = Self::check_misbehavior(&misbehavior);
 
-			for head in heads {
-				let id = head.parachain_index();
-				<Heads<T>>::insert(id, head.candidate.head_data.0);
+			Self::enact_candidates(&enacted);
+			Self::add_pending(still_pending, heads, voters);
+
+			// applied last, so that reverting a candidate also reverts the ones
+			// added above on top of it.
+			Self::apply_dispute_votes(disputes);
+
+			for offender in offenders {
+				T::HandleMisbehavior::handle_misbehavior(offender);
 			}
 
 			<DidUpdate<T>>::put(true);
@@ -126,6 +445,8 @@ decl_module! {
 		/// Register a parachain with given code.
 		/// Fails if given ID is already used.
 		pub fn register_parachain(id: ParaId, code: Vec<u8>, initial_head_data: Vec<u8>) -> Result {
+			ensure!(Self::parathreads().binary_search(&id).is_err(), "Parathread already exists");
+
 			let mut parachains = Self::active_parachains();
 			match parachains.binary_search(&id) {
 				Ok(_) => fail!("Parachain already exists"),
@@ -136,6 +457,10 @@ decl_module! {
 			<Parachains<T>>::put(parachains);
 			<Heads<T>>::insert(id, initial_head_data);
 
+			// no ingress can be posted to the parachain until after it is registered.
+			<Watermarks<T>>::insert(id, <system::Module<T>>::block_number());
+
+			Self::deposit_event(RawEvent::ParachainRegistered(id));
 			Ok(())
 		}
 
@@ -143,26 +468,230 @@ decl_module! {
 		pub fn deregister_parachain(id: ParaId) -> Result {
 			let mut parachains = Self::active_parachains();
 			match parachains.binary_search(&id) {
-				Ok(idx) => { parachains.remove(idx); }
+				Ok(idx) => {
+					parachains.remove(idx);
+					Self::deposit_event(RawEvent::ParachainDeregistered(id));
+				}
 				Err(_) => {}
 			}
 
-			<Code<T>>::remove(id);
-			<Heads<T>>::remove(id);
+			Self::clear_chain(id);
+
 			<Parachains<T>>::put(parachains);
 			Ok(())
 		}
 
-		fn on_finalise(_n: T::BlockNumber) {
+		/// Register a parathread with given code, reserving the parathread deposit
+		/// from the sender. Fails if given ID is already used, or is in the range
+		/// of IDs given to parachains leased through auctions.
+		fn register_parathread(origin, id: ParaId, code: Vec<u8>, initial_head_data: Vec<u8>) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(u32::from(id) < FIRST_AUCTIONED_PARA_ID, "Parathread ID reserved for auctioned parachains");
+			ensure!(Self::active_parachains().binary_search(&id).is_err(), "Parachain already exists");
+
+			let mut parathreads = Self::parathreads();
+			let idx = match parathreads.binary_search(&id) {
+				Ok(_) => fail!("Parathread already exists"),
+				Err(idx) => idx,
+			};
+
+			let deposit = Self::parathread_deposit();
+			<balances::Module<T>>::reserve(&who, deposit)?;
+
+			parathreads.insert(idx, id);
+			<Parathreads<T>>::put(parathreads);
+			<ParathreadOwners<T>>::insert(id, (who.clone(), deposit));
+			<Code<T>>::insert(id, code);
+			<Heads<T>>::insert(id, initial_head_data);
+
+			// no ingress can be posted to the parathread until after it is registered.
+			<Watermarks<T>>::insert(id, <system::Module<T>>::block_number());
+
+			Self::deposit_event(RawEvent::ParathreadRegistered(id, who));
+			Ok(())
+		}
+
+		/// Deregister a parathread registered by the sender, unreserving its deposit
+		/// and any outstanding bids for it.
+		fn deregister_parathread(origin, id: ParaId) -> Result {
+			let who = ensure_signed(origin)?;
+			let (owner, deposit) = Self::parathread_owner(id).ok_or("Parathread does not exist")?;
+			ensure!(owner == who, "Parathread not registered by sender");
+
+			<Parathreads<T>>::mutate(|parathreads| parathreads.retain(|x| x != &id));
+			<ParathreadSchedule<T>>::mutate(|schedule| schedule.retain(|x| x != &id));
+			<ParathreadOwners<T>>::remove(id);
+
+			let (removed, claims): (Vec<_>, Vec<_>) = <ParathreadClaims<T>>::take().into_iter()
+				.partition(|&(ref claimed, _, _)| claimed == &id);
+			for (_, bidder, bid) in removed {
+				<balances::Module<T>>::unreserve(&bidder, bid);
+			}
+			<ParathreadClaims<T>>::put(claims);
+
+			Self::clear_chain(id);
+			<balances::Module<T>>::unreserve(&owner, deposit);
+
+			Self::deposit_event(RawEvent::ParathreadDeregistered(id));
+			Ok(())
+		}
+
+		/// Bid to have a candidate of the given parathread included in the next block.
+		///
+		/// The bid is reserved, and only paid if the parathread is scheduled. The highest
+		/// bids placed in a block are scheduled, up to the maximum number of parathreads
+		/// per block.
+		fn claim_parathread(origin, id: ParaId, bid: T::Balance) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(Self::parathreads().binary_search(&id).is_ok(), "Parathread does not exist");
+			ensure!(!bid.is_zero(), "Bid must be non-zero");
+
+			let mut claims = Self::parathread_claims();
+			ensure!(
+				!claims.iter().any(|&(ref claimed, ref bidder, _)| claimed == &id && bidder == &who),
+				"Parathread already claimed by sender"
+			);
+
+			<balances::Module<T>>::reserve(&who, bid)?;
+
+			claims.push((id, who, bid));
+			<ParathreadClaims<T>>::put(claims);
+
+			Ok(())
+		}
+
+		/// Set the fee schedule for messages posted by parachains.
+		pub fn set_fee_schedule(schedule: FeeSchedule) -> Result {
+			<MessageFeeSchedule<T>>::put(schedule);
+			Ok(())
+		}
+
+		/// Set the amount of fuel the validation function of a parachain may consume
+		/// when validating a candidate.
+		pub fn set_validation_fuel_limit(limit: u64) -> Result {
+			ensure!(limit != 0, "Validation fuel limit must be non-zero");
+
+			<ValidationFuelLimit<T>>::put(limit);
+			Ok(())
+		}
+
+		/// Set the share of a validator group which must be exceeded by the attestations
+		/// to a candidate's validity for it to be included.
+		pub fn set_validity_threshold(threshold: ValidityThreshold) -> Result {
+			ensure!(threshold.is_valid(), "Validity threshold must be a share of less than the whole group");
+			// attestations must come from a strict majority of the group, since otherwise
+			// disjoint parts of it could back conflicting candidates.
+			ensure!(
+				threshold.numerator as u64 * 2 >= threshold.denominator as u64,
+				"Validity threshold must be at least half of the group"
+			);
+
+			<RequiredValidity<T>>::put(threshold);
+			Ok(())
+		}
+
+		/// Set the number of blocks between a candidate scheduling new validation
+		/// code and the code coming into use. Already scheduled upgrades are unaffected.
+		pub fn set_code_upgrade_delay(delay: T::BlockNumber) -> Result {
+			<CodeUpgradeDelay<T>>::put(delay);
+			Ok(())
+		}
+
+		/// Submit the validation code of the scheduled upgrade of a parachain. Candidates
+		/// only commit to the hash of new code, so the code must be submitted before the
+		/// upgrade is due, or the upgrade is dropped.
+		fn submit_validation_code(origin, id: ParaId, code: Vec<u8>) -> Result {
+			ensure_signed(origin)?;
+			ensure!(code.len() <= Self::max_code_size() as usize, "Validation code too large");
+
+			let (_, code_hash) = Self::parachain_pending_code(&id).ok_or("No code upgrade scheduled")?;
+			ensure!(!<SubmittedCode<T>>::exists(&id), "Validation code already submitted");
+			ensure!(BlakeTwo256::hash(&code[..]) == code_hash, "Validation code does not match the scheduled upgrade");
+
+			<SubmittedCode<T>>::insert(id, code);
+			Ok(())
+		}
+
+		/// Set the maximum size of new validation code for a parachain, in bytes.
+		pub fn set_max_code_size(size: u32) -> Result {
+			<MaxCodeSize<T>>::put(size);
+			Ok(())
+		}
+
+		/// Set the minimum and maximum validator group sizes, and the number of blocks
+		/// after which groups rotate. Zero disables the respective parameter.
+		pub fn set_group_parameters(
+			min_group_size: u32,
+			max_group_size: u32,
+			rotation_frequency: T::BlockNumber
+		) -> Result {
+			ensure!(
+				max_group_size == 0 || min_group_size <= max_group_size,
+				"Minimum group size exceeds maximum"
+			);
+
+			<MinGroupSize<T>>::put(min_group_size);
+			<MaxGroupSize<T>>::put(max_group_size);
+			<GroupRotationFrequency<T>>::put(rotation_frequency);
+			Ok(())
+		}
+
+		/// Dispute the validity of a recently included candidate, providing the `Invalid`
+		/// statement of an authority about it. The statement must be localized to the
+		/// relay-chain block the candidate was included on top of.
+		///
+		/// Backers of the candidate are counted as voting for its validity.
+		fn raise_dispute(origin, candidate_hash: Hash, voter: SessionKey, signature: CandidateSignature) -> Result {
+			ensure_signed(origin)?;
+			Self::do_raise_dispute(candidate_hash, voter, signature)
+		}
+
+		/// Vote on an ongoing dispute, providing the `Valid` or `Invalid` statement of
+		/// an authority about the disputed candidate. The dispute is resolved once more
+		/// than two thirds of the authorities agree, and the losing side is punished.
+		fn vote_on_dispute(
+			origin,
+			candidate_hash: Hash,
+			voter: SessionKey,
+			valid: bool,
+			signature: CandidateSignature
+		) -> Result {
+			ensure_signed(origin)?;
+			Self::do_vote_on_dispute(candidate_hash, voter, valid, signature)
+		}
+
+		/// Set the number of blocks after its inclusion during which a candidate may be
+		/// disputed, and after which an unresolved dispute is dropped.
+		pub fn set_dispute_period(period: T::BlockNumber) -> Result {
+			<DisputePeriod<T>>::put(period);
+			Ok(())
+		}
+
+		/// Set the deposit reserved for registering a parathread. Parathreads already
+		/// registered keep their deposit.
+		pub fn set_parathread_deposit(deposit: T::Balance) -> Result {
+			ensure!(!deposit.is_zero(), "Parathread deposit must be non-zero");
+
+			<ParathreadDeposit<T>>::put(deposit);
+			Ok(())
+		}
+
+		/// Set the number of blocks a candidate may remain pending availability
+		/// before it is reverted.
+		pub fn set_availability_timeout(timeout: T::BlockNumber) -> Result {
+			<AvailabilityTimeout<T>>::put(timeout);
+			Ok(())
+		}
+
+		fn on_finalise(n: T::BlockNumber) {
 			assert!(<Self as Store>::DidUpdate::take(), "Parachain heads must be updated once in the block");
+			Self::apply_code_upgrades(n);
+			Self::schedule_parathreads();
+			Self::expire_disputes(n);
 		}
 	}
 }
 
-fn majority_of(list_len: usize) -> usize {
-	list_len / 2 + list_len % 2
-}
-
 fn localized_payload(statement: Statement, parent_hash: ::primitives::Hash) -> Vec<u8> {
 	use codec::Encode;
 
@@ -172,519 +701,3048 @@ fn localized_payload(statement: Statement, parent_hash: ::primitives::Hash) -> V
 }
 
 impl<T: Trait> Module<T> {
-	/// Calculate the current block's duty roster using system's random seed.
-	pub fn calculate_duty_roster() -> DutyRoster {
-		let parachains = Self::active_parachains();
-		let parachain_count = parachains.len();
-		let validator_count = <session::Module<T>>::validator_count() as usize;
-		let validators_per_parachain = if parachain_count != 0 { (validator_count - 1) / parachain_count } else { 0 };
-
-		let mut roles_val = (0..validator_count).map(|i| match i {
-			i if i < parachain_count * validators_per_parachain => {
-				let idx = i / validators_per_parachain;
-				Chain::Parachain(parachains[idx].clone())
-			}
-			_ => Chain::Relay,
-		}).collect::<Vec<_>>();
+	/// Raise a dispute about a recently included candidate with the `Invalid`
+	/// statement of an authority. Nothing is written to storage on failure.
+	fn do_raise_dispute(candidate_hash: Hash, voter: SessionKey, signature: CandidateSignature) -> Result {
+		ensure!(!<Disputes<T>>::exists(&candidate_hash), "Candidate is already disputed");
 
-		let mut random_seed = system::Module::<T>::random_seed().as_ref().to_vec();
-		random_seed.extend(b"validator_role_pairs");
-		let mut seed = BlakeTwo256::hash(&random_seed);
+		let candidate = Self::included_candidate(&candidate_hash)
+			.ok_or("Candidate unknown or no longer disputable")?;
 
-		// shuffle
-		for i in 0..(validator_count - 1) {
-			// 8 bytes of entropy used per cycle, 32 bytes entropy per hash
-			let offset = (i * 8 % 32) as usize;
+		ensure!(!candidate.backers.contains(&voter), "Backers can't dispute their own candidate");
+		Self::check_dispute_vote(&candidate, candidate_hash, &voter, false, &signature)?;
 
-			// number of roles remaining to select from.
-			let remaining = (validator_count - i) as usize;
+		<IncludedCandidates<T>>::remove(&candidate_hash);
+		<OpenDisputes<T>>::mutate(|disputes| disputes.push(candidate_hash));
 
-			// 4 * 2 32-bit ints per 256-bit seed.
-			let val_index = u32::decode(&mut &seed[offset..offset + 4]).expect("using 4 bytes for a 32-bit quantity") as usize % remaining;
+		Self::deposit_event(RawEvent::DisputeRaised(candidate.parachain_index, candidate_hash));
 
-			if offset == 24 {
-				// into the last 8 bytes - rehash to gather new entropy
-				seed = BlakeTwo256::hash(seed.as_ref());
-			}
+		let dispute = Dispute {
+			raised_at: <system::Module<T>>::block_number(),
+			valid: candidate.backers.clone(),
+			invalid: vec![voter],
+			candidate,
+		};
+		Self::update_dispute(candidate_hash, dispute);
+		Ok(())
+	}
 
-			// exchange last item with randomly chosen first.
-			roles_val.swap(remaining - 1, val_index);
+	/// Record the vote of an authority in an ongoing dispute. Nothing is written
+	/// to storage on failure.
+	fn do_vote_on_dispute(
+		candidate_hash: Hash,
+		voter: SessionKey,
+		valid: bool,
+		signature: CandidateSignature,
+	) -> Result {
+		let mut dispute = Self::dispute(&candidate_hash).ok_or("No dispute about candidate")?;
+		ensure!(
+			!dispute.valid.contains(&voter) && !dispute.invalid.contains(&voter),
+			"Authority already voted in dispute"
+		);
+		Self::check_dispute_vote(&dispute.candidate, candidate_hash, &voter, valid, &signature)?;
+
+		if valid {
+			dispute.valid.push(voter);
+		} else {
+			dispute.invalid.push(voter);
 		}
 
-		DutyRoster {
-			validator_duty: roles_val,
+		Self::update_dispute(candidate_hash, dispute);
+		Ok(())
+	}
+
+	/// Apply the dispute votes provided with the heads: votes on candidates already
+	/// under dispute are counted, and `Invalid` votes on other recently included
+	/// candidates raise a dispute. Votes that can't be applied are skipped, since
+	/// they may have been applied in an earlier block or concern another fork.
+	fn apply_dispute_votes(votes: Vec<DisputeVote>) {
+		for (candidate_hash, voter, valid, signature) in votes {
+			let _ = if <Disputes<T>>::exists(&candidate_hash) {
+				Self::do_vote_on_dispute(candidate_hash, voter, valid, signature)
+			} else if !valid {
+				Self::do_raise_dispute(candidate_hash, voter, signature)
+			} else {
+				Ok(())
+			};
 		}
 	}
 
-	// check the attestations on these candidates. The candidates should have been checked
-	// that each candidates' chain ID is valid.
-	fn check_attestations(attested_candidates: &[AttestedCandidate]) -> Result {
-		use primitives::parachain::ValidityAttestation;
-		use sr_primitives::traits::Verify;
+	/// The chains whose candidates may be included in the current block: all
+	/// parachains, along with the scheduled parathreads. Sorted ascending.
+	pub fn scheduled_chains() -> Vec<ParaId> {
+		let mut chains = Self::active_parachains();
+		chains.extend(Self::parathread_schedule());
+		chains.sort();
+		chains
+	}
 
-		// returns groups of slices that have the same chain ID.
-		// assumes the inner slice is sorted by id.
-		struct GroupedDutyIter<'a> {
-			next_idx: usize,
-			inner: &'a [(usize, ParaId)],
+	// remove the code, head and routing entries of a parachain or parathread.
+	fn clear_chain(id: ParaId) {
+		<Code<T>>::remove(id);
+		<PendingCode<T>>::remove(id);
+		<SubmittedCode<T>>::remove(id);
+		<Heads<T>>::remove(id);
+		<RecentHeads<T>>::remove(id);
+		<PendingCandidates<T>>::mutate(|pending| pending.retain(|p| p.candidate.parachain_index != id));
+
+		// clear all routing entries to this chain.
+		if let Some(watermark) = <Watermarks<T>>::take(id) {
+			let now = <system::Module<T>>::block_number();
+			for height in number_range(watermark + One::one(), now + One::one()) {
+				<UnroutedIngress<T>>::remove(&(height, id));
+			}
 		}
+	}
 
-		impl<'a> GroupedDutyIter<'a> {
-			fn new(inner: &'a [(usize, ParaId)]) -> Self {
-				GroupedDutyIter { next_idx: 0, inner }
-			}
+	// schedule the parathreads with the highest bids placed in this block for the
+	// next one. Scheduled bids are paid, the rest are unreserved.
+	fn schedule_parathreads() {
+		let mut claims = <ParathreadClaims<T>>::take();
 
-			fn group_for(&mut self, wanted_id: ParaId) -> Option<&'a [(usize, ParaId)]> {
-				while let Some((id, keys)) = self.next() {
-					if wanted_id == id {
-						return Some(keys)
-					}
-				}
+		// highest bids first. the sort is stable, so earlier claims win ties.
+		claims.sort_by(|a, b| b.2.cmp(&a.2));
 
-				None
+		let max = Self::max_parathreads_per_block() as usize;
+		let mut schedule = Vec::new();
+		for (id, bidder, bid) in claims {
+			if schedule.len() < max && !schedule.contains(&id) {
+				let _ = <balances::Module<T>>::slash_reserved(&bidder, bid);
+				schedule.push(id);
+			} else {
+				<balances::Module<T>>::unreserve(&bidder, bid);
 			}
 		}
 
-		impl<'a> Iterator for GroupedDutyIter<'a> {
-			type Item = (ParaId, &'a [(usize, ParaId)]);
+		schedule.sort();
+		<ParathreadSchedule<T>>::put(schedule);
+	}
 
-			fn next(&mut self) -> Option<Self::Item> {
-				if self.next_idx == self.inner.len() { return None }
-				let start_idx = self.next_idx;
-				self.next_idx += 1;
-				let start_id = self.inner[start_idx].1;
+	/// Calculate the ingress to a specific parachain.
+	///
+	/// Yields all unrouted ingress roots to the parachain, ordered ascending by the
+	/// block number in which they were posted. `None` if the parachain doesn't exist.
+	pub fn ingress(to: ParaId) -> Option<Vec<(T::BlockNumber, BlockIngressRoots)>> {
+		let watermark = <Watermarks<T>>::get(&to)?;
+		let now = <system::Module<T>>::block_number();
+
+		Some(number_range(watermark + One::one(), now)
+			.filter_map(|unrouted_height| {
+				<UnroutedIngress<T>>::get(&(unrouted_height, to)).map(|roots| {
+					(unrouted_height, BlockIngressRoots(roots))
+				})
+			})
+			.collect())
+	}
 
-				while self.inner.get(self.next_idx).map_or(false, |&(_, ref id)| id == &start_id) {
-					self.next_idx += 1;
-				}
+	/// The relay-chain account holding the balance of a parachain.
+	///
+	/// Balance uploads and fees of the parachain's candidates are paid from here.
+	pub fn parachain_account(id: ParaId) -> AccountId {
+		use codec::Encode;
 
-				Some((start_id, &self.inner[start_idx..self.next_idx]))
-			}
-		}
+		let mut encoded = b"para".to_vec();
+		id.using_encoded(|s| encoded.extend(s));
+		BlakeTwo256::hash(&encoded[..])
+	}
 
-		let authorities = super::Consensus::authorities();
-		let duty_roster = Self::calculate_duty_roster();
+	// the total balance a candidate moves out of its parachain's account.
+	// `None` on overflow.
+	fn candidate_debit(candidate: &CandidateReceipt) -> Option<u64> {
+		candidate.balance_uploads.iter()
+			.try_fold(candidate.fees, |acc, &(_, amount)| acc.checked_add(amount))
+	}
 
-		// convert a duty roster, which is originally a Vec<Chain>, where each
-		// item corresponds to the same position in the session keys, into
-		// a list containing (index, parachain duty) where indices are into the session keys.
-		// this list is sorted ascending by parachain duty, just like the
-		// parachain candidates are.
-		let make_sorted_duties = |duty: &[Chain]| {
-			let mut sorted_duties = Vec::with_capacity(duty.len());
-			for (val_idx, duty) in duty.iter().enumerate() {
-				let id = match duty {
-					Chain::Relay => continue,
-					Chain::Parachain(id) => id,
-				};
+	// check that the account of a parachain can cover the balance uploads
+	// and fees of its candidate.
+	fn check_balance(candidate: &CandidateReceipt) -> Result {
+		let debit = Self::candidate_debit(candidate)
+			.ok_or("Parachain balance uploads and fees overflow")?;
+		let account = Self::parachain_account(candidate.parachain_index);
 
-				let idx = sorted_duties.binary_search_by_key(&id, |&(_, ref id)| id)
-					.unwrap_or_else(|idx| idx);
+		ensure!(
+			<balances::Module<T>>::free_balance(&account) >= T::Balance::sa(debit),
+			"Parachain balance too low for balance uploads and fees"
+		);
 
-				sorted_duties.insert(idx, (val_idx, *id));
-			}
+		Ok(())
+	}
 
-			sorted_duties
-		};
+	// move candidates pending availability since before the runtime upgrade to the
+	// compact attestation encoding into current storage. this is a no-op once done.
+	fn migrate_pending_candidates() {
+		let legacy: Option<Vec<LegacyPendingCandidate<T::BlockNumber>>> =
+			::srml_support::storage::take(LEGACY_PENDING_AVAILABILITY_KEY);
+
+		if let Some(legacy) = legacy {
+			let pending: Vec<_> = legacy.into_iter().map(|p| PendingCandidate {
+				candidate: p.attested.candidate,
+				relay_parent: p.relay_parent,
+				included_at: p.included_at,
+				voters: p.voters,
+				available_from: p.available_from,
+			}).collect();
+
+			<PendingCandidates<T>>::put(pending);
+		}
+	}
 
-		let sorted_validators = make_sorted_duties(&duty_roster.validator_duty);
+	// account the availability signalled by validators for the candidates pending
+	// at the parent block. returns the candidates which have become available, and those
+	// which are still pending. candidates which have been pending for too long are dropped.
+	fn process_availability(availability: &[SignedActivity]) -> rstd::result::Result<
+		(Vec<PendingCandidate<T::BlockNumber>>, Vec<PendingCandidate<T::BlockNumber>>),
+		&'static str,
+	> {
+		use sr_primitives::traits::Verify;
 
+		let mut pending = Self::pending_availability();
+		let authorities = super::Consensus::authorities();
 		let parent_hash = super::System::parent_hash();
-		let localized_payload = |statement: Statement| localized_payload(statement, parent_hash);
 
-		let mut validator_groups = GroupedDutyIter::new(&sorted_validators[..]);
+		let mut last_index = None;
+		for signed in availability {
+			ensure!(
+				last_index.map_or(true, |i| i < signed.validator_index),
+				"Availability bit fields out of order by validator"
+			);
+			last_index = Some(signed.validator_index);
 
-		for candidate in attested_candidates {
-			let validator_group = validator_groups.group_for(candidate.parachain_index())
-				.ok_or("no validator group for parachain")?;
+			let key = authorities.get(signed.validator_index as usize)
+				.ok_or("Availability signalled by unknown validator")?;
 
 			ensure!(
-				candidate.validity_votes.len() >= majority_of(validator_group.len()),
-				"Not enough validity attestations"
+				signed.activity.0.len() == (pending.len() + 7) / 8,
+				"Availability bit field has wrong length"
 			);
 
-			let mut candidate_hash = None;
-			let mut encoded_implicit = None;
-			let mut encoded_explicit = None;
+			ensure!(
+				signed.signature.verify(&signed.activity.signing_payload(&parent_hash)[..], &key.0.into()),
+				"Availability bit field signature is bad"
+			);
 
-			// track which voters have voted already, 1 bit per authority.
-			let mut track_voters = bitvec![0; authorities.len()];
-			for (auth_id, validity_attestation) in &candidate.validity_votes {
-				// protect against double-votes.
-				match validator_group.iter().find(|&(idx, _)| &authorities[*idx] == auth_id) {
-					None => return Err("Attesting validator not on this chain's validation duty."),
-					Some(&(idx, _)) => {
-						if track_voters.get(idx) {
-							return Err("Voter already attested validity once")
-						}
-						track_voters.set(idx, true)
-					}
+			for (i, candidate) in pending.iter_mut().enumerate() {
+				if signed.activity.get(i) && !candidate.available_from.contains(&signed.validator_index) {
+					candidate.available_from.push(signed.validator_index);
 				}
+			}
+		}
 
-				let (payload, sig) = match validity_attestation {
-					ValidityAttestation::Implicit(sig) => {
-						let payload = encoded_implicit.get_or_insert_with(|| localized_payload(
-							Statement::Candidate(candidate.candidate.clone()),
-						));
+		let n_validators = authorities.len();
+		let (available, unavailable): (Vec<_>, Vec<_>) = pending.into_iter()
+			.partition(|p| p.available_from.len() * 3 > n_validators * 2);
+
+		// the effects of a candidate are only applied once available, so reverting
+		// a candidate which timed out means forgetting about it.
+		let now = <system::Module<T>>::block_number();
+		let timeout = Self::availability_timeout();
+		let still_pending = unavailable.into_iter()
+			.filter(|p| p.included_at + timeout > now)
+			.collect();
+
+		// the balance of a parachain may have changed since its candidate was included.
+		// candidates which can no longer be paid for are reverted.
+		let enacted = available.into_iter()
+			.filter(|p| Self::check_balance(&p.candidate).is_ok())
+			.collect();
+
+		Ok((enacted, still_pending))
+	}
 
-						(payload, sig)
-					}
-					ValidityAttestation::Explicit(sig) => {
-						let hash = candidate_hash
-							.get_or_insert_with(|| candidate.candidate.hash())
-							.clone();
+	// check reports of misbehavior in the statement table of the parent block.
+	// returns the authority indices of the offenders. reports which can't be proven,
+	// or which name an authority already reported, are skipped rather than failing
+	// the whole inherent.
+	fn check_misbehavior(reports: &[MisbehaviorReport]) -> Vec<usize> {
+		use sr_primitives::traits::Verify;
+		use statement_table::generic::{
+			Misbehavior, ValidityDoubleVote, DoubleSign, MultipleCandidates, UnauthorizedStatement,
+			Statement as TableStatement,
+		};
 
-						let payload = encoded_explicit.get_or_insert_with(|| localized_payload(
-							Statement::Valid(hash),
-						));
+		if reports.is_empty() { return Vec::new() }
 
-						(payload, sig)
+		let authorities = super::Consensus::authorities();
+		let parent_hash = super::System::parent_hash();
+		let duty_roster = Self::calculate_duty_roster();
+
+		let mut offenders = Vec::with_capacity(reports.len());
+		for &(ref offender, ref misbehavior) in reports {
+			let idx = match authorities.iter().position(|a| a == offender) {
+				Some(idx) => idx,
+				None => continue,
+			};
+
+			if offenders.contains(&idx) { continue }
+
+			let signed = |statement: Statement, signature: &CandidateSignature| {
+				let payload = localized_payload(statement, parent_hash);
+				signature.verify(&payload[..], &offender.0.into())
+			};
+
+			let proven = match *misbehavior {
+				Misbehavior::ValidityDoubleVote(ValidityDoubleVote::IssuedAndValidity(
+					(ref candidate, ref s1),
+					(ref digest, ref s2),
+				)) => candidate.hash() == *digest
+					&& signed(Statement::Candidate(candidate.clone()), s1)
+					&& signed(Statement::Valid(*digest), s2),
+				Misbehavior::ValidityDoubleVote(ValidityDoubleVote::IssuedAndInvalidity(
+					(ref candidate, ref s1),
+					(ref digest, ref s2),
+				)) => candidate.hash() == *digest
+					&& signed(Statement::Candidate(candidate.clone()), s1)
+					&& signed(Statement::Invalid(*digest), s2),
+				Misbehavior::ValidityDoubleVote(ValidityDoubleVote::ValidityAndInvalidity(
+					ref digest,
+					ref s1,
+					ref s2,
+				)) => signed(Statement::Valid(*digest), s1) && signed(Statement::Invalid(*digest), s2),
+				Misbehavior::MultipleCandidates(MultipleCandidates {
+					first: (ref first, ref s1),
+					second: (ref second, ref s2),
+				}) => first != second
+					&& signed(Statement::Candidate(first.clone()), s1)
+					&& signed(Statement::Candidate(second.clone()), s2),
+				Misbehavior::UnauthorizedStatement(UnauthorizedStatement { ref statement }) => {
+					// only candidate statements name their parachain, so only those
+					// can be checked against the duty roster.
+					match statement.statement {
+						TableStatement::Candidate(ref candidate) => statement.sender == *offender
+							&& duty_roster.validator_duty.get(idx) != Some(&Chain::Parachain(candidate.parachain_index))
+							&& signed(Statement::Candidate(candidate.clone()), &statement.signature),
+						TableStatement::Valid(_) | TableStatement::Invalid(_) => false,
 					}
-				};
+				}
+				Misbehavior::DoubleSign(ref double_sign) => {
+					let (statement, s1, s2) = match *double_sign {
+						DoubleSign::Candidate(ref candidate, ref s1, ref s2) =>
+							(Statement::Candidate(candidate.clone()), s1, s2),
+						DoubleSign::Validity(ref digest, ref s1, ref s2) =>
+							(Statement::Valid(*digest), s1, s2),
+						DoubleSign::Invalidity(ref digest, ref s1, ref s2) =>
+							(Statement::Invalid(*digest), s1, s2),
+					};
+
+					s1 != s2 && signed(statement.clone(), s1) && signed(statement, s2)
+				}
+			};
 
-				ensure!(
-					sig.verify(&payload[..], &auth_id.0.into()),
-					"Candidate validity attestation signature is bad."
-				);
+			if proven {
+				offenders.push(idx);
 			}
 		}
 
-		Ok(())
+		offenders
 	}
 
-/*
-	// TODO: Consider integrating if needed.
-	/// Extract the parachain heads from the block.
-	pub fn parachain_heads(&self) -> &[CandidateReceipt] {
-		let x = self.inner.extrinsics.get(PARACHAINS_SET_POSITION as usize).and_then(|xt| match xt.function {
-			Call::Parachains(ParachainsCall::set_heads(ref x)) => Some(&x[..]),
-			_ => None
-		});
+	// apply the effects of candidates which have become available.
+	fn enact_candidates(enacted: &[PendingCandidate<T::BlockNumber>]) {
+		Self::update_routing(enacted);
+		Self::record_heads(enacted);
+		Self::note_enacted(enacted);
+		Self::apply_balances(enacted);
+		Self::schedule_code_upgrades(enacted);
+	}
 
-		match x {
-			Some(x) => x,
-			None => panic!("Invalid polkadot block asserted at {:?}", self.file_line),
+	// note the new heads of the enacted candidates in the recent history of their chains.
+	fn record_heads(enacted: &[PendingCandidate<T::BlockNumber>]) {
+		let now = <system::Module<T>>::block_number();
+
+		for pending in enacted {
+			let candidate = &pending.candidate;
+			let id = candidate.parachain_index;
+			let candidate_hash = candidate.hash();
+
+			<RecentHeads<T>>::mutate(id, |heads| {
+				heads.push((now, candidate_hash, candidate.head_data.0.clone()));
+				if heads.len() > MAX_HEAD_HISTORY {
+					let excess = heads.len() - MAX_HEAD_HISTORY;
+					heads.drain(..excess);
+				}
+			});
+
+			Self::deposit_event(RawEvent::HeadUpdated(id, candidate_hash, candidate.collator));
 		}
 	}
-*/
-}
 
-pub const INHERENT_IDENTIFIER: InherentIdentifier = *b"newheads";
+	// note that the enacted candidates were applied, in case they are disputed later.
+	fn note_enacted(enacted: &[PendingCandidate<T::BlockNumber>]) {
+		for pending in enacted {
+			let candidate_hash = pending.candidate.hash();
+			<IncludedCandidates<T>>::mutate(&candidate_hash, |c| if let Some(c) = c.as_mut() {
+				c.enacted = true;
+			});
+			<Disputes<T>>::mutate(&candidate_hash, |d| if let Some(d) = d.as_mut() {
+				d.candidate.enacted = true;
+			});
+		}
+	}
 
-pub type InherentType = Vec<AttestedCandidate>;
+	// add the candidates included in this block to those pending availability.
+	// `voters` holds the authority indices which voted for each candidate.
+	fn add_pending(
+		mut pending: Vec<PendingCandidate<T::BlockNumber>>,
+		heads: Vec<AttestedCandidate>,
+		voters: Vec<Vec<usize>>,
+	) {
+		let relay_parent = super::System::parent_hash();
+		let now = <system::Module<T>>::block_number();
+		let authorities = super::Consensus::authorities();
 
-impl<T: Trait> ProvideInherent for Module<T> {
-	type Call = Call<T>;
-	type Error = MakeFatalError<RuntimeString>;
-	const INHERENT_IDENTIFIER: InherentIdentifier = INHERENT_IDENTIFIER;
+		let mut included = Vec::with_capacity(heads.len());
+		for (head, voters) in heads.into_iter().zip(voters) {
+			let candidate_hash = head.candidate.hash();
+			<IncludedCandidates<T>>::insert(candidate_hash, IncludedCandidate {
+				parachain_index: head.parachain_index(),
+				relay_parent,
+				included_at: now,
+				parent_head: Self::parachain_head(&head.parachain_index()).unwrap_or_default(),
+				backers: voters.iter().filter_map(|&i| authorities.get(i).cloned()).collect(),
+				enacted: false,
+			});
+			included.push(candidate_hash);
+
+			pending.push(PendingCandidate {
+				candidate: head.candidate,
+				relay_parent,
+				included_at: now,
+				voters: voters.into_iter().map(|i| i as u32).collect(),
+				available_from: Vec::new(),
+			});
+		}
 
-	fn create_inherent(data: &InherentData) -> Option<Self::Call> {
-		let data = data.get_data::<InherentType>(&INHERENT_IDENTIFIER)
-			.expect("Parachain heads could not be decoded.")
-			.expect("No parachain heads found in inherent data.");
+		pending.sort_unstable_by_key(|p| p.candidate.parachain_index);
+		<PendingCandidates<T>>::put(pending);
 
-		Some(Call::set_heads(data))
+		if !included.is_empty() {
+			<IncludedAt<T>>::insert(now, included);
+		}
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use sr_io::{TestExternalities, with_externalities};
-	use substrate_primitives::{H256, Blake2Hasher};
-	use sr_primitives::{generic, BuildStorage};
-	use sr_primitives::traits::{BlakeTwo256, IdentityLookup};
-	use primitives::{parachain::{CandidateReceipt, HeadData, ValidityAttestation}, SessionKey};
-	use keyring::Keyring;
-	use {consensus, timestamp};
+	// check that the given vote in a dispute about a candidate is signed by an authority.
+	fn check_dispute_vote(
+		candidate: &IncludedCandidate<T::BlockNumber>,
+		candidate_hash: Hash,
+		voter: &SessionKey,
+		valid: bool,
+		signature: &CandidateSignature,
+	) -> Result {
+		use sr_primitives::traits::Verify;
 
-	impl_outer_origin! {
+		ensure!(
+			super::Consensus::authorities().contains(voter),
+			"Dispute vote by non-authority"
+		);
+
+		let statement = if valid {
+			Statement::Valid(candidate_hash)
+		} else {
+			Statement::Invalid(candidate_hash)
+		};
+
+		let payload = localized_payload(statement, candidate.relay_parent);
+		ensure!(signature.verify(&payload[..], &voter.0.into()), "Dispute vote signature is bad");
+		Ok(())
+	}
+
+	// store a dispute with new votes, or resolve it if more than two thirds of the
+	// authorities agree on the validity of the candidate.
+	fn update_dispute(candidate_hash: Hash, dispute: Dispute<T::BlockNumber>) {
+		let n_authorities = super::Consensus::authorities().len();
+		let supermajority = |votes: &[SessionKey]| votes.len() * 3 > n_authorities * 2;
+
+		let valid = if supermajority(&dispute.valid) {
+			true
+		} else if supermajority(&dispute.invalid) {
+			false
+		} else {
+			<Disputes<T>>::insert(candidate_hash, dispute);
+			return;
+		};
+
+		<Disputes<T>>::remove(&candidate_hash);
+		<OpenDisputes<T>>::mutate(|disputes| disputes.retain(|h| h != &candidate_hash));
+
+		if valid {
+			Self::punish_authorities(&dispute.invalid);
+			Self::release_credits(candidate_hash);
+		} else {
+			Self::punish_authorities(&dispute.valid);
+			Self::revert_candidate(candidate_hash, &dispute.candidate);
+		}
+
+		Self::deposit_event(RawEvent::DisputeResolved(candidate_hash, valid));
+	}
+
+	// punish the given authorities, if they are still authorities.
+	fn punish_authorities(offenders: &[SessionKey]) {
+		let authorities = super::Consensus::authorities();
+		for offender in offenders {
+			if let Some(idx) = authorities.iter().position(|a| a == offender) {
+				T::HandleMisbehavior::handle_misbehavior(idx);
+			}
+		}
+	}
+
+	// revert a candidate found to be invalid, along with all candidates of its
+	// chain built on top of it. if the candidate was already enacted, the head of
+	// the chain is reset to the one the candidate was built on and pending code
+	// upgrades are discarded. the balance uploads of reverted candidates are
+	// returned to the chain, but messages can't be undone.
+	fn revert_candidate(candidate_hash: Hash, candidate: &IncludedCandidate<T::BlockNumber>) {
+		let id = candidate.parachain_index;
+		Self::refund_credits(id, candidate_hash);
+
+		// unless the candidate was dropped without being enacted, a candidate pending
+		// availability for the chain is either the disputed candidate or built on top
+		// of it, as are all candidates of the chain included later.
+		let mut pending = Self::pending_availability();
+		if !candidate.enacted && !pending.iter().any(|p| p.candidate.hash() == candidate_hash) {
+			return;
+		}
+
+		pending.retain(|p| p.candidate.parachain_index != id);
+		<PendingCandidates<T>>::put(pending);
+		Self::purge_later_candidates(id, candidate.included_at);
+
+		// the chain may have been deregistered since.
+		if !candidate.enacted || !<Heads<T>>::exists(&id) {
+			return;
+		}
+
+		<Heads<T>>::insert(id, &candidate.parent_head);
+		<PendingCode<T>>::remove(id);
+		<SubmittedCode<T>>::remove(id);
+		<RecentHeads<T>>::mutate(id, |heads| {
+			// heads older than the candidate may have been pruned from the history,
+			// in which case all remaining heads were built on top of it.
+			let pos = heads.iter().position(|&(_, ref hash, _)| hash == &candidate_hash).unwrap_or(0);
+			heads.truncate(pos);
+		});
+	}
+
+	// forget the candidates of a chain included after the given block, which were
+	// built on top of a reverted candidate, along with any disputes about them.
+	fn purge_later_candidates(id: ParaId, included_at: T::BlockNumber) {
+		let now = <system::Module<T>>::block_number();
+		let mut purged = Vec::new();
+
+		for n in number_range(included_at + One::one(), now + One::one()) {
+			for candidate_hash in <IncludedAt<T>>::get(n) {
+				let chain = Self::included_candidate(&candidate_hash).map(|c| c.parachain_index)
+					.or_else(|| Self::dispute(&candidate_hash).map(|d| d.candidate.parachain_index));
+
+				if chain == Some(id) {
+					<IncludedCandidates<T>>::remove(&candidate_hash);
+					<Disputes<T>>::remove(&candidate_hash);
+					Self::refund_credits(id, candidate_hash);
+					purged.push(candidate_hash);
+				}
+			}
+		}
+
+		if !purged.is_empty() {
+			<OpenDisputes<T>>::mutate(|disputes| disputes.retain(|h| !purged.contains(h)));
+		}
+	}
+
+	// forget candidates which can no longer be disputed, paying out their held
+	// credits, and drop disputes which remained unresolved for too long.
+	fn expire_disputes(now: T::BlockNumber) {
+		let period = Self::dispute_period();
+		if now < period {
+			return;
+		}
+
+		let expired = now - period;
+		for candidate_hash in <IncludedAt<T>>::take(expired) {
+			// disputed candidates hold their credits until the dispute ends.
+			if <IncludedCandidates<T>>::take(&candidate_hash).is_some() {
+				Self::release_credits(candidate_hash);
+			}
+		}
+
+		let (timed_out, open): (Vec<_>, Vec<_>) = Self::open_disputes().into_iter()
+			.partition(|h| Self::dispute(h).map_or(true, |d| d.raised_at <= expired));
+
+		if timed_out.is_empty() {
+			return;
+		}
+
+		for candidate_hash in timed_out {
+			<Disputes<T>>::remove(&candidate_hash);
+			Self::release_credits(candidate_hash);
+			Self::deposit_event(RawEvent::DisputeTimedOut(candidate_hash));
+		}
+
+		<OpenDisputes<T>>::put(open);
+	}
+
+	// move balance uploads from the parachains' accounts to their targets and
+	// split the fees between the validators which attested to each candidate.
+	// any indivisible remainder of the fees stays with the parachain. the
+	// targets are only credited once the candidate can't be disputed anymore.
+	//
+	// the balances must have been checked with `check_balance`.
+	fn apply_balances(heads: &[PendingCandidate<T::BlockNumber>]) {
+		let validators = <session::Module<T>>::validators();
+
+		for head in heads {
+			let candidate = &head.candidate;
+			let account = Self::parachain_account(candidate.parachain_index);
+
+			let fee_recipients: Vec<_> = head.voters.iter()
+				.filter_map(|&idx| validators.get(idx as usize))
+				.collect();
+
+			let fee_share = if fee_recipients.is_empty() {
+				0
+			} else {
+				candidate.fees / fee_recipients.len() as u64
+			};
+
+			let uploaded = Self::candidate_debit(candidate)
+				.expect("checked in `check_balance`; qed") - candidate.fees;
+			let debit = uploaded + fee_share * fee_recipients.len() as u64;
+
+			let free = <balances::Module<T>>::free_balance(&account);
+			<balances::Module<T>>::set_free_balance(&account, free - T::Balance::sa(debit));
+
+			let mut credits = candidate.balance_uploads.clone();
+			if fee_share != 0 {
+				credits.extend(fee_recipients.into_iter().map(|who| (who.clone(), fee_share)));
+			}
+
+			let candidate_hash = candidate.hash();
+			let disputable = <IncludedCandidates<T>>::exists(&candidate_hash)
+				|| <Disputes<T>>::exists(&candidate_hash);
+
+			if !disputable {
+				Self::pay_credits(credits);
+			} else if !credits.is_empty() {
+				<HeldCredits<T>>::insert(candidate_hash, credits);
+			}
+		}
+	}
+
+	fn pay_credits(credits: Vec<(AccountId, u64)>) {
+		for (who, amount) in credits {
+			<balances::Module<T>>::increase_free_balance_creating(&who, T::Balance::sa(amount));
+		}
+	}
+
+	// pay out the credits held for a candidate which can't be disputed anymore.
+	fn release_credits(candidate_hash: Hash) {
+		Self::pay_credits(<HeldCredits<T>>::take(&candidate_hash));
+	}
+
+	// return the credits held for a reverted candidate to the account of its chain.
+	fn refund_credits(id: ParaId, candidate_hash: Hash) {
+		let refund = <HeldCredits<T>>::take(&candidate_hash).into_iter()
+			.fold(0u64, |total, (_, amount)| total.saturating_add(amount));
+
+		if refund != 0 {
+			let account = Self::parachain_account(id);
+			<balances::Module<T>>::increase_free_balance_creating(&account, T::Balance::sa(refund));
+		}
+	}
+
+	// schedule the validation code upgrades of the enacted candidates.
+	fn schedule_code_upgrades(heads: &[PendingCandidate<T::BlockNumber>]) {
+		let apply_at = <system::Module<T>>::block_number() + Self::code_upgrade_delay();
+
+		for head in heads {
+			if let Some(code_hash) = head.candidate.new_validation_code_hash {
+				let id = head.candidate.parachain_index;
+				<PendingCode<T>>::insert(id, (apply_at, code_hash));
+				<CodeUpgradesAt<T>>::mutate(apply_at, |ids| ids.push(id));
+				Self::deposit_event(RawEvent::CodeUpgradeScheduled(id, apply_at));
+			}
+		}
+	}
+
+	// bring into use the validation code scheduled for the end of block `now`.
+	// candidates built on top of this block are validated against the new code.
+	fn apply_code_upgrades(now: T::BlockNumber) {
+		for id in <CodeUpgradesAt<T>>::take(now) {
+			// the upgrade may have been discarded by deregistering the parachain.
+			match <PendingCode<T>>::get(&id) {
+				Some((apply_at, _)) if apply_at == now => {
+					<PendingCode<T>>::remove(&id);
+					match <SubmittedCode<T>>::take(&id) {
+						Some(code) => {
+							<Code<T>>::insert(&id, code);
+							Self::deposit_event(RawEvent::CodeUpgraded(id));
+						}
+						None => Self::deposit_event(RawEvent::CodeUpgradeDropped(id)),
+					}
+				}
+				_ => {}
+			}
+		}
+	}
+
+	/// Update routing information from the parachain heads. This queues egress
+	/// roots of the enacted candidates for their targets and discards ingress
+	/// which has been routed to the enacted parachains.
+	fn update_routing(heads: &[PendingCandidate<T::BlockNumber>]) {
+		let now = <system::Module<T>>::block_number();
+
+		let mut ingress_update = BTreeMap::new();
+
+		for pending in heads {
+			let head = &pending.candidate;
+			let id = head.parachain_index;
+			<Heads<T>>::insert(id, &head.head_data.0);
+
+			// candidates were built on top of the parent of the block they were included
+			// in, so they have processed all ingress posted up to and including it.
+			let included_at = pending.included_at;
+			let routed_up_to = if included_at.is_zero() { included_at } else { included_at - One::one() };
+
+			let last_watermark = <Watermarks<T>>::mutate(id, |mark| {
+				rstd::mem::replace(mark, Some(routed_up_to))
+			});
+
+			if let Some(last_watermark) = last_watermark {
+				// discard routed ingress.
+				for routed_from_block in number_range(last_watermark + One::one(), included_at) {
+					<UnroutedIngress<T>>::remove(&(routed_from_block, id));
+				}
+			}
+
+			// place our egress root to `to` into the ingress table for (now, `to`).
+			for &(to, root) in &head.egress_queue_roots {
+				ingress_update.entry(to).or_insert_with(Vec::new).push((id, root));
+			}
+		}
+
+		// apply the ingress update.
+		for (to, ingress_roots) in ingress_update {
+			<UnroutedIngress<T>>::insert((now, to), ingress_roots);
+		}
+	}
+
+	// check the egress queue roots of a candidate. roots must be sorted ascending by
+	// target without duplicates, and may only target other registered parachains.
+	fn check_egress_queue_roots(head: &AttestedCandidate, active_parachains: &[ParaId]) -> Result {
+		let mut last_egress_id = None;
+		let mut iter = active_parachains.iter();
+		for &(egress_para_id, _) in &head.candidate.egress_queue_roots {
+			// egress routes should be ascending order by parachain ID without duplicate.
+			ensure!(
+				last_egress_id.as_ref().map_or(true, |x| x < &egress_para_id),
+				"Egress routes out of order by ID"
+			);
+
+			// a parachain can't route to self
+			ensure!(
+				egress_para_id != head.parachain_index(),
+				"Parachain routing to self"
+			);
+
+			// can't route to a parachain which doesn't exist
+			ensure!(
+				iter.find(|x| x == &&egress_para_id).is_some(),
+				"Routing to non-existent parachain"
+			);
+
+			last_egress_id = Some(egress_para_id)
+		}
+		Ok(())
+	}
+
+	/// Calculate the current block's duty roster.
+	///
+	/// Validator groups are either reshuffled every block using system's random seed,
+	/// or rotate deterministically to the next chain every `GroupRotationFrequency` blocks.
+	pub fn calculate_duty_roster() -> DutyRoster {
+		let now = <system::Module<T>>::block_number();
+		let (validator_duty, chains) = if Self::group_rotation_frequency().is_zero() {
+			Self::shuffled_duties(now)
+		} else {
+			Self::rotated_duties(now)
+		};
+
+		DutyRoster {
+			secondary_checks: Self::secondary_checks(&validator_duty, &chains),
+			validator_duty,
+		}
+	}
+
+	/// Calculate the duty roster of a future block, assuming the validators and scheduled
+	/// chains stay the same until then. `None` if validator groups are reshuffled at
+	/// random every block, in which case the roster can't be known in advance.
+	///
+	/// Secondary checkers are chosen at random every block, so none are assigned.
+	pub fn future_duty_roster(at: T::BlockNumber) -> Option<DutyRoster> {
+		if Self::group_rotation_frequency().is_zero() {
+			None
+		} else {
+			let (validator_duty, _) = Self::rotated_duties(at);
+			Some(DutyRoster {
+				secondary_checks: vec![None; validator_duty.len()],
+				validator_duty,
+			})
+		}
+	}
+
+	// the number of group rotations which have happened by the given block. Groups
+	// rotate every block if they are reshuffled.
+	fn rotation_index(at: T::BlockNumber) -> u64 {
+		let frequency = Self::group_rotation_frequency();
+		if frequency.is_zero() {
+			at.as_()
+		} else {
+			(at / frequency).as_()
+		}
+	}
+
+	// the chains given a validator group at the given block, sorted ascending, along with
+	// the size of the groups. one validator is always left to the relay chain.
+	fn assigned_chains(validator_count: usize, at: T::BlockNumber) -> (Vec<ParaId>, usize) {
+		let mut chains = Self::scheduled_chains();
+		if chains.is_empty() || validator_count == 0 {
+			return (Vec::new(), 0);
+		}
+
+		let available = validator_count - 1;
+		let min_group_size = Self::min_group_size() as usize;
+		let max_group_size = Self::max_group_size() as usize;
+
+		let mut group_size = available / chains.len();
+		if min_group_size != 0 && group_size < min_group_size {
+			// not every chain can be given a group of the minimum size, so they take turns.
+			let group_count = available / min_group_size;
+			let offset = (Self::rotation_index(at) % chains.len() as u64) as usize;
+
+			chains.rotate_left(offset);
+			chains.truncate(group_count);
+			chains.sort();
+
+			group_size = if group_count == 0 { 0 } else { available / group_count };
+		}
+
+		if max_group_size != 0 {
+			group_size = rstd::cmp::min(group_size, max_group_size);
+		}
+
+		if group_size == 0 {
+			return (Vec::new(), 0);
+		}
+
+		(chains, group_size)
+	}
+
+	// the duty of the validator at the given position, when the first positions
+	// are split into groups for the given chains and the rest validate the relay chain.
+	fn duty_at_position(position: usize, chains: &[ParaId], group_size: usize) -> Chain {
+		if position < chains.len() * group_size {
+			Chain::Parachain(chains[position / group_size])
+		} else {
+			Chain::Relay
+		}
+	}
+
+	// the parachain re-checked by each validator of the relay chain. relay-chain validators
+	// are spread over the chains in turn, starting from a chain chosen at random using
+	// system's random seed, so that checkers aren't known before the block.
+	fn secondary_checks(validator_duty: &[Chain], chains: &[ParaId]) -> Vec<Option<ParaId>> {
+		if chains.is_empty() {
+			return vec![None; validator_duty.len()];
+		}
+
+		let mut random_seed = system::Module::<T>::random_seed().as_ref().to_vec();
+		random_seed.extend(b"secondary_checks");
+		let seed = BlakeTwo256::hash(&random_seed);
+		let random = u64::decode(&mut seed.as_ref()).unwrap_or_default();
+
+		let offset = (random % chains.len() as u64) as usize;
+		let mut relay_validators = 0;
+
+		validator_duty.iter().map(|duty| match *duty {
+			Chain::Relay => {
+				let checked = chains[(offset + relay_validators) % chains.len()];
+				relay_validators += 1;
+				Some(checked)
+			}
+			Chain::Parachain(_) => None,
+		}).collect()
+	}
+
+	// the duty of each validator, shuffled at random using system's random seed, along
+	// with the chains assigned a group.
+	fn shuffled_duties(now: T::BlockNumber) -> (Vec<Chain>, Vec<ParaId>) {
+		let validator_count = <session::Module<T>>::validator_count() as usize;
+		let (chains, group_size) = Self::assigned_chains(validator_count, now);
+
+		let mut roles_val = (0..validator_count)
+			.map(|i| Self::duty_at_position(i, &chains, group_size))
+			.collect::<Vec<_>>();
+
+		let mut random_seed = system::Module::<T>::random_seed().as_ref().to_vec();
+		random_seed.extend(b"validator_role_pairs");
+		let mut seed = BlakeTwo256::hash(&random_seed);
+
+		// shuffle
+		for i in 0..validator_count.saturating_sub(1) {
+			// 8 bytes of entropy used per cycle, 32 bytes entropy per hash
+			let offset = (i * 8 % 32) as usize;
+
+			// number of roles remaining to select from.
+			let remaining = (validator_count - i) as usize;
+
+			// 4 * 2 32-bit ints per 256-bit seed.
+			let val_index = u32::decode(&mut &seed[offset..offset + 4]).expect("using 4 bytes for a 32-bit quantity") as usize % remaining;
+
+			if offset == 24 {
+				// into the last 8 bytes - rehash to gather new entropy
+				seed = BlakeTwo256::hash(seed.as_ref());
+			}
+
+			// exchange last item with randomly chosen first.
+			roles_val.swap(remaining - 1, val_index);
+		}
+
+		(roles_val, chains)
+	}
+
+	// the duty of each validator, moved on by one group per rotation, along with the
+	// chains assigned a group.
+	fn rotated_duties(at: T::BlockNumber) -> (Vec<Chain>, Vec<ParaId>) {
+		let validator_count = <session::Module<T>>::validator_count() as usize;
+		let (chains, group_size) = Self::assigned_chains(validator_count, at);
+
+		let shift = if validator_count == 0 {
+			0
+		} else {
+			(Self::rotation_index(at) % validator_count as u64) as usize * rstd::cmp::max(group_size, 1)
+		};
+
+		let validator_duty = (0..validator_count)
+			.map(|i| Self::duty_at_position((i + shift) % validator_count, &chains, group_size))
+			.collect::<Vec<_>>();
+
+		(validator_duty, chains)
+	}
+
+	// the authority indices of the members of each validator group, in the order
+	// of the duty roster.
+	fn validator_groups(duty_roster: &DutyRoster) -> BTreeMap<ParaId, Vec<usize>> {
+		let mut groups = BTreeMap::new();
+		for (idx, duty) in duty_roster.validator_duty.iter().enumerate() {
+			if let Chain::Parachain(id) = *duty {
+				groups.entry(id).or_insert_with(Vec::new).push(idx);
+			}
+		}
+
+		groups
+	}
+
+	/// Convert candidates in the legacy encoding, naming the validator with each
+	/// attestation, into the compact encoding using the current duty roster.
+	/// Candidates with attestations by validators outside of their group are dropped.
+	pub fn compact_candidates(candidates: Vec<LegacyAttestedCandidate>) -> Vec<AttestedCandidate> {
+		let authorities = super::Consensus::authorities();
+		let groups = Self::validator_groups(&Self::calculate_duty_roster());
+
+		candidates.into_iter().filter_map(|candidate| {
+			let group: Vec<_> = groups.get(&candidate.candidate.parachain_index)?
+				.iter()
+				.map(|&idx| authorities[idx])
+				.collect();
+
+			candidate.into_compact(&group)
+		}).collect()
+	}
+
+	// check the attestations on these candidates. The candidates should have been checked
+	// that each candidates' chain ID is valid.
+	//
+	// returns the indices of the authorities which attested to each candidate.
+	fn check_attestations(attested_candidates: &[AttestedCandidate])
+		-> rstd::result::Result<Vec<Vec<usize>>, &'static str>
+	{
+		use primitives::parachain::ValidityAttestation;
+		use sr_primitives::traits::Verify;
+
+		let authorities = super::Consensus::authorities();
+		let validator_groups = Self::validator_groups(&Self::calculate_duty_roster());
+		let validity_threshold = Self::validity_threshold();
+
+		let parent_hash = super::System::parent_hash();
+		let localized_payload = |statement: Statement| localized_payload(statement, parent_hash);
+
+		let mut all_voters = Vec::with_capacity(attested_candidates.len());
+
+		for candidate in attested_candidates {
+			let validator_group = validator_groups.get(&candidate.parachain_index())
+				.ok_or("no validator group for parachain")?;
+
+			// votes are given in the order of the validator group, one bit per member.
+			ensure!(
+				candidate.validator_indices.0.len() == (validator_group.len() + 7) / 8,
+				"Attesting validator bit field has wrong length"
+			);
+
+			let positions = candidate.voter_positions();
+			ensure!(
+				positions.len() == candidate.validity_votes.len(),
+				"Number of attestations doesn't match attesting validators"
+			);
+			ensure!(
+				positions.len() >= validity_threshold.required_votes(validator_group.len()),
+				"Not enough validity attestations"
+			);
+
+			let mut candidate_hash = None;
+			let mut encoded_implicit = None;
+			let mut encoded_explicit = None;
+
+			let mut voters = Vec::with_capacity(positions.len());
+			for (position, validity_attestation) in positions.into_iter().zip(&candidate.validity_votes) {
+				let idx = *validator_group.get(position)
+					.ok_or("Attesting validator not on this chain's validation duty.")?;
+				voters.push(idx);
+
+				let (payload, sig) = match validity_attestation {
+					ValidityAttestation::Implicit(sig) => {
+						let payload = encoded_implicit.get_or_insert_with(|| localized_payload(
+							Statement::Candidate(candidate.candidate.clone()),
+						));
+
+						(payload, sig)
+					}
+					ValidityAttestation::Explicit(sig) => {
+						let hash = candidate_hash
+							.get_or_insert_with(|| candidate.candidate.hash())
+							.clone();
+
+						let payload = encoded_explicit.get_or_insert_with(|| localized_payload(
+							Statement::Valid(hash),
+						));
+
+						(payload, sig)
+					}
+				};
+
+				ensure!(
+					sig.verify(&payload[..], &authorities[idx].0.into()),
+					"Candidate validity attestation signature is bad."
+				);
+			}
+
+			all_voters.push(voters);
+		}
+
+		Ok(all_voters)
+	}
+
+/*
+	// TODO: Consider integrating if needed.
+	/// Extract the parachain heads from the block.
+	pub fn parachain_heads(&self) -> &[CandidateReceipt] {
+		let x = self.inner.extrinsics.get(PARACHAINS_SET_POSITION as usize).and_then(|xt| match xt.function {
+			Call::Parachains(ParachainsCall::set_heads(ref x)) => Some(&x[..]),
+			_ => None
+		});
+
+		match x {
+			Some(x) => x,
+			None => panic!("Invalid polkadot block asserted at {:?}", self.file_line),
+		}
+	}
+*/
+}
+
+// creates a range iterator between `low` and `high`. `low` must be <= `high`.
+fn number_range<N>(low: N, high: N) -> BlockNumberRange<N> {
+	BlockNumberRange { low, high }
+}
+
+// An iterator over block numbers, from `low` inclusive to `high` exclusive.
+struct BlockNumberRange<N> {
+	low: N,
+	high: N,
+}
+
+impl<N: SimpleArithmetic + Copy> Iterator for BlockNumberRange<N> {
+	type Item = N;
+
+	fn next(&mut self) -> Option<N> {
+		if self.low >= self.high {
+			return None
+		}
+
+		let item = self.low;
+		self.low = self.low + One::one();
+		Some(item)
+	}
+}
+
+pub const INHERENT_IDENTIFIER: InherentIdentifier = *b"cmpheads";
+
+pub type InherentType = Vec<AttestedCandidate>;
+
+/// Identifier of the parachain heads in the inherent data of nodes which still
+/// provide them in the legacy encoding. Only used if the compact heads are missing.
+pub const LEGACY_INHERENT_IDENTIFIER: InherentIdentifier = *b"newheads";
+
+pub type LegacyInherentType = Vec<LegacyAttestedCandidate>;
+
+/// Identifier of the availability bit fields in the inherent data. They are
+/// included along with the parachain heads.
+pub const AVAILABILITY_INHERENT_IDENTIFIER: InherentIdentifier = *b"availbty";
+
+pub type AvailabilityInherentType = Vec<SignedActivity>;
+
+/// Identifier of the statement-table misbehavior reports in the inherent data.
+/// They are included along with the parachain heads.
+pub const MISBEHAVIOR_INHERENT_IDENTIFIER: InherentIdentifier = *b"misbehav";
+
+pub type MisbehaviorInherentType = Vec<MisbehaviorReport>;
+
+/// Identifier of the votes about the validity of recently included candidates
+/// in the inherent data. They are included along with the parachain heads.
+pub const DISPUTES_INHERENT_IDENTIFIER: InherentIdentifier = *b"disputes";
+
+pub type DisputesInherentType = Vec<DisputeVote>;
+
+/// Identifier of the local views of the statement tables in the inherent data.
+/// They are provided by validators importing a block, to check its parachain heads against.
+pub const LOCAL_CANDIDATES_INHERENT_IDENTIFIER: InherentIdentifier = *b"localcnd";
+
+pub type LocalCandidatesInherentType = Vec<LocalCandidates>;
+
+/// Identifier of the parent hash of the block whose inherents are being checked.
+pub const RELAY_PARENT_INHERENT_IDENTIFIER: InherentIdentifier = *b"relaypar";
+
+/// The candidates known to a validator from its statement table on top of a relay parent.
+#[derive(Clone, PartialEq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct LocalCandidates {
+	/// The relay parent the statement table was built on top of.
+	pub relay_parent: Hash,
+	/// Parachains with a candidate which the validator considers includable.
+	pub includable: Vec<ParaId>,
+	/// Hashes of candidates which the validator has seen declared invalid by at least
+	/// as many validators of their group as must attest to their validity. Fewer votes
+	/// of invalidity are settled by disputes instead.
+	pub invalid: Vec<Hash>,
+}
+
+/// Errors from checking the parachain heads against the local view of a validator.
+#[derive(Encode)]
+#[cfg_attr(feature = "std", derive(Debug, Decode, PartialEq))]
+pub enum InherentError {
+	/// A candidate was included which the validator has seen declared invalid by
+	/// a threshold of its group.
+	InvalidCandidate(Hash),
+	/// Parachains with includable candidates were left out.
+	SkippedCandidates(Vec<ParaId>),
+}
+
+impl IsFatalError for InherentError {
+	fn is_fatal_error(&self) -> bool {
+		match *self {
+			InherentError::InvalidCandidate(_) => true,
+			InherentError::SkippedCandidates(_) => false,
+		}
+	}
+}
+
+fn check_local_candidates(heads: &[AttestedCandidate], local: &LocalCandidates)
+	-> rstd::result::Result<(), InherentError>
+{
+	if let Some(invalid) = heads.iter().map(|head| head.candidate.hash()).find(|h| local.invalid.contains(h)) {
+		return Err(InherentError::InvalidCandidate(invalid));
+	}
+
+	let skipped: Vec<_> = local.includable.iter()
+		.filter(|id| !heads.iter().any(|head| head.parachain_index() == **id))
+		.cloned()
+		.collect();
+
+	if skipped.is_empty() {
+		Ok(())
+	} else {
+		Err(InherentError::SkippedCandidates(skipped))
+	}
+}
+
+impl<T: Trait> ProvideInherent for Module<T> {
+	type Call = Call<T>;
+	type Error = InherentError;
+	const INHERENT_IDENTIFIER: InherentIdentifier = INHERENT_IDENTIFIER;
+
+	fn create_inherent(data: &InherentData) -> Option<Self::Call> {
+		let heads = match data.get_data::<InherentType>(&INHERENT_IDENTIFIER)
+			.expect("Parachain heads could not be decoded.")
+		{
+			Some(heads) => heads,
+			None => Self::compact_candidates(
+				data.get_data::<LegacyInherentType>(&LEGACY_INHERENT_IDENTIFIER)
+					.expect("Legacy parachain heads could not be decoded.")
+					.expect("No parachain heads found in inherent data.")
+			),
+		};
+
+		// blocks may be authored without any availability having been signalled.
+		let availability = data.get_data::<AvailabilityInherentType>(&AVAILABILITY_INHERENT_IDENTIFIER)
+			.expect("Availability bit fields could not be decoded.")
+			.unwrap_or_default();
+
+		let misbehavior = data.get_data::<MisbehaviorInherentType>(&MISBEHAVIOR_INHERENT_IDENTIFIER)
+			.expect("Misbehavior reports could not be decoded.")
+			.unwrap_or_default();
+
+		let disputes = data.get_data::<DisputesInherentType>(&DISPUTES_INHERENT_IDENTIFIER)
+			.expect("Dispute votes could not be decoded.")
+			.unwrap_or_default();
+
+		Some(Call::set_heads(heads, availability, misbehavior, disputes))
+	}
+
+	fn check_inherent(call: &Self::Call, data: &InherentData) -> rstd::result::Result<(), Self::Error> {
+		let heads = match *call {
+			Call::set_heads(ref heads, _, _, _) => heads,
+			_ => return Ok(()),
+		};
+
+		// only validators have a local view of the statement table to check against.
+		let relay_parent = match data.get_data::<Hash>(&RELAY_PARENT_INHERENT_IDENTIFIER) {
+			Ok(Some(relay_parent)) => relay_parent,
+			_ => return Ok(()),
+		};
+
+		let local = data.get_data::<LocalCandidatesInherentType>(&LOCAL_CANDIDATES_INHERENT_IDENTIFIER)
+			.ok()
+			.and_then(|local| local)
+			.and_then(|local| local.into_iter().find(|l| l.relay_parent == relay_parent));
+
+		match local {
+			Some(local) => check_local_candidates(heads, &local),
+			None => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sr_io::{TestExternalities, with_externalities};
+	use substrate_primitives::{H256, Blake2Hasher};
+	use sr_primitives::{generic, BuildStorage};
+	use sr_primitives::traits::{BlakeTwo256, IdentityLookup, OnFinalise};
+	use primitives::{parachain::{CandidateReceipt, HeadData, ValidityAttestation, Activity}, SessionKey};
+	use keyring::Keyring;
+	use statement_table::generic::{Misbehavior, ValidityDoubleVote, MultipleCandidates};
+	use std::cell::RefCell;
+	use {consensus, timestamp};
+
+	impl_outer_origin! {
 		pub enum Origin for Test {}
 	}
 
-	#[derive(Clone, Eq, PartialEq)]
-	pub struct Test;
-	impl consensus::Trait for Test {
-		type SessionKey = SessionKey;
-		type InherentOfflineReport = ();
-		type Log = ::Log;
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl consensus::Trait for Test {
+		type SessionKey = SessionKey;
+		type InherentOfflineReport = ();
+		type Log = ::Log;
+	}
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = ::Nonce;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = generic::Digest<::Log>;
+		type AccountId = ::AccountId;
+		type Lookup = IdentityLookup<::AccountId>;
+		type Header = ::Header;
+		type Event = ();
+		type Log = ::Log;
+	}
+	impl session::Trait for Test {
+		type ConvertAccountIdToSessionKey = ::SessionKeyConversion;
+		type OnSessionChange = ();
+		type Event = ();
+	}
+	impl timestamp::Trait for Test {
+		type Moment = u64;
+		type OnTimestampSet = ();
+	}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type EnsureAccountLiquid = ();
+		type Event = ();
+	}
+	impl staking::Trait for Test {
+		type OnRewardMinted = ();
+		type Event = ();
+	}
+	impl Trait for Test {
+		type Event = ();
+		type HandleMisbehavior = RecordMisbehavior;
+	}
+
+	thread_local! {
+		static MISBEHAVING: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+	}
+
+	pub struct RecordMisbehavior;
+	impl HandleMisbehavior for RecordMisbehavior {
+		fn handle_misbehavior(authority_index: usize) {
+			MISBEHAVING.with(|m| m.borrow_mut().push(authority_index));
+		}
+	}
+
+	type Parachains = Module<Test>;
+	type Balances = balances::Module<Test>;
+
+	fn new_test_ext(parachains: Vec<(ParaId, Vec<u8>, Vec<u8>)>) -> TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		let authority_keys = [
+			Keyring::Alice,
+			Keyring::Bob,
+			Keyring::Charlie,
+			Keyring::Dave,
+			Keyring::Eve,
+			Keyring::Ferdie,
+			Keyring::One,
+			Keyring::Two,
+		];
+
+		t.extend(consensus::GenesisConfig::<Test>{
+			code: vec![],
+			authorities: authority_keys.iter().map(|k| k.to_raw_public().into()).collect(),
+		}.build_storage().unwrap().0);
+		t.extend(session::GenesisConfig::<Test>{
+			session_length: 1000,
+			validators: authority_keys.iter().map(|k| k.to_raw_public().into()).collect(),
+		}.build_storage().unwrap().0);
+		t.extend(balances::GenesisConfig::<Test>::default().build_storage().unwrap().0);
+		t.extend(staking::GenesisConfig::<Test>{
+			intentions: vec![Keyring::Bob.to_raw_public().into()],
+			minimum_validator_count: 0,
+			current_offline_slash: 100,
+			offline_slash_grace: 2,
+			..Default::default()
+		}.build_storage().unwrap().0);
+		t.extend(GenesisConfig::<Test>{
+			parachains: parachains,
+			fee_schedule: Default::default(),
+			validation_fuel_limit: DEFAULT_VALIDATION_FUEL_LIMIT,
+			max_code_size: 16,
+			validity_threshold: Default::default(),
+			code_upgrade_delay: 2,
+			availability_timeout: 3,
+			parathread_deposit: 10,
+			max_parathreads_per_block: 1,
+			dispute_period: 10,
+			min_group_size: 0,
+			max_group_size: 0,
+			group_rotation_frequency: 0,
+			_phdata: Default::default(),
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn make_attestations(candidate: &mut AttestedCandidate) {
+		let mut vote_implicit = false;
+		let parent_hash = ::System::parent_hash();
+
+		let duty_roster = Parachains::calculate_duty_roster();
+		let candidate_hash = candidate.candidate.hash();
+
+		let authorities = ::Consensus::authorities();
+		let extract_key = |public: SessionKey| {
+			Keyring::from_raw_public(public.0).unwrap()
+		};
+
+		let validation_entries = duty_roster.validator_duty.iter()
+			.enumerate();
+
+		let mut group = Vec::new();
+		let mut votes = Vec::new();
+		for (idx, &duty) in validation_entries {
+			if duty != Chain::Parachain(candidate.parachain_index()) { continue }
+			vote_implicit = !vote_implicit;
+			group.push(authorities[idx]);
+
+			let key = extract_key(authorities[idx]);
+
+			let statement = if vote_implicit {
+				Statement::Candidate(candidate.candidate.clone())
+			} else {
+				Statement::Valid(candidate_hash.clone())
+			};
+
+			let payload = localized_payload(statement, parent_hash);
+			let signature = key.sign(&payload[..]).into();
+
+			votes.push((authorities[idx], if vote_implicit {
+				ValidityAttestation::Implicit(signature)
+			} else {
+				ValidityAttestation::Explicit(signature)
+			}));
+		}
+
+		*candidate = AttestedCandidate::from_attestations(candidate.candidate.clone(), &group, votes)
+			.expect("all votes are given by members of the group; qed");
+	}
+
+	// the keys of the authorities which attested to a candidate.
+	fn attesting_keys(candidate: &AttestedCandidate) -> Vec<SessionKey> {
+		let authorities = ::Consensus::authorities();
+		let groups = Parachains::validator_groups(&Parachains::calculate_duty_roster());
+		let group = &groups[&candidate.parachain_index()];
+
+		candidate.voter_positions().into_iter().map(|position| authorities[group[position]]).collect()
+	}
+
+	// signal availability of all pending candidates by the authorities with the given indices.
+	fn make_availability<I: IntoIterator<Item=u32>>(indices: I) -> Vec<SignedActivity> {
+		let parent_hash = ::System::parent_hash();
+		let authorities = ::Consensus::authorities();
+		let n_pending = Parachains::pending_availability().len();
+
+		indices.into_iter().map(|validator_index| {
+			let mut activity = Activity::new(n_pending);
+			for i in 0..n_pending {
+				activity.set(i, true);
+			}
+
+			let key = Keyring::from_raw_public(authorities[validator_index as usize].0).unwrap();
+			let signature = key.sign(&activity.signing_payload(&parent_hash)[..]).into();
+
+			SignedActivity { validator_index, activity, signature }
+		}).collect()
+	}
+
+	fn full_availability() -> Vec<SignedActivity> {
+		make_availability(0..8)
+	}
+
+	#[test]
+	fn active_parachains_should_work() {
+		let parachains = vec![
+			(5u32.into(), vec![1,2,3], vec![1]),
+			(100u32.into(), vec![4,5,6], vec![2]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			assert_eq!(Parachains::active_parachains(), vec![5u32.into(), 100u32.into()]);
+			assert_eq!(Parachains::parachain_code(&5u32.into()), Some(vec![1,2,3]));
+			assert_eq!(Parachains::parachain_code(&100u32.into()), Some(vec![4,5,6]));
+		});
+	}
+
+	#[test]
+	fn register_deregister() {
+		let parachains = vec![
+			(5u32.into(), vec![1,2,3], vec![1]),
+			(100u32.into(), vec![4,5,6], vec![2,]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			assert_eq!(Parachains::active_parachains(), vec![5u32.into(), 100u32.into()]);
+
+			assert_eq!(Parachains::parachain_code(&5u32.into()), Some(vec![1,2,3]));
+			assert_eq!(Parachains::parachain_code(&100u32.into()), Some(vec![4,5,6]));
+
+			assert_ok!(Parachains::register_parachain(99u32.into(), vec![7,8,9], vec![1, 1, 1]));
+
+			assert_eq!(Parachains::active_parachains(), vec![5u32.into(), 99u32.into(), 100u32.into()]);
+			assert_eq!(Parachains::parachain_code(&99u32.into()), Some(vec![7,8,9]));
+
+			assert_ok!(Parachains::deregister_parachain(5u32.into()));
+
+			assert_eq!(Parachains::active_parachains(), vec![99u32.into(), 100u32.into()]);
+			assert_eq!(Parachains::parachain_code(&5u32.into()), None);
+		});
+	}
+
+	#[test]
+	fn validity_thresholds_are_exceeded_by_required_votes() {
+		let majority = ValidityThreshold::MAJORITY;
+		let supermajority = ValidityThreshold::SUPERMAJORITY;
+
+		// a majority is strict, so two halves of an even group can't both back candidates.
+		assert_eq!(majority.required_votes(1), 1);
+		assert_eq!(majority.required_votes(2), 2);
+		assert_eq!(majority.required_votes(3), 2);
+		assert_eq!(majority.required_votes(4), 3);
+		assert_eq!(majority.required_votes(5), 3);
+		assert_eq!(majority.required_votes(6), 4);
+
+		assert_eq!(supermajority.required_votes(3), 3);
+		assert_eq!(supermajority.required_votes(4), 3);
+		assert_eq!(supermajority.required_votes(5), 4);
+		assert_eq!(supermajority.required_votes(6), 5);
+		assert_eq!(supermajority.required_votes(7), 5);
+
+		// invalid thresholds require the whole group.
+		assert_eq!(ValidityThreshold { numerator: 3, denominator: 3 }.required_votes(5), 5);
+	}
+
+	#[test]
+	fn validity_threshold_is_applied() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_block_number(1);
+
+			assert_eq!(Parachains::validity_threshold(), ValidityThreshold::MAJORITY);
+			let below_majority = ValidityThreshold { numerator: 1, denominator: 3 };
+			assert!(Parachains::set_validity_threshold(below_majority).is_err());
+			let invalid = ValidityThreshold { numerator: 4, denominator: 3 };
+			assert!(Parachains::set_validity_threshold(invalid).is_err());
+			let whole_group = ValidityThreshold { numerator: 3, denominator: 3 };
+			assert!(Parachains::set_validity_threshold(whole_group).is_err());
+			assert_ok!(Parachains::set_validity_threshold(ValidityThreshold::SUPERMAJORITY));
+
+			// every member of the group attests to the simple candidate.
+			let candidate = simple_candidate(0, vec![1, 2, 3]);
+			let group = attesting_keys(&candidate);
+			let votes: Vec<_> = group.iter().cloned()
+				.zip(candidate.validity_votes.iter().cloned())
+				.collect();
+
+			// a majority of the group of 7 is 4 validators, a supermajority 5.
+			assert_eq!(group.len(), 7);
+			let attested_by = |count: usize| AttestedCandidate::from_attestations(
+				candidate.candidate.clone(),
+				&group,
+				votes[..count].iter().cloned(),
+			).unwrap();
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![attested_by(4)], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			).is_err());
+
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![attested_by(5)], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+		});
+	}
+
+	#[test]
+	fn validation_fuel_limit_can_be_set() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_eq!(Parachains::validation_fuel_limit(), DEFAULT_VALIDATION_FUEL_LIMIT);
+
+			assert!(Parachains::set_validation_fuel_limit(0).is_err());
+			assert_ok!(Parachains::set_validation_fuel_limit(5_000));
+			assert_eq!(Parachains::validation_fuel_limit(), 5_000);
+		});
+	}
+
+	#[test]
+	fn parathread_deposit_can_be_set() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_eq!(Parachains::parathread_deposit(), 10);
+
+			assert!(Parachains::set_parathread_deposit(0).is_err());
+			assert_ok!(Parachains::set_parathread_deposit(25));
+			assert_eq!(Parachains::parathread_deposit(), 25);
+		});
+
+		let default_config = GenesisConfig::<Test>::default();
+		assert_eq!(default_config.parathread_deposit, DEFAULT_PARATHREAD_DEPOSIT);
+	}
+
+	#[test]
+	fn fee_schedule_can_be_set() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_eq!(Parachains::fee_schedule(), FeeSchedule::default());
+
+			let schedule = FeeSchedule {
+				base: 10,
+				per_byte: 1,
+				max_messages: 16,
+				max_message_size: 1024,
+			};
+
+			assert_ok!(Parachains::set_fee_schedule(schedule));
+			assert_eq!(Parachains::fee_schedule(), schedule);
+		});
+	}
+
+	#[test]
+	fn duty_roster_works() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let check_roster = |duty_roster: &DutyRoster| {
+				assert_eq!(duty_roster.validator_duty.len(), 8);
+				for i in (0..2).map(ParaId::from) {
+					assert_eq!(duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Parachain(i)).count(), 3);
+				}
+				assert_eq!(duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Relay).count(), 2);
+			};
+
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let duty_roster_0 = Parachains::calculate_duty_roster();
+			check_roster(&duty_roster_0);
+
+			system::Module::<Test>::set_random_seed([1u8; 32].into());
+			let duty_roster_1 = Parachains::calculate_duty_roster();
+			check_roster(&duty_roster_1);
+			assert!(duty_roster_0 != duty_roster_1);
+
+
+			system::Module::<Test>::set_random_seed([2u8; 32].into());
+			let duty_roster_2 = Parachains::calculate_duty_roster();
+			check_roster(&duty_roster_2);
+			assert!(duty_roster_0 != duty_roster_2);
+			assert!(duty_roster_1 != duty_roster_2);
+		});
+	}
+
+	#[test]
+	fn rotated_duty_roster_is_deterministic() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			assert!(Parachains::future_duty_roster(1).is_none());
+			assert_ok!(Parachains::set_group_parameters(0, 0, 5));
+
+			let check_roster = |duty_roster: &DutyRoster| {
+				assert_eq!(duty_roster.validator_duty.len(), 8);
+				for i in (0..2).map(ParaId::from) {
+					assert_eq!(duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Parachain(i)).count(), 3);
+				}
+				assert_eq!(duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Relay).count(), 2);
+			};
+
+			system::Module::<Test>::set_block_number(1);
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let duty_roster_1 = Parachains::calculate_duty_roster();
+			check_roster(&duty_roster_1);
+
+			// the random seed has no influence on duties, which are known in advance.
+			system::Module::<Test>::set_random_seed([1u8; 32].into());
+			assert_eq!(Parachains::calculate_duty_roster().validator_duty, duty_roster_1.validator_duty);
+			assert_eq!(Parachains::future_duty_roster(4).unwrap().validator_duty, duty_roster_1.validator_duty);
+
+			let duty_roster_5 = Parachains::future_duty_roster(5).unwrap();
+			check_roster(&duty_roster_5);
+			assert!(duty_roster_1.validator_duty != duty_roster_5.validator_duty);
+
+			system::Module::<Test>::set_block_number(5);
+			assert_eq!(Parachains::calculate_duty_roster().validator_duty, duty_roster_5.validator_duty);
+		});
+	}
+
+	#[test]
+	fn group_sizes_are_bounded() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+			(2u32.into(), vec![], vec![]),
+			(3u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let group_size = |duty_roster: &DutyRoster, id: u32| {
+				duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Parachain(id.into())).count()
+			};
+
+			assert!(Parachains::set_group_parameters(3, 2, 1).is_err());
+
+			// 7 validators can only form 2 groups of at least 3, so chains take turns.
+			assert_ok!(Parachains::set_group_parameters(3, 0, 1));
+			let duty_roster = Parachains::future_duty_roster(0).unwrap();
+			assert_eq!((0..4).map(|id| group_size(&duty_roster, id)).collect::<Vec<_>>(), vec![3, 3, 0, 0]);
+			let duty_roster = Parachains::future_duty_roster(1).unwrap();
+			assert_eq!((0..4).map(|id| group_size(&duty_roster, id)).collect::<Vec<_>>(), vec![0, 3, 3, 0]);
+			let duty_roster = Parachains::future_duty_roster(3).unwrap();
+			assert_eq!((0..4).map(|id| group_size(&duty_roster, id)).collect::<Vec<_>>(), vec![3, 0, 0, 3]);
+
+			// leftover validators go to the relay chain.
+			assert_ok!(Parachains::set_group_parameters(0, 1, 1));
+			let duty_roster = Parachains::future_duty_roster(0).unwrap();
+			assert_eq!((0..4).map(|id| group_size(&duty_roster, id)).collect::<Vec<_>>(), vec![1, 1, 1, 1]);
+			assert_eq!(duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Relay).count(), 4);
+		});
+	}
+
+	#[test]
+	fn relay_validators_are_secondary_checkers() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let check_roster = |duty_roster: &DutyRoster| {
+				assert_eq!(duty_roster.secondary_checks.len(), 8);
+				let mut checked = Vec::new();
+				for (duty, check) in duty_roster.validator_duty.iter().zip(&duty_roster.secondary_checks) {
+					match *duty {
+						Chain::Relay => checked.push(check.expect("relay validators re-check a chain")),
+						Chain::Parachain(_) => assert!(check.is_none()),
+					}
+				}
+
+				checked.sort();
+				assert_eq!(checked, vec![0u32.into(), 1u32.into()]);
+			};
+
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			check_roster(&Parachains::calculate_duty_roster());
+
+			// with rotating groups, the duties are known in advance but the secondary
+			// checks are chosen using the random seed.
+			assert_ok!(Parachains::set_group_parameters(0, 0, 1));
+			let checks = (0..8u8)
+				.map(|i| {
+					system::Module::<Test>::set_random_seed([i; 32].into());
+					let duty_roster = Parachains::calculate_duty_roster();
+					check_roster(&duty_roster);
+					duty_roster.secondary_checks
+				})
+				.collect::<Vec<_>>();
+			assert!(checks.iter().any(|c| c != &checks[0]));
+
+			let future_roster = Parachains::future_duty_roster(1).unwrap();
+			assert_eq!(future_roster.secondary_checks, vec![None; 8]);
+		});
+	}
+
+	#[test]
+	fn duty_roster_without_validators_is_empty() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			session::Module::<Test>::set_validators(&[]);
+			assert!(Parachains::calculate_duty_roster().validator_duty.is_empty());
+
+			assert_ok!(Parachains::set_group_parameters(1, 0, 1));
+			assert!(Parachains::calculate_duty_roster().validator_duty.is_empty());
+			assert_eq!(Parachains::future_duty_roster(10).unwrap().validator_duty, vec![]);
+		});
+	}
+
+	#[test]
+	fn unattested_candidate_is_rejected() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let candidate = AttestedCandidate {
+				validity_votes: vec![],
+				validator_indices: Default::default(),
+				candidate: CandidateReceipt {
+					parachain_index: 0.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: vec![],
+					egress_queue_roots: vec![],
+					fees: 0,
+					block_data_hash: Default::default(),
+					new_validation_code_hash: None,
+					erasure_root: Default::default(),
+				}
+			};
+
+			assert!(Parachains::dispatch(Call::set_heads(vec![candidate], vec![], vec![], vec![]), Origin::INHERENT).is_err());
+		})
+	}
+
+	#[test]
+	fn attested_candidates_accepted_in_order() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let mut candidate_a = AttestedCandidate {
+				validity_votes: vec![],
+				validator_indices: Default::default(),
+				candidate: CandidateReceipt {
+					parachain_index: 0.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: vec![],
+					egress_queue_roots: vec![],
+					fees: 0,
+					block_data_hash: Default::default(),
+					new_validation_code_hash: None,
+					erasure_root: Default::default(),
+				}
+			};
+
+			let mut candidate_b = AttestedCandidate {
+				validity_votes: vec![],
+				validator_indices: Default::default(),
+				candidate: CandidateReceipt {
+					parachain_index: 1.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![2, 3, 4]),
+					balance_uploads: vec![],
+					egress_queue_roots: vec![],
+					fees: 0,
+					block_data_hash: Default::default(),
+					new_validation_code_hash: None,
+					erasure_root: Default::default(),
+				}
+			};
+
+			make_attestations(&mut candidate_a);
+			make_attestations(&mut candidate_b);
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate_b.clone(), candidate_a.clone()], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			).is_err());
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate_a.clone(), candidate_b.clone()], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			).is_ok());
+		});
+	}
+
+	#[test]
+	fn duplicate_vote_is_rejected() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let mut candidate = AttestedCandidate {
+				validity_votes: vec![],
+				validator_indices: Default::default(),
+				candidate: CandidateReceipt {
+					parachain_index: 0.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: vec![],
+					egress_queue_roots: vec![],
+					fees: 0,
+					block_data_hash: Default::default(),
+					new_validation_code_hash: None,
+					erasure_root: Default::default(),
+				}
+			};
+
+			make_attestations(&mut candidate);
+
+			// every vote needs its own bit in the group's bit field.
+			let mut double_validity = candidate.clone();
+			double_validity.validity_votes.push(candidate.validity_votes[0].clone());
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![double_validity], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			).is_err());
+
+			// bit fields longer than the group are rejected.
+			let mut outside_group = candidate.clone();
+			outside_group.validator_indices.0.push(0x80);
+			outside_group.validity_votes.push(candidate.validity_votes[0].clone());
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![outside_group], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			).is_err());
+		});
+	}
+
+	#[test]
+	fn ingress_works() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+			(99u32.into(), vec![1, 2, 3], vec![4, 5, 6]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let from_a: Vec<(ParaId, Hash)> = vec![(1.into(), [1; 32].into())];
+			let mut candidate_a = AttestedCandidate {
+				validity_votes: vec![],
+				validator_indices: Default::default(),
+				candidate: CandidateReceipt {
+					parachain_index: 0.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: vec![],
+					egress_queue_roots: from_a.clone(),
+					fees: 0,
+					block_data_hash: Default::default(),
+					new_validation_code_hash: None,
+					erasure_root: Default::default(),
+				}
+			};
+
+			let from_b: Vec<(ParaId, Hash)> = vec![(99.into(), [1; 32].into())];
+			let mut candidate_b = AttestedCandidate {
+				validity_votes: vec![],
+				validator_indices: Default::default(),
+				candidate: CandidateReceipt {
+					parachain_index: 1.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: vec![],
+					egress_queue_roots: from_b.clone(),
+					fees: 0,
+					block_data_hash: Default::default(),
+					new_validation_code_hash: None,
+					erasure_root: Default::default(),
+				}
+			};
+
+			make_attestations(&mut candidate_a);
+			make_attestations(&mut candidate_b);
+
+			assert_eq!(Parachains::ingress(ParaId::from(1)), Some(Vec::new()));
+			assert_eq!(Parachains::ingress(ParaId::from(99)), Some(Vec::new()));
+
+			// each block makes the candidate of the previous block available.
+			for i in 1..5 {
+				system::Module::<Test>::set_block_number(i);
+				assert_ok!(Parachains::dispatch(
+					Call::set_heads(vec![candidate_a.clone()], full_availability(), vec![], vec![]),
+					Origin::INHERENT,
+				));
+				Parachains::on_finalise(i);
+			}
+
+			// parachain 1 hasn't been included, so its ingress keeps growing.
+			system::Module::<Test>::set_block_number(5);
+			assert_eq!(
+				Parachains::ingress(ParaId::from(1)),
+				Some((2..5).map(|i| (i, BlockIngressRoots(from_a.clone()))).collect()),
+			);
+			assert_eq!(Parachains::ingress(ParaId::from(99)), Some(Vec::new()));
+
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![candidate_a.clone(), candidate_b.clone()], full_availability(), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(5);
+
+			system::Module::<Test>::set_block_number(6);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![], full_availability(), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(6);
+
+			// the candidate of parachain 1 was included in block 5, so enacting it routes
+			// everything posted before block 5.
+			system::Module::<Test>::set_block_number(7);
+			assert_eq!(
+				Parachains::ingress(ParaId::from(1)),
+				Some(vec![(5, BlockIngressRoots(from_a.clone())), (6, BlockIngressRoots(from_a.clone()))]),
+			);
+			assert_eq!(
+				Parachains::ingress(ParaId::from(99)),
+				Some(vec![(6, BlockIngressRoots(from_b.clone()))]),
+			);
+
+			assert_ok!(Parachains::deregister_parachain(99u32.into()));
+			assert_eq!(Parachains::ingress(ParaId::from(99)), None);
+			assert!(!<UnroutedIngress<Test>>::exists(&(6, ParaId::from(99))));
+		});
 	}
-	impl system::Trait for Test {
-		type Origin = Origin;
-		type Index = ::Nonce;
-		type BlockNumber = u64;
-		type Hash = H256;
-		type Hashing = BlakeTwo256;
-		type Digest = generic::Digest<::Log>;
-		type AccountId = ::AccountId;
-		type Lookup = IdentityLookup<::AccountId>;
-		type Header = ::Header;
-		type Event = ();
-		type Log = ::Log;
+
+	#[test]
+	fn balance_uploads_and_fees_are_applied() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let para_account = Parachains::parachain_account(0.into());
+			let recipient: AccountId = [42; 32].into();
+			Balances::set_free_balance(&para_account, 1_000);
+
+			let mut candidate = AttestedCandidate {
+				validity_votes: vec![],
+				validator_indices: Default::default(),
+				candidate: CandidateReceipt {
+					parachain_index: 0.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: vec![(recipient, 100)],
+					egress_queue_roots: vec![],
+					fees: 10,
+					block_data_hash: Default::default(),
+					new_validation_code_hash: None,
+					erasure_root: Default::default(),
+				}
+			};
+
+			make_attestations(&mut candidate);
+			let voters: Vec<AccountId> = attesting_keys(&candidate).iter()
+				.map(|key| key.0.into())
+				.collect();
+			let fee_share = 10 / voters.len() as u64;
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![candidate], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(1);
+
+			// nothing is applied until the candidate is available.
+			assert_eq!(Balances::free_balance(&recipient), 0);
+			assert_eq!(Balances::free_balance(&para_account), 1_000);
+
+			system::Module::<Test>::set_block_number(2);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![], full_availability(), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(2);
+
+			// the indivisible remainder of the fees stays with the parachain.
+			let remaining = 1_000 - 100 - fee_share * voters.len() as u64;
+			assert_eq!(Balances::free_balance(&para_account), remaining);
+
+			// the credits are held while the candidate may be disputed, which is
+			// until the end of block 11 with a dispute period of 10.
+			for n in 3..12 {
+				assert_eq!(Balances::free_balance(&recipient), 0);
+				system::Module::<Test>::set_block_number(n);
+				assert_ok!(Parachains::dispatch(Call::set_heads(vec![], vec![], vec![], vec![]), Origin::INHERENT));
+				Parachains::on_finalise(n);
+			}
+
+			assert_eq!(Balances::free_balance(&recipient), 100);
+			for voter in &voters {
+				assert_eq!(Balances::free_balance(voter), fee_share);
+			}
+			assert_eq!(Balances::free_balance(&para_account), remaining);
+		});
 	}
-	impl session::Trait for Test {
-		type ConvertAccountIdToSessionKey = ::SessionKeyConversion;
-		type OnSessionChange = ();
-		type Event = ();
+
+	#[test]
+	fn candidate_exceeding_parachain_balance_is_skipped() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let para_account = Parachains::parachain_account(0.into());
+			let recipient: AccountId = [42; 32].into();
+			Balances::set_free_balance(&para_account, 100);
+
+			let mut candidate = AttestedCandidate {
+				validity_votes: vec![],
+				validator_indices: Default::default(),
+				candidate: CandidateReceipt {
+					parachain_index: 0.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: vec![(recipient, 100)],
+					egress_queue_roots: vec![],
+					fees: 10,
+					block_data_hash: Default::default(),
+					new_validation_code_hash: None,
+					erasure_root: Default::default(),
+				}
+			};
+
+			make_attestations(&mut candidate);
+			let funded = simple_candidate(1, vec![4, 5, 6]);
+
+			// the candidate which can't be paid for doesn't keep the other one out.
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![candidate, funded], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+
+			let pending: Vec<_> = Parachains::pending_availability().into_iter()
+				.map(|p| p.candidate.parachain_index)
+				.collect();
+			assert_eq!(pending, vec![1u32.into()]);
+
+			assert_eq!(Balances::free_balance(&recipient), 0);
+			assert_eq!(Balances::free_balance(&para_account), 100);
+			assert_eq!(Parachains::parachain_head(&0u32.into()), Some(vec![]));
+		});
+	}
+
+	#[test]
+	fn egress_to_self_or_unknown_is_rejected() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let candidate_with_egress = |egress_queue_roots: Vec<(ParaId, Hash)>| {
+				let mut candidate = AttestedCandidate {
+					validity_votes: vec![],
+					validator_indices: Default::default(),
+					candidate: CandidateReceipt {
+						parachain_index: 0.into(),
+						collator: Default::default(),
+						signature: Default::default(),
+						head_data: HeadData(vec![1, 2, 3]),
+						balance_uploads: vec![],
+						egress_queue_roots,
+						fees: 0,
+						block_data_hash: Default::default(),
+						new_validation_code_hash: None,
+						erasure_root: Default::default(),
+					}
+				};
+
+				make_attestations(&mut candidate);
+				candidate
+			};
+
+			let root: Hash = [1; 32].into();
+			let to_self = candidate_with_egress(vec![(0.into(), root)]);
+			let to_unknown = candidate_with_egress(vec![(2.into(), root)]);
+			let duplicate = candidate_with_egress(vec![(1.into(), root), (1.into(), root)]);
+
+			for candidate in vec![to_self, to_unknown, duplicate] {
+				assert!(Parachains::dispatch(
+					Call::set_heads(vec![candidate], vec![], vec![], vec![]),
+					Origin::INHERENT,
+				).is_err());
+			}
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate_with_egress(vec![(1.into(), root)])], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			).is_ok());
+		});
+	}
+
+	fn code_upgrade_candidate(new_validation_code: Option<Vec<u8>>) -> AttestedCandidate {
+		let mut candidate = AttestedCandidate {
+			validity_votes: vec![],
+			validator_indices: Default::default(),
+			candidate: CandidateReceipt {
+				parachain_index: 0.into(),
+				collator: Default::default(),
+				signature: Default::default(),
+				head_data: HeadData(vec![1, 2, 3]),
+				balance_uploads: vec![],
+				egress_queue_roots: vec![],
+				fees: 0,
+				block_data_hash: Default::default(),
+				new_validation_code_hash: new_validation_code.map(|code| BlakeTwo256::hash(&code[..])),
+				erasure_root: Default::default(),
+			}
+		};
+
+		make_attestations(&mut candidate);
+		candidate
+	}
+
+	#[test]
+	fn code_upgrade_is_applied_after_delay() {
+		let parachains = vec![
+			(0u32.into(), vec![1, 2, 3], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let alice: ::AccountId = Keyring::Alice.to_raw_public().into();
+			let submit = |code: Vec<u8>| Parachains::submit_validation_code(Origin::signed(alice), 0u32.into(), code);
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![code_upgrade_candidate(Some(vec![4, 5, 6]))], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(1);
+
+			// the upgrade is scheduled once the candidate is available.
+			assert_eq!(Parachains::parachain_pending_code(&0u32.into()), None);
+			assert!(submit(vec![4, 5, 6]).is_err());
+
+			system::Module::<Test>::set_block_number(2);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![code_upgrade_candidate(None)], full_availability(), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(2);
+
+			let code_hash = BlakeTwo256::hash(&[4, 5, 6][..]);
+			assert_eq!(Parachains::parachain_code(&0u32.into()), Some(vec![1, 2, 3]));
+			assert_eq!(Parachains::parachain_pending_code(&0u32.into()), Some((4, code_hash)));
+
+			// only the scheduled code is accepted, and only once.
+			assert_noop!(submit(vec![7, 8, 9]), "Validation code does not match the scheduled upgrade");
+			assert_ok!(submit(vec![4, 5, 6]));
+			assert_noop!(submit(vec![4, 5, 6]), "Validation code already submitted");
+
+			// a second upgrade can't be scheduled while one is pending.
+			system::Module::<Test>::set_block_number(3);
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![code_upgrade_candidate(Some(vec![7, 8, 9]))], full_availability(), vec![], vec![]),
+				Origin::INHERENT,
+			).is_err());
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![code_upgrade_candidate(None)], full_availability(), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(3);
+
+			assert_eq!(Parachains::parachain_code(&0u32.into()), Some(vec![1, 2, 3]));
+
+			system::Module::<Test>::set_block_number(4);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![], full_availability(), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(4);
+
+			assert_eq!(Parachains::parachain_code(&0u32.into()), Some(vec![4, 5, 6]));
+			assert_eq!(Parachains::parachain_pending_code(&0u32.into()), None);
+			assert_eq!(Parachains::parachain_submitted_code(&0u32.into()), None);
+		});
+	}
+
+	#[test]
+	fn code_upgrade_without_submitted_code_is_dropped() {
+		let parachains = vec![
+			(0u32.into(), vec![1, 2, 3], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let alice: ::AccountId = Keyring::Alice.to_raw_public().into();
+			let too_large = vec![0; 17];
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![code_upgrade_candidate(Some(too_large.clone()))], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(1);
+
+			system::Module::<Test>::set_block_number(2);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![], full_availability(), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(2);
+
+			// the code is over the maximum size of 16 bytes, so it can't be submitted.
+			assert!(Parachains::parachain_pending_code(&0u32.into()).is_some());
+			assert_noop!(
+				Parachains::submit_validation_code(Origin::signed(alice), 0u32.into(), too_large),
+				"Validation code too large"
+			);
+
+			for n in 3..5 {
+				system::Module::<Test>::set_block_number(n);
+				assert_ok!(Parachains::dispatch(Call::set_heads(vec![], vec![], vec![], vec![]), Origin::INHERENT));
+				Parachains::on_finalise(n);
+			}
+
+			assert_eq!(Parachains::parachain_code(&0u32.into()), Some(vec![1, 2, 3]));
+			assert_eq!(Parachains::parachain_pending_code(&0u32.into()), None);
+		});
+	}
+
+	fn simple_candidate(para_id: u32, head_data: Vec<u8>) -> AttestedCandidate {
+		let mut candidate = AttestedCandidate {
+			validity_votes: vec![],
+			validator_indices: Default::default(),
+			candidate: CandidateReceipt {
+				parachain_index: para_id.into(),
+				collator: Default::default(),
+				signature: Default::default(),
+				head_data: HeadData(head_data),
+				balance_uploads: vec![],
+				egress_queue_roots: vec![],
+				fees: 0,
+				block_data_hash: Default::default(),
+				new_validation_code_hash: None,
+				erasure_root: Default::default(),
+			}
+		};
+
+		make_attestations(&mut candidate);
+		candidate
+	}
+
+	#[test]
+	fn candidate_is_enacted_once_available() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![simple_candidate(0, vec![1, 2, 3])], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(1);
+
+			assert_eq!(Parachains::parachain_head(&0u32.into()), Some(vec![]));
+			assert_eq!(Parachains::pending_availability().len(), 1);
+
+			// a second candidate can't be included while the first is pending.
+			system::Module::<Test>::set_block_number(2);
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![simple_candidate(0, vec![4, 5, 6])], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			).is_err());
+
+			// 5 of 8 validators isn't more than two thirds.
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![], make_availability(0..5), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(2);
+
+			assert_eq!(Parachains::parachain_head(&0u32.into()), Some(vec![]));
+			assert_eq!(Parachains::pending_availability()[0].available_from, vec![0, 1, 2, 3, 4]);
+
+			system::Module::<Test>::set_block_number(3);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![], make_availability(5..6), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(3);
+
+			assert_eq!(Parachains::parachain_head(&0u32.into()), Some(vec![1, 2, 3]));
+			assert!(Parachains::pending_availability().is_empty());
+		});
+	}
+
+	#[test]
+	fn bad_availability_is_rejected() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![simple_candidate(0, vec![1, 2, 3])], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(1);
+
+			system::Module::<Test>::set_block_number(2);
+
+			let out_of_order = make_availability(vec![1, 0]);
+			let mut unknown_validator = make_availability(0..1);
+			unknown_validator[0].validator_index = 8;
+
+			let mut bad_signature = make_availability(0..1);
+			bad_signature[0].validator_index = 1;
+
+			let mut wrong_length = make_availability(0..1);
+			wrong_length[0].activity.0.push(0);
+
+			for availability in vec![out_of_order, unknown_validator, bad_signature, wrong_length] {
+				assert!(Parachains::dispatch(
+					Call::set_heads(vec![], availability, vec![], vec![]),
+					Origin::INHERENT,
+				).is_err());
+			}
+
+			assert!(Parachains::pending_availability()[0].available_from.is_empty());
+		});
+	}
+
+	#[test]
+	fn unavailable_candidate_is_reverted() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![simple_candidate(0, vec![1, 2, 3])], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(1);
+
+			for i in 2..4 {
+				system::Module::<Test>::set_block_number(i);
+				assert_ok!(Parachains::dispatch(
+					Call::set_heads(vec![], make_availability(0..1), vec![], vec![]),
+					Origin::INHERENT,
+				));
+				Parachains::on_finalise(i);
+			}
+
+			assert_eq!(Parachains::pending_availability().len(), 1);
+
+			// the candidate times out, and a new one for the same parachain may be included.
+			system::Module::<Test>::set_block_number(4);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![simple_candidate(0, vec![4, 5, 6])], make_availability(0..1), vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(4);
+
+			assert_eq!(Parachains::parachain_head(&0u32.into()), Some(vec![]));
+
+			let pending = Parachains::pending_availability();
+			assert_eq!(pending.len(), 1);
+			assert_eq!(pending[0].candidate.head_data, HeadData(vec![4, 5, 6]));
+			assert_eq!(pending[0].included_at, 4);
+		});
 	}
-	impl timestamp::Trait for Test {
-		type Moment = u64;
-		type OnTimestampSet = ();
+
+	#[test]
+	fn misbehaving_authority_is_punished() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let parent_hash = ::System::parent_hash();
+			let digest: Hash = [1; 32].into();
+			let sign = |key: Keyring, statement: Statement| -> CandidateSignature {
+				key.sign(&localized_payload(statement, parent_hash)[..]).into()
+			};
+
+			let double_vote = Misbehavior::ValidityDoubleVote(ValidityDoubleVote::ValidityAndInvalidity(
+				digest,
+				sign(Keyring::Bob, Statement::Valid(digest)),
+				sign(Keyring::Bob, Statement::Invalid(digest)),
+			));
+
+			let candidate = simple_candidate(0, vec![1, 2, 3]).candidate;
+			let mut other_candidate = candidate.clone();
+			other_candidate.head_data = HeadData(vec![4, 5, 6]);
+			let multiple_candidates = Misbehavior::MultipleCandidates(MultipleCandidates {
+				first: (candidate.clone(), sign(Keyring::Charlie, Statement::Candidate(candidate))),
+				second: (other_candidate.clone(), sign(Keyring::Charlie, Statement::Candidate(other_candidate))),
+			});
+
+			// reports which can't be proven are skipped without failing the inherent:
+			// a proof signed by someone other than the reported authority, a report
+			// of a non-authority and a second report of the same authority.
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![], vec![], vec![
+					(Keyring::Alice.to_raw_public().into(), double_vote.clone()),
+					(Keyring::Bob.to_raw_public().into(), double_vote.clone()),
+					([99; 32].into(), double_vote.clone()),
+					(Keyring::Bob.to_raw_public().into(), double_vote),
+					(Keyring::Charlie.to_raw_public().into(), multiple_candidates),
+				], vec![]),
+				Origin::INHERENT,
+			));
+
+			MISBEHAVING.with(|m| assert_eq!(*m.borrow(), vec![1, 2]));
+		});
 	}
-	impl Trait for Test {}
 
-	type Parachains = Module<Test>;
+	#[test]
+	fn slasher_reports_misbehaving_validator_to_staking() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			let bob: AccountId = Keyring::Bob.to_raw_public().into();
+			Balances::set_free_balance(&bob, 1_000);
+
+			// slashed straight away, without the warnings of the grace period.
+			Slasher::<Test>::handle_misbehavior(1);
+			assert_eq!(Balances::free_balance(&bob), 900);
+			assert_eq!(staking::Module::<Test>::slash_count(&bob), 3);
+
+			// authorities which are no longer validators are ignored.
+			Slasher::<Test>::handle_misbehavior(100);
+		});
+	}
 
-	fn new_test_ext(parachains: Vec<(ParaId, Vec<u8>, Vec<u8>)>) -> TestExternalities<Blake2Hasher> {
-		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
-		let authority_keys = [
-			Keyring::Alice,
-			Keyring::Bob,
-			Keyring::Charlie,
-			Keyring::Dave,
-			Keyring::Eve,
-			Keyring::Ferdie,
-			Keyring::One,
-			Keyring::Two,
+	#[test]
+	fn heads_are_checked_against_local_candidates() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
 		];
 
-		t.extend(consensus::GenesisConfig::<Test>{
-			code: vec![],
-			authorities: authority_keys.iter().map(|k| k.to_raw_public().into()).collect(),
-		}.build_storage().unwrap().0);
-		t.extend(session::GenesisConfig::<Test>{
-			session_length: 1000,
-			validators: authority_keys.iter().map(|k| k.to_raw_public().into()).collect(),
-		}.build_storage().unwrap().0);
-		t.extend(GenesisConfig::<Test>{
-			parachains: parachains,
-			_phdata: Default::default(),
-		}.build_storage().unwrap().0);
-		t.into()
-	}
+		with_externalities(&mut new_test_ext(parachains), || {
+			let candidate_a = simple_candidate(0, vec![1, 2, 3]);
+			let candidate_b = simple_candidate(1, vec![4, 5, 6]);
+			let relay_parent: Hash = [1; 32].into();
 
-	fn make_attestations(candidate: &mut AttestedCandidate) {
-		let mut vote_implicit = false;
-		let parent_hash = ::System::parent_hash();
+			let check = |heads: Vec<AttestedCandidate>, local: LocalCandidates| {
+				let mut data = InherentData::new();
+				data.put_data(RELAY_PARENT_INHERENT_IDENTIFIER, &relay_parent).unwrap();
+				data.put_data(LOCAL_CANDIDATES_INHERENT_IDENTIFIER, &vec![local]).unwrap();
 
-		let duty_roster = Parachains::calculate_duty_roster();
-		let candidate_hash = candidate.candidate.hash();
+				Parachains::check_inherent(&Call::set_heads(heads, vec![], vec![], vec![]), &data)
+			};
 
-		let authorities = ::Consensus::authorities();
-		let extract_key = |public: SessionKey| {
-			Keyring::from_raw_public(public.0).unwrap()
-		};
+			let local = |includable: Vec<u32>, invalid: Vec<Hash>| LocalCandidates {
+				relay_parent,
+				includable: includable.into_iter().map(Into::into).collect(),
+				invalid,
+			};
 
-		let validation_entries = duty_roster.validator_duty.iter()
-			.enumerate();
+			assert!(check(vec![candidate_a.clone(), candidate_b.clone()], local(vec![0, 1], vec![])).is_ok());
 
-		for (idx, &duty) in validation_entries {
-			if duty != Chain::Parachain(candidate.parachain_index()) { continue }
-			vote_implicit = !vote_implicit;
+			match check(vec![candidate_a.clone()], local(vec![0, 1], vec![])) {
+				Err(e @ InherentError::SkippedCandidates(_)) => {
+					assert_eq!(e, InherentError::SkippedCandidates(vec![1u32.into()]));
+					assert!(!e.is_fatal_error());
+				}
+				x => panic!("unexpected result {:?}", x),
+			}
 
-			let key = extract_key(authorities[idx]);
+			match check(vec![candidate_a.clone(), candidate_b.clone()], local(vec![0], vec![candidate_b.candidate.hash()])) {
+				Err(e @ InherentError::InvalidCandidate(_)) => assert!(e.is_fatal_error()),
+				x => panic!("unexpected result {:?}", x),
+			}
 
-			let statement = if vote_implicit {
-				Statement::Candidate(candidate.candidate.clone())
-			} else {
-				Statement::Valid(candidate_hash.clone())
-			};
+			// views on top of other relay parents are not checked against.
+			let mut other = local(vec![0, 1], vec![candidate_a.candidate.hash()]);
+			other.relay_parent = [2; 32].into();
+			assert!(check(vec![candidate_a], other).is_ok());
 
-			let payload = localized_payload(statement, parent_hash);
-			let signature = key.sign(&payload[..]).into();
+			// nor is anything without a local view.
+			assert!(Parachains::check_inherent(
+				&Call::set_heads(vec![candidate_b], vec![], vec![], vec![]),
+				&InherentData::new(),
+			).is_ok());
+		});
+	}
 
-			candidate.validity_votes.push((authorities[idx], if vote_implicit {
-				ValidityAttestation::Implicit(signature)
-			} else {
-				ValidityAttestation::Explicit(signature)
-			}));
-		}
+	#[test]
+	fn parathreads_are_scheduled_by_bid() {
+		let parachains = vec![(5u32.into(), vec![], vec![])];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let alice: ::AccountId = Keyring::Alice.to_raw_public().into();
+			let bob: ::AccountId = Keyring::Bob.to_raw_public().into();
+			Balances::set_free_balance(&alice, 100);
+			Balances::set_free_balance(&bob, 100);
+
+			assert_noop!(
+				Parachains::register_parathread(Origin::signed(alice), 5u32.into(), vec![], vec![]),
+				"Parachain already exists"
+			);
+			assert_noop!(
+				Parachains::register_parathread(Origin::signed(alice), FIRST_AUCTIONED_PARA_ID.into(), vec![], vec![]),
+				"Parathread ID reserved for auctioned parachains"
+			);
+			assert_ok!(Parachains::register_parathread(Origin::signed(alice), 7u32.into(), vec![1], vec![2]));
+			assert_ok!(Parachains::register_parathread(Origin::signed(bob), 8u32.into(), vec![3], vec![4]));
+			assert_eq!(Parachains::parathreads(), vec![7u32.into(), 8u32.into()]);
+			assert_eq!(Parachains::parachain_head(&7u32.into()), Some(vec![2]));
+			assert_eq!(Balances::reserved_balance(&alice), 10);
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::claim_parathread(Origin::signed(alice), 7u32.into(), 5));
+			assert_ok!(Parachains::claim_parathread(Origin::signed(bob), 7u32.into(), 3));
+			assert_ok!(Parachains::claim_parathread(Origin::signed(bob), 8u32.into(), 4));
+			assert_noop!(
+				Parachains::claim_parathread(Origin::signed(alice), 7u32.into(), 6),
+				"Parathread already claimed by sender"
+			);
+
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![], vec![], vec![], vec![]), Origin::INHERENT));
+			Parachains::on_finalise(1);
+
+			// only one parathread is scheduled per block, so only the highest bid is paid.
+			assert_eq!(Parachains::parathread_schedule(), vec![7u32.into()]);
+			assert_eq!(Parachains::scheduled_chains(), vec![5u32.into(), 7u32.into()]);
+			assert_eq!(Balances::free_balance(&alice), 85);
+			assert_eq!(Balances::reserved_balance(&alice), 10);
+			assert_eq!(Balances::free_balance(&bob), 90);
+			assert_eq!(Balances::reserved_balance(&bob), 10);
+
+			assert!(Parachains::calculate_duty_roster().validator_duty.contains(&Chain::Parachain(7u32.into())));
+			assert!(!Parachains::calculate_duty_roster().validator_duty.contains(&Chain::Parachain(8u32.into())));
+
+			// candidates are only accepted for scheduled parathreads.
+			system::Module::<Test>::set_block_number(2);
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![simple_candidate(8, vec![1])], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			).is_err());
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![simple_candidate(7, vec![1])], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(2);
+
+			assert!(Parachains::parathread_schedule().is_empty());
+			assert_eq!(Parachains::pending_availability().len(), 1);
+
+			assert_noop!(
+				Parachains::deregister_parathread(Origin::signed(bob), 7u32.into()),
+				"Parathread not registered by sender"
+			);
+			assert_ok!(Parachains::deregister_parathread(Origin::signed(alice), 7u32.into()));
+			assert_eq!(Parachains::parathreads(), vec![8u32.into()]);
+			assert_eq!(Parachains::parachain_code(&7u32.into()), None);
+			assert!(Parachains::pending_availability().is_empty());
+			assert_eq!(Balances::reserved_balance(&alice), 0);
+		});
 	}
 
 	#[test]
-	fn active_parachains_should_work() {
-		let parachains = vec![
-			(5u32.into(), vec![1,2,3], vec![1]),
-			(100u32.into(), vec![4,5,6], vec![2]),
-		];
+	fn recent_heads_are_recorded() {
+		let parachains = vec![(0u32.into(), vec![], vec![])];
 
 		with_externalities(&mut new_test_ext(parachains), || {
-			assert_eq!(Parachains::active_parachains(), vec![5u32.into(), 100u32.into()]);
-			assert_eq!(Parachains::parachain_code(&5u32.into()), Some(vec![1,2,3]));
-			assert_eq!(Parachains::parachain_code(&100u32.into()), Some(vec![4,5,6]));
+			let id: ParaId = 0u32.into();
+			let candidate = simple_candidate(0, vec![1, 2, 3]);
+			let candidate_hash = candidate.candidate.hash();
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![candidate], vec![], vec![], vec![]), Origin::INHERENT));
+			Parachains::on_finalise(1);
+
+			// heads are only recorded once available.
+			assert!(Parachains::recent_heads(&id).is_empty());
+
+			system::Module::<Test>::set_block_number(2);
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![], full_availability(), vec![], vec![]), Origin::INHERENT));
+			Parachains::on_finalise(2);
+
+			assert_eq!(Parachains::recent_heads(&id), vec![(2, candidate_hash, vec![1, 2, 3])]);
+
+			// only the most recent heads are kept.
+			for i in 0..(MAX_HEAD_HISTORY as u8 + 2) {
+				Parachains::record_heads(&[PendingCandidate {
+					candidate: simple_candidate(0, vec![i]).candidate,
+					relay_parent: Default::default(),
+					included_at: 2,
+					voters: Vec::new(),
+					available_from: Vec::new(),
+				}]);
+			}
+
+			let recent = Parachains::recent_heads(&id);
+			assert_eq!(recent.len(), MAX_HEAD_HISTORY);
+			assert_eq!(recent[0].2, vec![2]);
+			assert_eq!(recent[MAX_HEAD_HISTORY - 1].2, vec![MAX_HEAD_HISTORY as u8 + 1]);
+
+			assert_ok!(Parachains::deregister_parachain(id));
+			assert!(Parachains::recent_heads(&id).is_empty());
 		});
 	}
 
+	const AUTHORITY_KEYS: [Keyring; 8] = [
+		Keyring::Alice,
+		Keyring::Bob,
+		Keyring::Charlie,
+		Keyring::Dave,
+		Keyring::Eve,
+		Keyring::Ferdie,
+		Keyring::One,
+		Keyring::Two,
+	];
+
+	// sign a dispute vote about an included candidate.
+	fn dispute_vote(key: Keyring, candidate_hash: Hash, valid: bool) -> (SessionKey, CandidateSignature) {
+		let relay_parent = Parachains::included_candidate(&candidate_hash)
+			.or_else(|| Parachains::dispute(&candidate_hash).map(|d| d.candidate))
+			.unwrap()
+			.relay_parent;
+
+		let statement = if valid { Statement::Valid(candidate_hash) } else { Statement::Invalid(candidate_hash) };
+		let signature = key.sign(&localized_payload(statement, relay_parent)[..]).into();
+		(key.to_raw_public().into(), signature)
+	}
+
+	// the authorities which didn't back the given candidate.
+	fn non_backers(candidate_hash: Hash) -> Vec<Keyring> {
+		let backers = Parachains::included_candidate(&candidate_hash).unwrap().backers;
+		AUTHORITY_KEYS.iter()
+			.filter(|k| !backers.contains(&k.to_raw_public().into()))
+			.cloned()
+			.collect()
+	}
+
 	#[test]
-	fn register_deregister() {
+	fn invalid_candidate_is_reverted_after_dispute() {
 		let parachains = vec![
-			(5u32.into(), vec![1,2,3], vec![1]),
-			(100u32.into(), vec![4,5,6], vec![2,]),
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+			(2u32.into(), vec![], vec![]),
 		];
 
 		with_externalities(&mut new_test_ext(parachains), || {
-			assert_eq!(Parachains::active_parachains(), vec![5u32.into(), 100u32.into()]);
+			let id = 0u32.into();
+			let candidate = simple_candidate(0, vec![1, 2, 3]);
+			let candidate_hash = candidate.candidate.hash();
 
-			assert_eq!(Parachains::parachain_code(&5u32.into()), Some(vec![1,2,3]));
-			assert_eq!(Parachains::parachain_code(&100u32.into()), Some(vec![4,5,6]));
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![candidate], vec![], vec![], vec![]), Origin::INHERENT));
+			Parachains::on_finalise(1);
 
-			assert_ok!(Parachains::register_parachain(99u32.into(), vec![7,8,9], vec![1, 1, 1]));
+			system::Module::<Test>::set_block_number(2);
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![], full_availability(), vec![], vec![]), Origin::INHERENT));
+			Parachains::on_finalise(2);
 
-			assert_eq!(Parachains::active_parachains(), vec![5u32.into(), 99u32.into(), 100u32.into()]);
-			assert_eq!(Parachains::parachain_code(&99u32.into()), Some(vec![7,8,9]));
+			assert_eq!(Parachains::parachain_head(&id), Some(vec![1, 2, 3]));
 
-			assert_ok!(Parachains::deregister_parachain(5u32.into()));
+			let included = Parachains::included_candidate(&candidate_hash).unwrap();
+			assert!(included.enacted);
+			assert_eq!(included.backers.len(), 2);
 
-			assert_eq!(Parachains::active_parachains(), vec![99u32.into(), 100u32.into()]);
-			assert_eq!(Parachains::parachain_code(&5u32.into()), None);
+			let origin = || Origin::signed(Keyring::Alice.to_raw_public().into());
+			let disputers = non_backers(candidate_hash);
+			let backer = AUTHORITY_KEYS.iter().find(|k| !disputers.contains(k)).unwrap().clone();
+
+			// backers can't dispute their own candidate.
+			let (voter, signature) = dispute_vote(backer, candidate_hash, false);
+			assert!(Parachains::raise_dispute(origin(), candidate_hash, voter, signature).is_err());
+
+			// the vote must be signed by the voter.
+			let (voter, _) = dispute_vote(disputers[0], candidate_hash, false);
+			let (_, signature) = dispute_vote(disputers[1], candidate_hash, false);
+			assert!(Parachains::raise_dispute(origin(), candidate_hash, voter, signature).is_err());
+
+			let (voter, signature) = dispute_vote(disputers[0], candidate_hash, false);
+			assert_ok!(Parachains::raise_dispute(origin(), candidate_hash, voter, signature));
+			assert!(Parachains::included_candidate(&candidate_hash).is_none());
+			assert_eq!(Parachains::open_disputes(), vec![candidate_hash]);
+
+			let (voter, signature) = dispute_vote(disputers[0], candidate_hash, false);
+			assert!(Parachains::vote_on_dispute(origin(), candidate_hash, voter, false, signature).is_err());
+
+			// 6 of 8 authorities is more than two thirds.
+			for &key in &disputers[1..5] {
+				let (voter, signature) = dispute_vote(key, candidate_hash, false);
+				assert_ok!(Parachains::vote_on_dispute(origin(), candidate_hash, voter, false, signature));
+			}
+
+			assert_eq!(Parachains::parachain_head(&id), Some(vec![1, 2, 3]));
+			MISBEHAVING.with(|m| assert!(m.borrow().is_empty()));
+
+			let (voter, signature) = dispute_vote(disputers[5], candidate_hash, false);
+			assert_ok!(Parachains::vote_on_dispute(origin(), candidate_hash, voter, false, signature));
+
+			assert!(Parachains::dispute(&candidate_hash).is_none());
+			assert!(Parachains::open_disputes().is_empty());
+			assert_eq!(Parachains::parachain_head(&id), Some(vec![]));
+			assert!(Parachains::recent_heads(&id).is_empty());
+
+			let authorities = ::Consensus::authorities();
+			let mut punished = MISBEHAVING.with(|m| m.borrow().clone());
+			punished.sort();
+			let mut backers = included.backers.iter()
+				.map(|b| authorities.iter().position(|a| a == b).unwrap())
+				.collect::<Vec<_>>();
+			backers.sort();
+			assert_eq!(punished, backers);
 		});
 	}
 
 	#[test]
-	fn duty_roster_works() {
+	fn reverting_candidate_reverts_later_candidates_of_chain() {
 		let parachains = vec![
 			(0u32.into(), vec![], vec![]),
 			(1u32.into(), vec![], vec![]),
+			(2u32.into(), vec![], vec![]),
 		];
 
 		with_externalities(&mut new_test_ext(parachains), || {
-			let check_roster = |duty_roster: &DutyRoster| {
-				assert_eq!(duty_roster.validator_duty.len(), 8);
-				for i in (0..2).map(ParaId::from) {
-					assert_eq!(duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Parachain(i)).count(), 3);
-				}
-				assert_eq!(duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Relay).count(), 2);
+			let id = 0u32.into();
+			let para_account = Parachains::parachain_account(id);
+			let recipient: AccountId = [42; 32].into();
+			Balances::set_free_balance(&para_account, 1_000);
+
+			let uploading_candidate = |head_data: Vec<u8>, amount: u64| {
+				let mut candidate = AttestedCandidate {
+					validity_votes: vec![],
+					validator_indices: Default::default(),
+					candidate: CandidateReceipt {
+						parachain_index: id,
+						collator: Default::default(),
+						signature: Default::default(),
+						head_data: HeadData(head_data),
+						balance_uploads: vec![(recipient, amount)],
+						egress_queue_roots: vec![],
+						fees: 0,
+						block_data_hash: Default::default(),
+						new_validation_code_hash: None,
+						erasure_root: Default::default(),
+					}
+				};
+				make_attestations(&mut candidate);
+				candidate
 			};
 
-			system::Module::<Test>::set_random_seed([0u8; 32].into());
-			let duty_roster_0 = Parachains::calculate_duty_roster();
-			check_roster(&duty_roster_0);
+			// include and enact a candidate, then another one built on top of it.
+			let candidate_a = uploading_candidate(vec![1, 2, 3], 100);
+			let hash_a = candidate_a.candidate.hash();
+			let candidate_b = uploading_candidate(vec![4, 5, 6], 50);
+			let hash_b = candidate_b.candidate.hash();
+
+			for (n, heads) in vec![(1, vec![candidate_a]), (2, vec![]), (3, vec![candidate_b]), (4, vec![])] {
+				system::Module::<Test>::set_block_number(n);
+				let availability = if heads.is_empty() { full_availability() } else { vec![] };
+				assert_ok!(Parachains::dispatch(Call::set_heads(heads, availability, vec![], vec![]), Origin::INHERENT));
+				Parachains::on_finalise(n);
+			}
 
-			system::Module::<Test>::set_random_seed([1u8; 32].into());
-			let duty_roster_1 = Parachains::calculate_duty_roster();
-			check_roster(&duty_roster_1);
-			assert!(duty_roster_0 != duty_roster_1);
+			assert_eq!(Parachains::parachain_head(&id), Some(vec![4, 5, 6]));
+			assert_eq!(Balances::free_balance(&para_account), 850);
+			assert_eq!(Balances::free_balance(&recipient), 0);
 
+			let origin = || Origin::signed(Keyring::Alice.to_raw_public().into());
+			let disputers_a = non_backers(hash_a);
+			let disputers_b = non_backers(hash_b);
 
-			system::Module::<Test>::set_random_seed([2u8; 32].into());
-			let duty_roster_2 = Parachains::calculate_duty_roster();
-			check_roster(&duty_roster_2);
-			assert!(duty_roster_0 != duty_roster_2);
-			assert!(duty_roster_1 != duty_roster_2);
+			let (voter, signature) = dispute_vote(disputers_b[0], hash_b, false);
+			assert_ok!(Parachains::raise_dispute(origin(), hash_b, voter, signature));
+
+			let (voter, signature) = dispute_vote(disputers_a[0], hash_a, false);
+			assert_ok!(Parachains::raise_dispute(origin(), hash_a, voter, signature));
+			for &key in &disputers_a[1..6] {
+				let (voter, signature) = dispute_vote(key, hash_a, false);
+				assert_ok!(Parachains::vote_on_dispute(origin(), hash_a, voter, false, signature));
+			}
+
+			// the later candidate and the dispute about it went along with the first one.
+			assert_eq!(Parachains::parachain_head(&id), Some(vec![]));
+			assert!(Parachains::recent_heads(&id).is_empty());
+			assert!(Parachains::included_candidate(&hash_b).is_none());
+			assert!(Parachains::dispute(&hash_b).is_none());
+			assert!(Parachains::open_disputes().is_empty());
+
+			// the balance uploads were never paid out and are returned to the parachain.
+			assert!(Parachains::held_credits(&hash_a).is_empty());
+			assert!(Parachains::held_credits(&hash_b).is_empty());
+			assert_eq!(Balances::free_balance(&para_account), 1_000);
+			assert_eq!(Balances::free_balance(&recipient), 0);
 		});
 	}
 
 	#[test]
-	fn unattested_candidate_is_rejected() {
+	fn dispute_votes_are_applied_with_heads() {
 		let parachains = vec![
 			(0u32.into(), vec![], vec![]),
 			(1u32.into(), vec![], vec![]),
+			(2u32.into(), vec![], vec![]),
 		];
 
 		with_externalities(&mut new_test_ext(parachains), || {
-			system::Module::<Test>::set_random_seed([0u8; 32].into());
-			let candidate = AttestedCandidate {
-				validity_votes: vec![],
-				candidate: CandidateReceipt {
-					parachain_index: 0.into(),
-					collator: Default::default(),
-					signature: Default::default(),
-					head_data: HeadData(vec![1, 2, 3]),
-					balance_uploads: vec![],
-					egress_queue_roots: vec![],
-					fees: 0,
-					block_data_hash: Default::default(),
-				}
+			let candidate = simple_candidate(0, vec![1, 2, 3]);
+			let candidate_hash = candidate.candidate.hash();
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![candidate], vec![], vec![], vec![]), Origin::INHERENT));
+			Parachains::on_finalise(1);
+
+			system::Module::<Test>::set_block_number(2);
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![], full_availability(), vec![], vec![]), Origin::INHERENT));
+			Parachains::on_finalise(2);
+
+			let disputers = non_backers(candidate_hash);
+			let backer = AUTHORITY_KEYS.iter().find(|k| !disputers.contains(k)).unwrap().clone();
+			let vote = |key, valid| {
+				let (voter, signature) = dispute_vote(key, candidate_hash, valid);
+				(candidate_hash, voter, valid, signature)
 			};
 
-			assert!(Parachains::dispatch(Call::set_heads(vec![candidate]), Origin::INHERENT).is_err());
-		})
+			// votes which can't be applied are skipped without failing the block.
+			let votes = vec![
+				vote(backer, false),
+				vote(disputers[0], true),
+				vote(disputers[1], false),
+				vote(disputers[2], false),
+				vote(disputers[2], false),
+				(Default::default(), vote(disputers[3], false).1, false, vote(disputers[3], false).3),
+			];
+
+			system::Module::<Test>::set_block_number(3);
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![], vec![], vec![], votes), Origin::INHERENT));
+			Parachains::on_finalise(3);
+
+			let dispute = Parachains::dispute(&candidate_hash).unwrap();
+			let invalid: Vec<SessionKey> = vec![disputers[1].to_raw_public().into(), disputers[2].to_raw_public().into()];
+			assert_eq!(dispute.invalid, invalid);
+			assert_eq!(dispute.valid.len(), 2);
+			assert_eq!(Parachains::open_disputes(), vec![candidate_hash]);
+		});
 	}
 
 	#[test]
-	fn attested_candidates_accepted_in_order() {
+	fn disputes_are_resolved_or_time_out() {
 		let parachains = vec![
 			(0u32.into(), vec![], vec![]),
 			(1u32.into(), vec![], vec![]),
+			(2u32.into(), vec![], vec![]),
 		];
 
 		with_externalities(&mut new_test_ext(parachains), || {
-			system::Module::<Test>::set_random_seed([0u8; 32].into());
-			let mut candidate_a = AttestedCandidate {
-				validity_votes: vec![],
-				candidate: CandidateReceipt {
-					parachain_index: 0.into(),
-					collator: Default::default(),
-					signature: Default::default(),
-					head_data: HeadData(vec![1, 2, 3]),
-					balance_uploads: vec![],
-					egress_queue_roots: vec![],
-					fees: 0,
-					block_data_hash: Default::default(),
-				}
-			};
+			let candidate_a = simple_candidate(0, vec![1, 2, 3]);
+			let candidate_b = simple_candidate(1, vec![4, 5, 6]);
+			let hash_a = candidate_a.candidate.hash();
+			let hash_b = candidate_b.candidate.hash();
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![candidate_a, candidate_b], vec![], vec![], vec![]),
+				Origin::INHERENT,
+			));
+			Parachains::on_finalise(1);
+
+			let origin = || Origin::signed(Keyring::Alice.to_raw_public().into());
+			let disputers_a = non_backers(hash_a);
+			let disputers_b = non_backers(hash_b);
+
+			system::Module::<Test>::set_block_number(2);
+			let (voter, signature) = dispute_vote(disputers_a[0], hash_a, false);
+			assert_ok!(Parachains::raise_dispute(origin(), hash_a, voter, signature));
+			let (voter, signature) = dispute_vote(disputers_b[0], hash_b, false);
+			assert_ok!(Parachains::raise_dispute(origin(), hash_b, voter, signature));
+
+			// the backers and 4 others make 6 of 8 authorities finding the candidate valid.
+			for &key in &disputers_a[1..5] {
+				let (voter, signature) = dispute_vote(key, hash_a, true);
+				assert_ok!(Parachains::vote_on_dispute(origin(), hash_a, voter, true, signature));
+			}
 
-			let mut candidate_b = AttestedCandidate {
-				validity_votes: vec![],
-				candidate: CandidateReceipt {
-					parachain_index: 1.into(),
-					collator: Default::default(),
-					signature: Default::default(),
-					head_data: HeadData(vec![2, 3, 4]),
-					balance_uploads: vec![],
-					egress_queue_roots: vec![],
-					fees: 0,
-					block_data_hash: Default::default(),
-				}
+			let disputer_index = AUTHORITY_KEYS.iter().position(|k| k == &disputers_a[0]).unwrap();
+			MISBEHAVING.with(|m| assert_eq!(*m.borrow(), vec![disputer_index]));
+			assert!(Parachains::dispute(&hash_a).is_none());
+			assert_eq!(Parachains::pending_availability().len(), 2);
+
+			// the other dispute is dropped once the dispute period has passed.
+			for n in 2..12 {
+				system::Module::<Test>::set_block_number(n);
+				assert_ok!(Parachains::dispatch(Call::set_heads(vec![], vec![], vec![], vec![]), Origin::INHERENT));
+				Parachains::on_finalise(n);
+				assert_eq!(Parachains::open_disputes(), vec![hash_b]);
+			}
+
+			system::Module::<Test>::set_block_number(12);
+			assert_ok!(Parachains::dispatch(Call::set_heads(vec![], vec![], vec![], vec![]), Origin::INHERENT));
+			Parachains::on_finalise(12);
+
+			assert!(Parachains::open_disputes().is_empty());
+			assert!(Parachains::dispute(&hash_b).is_none());
+			MISBEHAVING.with(|m| assert_eq!(m.borrow().len(), 1));
+		});
+	}
+
+	#[test]
+	fn legacy_inherent_is_compacted() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_block_number(1);
+
+			let candidate = simple_candidate(0, vec![1, 2, 3]);
+			let legacy = LegacyAttestedCandidate {
+				candidate: candidate.candidate.clone(),
+				validity_votes: attesting_keys(&candidate).into_iter()
+					.zip(candidate.validity_votes.iter().cloned())
+					.rev()
+					.collect(),
 			};
 
-			make_attestations(&mut candidate_a);
-			make_attestations(&mut candidate_b);
+			assert_eq!(Parachains::compact_candidates(vec![legacy.clone()]), vec![candidate.clone()]);
 
-			assert!(Parachains::dispatch(
-				Call::set_heads(vec![candidate_b.clone(), candidate_a.clone()]),
-				Origin::INHERENT,
-			).is_err());
+			// candidates with attestations by validators outside the group are dropped.
+			let authorities = ::Consensus::authorities();
+			let groups = Parachains::validator_groups(&Parachains::calculate_duty_roster());
+			let outside = (0..authorities.len())
+				.find(|idx| !groups[&0u32.into()].contains(idx))
+				.unwrap();
 
-			assert!(Parachains::dispatch(
-				Call::set_heads(vec![candidate_a.clone(), candidate_b.clone()]),
-				Origin::INHERENT,
-			).is_ok());
+			let mut outsider = legacy.clone();
+			outsider.validity_votes[0].0 = authorities[outside];
+			assert!(Parachains::compact_candidates(vec![outsider]).is_empty());
+
+			let mut data = InherentData::new();
+			data.put_data(LEGACY_INHERENT_IDENTIFIER, &vec![legacy]).unwrap();
+
+			match Parachains::create_inherent(&data) {
+				Some(Call::set_heads(heads, _, _, _)) => assert_eq!(heads, vec![candidate]),
+				_ => panic!("inherent created from legacy data"),
+			}
 		});
 	}
 
 	#[test]
-	fn duplicate_vote_is_rejected() {
+	fn legacy_pending_candidates_are_migrated() {
 		let parachains = vec![
 			(0u32.into(), vec![], vec![]),
 			(1u32.into(), vec![], vec![]),
 		];
 
 		with_externalities(&mut new_test_ext(parachains), || {
-			system::Module::<Test>::set_random_seed([0u8; 32].into());
-			let mut candidate = AttestedCandidate {
-				validity_votes: vec![],
-				candidate: CandidateReceipt {
-					parachain_index: 0.into(),
-					collator: Default::default(),
-					signature: Default::default(),
-					head_data: HeadData(vec![1, 2, 3]),
-					balance_uploads: vec![],
-					egress_queue_roots: vec![],
-					fees: 0,
-					block_data_hash: Default::default(),
-				}
+			system::Module::<Test>::set_block_number(1);
+
+			let candidate = simple_candidate(0, vec![1, 2, 3]);
+			let legacy = LegacyPendingCandidate {
+				attested: LegacyAttestedCandidate {
+					candidate: candidate.candidate.clone(),
+					validity_votes: attesting_keys(&candidate).into_iter()
+						.zip(candidate.validity_votes.iter().cloned())
+						.collect(),
+				},
+				relay_parent: Default::default(),
+				included_at: 1u64,
+				voters: vec![0, 1],
+				available_from: vec![],
 			};
 
-			make_attestations(&mut candidate);
-
-			let mut double_validity = candidate.clone();
-			double_validity.validity_votes.push(candidate.validity_votes[0].clone());
+			::srml_support::storage::put(LEGACY_PENDING_AVAILABILITY_KEY, &vec![legacy]);
 
-			assert!(Parachains::dispatch(
-				Call::set_heads(vec![double_validity]),
+			assert_ok!(Parachains::dispatch(
+				Call::set_heads(vec![], vec![], vec![], vec![]),
 				Origin::INHERENT,
-			).is_err());
+			));
+
+			let pending = Parachains::pending_availability();
+			assert_eq!(pending.len(), 1);
+			assert_eq!(pending[0].candidate, candidate.candidate);
+			assert_eq!(pending[0].voters, vec![0, 1]);
+
+			let legacy: Option<Vec<LegacyPendingCandidate<u64>>> =
+				::srml_support::storage::get(LEGACY_PENDING_AVAILABILITY_KEY);
+			assert!(legacy.is_none());
 		});
 	}
 }
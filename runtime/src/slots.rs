@@ -0,0 +1,695 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Auctions of parachain slots.
+//!
+//! Parachain slots are leased for a range of lease periods. Each auction offers
+//! `SLOT_RANGE_LEN` consecutive lease periods, and accounts bid reserved DOTs
+//! on any range of them. Auctions are candle auctions: once the bidding period
+//! is over, the auction may end at any block of the ending period, only known
+//! in retrospect. The set of non-overlapping bids winning the most in total at
+//! that block wins.
+//!
+//! Every winning bid leases a new parachain slot to its bidder, who then provides
+//! the code and initial head data of the parachain. The parachain is registered
+//! at the start of its first lease period and deregistered once its last one is
+//! over, when the winning bid is unreserved.
+
+use rstd::prelude::*;
+use codec::Decode;
+use srml_support::{StorageValue, StorageMap};
+use srml_support::dispatch::Result;
+use sr_primitives::traits::{As, One, Zero};
+use primitives::parachain::Id as ParaId;
+use {system, balances, parachains};
+use system::ensure_signed;
+
+/// The number of consecutive lease periods offered in an auction.
+pub const SLOT_RANGE_LEN: usize = 4;
+
+/// The number of distinct ranges of lease periods which can be bid on in an auction.
+pub const SLOT_RANGE_COUNT: usize = SLOT_RANGE_LEN * (SLOT_RANGE_LEN + 1) / 2;
+
+/// The default number of blocks in a lease period.
+pub const DEFAULT_LEASE_PERIOD: u64 = 100_000;
+
+/// The default number of blocks during which an auction may end.
+pub const DEFAULT_ENDING_PERIOD: u64 = 1_000;
+
+/// Parachains leased through auctions are given IDs starting from this one.
+pub const FIRST_AUCTIONED_PARA_ID: u32 = 100;
+
+/// Configuration trait.
+pub trait Trait: parachains::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+/// The winning bids of an auction, indexed by range of lease periods.
+pub type WinningData<AccountId, Balance> = Vec<Option<(AccountId, Balance)>>;
+
+/// A parachain slot leased through an auction.
+#[derive(Clone, PartialEq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Lease<AccountId, Balance, BlockNumber> {
+	/// The account which won the slot.
+	pub owner: AccountId,
+	/// The winning bid, which stays reserved until the lease is over.
+	pub deposit: Balance,
+	/// The first lease period of the lease.
+	pub first_period: BlockNumber,
+	/// The last lease period of the lease.
+	pub last_period: BlockNumber,
+	/// The code and initial head data of the parachain, until it is registered.
+	pub deploy_data: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+type LeaseOf<T> = Lease<
+	<T as system::Trait>::AccountId,
+	<T as balances::Trait>::Balance,
+	<T as system::Trait>::BlockNumber,
+>;
+
+/// An event in this module.
+decl_event!(
+	pub enum Event<T> where
+		A = <T as system::Trait>::AccountId,
+		B = <T as balances::Trait>::Balance,
+		N = <T as system::Trait>::BlockNumber
+	{
+		/// An auction started for the lease periods from the given one onwards.
+		NewAuction(u32, N),
+		/// A bid won a lease of a parachain slot for a range of lease periods.
+		Won(A, ParaId, N, N, B),
+		/// The lease of a parachain is over.
+		LeaseEnded(ParaId),
+	}
+);
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Slots {
+		// The number of blocks in a lease period.
+		pub LeasePeriod get(lease_period) config():
+			T::BlockNumber = T::BlockNumber::sa(DEFAULT_LEASE_PERIOD);
+		// The number of blocks at the end of an auction during which it may end.
+		pub EndingPeriod get(ending_period) config():
+			T::BlockNumber = T::BlockNumber::sa(DEFAULT_ENDING_PERIOD);
+
+		// The number of auctions started so far.
+		pub AuctionCounter get(auction_counter): u32;
+		// The first lease period offered by the ongoing auction, along with the
+		// block at which its ending period begins.
+		pub AuctionInfo get(auction_info): Option<(T::BlockNumber, T::BlockNumber)>;
+		// The winning bids of the ongoing auction at every offset into its ending
+		// period. Bids placed before the ending period are kept under offset zero.
+		pub Winning get(winning): map T::BlockNumber => Option<WinningData<T::AccountId, T::Balance>>;
+		// The amount reserved by each bidder in the ongoing auction: the greatest total of
+		// its bids winning at any offset into the ending period.
+		pub ReservedAmounts get(reserved_amount): map T::AccountId => T::Balance;
+		// The accounts which have bid in the ongoing auction.
+		Bidders: Vec<T::AccountId>;
+
+		// The leases of parachain slots won in auctions.
+		pub Leases get(lease): map ParaId => Option<LeaseOf<T>>;
+		// The parachains with leases, sorted ascending.
+		pub LeasedParachains get(leased_parachains): Vec<ParaId>;
+		// The ID to give to the next parachain to win a slot.
+		NextParaId: u32 = FIRST_AUCTIONED_PARA_ID;
+	}
+}
+
+decl_module! {
+	/// Parachain slot auctions module.
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Bid on a range of the lease periods offered by the ongoing auction.
+		///
+		/// The bid must beat the current winning bid on the same range. Enough of the
+		/// bidder's balance is reserved until the auction ends to pay for all of its
+		/// bids winning at once, so only the amount they exceed the bidder's existing
+		/// reservation by is reserved.
+		fn bid(
+			origin,
+			auction_index: u32,
+			first_period: T::BlockNumber,
+			last_period: T::BlockNumber,
+			amount: T::Balance
+		) -> Result {
+			let who = ensure_signed(origin)?;
+
+			let (auction_first, ending_start) = Self::auction_info().ok_or("No auction in progress")?;
+			ensure!(auction_index == Self::auction_counter(), "Not the current auction");
+			ensure!(
+				auction_first <= first_period && first_period <= last_period
+					&& last_period < auction_first + T::BlockNumber::sa(SLOT_RANGE_LEN as u64),
+				"Lease periods not offered by the auction"
+			);
+			ensure!(!amount.is_zero(), "Bid must be non-zero");
+
+			let now = <system::Module<T>>::block_number();
+			let offset = if now < ending_start { Zero::zero() } else { now - ending_start };
+
+			let index = range_index(
+				(first_period - auction_first).as_() as usize,
+				(last_period - auction_first).as_() as usize,
+			);
+
+			let mut winning = Self::winning(offset).unwrap_or_else(|| vec![None; SLOT_RANGE_COUNT]);
+			if let Some((_, ref current)) = winning[index] {
+				ensure!(amount > *current, "Bid does not beat the winning bid");
+			}
+			winning[index] = Some((who.clone(), amount));
+
+			// the winners are taken from the bids winning at a single offset, so the most
+			// a bidder may have to pay is the total of its bids winning at any offset.
+			let needed = winning.iter()
+				.filter_map(|w| match *w {
+					Some((ref bidder, bid)) if bidder == &who => Some(bid),
+					_ => None,
+				})
+				.fold(T::Balance::zero(), |total, bid| total + bid);
+
+			let reserved = Self::reserved_amount(&who);
+			if needed > reserved {
+				<balances::Module<T>>::reserve(&who, needed - reserved)?;
+
+				if !<ReservedAmounts<T>>::exists(&who) {
+					<Bidders<T>>::mutate(|bidders| bidders.push(who.clone()));
+				}
+				<ReservedAmounts<T>>::insert(&who, needed);
+			}
+
+			<Winning<T>>::insert(offset, winning);
+			Ok(())
+		}
+
+		/// Provide the code and initial head data of a parachain leased by the sender.
+		///
+		/// The parachain is registered straight away if its lease has already begun.
+		fn fix_deploy_data(origin, id: ParaId, code: Vec<u8>, initial_head_data: Vec<u8>) -> Result {
+			let who = ensure_signed(origin)?;
+
+			let mut lease = Self::lease(id).ok_or("No lease for parachain")?;
+			ensure!(lease.owner == who, "Parachain not leased by sender");
+			ensure!(
+				lease.deploy_data.is_none() && !<parachains::Module<T>>::active_parachains().contains(&id),
+				"Parachain already deployed"
+			);
+
+			if Self::current_lease_period() >= lease.first_period {
+				<parachains::Module<T>>::register_parachain(id, code, initial_head_data)?;
+			} else {
+				lease.deploy_data = Some((code, initial_head_data));
+				<Leases<T>>::insert(id, lease);
+			}
+
+			Ok(())
+		}
+
+		/// Start an auction of the given lease period and the ones following it.
+		/// Bids are taken for `duration` blocks, after which the ending period begins.
+		pub fn new_auction(duration: T::BlockNumber, lease_period_index: T::BlockNumber) -> Result {
+			ensure!(Self::auction_info().is_none(), "Auction already in progress");
+			ensure!(!Self::lease_period().is_zero(), "No lease periods begin while the lease period is zero");
+			ensure!(lease_period_index > Self::current_lease_period(), "Lease periods must be in the future");
+
+			let ending_start = <system::Module<T>>::block_number() + duration;
+			<AuctionInfo<T>>::put((lease_period_index, ending_start));
+
+			let index = Self::auction_counter() + 1;
+			<AuctionCounter<T>>::put(index);
+
+			Self::deposit_event(RawEvent::NewAuction(index, lease_period_index));
+			Ok(())
+		}
+
+		/// Set the number of blocks in a lease period.
+		pub fn set_lease_period(lease_period: T::BlockNumber) -> Result {
+			ensure!(!lease_period.is_zero(), "Lease period must be non-zero");
+			<LeasePeriod<T>>::put(lease_period);
+			Ok(())
+		}
+
+		fn on_finalise(n: T::BlockNumber) {
+			if let Some((first_period, ending_start)) = Self::auction_info() {
+				let ending_period = Self::ending_period();
+				if n + One::one() >= ending_start + ending_period {
+					Self::close_auction(first_period, ending_period);
+				} else if n >= ending_start {
+					// carry the winning bids over into the next block of the ending period.
+					let offset = n - ending_start;
+					let next = offset + One::one();
+					if !<Winning<T>>::exists(&next) {
+						if let Some(winning) = Self::winning(offset) {
+							<Winning<T>>::insert(next, winning);
+						}
+					}
+				}
+			}
+
+			let lease_period = Self::lease_period();
+			if !lease_period.is_zero() && (n.as_() % lease_period.as_()) == 0 {
+				Self::begin_lease_period(n / lease_period);
+			}
+		}
+	}
+}
+
+// the index of the range of lease periods between the given offsets from the first
+// lease period of an auction, inclusive.
+fn range_index(first: usize, last: usize) -> usize {
+	(0..first).map(|f| SLOT_RANGE_LEN - f).sum::<usize>() + (last - first)
+}
+
+// the offsets from the first lease period of an auction bounding the range with the given index.
+fn range_bounds(index: usize) -> (usize, usize) {
+	(0..SLOT_RANGE_LEN)
+		.flat_map(|first| (first..SLOT_RANGE_LEN).map(move |last| (first, last)))
+		.nth(index)
+		.expect("range indices are less than SLOT_RANGE_COUNT; qed")
+}
+
+/// Select the set of non-overlapping winning bids with the highest total, yielding
+/// the range index of each along with the bid.
+pub fn calculate_winners<AccountId: Clone, Balance: As<u64> + Copy>(
+	winning: &[Option<(AccountId, Balance)>],
+) -> Vec<(usize, AccountId, Balance)> {
+	// the best total and set of ranges covering the first `i` lease periods.
+	let mut best: Vec<(u64, Vec<usize>)> = vec![(0, Vec::new())];
+
+	for end in 1..(SLOT_RANGE_LEN + 1) {
+		let mut best_here = best[end - 1].clone();
+
+		for start in 0..end {
+			let index = range_index(start, end - 1);
+			if let Some(&Some((_, amount))) = winning.get(index) {
+				let total = best[start].0 + amount.as_();
+				if total > best_here.0 {
+					let mut ranges = best[start].1.clone();
+					ranges.push(index);
+					best_here = (total, ranges);
+				}
+			}
+		}
+
+		best.push(best_here);
+	}
+
+	best.pop()
+		.map(|(_, ranges)| ranges)
+		.unwrap_or_default()
+		.into_iter()
+		.filter_map(|index| winning[index].clone().map(|(who, amount)| (index, who, amount)))
+		.collect()
+}
+
+impl<T: Trait> Module<T> {
+	/// The index of the lease period the current block is in. No lease periods
+	/// begin while the lease period is zero, so this stays at zero.
+	pub fn current_lease_period() -> T::BlockNumber {
+		let lease_period = Self::lease_period();
+		if lease_period.is_zero() {
+			return Zero::zero()
+		}
+
+		<system::Module<T>>::block_number() / lease_period
+	}
+
+	// end the ongoing auction at a block of its ending period chosen at random,
+	// leasing slots to the winners at that block and unreserving all other bids.
+	fn close_auction(first_period: T::BlockNumber, ending_period: T::BlockNumber) {
+		let offset = if ending_period.is_zero() {
+			Zero::zero()
+		} else {
+			let seed = <system::Module<T>>::random_seed();
+			let random = u64::decode(&mut seed.as_ref()).unwrap_or_default();
+			T::BlockNumber::sa(random % ending_period.as_())
+		};
+
+		let winning = Self::winning(offset).unwrap_or_default();
+
+		<AuctionInfo<T>>::kill();
+		let mut i = Zero::zero();
+		while i < ending_period {
+			<Winning<T>>::remove(&i);
+			i = i + One::one();
+		}
+
+		let mut leased = Self::leased_parachains();
+		for (index, who, amount) in calculate_winners(&winning) {
+			let (first, last) = range_bounds(index);
+			let id = Self::allocate_para_id();
+
+			let lease = Lease {
+				owner: who.clone(),
+				deposit: amount,
+				first_period: first_period + T::BlockNumber::sa(first as u64),
+				last_period: first_period + T::BlockNumber::sa(last as u64),
+				deploy_data: None,
+			};
+
+			Self::deposit_event(RawEvent::Won(who.clone(), id, lease.first_period, lease.last_period, amount));
+
+			// the winning bid stays reserved until the lease is over.
+			<ReservedAmounts<T>>::mutate(&who, |reserved| *reserved = *reserved - amount);
+			<Leases<T>>::insert(id, lease);
+			leased.push(id);
+		}
+		<LeasedParachains<T>>::put(leased);
+
+		for who in <Bidders<T>>::take() {
+			let reserved = <ReservedAmounts<T>>::take(&who);
+			<balances::Module<T>>::unreserve(&who, reserved);
+		}
+	}
+
+	// the parachain ID to give to the next winner of a slot.
+	fn allocate_para_id() -> ParaId {
+		let active = <parachains::Module<T>>::active_parachains();
+		let parathreads = <parachains::Module<T>>::parathreads();
+		let mut next = <NextParaId<T>>::get();
+		while active.contains(&next.into()) || parathreads.contains(&next.into()) {
+			next += 1;
+		}
+
+		<NextParaId<T>>::put(next + 1);
+		next.into()
+	}
+
+	// register the parachains whose leases begin with the given lease period, and
+	// deregister the ones whose leases are over.
+	fn begin_lease_period(period: T::BlockNumber) {
+		let mut leased = Self::leased_parachains();
+
+		leased.retain(|&id| {
+			let mut lease = match Self::lease(id) {
+				Some(lease) => lease,
+				None => return false,
+			};
+
+			if lease.last_period < period {
+				let _ = <parachains::Module<T>>::deregister_parachain(id);
+				<balances::Module<T>>::unreserve(&lease.owner, lease.deposit);
+				<Leases<T>>::remove(id);
+
+				Self::deposit_event(RawEvent::LeaseEnded(id));
+				return false;
+			}
+
+			if lease.first_period <= period {
+				if let Some((code, initial_head_data)) = lease.deploy_data.take() {
+					// parathreads can't take auctioned IDs, so this only fails if the
+					// parachain was registered by root in the meantime.
+					let _ = <parachains::Module<T>>::register_parachain(id, code, initial_head_data);
+					<Leases<T>>::insert(id, lease);
+				}
+			}
+
+			true
+		});
+
+		<LeasedParachains<T>>::put(leased);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sr_io::{TestExternalities, with_externalities};
+	use substrate_primitives::{H256, Blake2Hasher};
+	use sr_primitives::{generic, BuildStorage};
+	use sr_primitives::traits::{BlakeTwo256, IdentityLookup, OnFinalise};
+	use primitives::{AccountId, SessionKey};
+	use keyring::Keyring;
+	use {consensus, session, timestamp};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl consensus::Trait for Test {
+		type SessionKey = SessionKey;
+		type InherentOfflineReport = ();
+		type Log = ::Log;
+	}
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = ::Nonce;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = generic::Digest<::Log>;
+		type AccountId = ::AccountId;
+		type Lookup = IdentityLookup<::AccountId>;
+		type Header = ::Header;
+		type Event = ();
+		type Log = ::Log;
+	}
+	impl session::Trait for Test {
+		type ConvertAccountIdToSessionKey = ::SessionKeyConversion;
+		type OnSessionChange = ();
+		type Event = ();
+	}
+	impl timestamp::Trait for Test {
+		type Moment = u64;
+		type OnTimestampSet = ();
+	}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type EnsureAccountLiquid = ();
+		type Event = ();
+	}
+	impl parachains::Trait for Test {
+		type Event = ();
+		type HandleMisbehavior = ();
+	}
+	impl Trait for Test {
+		type Event = ();
+	}
+
+	type System = system::Module<Test>;
+	type Balances = balances::Module<Test>;
+	type Parachains = parachains::Module<Test>;
+	type Slots = Module<Test>;
+
+	fn account(key: Keyring) -> AccountId {
+		key.to_raw_public().into()
+	}
+
+	fn new_test_ext() -> TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(balances::GenesisConfig::<Test> {
+			balances: vec![(account(Keyring::Alice), 100), (account(Keyring::Bob), 100)],
+			..Default::default()
+		}.build_storage().unwrap().0);
+		t.extend(parachains::GenesisConfig::<Test>::default().build_storage().unwrap().0);
+		t.extend(GenesisConfig::<Test> {
+			lease_period: 10,
+			ending_period: 5,
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn run_to_block(n: u64) {
+		while System::block_number() < n {
+			let next = System::block_number() + 1;
+			System::set_block_number(next);
+			Slots::on_finalise(next);
+		}
+	}
+
+	#[test]
+	fn range_indices_are_consistent() {
+		for index in 0..SLOT_RANGE_COUNT {
+			let (first, last) = range_bounds(index);
+			assert!(first <= last && last < SLOT_RANGE_LEN);
+			assert_eq!(range_index(first, last), index);
+		}
+	}
+
+	#[test]
+	fn winners_have_the_highest_total() {
+		let mut winning = vec![None; SLOT_RANGE_COUNT];
+		winning[range_index(0, 3)] = Some((1, 10u64));
+		winning[range_index(0, 1)] = Some((2, 6));
+		winning[range_index(2, 3)] = Some((3, 6));
+		winning[range_index(1, 2)] = Some((4, 11));
+
+		assert_eq!(calculate_winners(&winning), vec![
+			(range_index(0, 1), 2, 6),
+			(range_index(2, 3), 3, 6),
+		]);
+
+		winning[range_index(0, 3)] = Some((1, 13));
+		assert_eq!(calculate_winners(&winning), vec![(range_index(0, 3), 1, 13)]);
+	}
+
+	#[test]
+	fn zero_lease_period_is_tolerated() {
+		with_externalities(&mut new_test_ext(), || {
+			<LeasePeriod<Test>>::put(0);
+			run_to_block(25);
+
+			assert_eq!(Slots::current_lease_period(), 0);
+			assert_noop!(Slots::new_auction(5, 1), "No lease periods begin while the lease period is zero");
+			assert_noop!(Slots::set_lease_period(0), "Lease period must be non-zero");
+
+			assert_ok!(Slots::set_lease_period(10));
+			assert_eq!(Slots::current_lease_period(), 2);
+		});
+	}
+
+	#[test]
+	fn bids_are_checked() {
+		with_externalities(&mut new_test_ext(), || {
+			let alice = account(Keyring::Alice);
+			let bob = account(Keyring::Bob);
+
+			assert_noop!(Slots::bid(Origin::signed(alice), 1, 1, 1, 10), "No auction in progress");
+
+			System::set_block_number(1);
+			assert_ok!(Slots::new_auction(5, 1));
+
+			assert_noop!(Slots::bid(Origin::signed(alice), 2, 1, 1, 10), "Not the current auction");
+			assert_noop!(Slots::bid(Origin::signed(alice), 1, 0, 1, 10), "Lease periods not offered by the auction");
+			assert_noop!(Slots::bid(Origin::signed(alice), 1, 2, 5, 10), "Lease periods not offered by the auction");
+			assert_noop!(Slots::bid(Origin::signed(alice), 1, 2, 1, 10), "Lease periods not offered by the auction");
+
+			assert_ok!(Slots::bid(Origin::signed(alice), 1, 1, 2, 10));
+			assert_noop!(Slots::bid(Origin::signed(bob), 1, 1, 2, 10), "Bid does not beat the winning bid");
+			assert!(Slots::bid(Origin::signed(bob), 1, 1, 2, 101).is_err());
+			assert_ok!(Slots::bid(Origin::signed(bob), 1, 1, 2, 11));
+
+			assert_eq!(Balances::reserved_balance(&alice), 10);
+			assert_eq!(Balances::reserved_balance(&bob), 11);
+
+			// only the amount over the existing reservation is reserved.
+			assert_ok!(Slots::bid(Origin::signed(alice), 1, 1, 2, 12));
+			assert_eq!(Balances::reserved_balance(&alice), 12);
+
+			// bids on other ranges may win alongside it.
+			assert_ok!(Slots::bid(Origin::signed(alice), 1, 3, 3, 5));
+			assert_eq!(Balances::reserved_balance(&alice), 17);
+			assert_eq!(Slots::reserved_amount(&alice), 17);
+		});
+	}
+
+	#[test]
+	fn winning_bid_leases_a_parachain_slot() {
+		with_externalities(&mut new_test_ext(), || {
+			let alice = account(Keyring::Alice);
+			let bob = account(Keyring::Bob);
+
+			System::set_block_number(1);
+			assert_ok!(Slots::new_auction(5, 1));
+
+			assert_ok!(Slots::bid(Origin::signed(alice), 1, 1, 2, 50));
+			assert_ok!(Slots::bid(Origin::signed(bob), 1, 1, 1, 20));
+			assert_ok!(Slots::bid(Origin::signed(bob), 1, 2, 2, 20));
+
+			// the ending period starts at block 6, so the auction ends at block 10.
+			run_to_block(9);
+			assert!(Slots::auction_info().is_some());
+			run_to_block(10);
+			assert!(Slots::auction_info().is_none());
+
+			let id: ParaId = FIRST_AUCTIONED_PARA_ID.into();
+			assert_eq!(Slots::leased_parachains(), vec![id]);
+			assert_eq!(Slots::lease(id), Some(Lease {
+				owner: alice,
+				deposit: 50,
+				first_period: 1,
+				last_period: 2,
+				deploy_data: None,
+			}));
+			assert_eq!(Balances::reserved_balance(&alice), 50);
+			assert_eq!(Balances::reserved_balance(&bob), 0);
+
+			assert_noop!(
+				Slots::fix_deploy_data(Origin::signed(bob), id, vec![1], vec![2]),
+				"Parachain not leased by sender"
+			);
+
+			// the lease has begun, so the parachain is registered straight away.
+			assert_ok!(Slots::fix_deploy_data(Origin::signed(alice), id, vec![1], vec![2]));
+			assert_eq!(Parachains::active_parachains(), vec![id]);
+			assert_eq!(Parachains::parachain_code(&id), Some(vec![1]));
+			assert_eq!(Parachains::parachain_head(&id), Some(vec![2]));
+
+			run_to_block(29);
+			assert_eq!(Parachains::active_parachains(), vec![id]);
+
+			// lease period 3 begins at block 30.
+			run_to_block(30);
+			assert!(Parachains::active_parachains().is_empty());
+			assert!(Slots::lease(id).is_none());
+			assert_eq!(Balances::reserved_balance(&alice), 0);
+		});
+	}
+
+	#[test]
+	fn auction_ends_with_bids_winning_at_random_offset() {
+		with_externalities(&mut new_test_ext(), || {
+			let alice = account(Keyring::Alice);
+			let bob = account(Keyring::Bob);
+
+			System::set_block_number(1);
+			assert_ok!(Slots::new_auction(5, 1));
+			assert_ok!(Slots::bid(Origin::signed(alice), 1, 1, 2, 30));
+
+			// bob outbids alice late in the ending period, which starts at block 6.
+			run_to_block(8);
+			assert_ok!(Slots::bid(Origin::signed(bob), 1, 1, 2, 40));
+			assert_eq!(Slots::winning(2).unwrap()[range_index(0, 1)], Some((bob, 40)));
+
+			// the random seed is zero in tests, so the auction ends as of the start
+			// of its ending period, before bob's bid.
+			run_to_block(10);
+			assert!(Slots::auction_info().is_none());
+
+			let id: ParaId = FIRST_AUCTIONED_PARA_ID.into();
+			assert_eq!(Slots::leased_parachains(), vec![id]);
+			assert_eq!(Slots::lease(id).unwrap().owner, alice);
+			assert_eq!(Balances::reserved_balance(&alice), 30);
+			assert_eq!(Balances::reserved_balance(&bob), 0);
+		});
+	}
+
+	#[test]
+	fn parachain_is_registered_when_lease_begins() {
+		with_externalities(&mut new_test_ext(), || {
+			let alice = account(Keyring::Alice);
+
+			System::set_block_number(1);
+			assert_ok!(Slots::new_auction(5, 2));
+			assert_ok!(Slots::bid(Origin::signed(alice), 1, 2, 2, 30));
+
+			run_to_block(10);
+			let id: ParaId = FIRST_AUCTIONED_PARA_ID.into();
+			assert_ok!(Slots::fix_deploy_data(Origin::signed(alice), id, vec![1], vec![2]));
+			assert!(Parachains::active_parachains().is_empty());
+
+			run_to_block(20);
+			assert_eq!(Parachains::active_parachains(), vec![id]);
+			assert!(Slots::lease(id).unwrap().deploy_data.is_none());
+		});
+	}
+}
@@ -133,6 +133,7 @@ fn staging_testnet_config_genesis() -> GenesisConfig {
 		claims: Some(ClaimsConfig {
 			claims: vec![],
 		}),
+		slots: Some(Default::default()),
 	}
 }
 
@@ -236,6 +237,7 @@ fn testnet_genesis(initial_authorities: Vec<AuthorityId>, upgrade_key: H256) ->
 		claims: Some(ClaimsConfig {
 			claims: vec![],
 		}),
+		slots: Some(Default::default()),
 	}
 }
 
@@ -203,6 +203,24 @@ construct_service_factory! {
 					})?
 				};
 
+				// serves block data and erasure chunks to peers, and receives recovered data.
+				{
+					let extrinsic_store = extrinsic_store.clone();
+					service.network().with_spec(|spec, _| spec.register_availability_store(extrinsic_store));
+				}
+
+				let signed_statements = {
+					use std::path::PathBuf;
+
+					let mut path = PathBuf::from(service.config.database_path.clone());
+					path.push("signed_statements");
+
+					::consensus::slashing_protection::Store::new(::consensus::slashing_protection::Config {
+						cache_size: None,
+						path,
+					})?
+				};
+
 				// run authorship only if authority.
 				let key = match key {
 					Some(key) => key,
@@ -225,9 +243,18 @@ construct_service_factory! {
 					executor.clone(),
 					key.clone(),
 					extrinsic_store,
+					signed_statements,
+					// candidates are validated in a worker process started from
+					// this executable, see `polkadot_cli::run`.
+					::consensus::ValidationPool::external(Default::default()),
 					SlotDuration::get_or_compute(&*client)?,
 				);
 
+				// lets imported blocks be checked against the local statement tables.
+				service.config.custom.inherent_data_providers
+					.register_provider(proposer_factory.local_candidates_provider())
+					.map_err(|e| format!("Failed to register local candidates provider: {:?}", e))?;
+
 				info!("Using authority key {}", key.public());
 				let task = start_aura(
 					SlotDuration::get_or_compute(&*client)?,
@@ -24,9 +24,11 @@
 //! indicating whether the candidate is valid or invalid. Once a threshold of the committee
 //! has signed validity statements, the candidate may be marked includable.
 
+#[cfg(feature = "std")]
 use std::collections::hash_map::{HashMap, Entry};
-use std::hash::Hash;
-use std::fmt::Debug;
+use rstd::prelude::*;
+use rstd::hash::Hash;
+use rstd::fmt::Debug;
 
 /// Context for the statement table.
 pub trait Context {
@@ -51,6 +53,11 @@ pub trait Context {
 	/// Members are meant to submit candidates and vote on validity.
 	fn is_member_of(&self, authority: &Self::AuthorityId, group: &Self::GroupId) -> bool;
 
+	/// Whether a authority is a secondary checker of a group.
+	/// Secondary checkers re-check the group's candidates and only vote when they
+	/// find one to be invalid.
+	fn is_secondary_checker_of(&self, authority: &Self::AuthorityId, group: &Self::GroupId) -> bool;
+
 	// requisite number of votes for validity from a group.
 	fn requisite_votes(&self, group: &Self::GroupId) -> usize;
 }
@@ -89,7 +96,7 @@ pub struct SignedStatement<C, D, V, S> {
 ///
 /// Since there are three possible ways to vote, a double vote is possible in
 /// three possible combinations (unordered)
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Encode, Decode)]
 pub enum ValidityDoubleVote<C, D, S> {
 	/// Implicit vote by issuing and explicity voting validity.
 	IssuedAndValidity((C, S), (D, S)),
@@ -100,7 +107,7 @@ pub enum ValidityDoubleVote<C, D, S> {
 }
 
 /// Misbehavior: multiple signatures on same statement.
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Encode, Decode)]
 pub enum DoubleSign<C, D, S> {
 	/// On candidate.
 	Candidate(C, S, S),
@@ -111,7 +118,7 @@ pub enum DoubleSign<C, D, S> {
 }
 
 /// Misbehavior: declaring multiple candidates.
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Encode, Decode)]
 pub struct MultipleCandidates<C, S> {
 	/// The first candidate seen.
 	pub first: (C, S),
@@ -120,7 +127,7 @@ pub struct MultipleCandidates<C, S> {
 }
 
 /// Misbehavior: submitted statement for wrong group.
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Encode, Decode)]
 pub struct UnauthorizedStatement<C, D, V, S> {
 	/// A signed statement which was submitted without proper authority.
 	pub statement: SignedStatement<C, D, V, S>,
@@ -128,7 +135,7 @@ pub struct UnauthorizedStatement<C, D, V, S> {
 
 /// Different kinds of misbehavior. All of these kinds of malicious misbehavior
 /// are easily provable and extremely disincentivized.
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Encode, Decode)]
 pub enum Misbehavior<C, D, V, S> {
 	/// Voted invalid and valid on validity.
 	ValidityDoubleVote(ValidityDoubleVote<C, D, S>),
@@ -144,6 +151,7 @@ pub enum Misbehavior<C, D, V, S> {
 pub type MisbehaviorFor<C> = Misbehavior<<C as Context>::Candidate, <C as Context>::Digest, <C as Context>::AuthorityId, <C as Context>::Signature>;
 
 // kinds of votes for validity
+#[cfg(feature = "std")]
 #[derive(Clone, PartialEq, Eq)]
 enum ValidityVote<S: Eq + Clone> {
 	// implicit validity vote by issuing
@@ -190,13 +198,16 @@ pub struct AttestedCandidate<Group, Candidate, AuthorityId, Signature> {
 }
 
 /// Stores votes and data about a candidate.
+#[cfg(feature = "std")]
 pub struct CandidateData<C: Context> {
 	group_id: C::GroupId,
 	candidate: C::Candidate,
 	validity_votes: HashMap<C::AuthorityId, ValidityVote<C::Signature>>,
 	indicated_bad_by: Vec<C::AuthorityId>,
+	disputed_by: Vec<C::AuthorityId>,
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> CandidateData<C> {
 	/// whether this has been indicated bad by anyone.
 	pub fn indicated_bad(&self) -> bool {
@@ -244,24 +255,36 @@ impl<C: Context> CandidateData<C> {
 	// and no authorities have called it bad.
 	fn can_be_included(&self, validity_threshold: usize) -> bool {
 		self.indicated_bad_by.is_empty()
-			&& self.validity_votes.len() >= validity_threshold
+			&& self.valid_votes() >= validity_threshold
+	}
+
+	// the number of votes for validity, issuance included.
+	fn valid_votes(&self) -> usize {
+		self.validity_votes.values()
+			.filter(|v| match **v {
+				ValidityVote::Issued(_) | ValidityVote::Valid(_) => true,
+				ValidityVote::Invalid(_) => false,
+			})
+			.count()
 	}
 
 	fn summary(&self, digest: C::Digest) -> Summary<C::Digest, C::GroupId> {
 		Summary {
 			candidate: digest,
 			group_id: self.group_id.clone(),
-			validity_votes: self.validity_votes.len() - self.indicated_bad_by.len(),
+			validity_votes: self.valid_votes(),
 			signalled_bad: self.indicated_bad(),
 		}
 	}
 }
 
 // authority metadata
+#[cfg(feature = "std")]
 struct AuthorityData<C: Context> {
 	proposal: Option<(C::Digest, C::Signature)>,
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> Default for AuthorityData<C> {
 	fn default() -> Self {
 		AuthorityData {
@@ -271,12 +294,14 @@ impl<C: Context> Default for AuthorityData<C> {
 }
 
 /// Type alias for the result of a statement import.
+#[cfg(feature = "std")]
 pub type ImportResult<C> = Result<
 	Option<Summary<<C as Context>::Digest, <C as Context>::GroupId>>,
 	MisbehaviorFor<C>
 >;
 
 /// Stores votes
+#[cfg(feature = "std")]
 pub struct Table<C: Context> {
 	authority_data: HashMap<C::AuthorityId, AuthorityData<C>>,
 	detected_misbehavior: HashMap<C::AuthorityId, MisbehaviorFor<C>>,
@@ -284,6 +309,7 @@ pub struct Table<C: Context> {
 	includable_count: HashMap<C::GroupId, usize>,
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> Default for Table<C> {
 	fn default() -> Self {
 		Table {
@@ -295,6 +321,7 @@ impl<C: Context> Default for Table<C> {
 	}
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> Table<C> {
 	/// Produce a set of proposed candidates.
 	///
@@ -397,6 +424,34 @@ impl<C: Context> Table<C> {
 		self.candidate_votes.get(digest).map(|d| &d.candidate)
 	}
 
+	/// Get the digests of all candidates which have been indicated bad by at least
+	/// as many authorities as must attest to the validity of a candidate of their group.
+	///
+	/// Fewer votes of invalidity can't prove anything on their own and are left to be
+	/// settled by a dispute.
+	pub fn indicated_bad_candidates(&self, context: &C) -> Vec<C::Digest> {
+		self.candidate_votes.iter()
+			.filter(|&(_, data)| data.indicated_bad_by.len() >= context.requisite_votes(&data.group_id))
+			.map(|(digest, _)| digest.clone())
+			.collect()
+	}
+
+	/// Get the votes of invalidity cast by secondary checkers, along with the digests
+	/// of the candidates they are about.
+	///
+	/// These don't keep candidates from being included, but should be used to dispute
+	/// the validity of the candidates once they are.
+	pub fn disputes(&self) -> Vec<(C::Digest, C::AuthorityId, C::Signature)> {
+		self.candidate_votes.iter()
+			.flat_map(|(digest, data)| data.disputed_by.iter().filter_map(move |authority| {
+				match data.validity_votes.get(authority) {
+					Some(&ValidityVote::Invalid(ref s)) => Some((digest.clone(), authority.clone(), s.clone())),
+					_ => None,
+				}
+			}))
+			.collect()
+	}
+
 	/// Access all witnessed misbehavior.
 	pub fn get_misbehavior(&self)
 		-> &HashMap<C::AuthorityId, MisbehaviorFor<C>>
@@ -476,6 +531,7 @@ impl<C: Context> Table<C> {
 				candidate: candidate,
 				validity_votes: HashMap::new(),
 				indicated_bad_by: Vec::new(),
+				disputed_by: Vec::new(),
 			});
 		}
 
@@ -502,8 +558,20 @@ impl<C: Context> Table<C> {
 		let v_threshold = context.requisite_votes(&votes.group_id);
 		let was_includable = votes.can_be_included(v_threshold);
 
+		// secondary checkers only get a say when they disagree with the group.
+		// their validity votes don't count towards the group's threshold, and
+		// their invalidity votes are settled by disputing the candidate on-chain.
+		let secondary_check = !context.is_member_of(&from, &votes.group_id)
+			&& context.is_secondary_checker_of(&from, &votes.group_id);
+
+		if secondary_check {
+			if let ValidityVote::Valid(_) = vote {
+				return Ok(None);
+			}
+		}
+
 		// check that this authority actually can vote in this group.
-		if !context.is_member_of(&from, &votes.group_id) {
+		if !secondary_check && !context.is_member_of(&from, &votes.group_id) {
 			let (sig, valid) = match vote {
 				ValidityVote::Valid(s) => (s, true),
 				ValidityVote::Invalid(s) => (s, false),
@@ -565,7 +633,11 @@ impl<C: Context> Table<C> {
 			}
 			Entry::Vacant(vacant) => {
 				if let ValidityVote::Invalid(_) = vote {
-					votes.indicated_bad_by.push(from);
+					if secondary_check {
+						votes.disputed_by.push(from);
+					} else {
+						votes.indicated_bad_by.push(from);
+					}
 				}
 
 				vacant.insert(vote);
@@ -579,6 +651,7 @@ impl<C: Context> Table<C> {
 	}
 }
 
+#[cfg(feature = "std")]
 fn update_includable_count<G: Hash + Eq + Clone>(map: &mut HashMap<G, usize>, group_id: &G, was_includable: bool, is_includable: bool) {
 	if was_includable && !is_includable {
 		if let Entry::Occupied(mut entry) = map.entry(group_id.clone()) {
@@ -622,7 +695,9 @@ mod tests {
 	#[derive(Debug, PartialEq, Eq)]
 	struct TestContext {
 		// v -> parachain group
-		authorities: HashMap<AuthorityId, GroupId>
+		authorities: HashMap<AuthorityId, GroupId>,
+		// v -> parachain group re-checked by v
+		secondary_checkers: HashMap<AuthorityId, GroupId>,
 	}
 
 	impl Context for TestContext {
@@ -648,6 +723,14 @@ mod tests {
 			self.authorities.get(authority).map(|v| v == group).unwrap_or(false)
 		}
 
+		fn is_secondary_checker_of(
+			&self,
+			authority: &AuthorityId,
+			group: &GroupId
+		) -> bool {
+			self.secondary_checkers.get(authority).map(|v| v == group).unwrap_or(false)
+		}
+
 		fn requisite_votes(&self, id: &GroupId) -> usize {
 			let mut total_validity = 0;
 
@@ -666,7 +749,8 @@ mod tests {
 				let mut map = HashMap::new();
 				map.insert(AuthorityId(1), GroupId(2));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -702,7 +786,8 @@ mod tests {
 				let mut map = HashMap::new();
 				map.insert(AuthorityId(1), GroupId(3));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -734,7 +819,8 @@ mod tests {
 				map.insert(AuthorityId(1), GroupId(2));
 				map.insert(AuthorityId(2), GroupId(3));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -770,6 +856,96 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn secondary_checker_invalidity_vote_disputes_candidate() {
+		let context = TestContext {
+			authorities: {
+				let mut map = HashMap::new();
+				map.insert(AuthorityId(1), GroupId(2));
+				map.insert(AuthorityId(2), GroupId(2));
+				map
+			},
+			secondary_checkers: {
+				let mut map = HashMap::new();
+				map.insert(AuthorityId(3), GroupId(2));
+				map
+			},
+		};
+
+		let mut table = create();
+		let candidate_digest = Digest(100);
+
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Candidate(Candidate(2, 100)),
+			signature: Signature(1),
+			sender: AuthorityId(1),
+		});
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Valid(candidate_digest.clone()),
+			signature: Signature(2),
+			sender: AuthorityId(2),
+		});
+		assert!(table.candidate_includable(&candidate_digest, &context));
+
+		// agreement of the secondary checker has no effect.
+		assert!(table.import_statement(&context, SignedStatement {
+			statement: Statement::Valid(candidate_digest.clone()),
+			signature: Signature(3),
+			sender: AuthorityId(3),
+		}).is_none());
+		assert!(table.candidate_includable(&candidate_digest, &context));
+
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Invalid(candidate_digest.clone()),
+			signature: Signature(3),
+			sender: AuthorityId(3),
+		});
+
+		// disagreement doesn't keep the candidate from being included, but is
+		// left to be settled by a dispute.
+		assert!(table.detected_misbehavior.is_empty());
+		assert!(table.candidate_includable(&candidate_digest, &context));
+		assert_eq!(table.includable_count.get(&GroupId(2)), Some(&1));
+		assert!(table.indicated_bad_candidates(&context).is_empty());
+		assert_eq!(table.disputes(), vec![(candidate_digest, AuthorityId(3), Signature(3))]);
+	}
+
+	#[test]
+	fn candidates_are_indicated_bad_by_threshold_of_invalidity_votes() {
+		let context = TestContext {
+			authorities: {
+				let mut map = HashMap::new();
+				map.insert(AuthorityId(1), GroupId(2));
+				map.insert(AuthorityId(2), GroupId(2));
+				map.insert(AuthorityId(3), GroupId(2));
+				map
+			},
+			secondary_checkers: HashMap::new(),
+		};
+
+		let mut table = create();
+		let candidate_digest = Digest(100);
+
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Candidate(Candidate(2, 100)),
+			signature: Signature(1),
+			sender: AuthorityId(1),
+		});
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Invalid(candidate_digest.clone()),
+			signature: Signature(2),
+			sender: AuthorityId(2),
+		});
+		assert!(table.indicated_bad_candidates(&context).is_empty());
+
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Invalid(candidate_digest.clone()),
+			signature: Signature(3),
+			sender: AuthorityId(3),
+		});
+		assert_eq!(table.indicated_bad_candidates(&context), vec![candidate_digest]);
+	}
+
 	#[test]
 	fn validity_double_vote_is_misbehavior() {
 		let context = TestContext {
@@ -778,7 +954,8 @@ mod tests {
 				map.insert(AuthorityId(1), GroupId(2));
 				map.insert(AuthorityId(2), GroupId(2));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -827,7 +1004,8 @@ mod tests {
 				map.insert(AuthorityId(1), GroupId(2));
 				map.insert(AuthorityId(2), GroupId(2));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -859,7 +1037,8 @@ mod tests {
 				map.insert(AuthorityId(2), GroupId(2));
 				map.insert(AuthorityId(3), GroupId(2));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -922,7 +1101,8 @@ mod tests {
 				let mut map = HashMap::new();
 				map.insert(AuthorityId(1), GroupId(2));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -961,6 +1141,7 @@ mod tests {
 			candidate: Candidate(4, 12345),
 			validity_votes: HashMap::new(),
 			indicated_bad_by: Vec::new(),
+			disputed_by: Vec::new(),
 		};
 
 		assert!(!candidate.can_be_included(validity_threshold));
@@ -985,7 +1166,8 @@ mod tests {
 				map.insert(AuthorityId(2), GroupId(2));
 				map.insert(AuthorityId(3), GroupId(2));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		// have 2/3 validity guarantors note validity.
@@ -1034,7 +1216,8 @@ mod tests {
 				let mut map = HashMap::new();
 				map.insert(AuthorityId(1), GroupId(2));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -1060,7 +1243,8 @@ mod tests {
 				map.insert(AuthorityId(1), GroupId(2));
 				map.insert(AuthorityId(2), GroupId(2));
 				map
-			}
+			},
+			secondary_checkers: HashMap::new(),
 		};
 
 		let mut table = create();
@@ -13,9 +13,16 @@
 //! Each parachain is associated with two sets of authorities: those which can
 //! propose and attest to validity of candidates, and those who can only attest
 //! to availability.
+//!
+//! The statement and misbehavior types are usable without `std`, so that the
+//! runtime can check reports of misbehavior. The table itself requires `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(feature = "std"), feature(alloc))]
 
 extern crate parity_codec as codec;
 extern crate substrate_primitives;
+extern crate sr_std as rstd;
 extern crate polkadot_primitives as primitives;
 
 #[macro_use]
@@ -23,6 +30,7 @@ extern crate parity_codec_derive;
 
 pub mod generic;
 
+#[cfg(feature = "std")]
 pub use generic::Table;
 
 use primitives::parachain::{
@@ -48,6 +56,11 @@ pub trait Context {
 	/// Members are meant to submit candidates and vote on validity.
 	fn is_member_of(&self, authority: &SessionKey, group: &Id) -> bool;
 
+	/// Whether a authority is a secondary checker of a group.
+	/// Secondary checkers re-check the group's candidates and only vote when they
+	/// find one to be invalid.
+	fn is_secondary_checker_of(&self, authority: &SessionKey, group: &Id) -> bool;
+
 	// requisite number of votes for validity from a group.
 	fn requisite_votes(&self, group: &Id) -> usize;
 }
@@ -71,6 +84,10 @@ impl<C: Context> generic::Context for C {
 		Context::is_member_of(self, authority, group)
 	}
 
+	fn is_secondary_checker_of(&self, authority: &SessionKey, group: &Id) -> bool {
+		Context::is_secondary_checker_of(self, authority, group)
+	}
+
 	fn requisite_votes(&self, group: &Id) -> usize {
 		Context::requisite_votes(self, group)
 	}
@@ -34,7 +34,7 @@ use adder::{HeadData as AdderHead, BlockData as AdderBody};
 use substrate_primitives::ed25519::Pair;
 use parachain::codec::{Encode, Decode};
 use primitives::parachain::{HeadData, BlockData, Id as ParaId, Message};
-use collator::{InvalidHead, ParachainContext, VersionInfo};
+use collator::{InvalidHead, ParachainContext, ParachainCandidate, VersionInfo};
 use parking_lot::Mutex;
 
 const GENESIS: AdderHead = AdderHead {
@@ -59,7 +59,7 @@ impl ParachainContext for AdderContext {
 		&self,
 		last_head: HeadData,
 		_ingress: I,
-	) -> Result<(BlockData, HeadData), InvalidHead>
+	) -> Result<ParachainCandidate, InvalidHead>
 	{
 		let adder_head = AdderHead::decode(&mut &last_head.0[..])
 			.ok_or(InvalidHead)?;
@@ -89,7 +89,14 @@ impl ParachainContext for AdderContext {
 			next_head.number, next_body.state.overflowing_add(next_body.add).0);
 
 		db.insert(next_head.clone(), next_body);
-		Ok((encoded_body, encoded_head))
+		Ok(ParachainCandidate {
+			block_data: encoded_body,
+			head_data: encoded_head,
+			balance_uploads: Vec::new(),
+			fees: 0,
+			new_validation_code: None,
+			outgoing_messages: Vec::new(),
+		})
 	}
 }
 
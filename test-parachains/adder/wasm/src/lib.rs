@@ -32,12 +32,12 @@ extern crate tiny_keccak;
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 use core::{intrinsics, panic};
+use alloc::vec::Vec;
 use parachain::ValidationResult;
 use parachain::codec::{Encode, Decode};
 use adder::{HeadData, BlockData};
 
 #[panic_handler]
-#[no_mangle]
 pub fn panic(_info: &panic::PanicInfo) -> ! {
 	unsafe {
 		intrinsics::abort()
@@ -45,7 +45,6 @@ pub fn panic(_info: &panic::PanicInfo) -> ! {
 }
 
 #[alloc_error_handler]
-#[no_mangle]
 pub fn oom(_: ::core::alloc::Layout) -> ! {
 	unsafe {
 		intrinsics::abort();
@@ -65,7 +64,11 @@ pub extern fn validate(offset: usize, len: usize) -> usize {
 
 	match ::adder::execute(parent_hash, parent_head, &block_data) {
 		Ok(new_head) => parachain::wasm_api::write_result(
-			ValidationResult { head_data: new_head.encode() }
+			ValidationResult {
+				head_data: new_head.encode(),
+				balance_uploads: Vec::new(),
+				new_validation_code: None,
+			}
 		),
 		Err(_) => panic!("execution failure"),
 	}